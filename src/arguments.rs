@@ -0,0 +1,138 @@
+use std::ffi::{OsStr, OsString};
+use widestring::U16String;
+
+/// The UTF-16 code units of the characters that decide how an argument is quoted
+const SPACE: u16 = b' ' as u16;
+const TAB: u16 = b'\t' as u16;
+const NEWLINE: u16 = b'\n' as u16;
+const VERTICAL_TAB: u16 = 0x0b;
+const QUOTE: u16 = b'"' as u16;
+const BACKSLASH: u16 = b'\\' as u16;
+
+/// The command line of a launch, the quoted executable followed by the arguments as given
+///
+/// The executable is quoted as well, for programs that read their path from the command line.
+pub(crate) fn command_line(application_name: &OsStr, args: &OsStr) -> OsString {
+    let mut command_line = OsString::new();
+    append_argument(&mut command_line, application_name);
+    if !args.is_empty() {
+        command_line.push(" ");
+        command_line.push(args);
+    }
+    command_line
+}
+
+/// Appends an argument to a command line, quoted so `CommandLineToArgvW` and the MSVC runtime
+/// split it back out unchanged
+///
+/// Works on UTF-16 code units, so arguments that are not valid UTF-8 pass through unchanged.
+pub(crate) fn append_argument(command_line: &mut OsString, argument: &OsStr) {
+    if !command_line.is_empty() {
+        command_line.push(" ");
+    }
+
+    let units = U16String::from_os_str(argument).into_vec();
+    let quote = units.is_empty()
+        || units
+            .iter()
+            .any(|unit| matches!(*unit, SPACE | TAB | NEWLINE | VERTICAL_TAB | QUOTE));
+    if !quote {
+        command_line.push(argument);
+        return;
+    }
+
+    let mut quoted = Vec::with_capacity(units.len() + 2);
+    quoted.push(QUOTE);
+    let mut backslashes = 0;
+    for unit in units {
+        if unit == BACKSLASH {
+            backslashes += 1;
+        } else {
+            // Backslashes only escape when a quote follows, which is escaped along with them
+            if unit == QUOTE {
+                quoted.extend(std::iter::repeat_n(BACKSLASH, backslashes + 1));
+            }
+            backslashes = 0;
+        }
+        quoted.push(unit);
+    }
+    // The closing quote must not be escaped by trailing backslashes
+    quoted.extend(std::iter::repeat_n(BACKSLASH, backslashes));
+    quoted.push(QUOTE);
+
+    command_line.push(U16String::from_vec(quoted).to_os_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(argument: &str) -> String {
+        let mut command_line = OsString::new();
+        append_argument(&mut command_line, OsStr::new(argument));
+        command_line.into_string().unwrap()
+    }
+
+    #[test]
+    fn quotes_arguments_as_windows_splits_them() {
+        let cases = [
+            // Empty arguments only survive quoted
+            ("", r#""""#),
+            ("plain", "plain"),
+            (
+                "C:\\Program Files\\app.exe",
+                r#""C:\Program Files\app.exe""#,
+            ),
+            ("tab\there", "\"tab\there\""),
+            ("new\nline", "\"new\nline\""),
+            ("vertical\u{b}tab", "\"vertical\u{b}tab\""),
+            // Embedded quotes are escaped
+            (r#"say "hi""#, r#""say \"hi\"""#),
+            (r#"""#, r#""\"""#),
+            // Backslashes before a quote are doubled, along with the quote's own escape
+            (r#"a\"b"#, r#""a\\\"b""#),
+            (r#"a\\"b"#, r#""a\\\\\"b""#),
+            // Backslashes elsewhere are taken literally
+            (r"C:\dir\file", r"C:\dir\file"),
+            (r"a\\b c", r#""a\\b c""#),
+            // Trailing backslashes are doubled so they do not escape the closing quote
+            (r"C:\Program Files\", r#""C:\Program Files\\""#),
+            (r"dir with space\\", r#""dir with space\\\\""#),
+            // Without quoting, trailing backslashes stay as they are
+            (r"C:\dir\", r"C:\dir\"),
+        ];
+        for (argument, expected) in cases {
+            assert_eq!(quote(argument), expected, "{argument:?}");
+        }
+    }
+
+    #[test]
+    fn separates_arguments_with_spaces() {
+        let mut command_line = OsString::new();
+        for argument in ["app.exe", "", "two words", "-flag"] {
+            append_argument(&mut command_line, OsStr::new(argument));
+        }
+        assert_eq!(command_line, r#"app.exe "" "two words" -flag"#);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn keeps_arguments_that_are_not_utf8() {
+        use std::os::windows::ffi::{OsStrExt, OsStringExt};
+
+        // An unpaired surrogate cannot be represented in UTF-8
+        let plain = OsString::from_wide(&[b'a' as u16, 0xD800, b'b' as u16]);
+        let mut command_line = OsString::new();
+        append_argument(&mut command_line, &plain);
+        assert_eq!(command_line, plain);
+
+        let spaced = OsString::from_wide(&[0xD800, SPACE, b'"' as u16]);
+        let mut command_line = OsString::new();
+        append_argument(&mut command_line, &spaced);
+        let units: Vec<u16> = command_line.encode_wide().collect();
+        assert_eq!(
+            units,
+            [QUOTE, 0xD800, SPACE, BACKSLASH, QUOTE, QUOTE].as_slice()
+        );
+    }
+}
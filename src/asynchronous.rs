@@ -0,0 +1,170 @@
+use crate::child::Child;
+use crate::error::{Error, Stage};
+use crate::safe_windows_bindings::high_level::{close_handle, copy_handle, wait_for_process};
+use crate::stdio::{ChildStderr, ChildStdout};
+use crate::ProcessBuilder;
+use std::io::Read;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::mpsc::{channel, Receiver};
+use tokio::task::spawn_blocking;
+
+/// How much of a stream is read at once
+const CHUNK_SIZE: usize = 4096;
+
+/// How many chunks a stream reads ahead of the caller
+const CHUNKS_AHEAD: usize = 16;
+
+impl ProcessBuilder {
+    /// Runs the built process from a thread of tokio's blocking pool and returns a handle to it
+    ///
+    /// Launching acquires tokens, loads profiles and waits for the backend, none of which
+    /// should block the runtime. Must be called from within a tokio runtime.
+    ///
+    /// ```no_run
+    /// # async fn launch() -> Result<(), win_run::Error> {
+    /// use win_run::win;
+    ///
+    /// let child = win("/path/to/worker").spawn_async().await?;
+    /// let status = child.wait_async().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn spawn_async(&self) -> Result<Child, Error> {
+        let builder = self.clone();
+        spawn_blocking(move || builder.spawn())
+            .await
+            .unwrap_or_else(|err| {
+                Err(Error::other(
+                    Stage::CreateProcess,
+                    format!("The launching thread failed: {err}"),
+                ))
+            })
+    }
+}
+
+impl Child {
+    /// Waits for the process to exit from a thread of tokio's blocking pool and returns its exit code
+    ///
+    /// The thread waits on its own handle to the process, so dropping the future does not stop it
+    /// before the process exits. Must be called from within a tokio runtime.
+    ///
+    /// Fails with [`Error::Timeout`] if the process was terminated for running longer than the
+    /// [timeout](ProcessBuilder::timeout) of its builder, as [`Child::wait`] does.
+    pub async fn wait_async(&self) -> Result<u32, Error> {
+        self.check_waitable()?;
+        let handle = copy_handle(self.handle)?;
+        let pumps = self.take_pumps();
+        spawn_blocking(move || {
+            let waited = match (wait_for_process(handle), close_handle(handle)) {
+                (Ok(()), Ok(())) => Ok(()),
+                (Ok(()), Err(err)) | (Err(err), Ok(())) => Err(err),
+                (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
+            };
+            // The copying threads only end once the process has exited
+            if waited.is_ok() {
+                for pump in pumps {
+                    let _ = pump.join();
+                }
+            }
+            waited
+        })
+        .await
+        .unwrap_or_else(|err| {
+            Err(Error::other(
+                Stage::Waiting,
+                format!("The waiting thread failed: {err}"),
+            ))
+        })?;
+        self.exit_code()
+    }
+}
+
+impl ChildStdout {
+    /// Turns the pipe into an async reader, see [`AsyncPipeReader`]
+    pub fn into_async(self) -> AsyncPipeReader {
+        AsyncPipeReader::new(self)
+    }
+}
+
+impl ChildStderr {
+    /// Turns the pipe into an async reader, see [`AsyncPipeReader`]
+    pub fn into_async(self) -> AsyncPipeReader {
+        AsyncPipeReader::new(self)
+    }
+}
+
+/// A piped output stream of a child, read asynchronously
+///
+/// A thread of its own reads the pipe ahead of the caller, ending once the process closed the
+/// stream or the reader is dropped. Use it through `tokio::io::AsyncReadExt` and the like.
+///
+/// ```no_run
+/// # async fn launch() -> Result<(), win_run::Error> {
+/// use tokio::io::AsyncReadExt;
+/// use win_run::{win, Stdio};
+///
+/// let mut child = win("/path/to/worker").stdout(Stdio::Piped).spawn_async().await?;
+/// let mut output = Vec::new();
+/// if let Some(stdout) = child.take_stdout() {
+///     stdout.into_async().read_to_end(&mut output).await.expect("Could not read the output");
+/// }
+/// child.wait_async().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct AsyncPipeReader {
+    chunks: Receiver<std::io::Result<Vec<u8>>>,
+    /// The part of the last chunk the caller has not read yet
+    pending: Vec<u8>,
+}
+
+impl AsyncPipeReader {
+    /// Starts reading the pipe on a thread of its own
+    fn new(mut reader: impl Read + Send + 'static) -> Self {
+        let (sender, chunks) = channel(CHUNKS_AHEAD);
+        std::thread::spawn(move || {
+            let mut buffer = [0u8; CHUNK_SIZE];
+            loop {
+                let chunk = match reader.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(read) => Ok(buffer[..read].to_vec()),
+                    Err(err) => Err(err),
+                };
+                let failed = chunk.is_err();
+                // The reader was dropped, nobody is interested in the rest
+                if sender.blocking_send(chunk).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Self {
+            chunks,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl AsyncRead for AsyncPipeReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if self.pending.is_empty() {
+            match self.chunks.poll_recv(cx) {
+                Poll::Pending => return Poll::Pending,
+                // The stream ended, which reads as nothing
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Err(err)),
+                Poll::Ready(Some(Ok(chunk))) => self.pending = chunk,
+            }
+        }
+        let read = self.pending.len().min(buf.remaining());
+        buf.put_slice(&self.pending[..read]);
+        self.pending.drain(..read);
+        Poll::Ready(Ok(()))
+    }
+}
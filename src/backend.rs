@@ -0,0 +1,351 @@
+use crate::child::PrimaryThread;
+use crate::error::{Error, Stage};
+use crate::events::LaunchEvent;
+use crate::identity::{ExpectedIdentity, TokenOrigin};
+use crate::privileges::{
+    current_privileges, holds, SE_ASSIGN_PRIMARY_TOKEN, SE_IMPERSONATE, SE_TCB,
+};
+use crate::safe_windows_bindings::high_level::{
+    close_handle, create_process_with_logon, create_process_with_token,
+    create_process_with_token_secondary, get_token_session_id, get_token_user_sid,
+    map_drives_for_token, NativeCommand,
+};
+use crate::safe_windows_bindings::types::{ERROR_ACCESS_DENIED, ERROR_PRIVILEGE_NOT_HELD, HANDLE};
+use crate::{Elevation, LogonFlags, ProcessBuilder};
+use std::ffi::{OsStr, OsString};
+
+/// A strategy for creating processes
+///
+/// Implement it to add launch strategies, e.g. through the task scheduler or WMI,
+/// or to replace launching with a fake in tests.
+pub trait LaunchBackend: Send + Sync {
+    /// A short name of the strategy, for diagnostics
+    fn name(&self) -> &str;
+
+    /// Starts the process described by the request
+    fn launch(&self, request: &LaunchRequest) -> Result<Launched, Error>;
+}
+
+/// Everything a backend needs to know to start a process
+pub struct LaunchRequest<'a> {
+    /// The builder the launch was started from
+    pub(crate) builder: &'a ProcessBuilder,
+    /// The path to the executable, with variables expanded if requested
+    pub(crate) application_name: OsString,
+    /// The full command line, with variables expanded if requested
+    pub(crate) command_line: OsString,
+    /// The directory to run from, with variables expanded if requested
+    pub(crate) current_directory: OsString,
+    /// The desktop to run on, in `WindowStation\Desktop` form or empty for the default one
+    pub(crate) desktop: String,
+    /// The inheritable input, output and error handles, if the streams are redirected
+    pub(crate) std_handles: Option<[HANDLE; 3]>,
+    /// The title of a new console window, if its settings are picked from the registry
+    pub(crate) console_title: Option<String>,
+    /// The environment variables of the process, the service's if missing
+    pub(crate) environment: Option<Vec<(OsString, OsString)>>,
+    /// The correlation id of the launch, if the builder correlates its launches
+    pub(crate) correlation_id: Option<String>,
+}
+
+impl LaunchRequest<'_> {
+    /// The path to the executable
+    pub fn application_name(&self) -> &OsStr {
+        &self.application_name
+    }
+
+    /// The full command line, starting with the executable
+    pub fn command_line(&self) -> &OsStr {
+        &self.command_line
+    }
+
+    /// The directory to run the executable from
+    pub fn current_directory(&self) -> &OsStr {
+        &self.current_directory
+    }
+
+    /// The desktop to run on, in `WindowStation\Desktop` form or empty for the default one
+    pub fn desktop(&self) -> &str {
+        &self.desktop
+    }
+
+    /// The user to run the executable as
+    pub fn elevation(&self) -> &Elevation {
+        &self.builder.elevation
+    }
+
+    /// The correlation id of the launch, see [`ProcessBuilder::correlate`](crate::ProcessBuilder::correlate)
+    pub fn correlation_id(&self) -> Option<&str> {
+        self.correlation_id.as_deref()
+    }
+
+    /// Converts the request into the native parameters of the process creation functions
+    fn native_command(&self) -> Result<NativeCommand, Error> {
+        let command = NativeCommand::new(
+            &self.application_name,
+            &self.command_line,
+            &self.current_directory,
+            &self.desktop,
+        )?;
+        let command = match self.std_handles {
+            Some(std_handles) => command.with_std_handles(std_handles),
+            None => command,
+        };
+        let command = match self.builder.window_position {
+            Some((x, y)) => command.with_window_position(x, y),
+            None => command,
+        };
+        let command = match self.builder.window_size {
+            Some((width, height)) => command.with_window_size(width, height),
+            None => command,
+        };
+        let command = match self.builder.show_window {
+            Some(show_window) => command.with_show_window(show_window.to_native()),
+            None => command,
+        };
+        let command = if self.builder.harden_dll_search {
+            command.with_hardened_dll_search()
+        } else {
+            command
+        };
+        let command = if self.builder.keep_primary_thread {
+            command.with_primary_thread()
+        } else {
+            command
+        };
+        let command = if self.builder.no_window {
+            command.with_no_window()
+        } else {
+            command
+        };
+        let command = if self.builder.new_console {
+            command.with_new_console()
+        } else {
+            command
+        };
+        let command = match self.builder.priority {
+            Some(priority) => command.with_priority_class(priority.to_native()),
+            None => command,
+        };
+        let command = command.with_creation_flags(self.builder.creation_flags);
+        // The process runs once it is set up, e.g. in the job, so it cannot start anything outside of it
+        let command = if self.builder.starts_suspended() {
+            command.with_suspended_start()
+        } else {
+            command
+        };
+        let command = match &self.environment {
+            Some(environment) => command.with_environment(environment)?,
+            None => command,
+        };
+        match &self.console_title {
+            Some(console_title) => command.with_console_title(console_title),
+            None => Ok(command),
+        }
+    }
+}
+
+/// A process started by a backend
+#[derive(Debug)]
+pub struct Launched {
+    /// The id of the process
+    pub(crate) pid: u32,
+    /// Handle to the process, invalid if the backend has none
+    pub(crate) handle: HANDLE,
+    /// Who the process should run as, as far as the backend knows
+    pub(crate) expected: ExpectedIdentity,
+    /// Where the launch token came from, `None` for backends of the caller
+    pub(crate) origin: Option<TokenOrigin>,
+    /// The primary thread of the process, if the builder keeps it
+    pub(crate) primary_thread: Option<PrimaryThread>,
+}
+
+impl Launched {
+    /// A launch for which the backend only knows the pid, e.g. a fake in tests
+    pub fn from_pid(pid: u32) -> Self {
+        Self {
+            pid,
+            handle: HANDLE::default(),
+            expected: ExpectedIdentity::default(),
+            origin: None,
+            primary_thread: None,
+        }
+    }
+
+    /// A launch for which the backend holds a handle to the process, and the id of and a handle
+    /// to its primary thread if it kept them
+    ///
+    /// The [`Child`](crate::Child) takes ownership of the handles and closes them once it is
+    /// dropped, so the backend must not close them itself. The process handle needs at least
+    /// `SYNCHRONIZE` and `PROCESS_QUERY_LIMITED_INFORMATION` access for the child to be waited for.
+    pub fn from_handle(pid: u32, handle: HANDLE, primary_thread: Option<(u32, HANDLE)>) -> Self {
+        Self {
+            pid,
+            handle,
+            expected: ExpectedIdentity::default(),
+            origin: None,
+            primary_thread: primary_thread.map(|(tid, handle)| PrimaryThread { tid, handle }),
+        }
+    }
+}
+
+/// The built-in process creation strategies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// CreateProcessAsUserW, requires SeAssignPrimaryTokenPrivilege and SeIncreaseQuotaPrivilege
+    #[default]
+    AsUser,
+    /// CreateProcessWithTokenW through the secondary logon service, requires SeImpersonatePrivilege
+    WithToken,
+    /// CreateProcessWithLogonW through the secondary logon service, requires `Elevation::Credentials`
+    WithLogon,
+    /// Picks the strongest of the other strategies the service holds the privileges for,
+    /// falling back to the next one when a strategy is denied
+    Auto,
+}
+
+impl Backend {
+    /// The strategies that can succeed with the privileges of the current process, strongest first
+    fn candidates(elevation: &Elevation) -> Result<Vec<Backend>, Error> {
+        let privileges = current_privileges()?;
+
+        // The token of the logged on user can only be taken when acting as part of the operating system
+        if matches!(elevation, Elevation::User | Elevation::Admin) && !holds(&privileges, SE_TCB) {
+            return Err(Error::other(
+                Stage::UserTokenQuery,
+                format!("Launching as the logged on user requires {SE_TCB}"),
+            ));
+        }
+
+        let mut candidates = Vec::new();
+        if holds(&privileges, SE_ASSIGN_PRIMARY_TOKEN) {
+            candidates.push(Backend::AsUser);
+        }
+        if holds(&privileges, SE_IMPERSONATE) {
+            candidates.push(Backend::WithToken);
+        }
+        if let Elevation::Credentials(_) = elevation {
+            candidates.push(Backend::WithLogon);
+        }
+
+        if candidates.is_empty() {
+            return Err(Error::other(
+                Stage::CreateProcess,
+                format!(
+                    "No launch strategy is available, the service holds neither {SE_ASSIGN_PRIMARY_TOKEN} nor {SE_IMPERSONATE}"
+                ),
+            ));
+        }
+
+        Ok(candidates)
+    }
+
+    /// Whether a failed launch may still succeed with another strategy
+    fn is_denied(error: &Error) -> bool {
+        error.stage() == Stage::CreateProcess
+            && matches!(
+                error.code(),
+                Some(code) if code == ERROR_ACCESS_DENIED.0 as i32 || code == ERROR_PRIVILEGE_NOT_HELD.0 as i32
+            )
+    }
+}
+
+impl LaunchBackend for Backend {
+    fn name(&self) -> &str {
+        match self {
+            Backend::AsUser => "CreateProcessAsUserW",
+            Backend::WithToken => "CreateProcessWithTokenW",
+            Backend::WithLogon => "CreateProcessWithLogonW",
+            Backend::Auto => "Auto",
+        }
+    }
+
+    fn launch(&self, request: &LaunchRequest) -> Result<Launched, Error> {
+        if let Backend::Auto = self {
+            let mut last_error = None;
+            for backend in Backend::candidates(request.elevation())? {
+                match backend.launch(request) {
+                    Err(err) if Backend::is_denied(&err) => last_error = Some(err),
+                    launched => return launched,
+                }
+            }
+            return Err(last_error.expect("There is always at least one candidate"));
+        }
+
+        // The secondary logon service takes no attribute lists, which carry the mitigation policy
+        if request.builder.harden_dll_search && *self != Backend::AsUser {
+            return Err(Error::other(
+                Stage::CreateProcess,
+                format!("{} cannot harden the DLL search of a process", self.name()),
+            ));
+        }
+
+        let mut command = request.native_command()?;
+
+        if let Backend::WithLogon = self {
+            // The secondary logon service logs the account on and creates the process in one go
+            let Elevation::Credentials(credentials) = request.elevation() else {
+                return Err(Error::other(
+                    Stage::CreateProcess,
+                    "CreateProcessWithLogonW can only launch with credentials",
+                ));
+            };
+            let logon_flags = credentials.logon_flags.unwrap_or(LogonFlags::WithProfile);
+            let (pid, handle, thread) = create_process_with_logon(
+                &credentials.username,
+                &credentials.domain,
+                &credentials.password,
+                logon_flags.to_native(),
+                &mut command,
+            )
+            .map_err(|err| err.with_hint(None))?;
+            return Ok(Launched {
+                pid,
+                handle,
+                expected: ExpectedIdentity::default(),
+                origin: Some(TokenOrigin::Credentials {
+                    username: credentials.username.clone(),
+                    domain: credentials.domain.clone(),
+                }),
+                primary_thread: thread.map(|(tid, handle)| PrimaryThread { tid, handle }),
+            });
+        }
+
+        let (token, origin) = request.builder.acquire_token()?;
+
+        // Remember who the process should run as before the token is consumed
+        let expected = ExpectedIdentity {
+            sid: get_token_user_sid(token).ok(),
+            session_id: get_token_session_id(token).ok(),
+        };
+        if !request.builder.drive_mappings.is_empty() {
+            if let Err(err) = map_drives_for_token(token, &request.builder.drive_mappings) {
+                return Err(match close_handle(token) {
+                    Ok(()) => err,
+                    Err(cleanup) => Error::combine(vec![err, cleanup]),
+                });
+            }
+        }
+        if let Some(events) = &request.builder.events {
+            events.send(LaunchEvent::TokenAcquired {
+                path: events.path(),
+                session_id: expected.session_id,
+                correlation_id: request.correlation_id.clone(),
+            });
+        }
+
+        let (pid, handle, thread) = match self {
+            Backend::WithToken => create_process_with_token_secondary(token, &mut command),
+            _ => create_process_with_token(token, &mut command),
+        }
+        .map_err(|err| err.with_hint(expected.session_id))?;
+
+        Ok(Launched {
+            pid,
+            handle,
+            expected,
+            origin: Some(origin),
+            primary_thread: thread.map(|(tid, handle)| PrimaryThread { tid, handle }),
+        })
+    }
+}
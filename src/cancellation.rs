@@ -0,0 +1,165 @@
+use crate::child::Child;
+use crate::error::{Error, Stage};
+use crate::safe_windows_bindings::high_level::has_exited;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// Called once a token is cancelled
+type Listener = Box<dyn FnOnce() + Send>;
+
+/// What a token has been asked to do
+#[derive(Default)]
+struct State {
+    cancelled: bool,
+    /// Whether the processes launched with the token are terminated as well
+    kill: bool,
+    /// The processes launched with the token, each through a handle of its own
+    children: Vec<Child>,
+    /// Wakes the waiting states of scheduled and recurring launches, taken once cancelled
+    listeners: Vec<Listener>,
+}
+
+/// The state shared between the clones of a token
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    /// Wakes launches sleeping on the token
+    wake: Condvar,
+}
+
+/// Calls off the launches it was given to, and optionally terminates what they launched
+///
+/// Give the token to builders through [`ProcessBuilder::cancellation`](crate::ProcessBuilder::cancellation).
+/// Once cancelled, launches that have not started fail at [`Stage::Cancellation`]: those
+/// waiting in a [`LaunchQueue`](crate::LaunchQueue), for the time of a scheduled launch,
+/// for the next run of a recurring one, or for a user to log on.
+/// Clones share their state, so one token can be handed to every subsystem of a service
+/// and cancelled from its stop handler.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use win_run::{win, CancellationToken, Recurrence};
+///
+/// let shutdown = CancellationToken::new();
+///
+/// let sync = win("/path/to/sync")
+///     .cancellation(&shutdown)
+///     .run_every(Recurrence::every(Duration::from_secs(15 * 60)));
+///
+/// // On SERVICE_CONTROL_STOP
+/// shutdown.cancel_and_kill();
+/// ```
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    shared: Arc<Shared>,
+}
+
+impl CancellationToken {
+    /// Creates a token that is not cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls off the launches that have not started, leaving the launched processes running
+    pub fn cancel(&self) {
+        self.cancel_with(false);
+    }
+
+    /// Calls off the launches that have not started and terminates the processes launched with the token
+    ///
+    /// Processes launched with the token later on are terminated right after launching.
+    pub fn cancel_and_kill(&self) {
+        self.cancel_with(true);
+    }
+
+    /// Whether the token was cancelled
+    pub fn is_cancelled(&self) -> bool {
+        lock(&self.shared.state).cancelled
+    }
+
+    fn cancel_with(&self, kill: bool) {
+        let (listeners, children) = {
+            let mut state = lock(&self.shared.state);
+            state.cancelled = true;
+            state.kill |= kill;
+            let children = if state.kill {
+                std::mem::take(&mut state.children)
+            } else {
+                Vec::new()
+            };
+            (std::mem::take(&mut state.listeners), children)
+        };
+        self.shared.wake.notify_all();
+
+        // Listeners take locks of their own, so they are called without holding the token's
+        for listener in listeners {
+            listener();
+        }
+        for child in children {
+            // The process may have exited already, nothing sensible can be done if killing fails
+            let _ = child.kill();
+        }
+    }
+
+    /// Fails if the token was cancelled
+    pub(crate) fn check(&self) -> Result<(), Error> {
+        if self.is_cancelled() {
+            return Err(cancelled());
+        }
+        Ok(())
+    }
+
+    /// Calls the listener once the token is cancelled, right away if it already is
+    pub(crate) fn on_cancel(&self, listener: impl FnOnce() + Send + 'static) {
+        let mut state = lock(&self.shared.state);
+        if !state.cancelled {
+            state.listeners.push(Box::new(listener));
+            return;
+        }
+        drop(state);
+        listener();
+    }
+
+    /// Sleeps for the duration, waking early if the token is cancelled
+    ///
+    /// Returns whether the token was cancelled.
+    pub(crate) fn sleep(&self, duration: Duration) -> bool {
+        let state = lock(&self.shared.state);
+        let (state, _) = self
+            .shared
+            .wake
+            .wait_timeout_while(state, duration, |state| !state.cancelled)
+            .unwrap_or_else(|err| err.into_inner());
+        state.cancelled
+    }
+
+    /// Keeps a process launched with the token, to terminate it when the token is cancelled with kill
+    pub(crate) fn track(&self, pid: u32) {
+        // Processes the crate cannot open, e.g. fakes of a test backend, cannot be terminated anyway
+        let Ok(child) = Child::from_pid(pid) else {
+            return;
+        };
+
+        let mut state = lock(&self.shared.state);
+        if state.kill {
+            drop(state);
+            let _ = child.kill();
+            return;
+        }
+        // Forget the processes that exited, so long running services do not pile up handles
+        state
+            .children
+            .retain(|child| !has_exited(child.handle).unwrap_or(false));
+        state.children.push(child);
+    }
+}
+
+/// The error of a launch called off by its token
+pub(crate) fn cancelled() -> Error {
+    Error::other(Stage::Cancellation, "The launch was cancelled")
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // A thread that panicked while holding the lock leaves the data as usable as before
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
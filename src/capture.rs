@@ -0,0 +1,177 @@
+use crate::encoding::Encoding;
+use crate::error::Error;
+use crate::safe_windows_bindings::high_level::{
+    close_handle, create_inheritable_input_pipe, create_inheritable_pipe, read_pipe, write_pipe,
+};
+use crate::safe_windows_bindings::types::HANDLE;
+use crate::stdio::LineCallback;
+use std::io::{Read, Write};
+use std::thread::JoinHandle;
+
+/// How much of a stream is read at once
+const CHUNK_SIZE: usize = 4096;
+
+/// The reading end of a pipe an output stream of the process is captured through, closed on drop
+pub(crate) struct PipeReader {
+    handle: HANDLE,
+}
+
+impl PipeReader {
+    /// Creates a pipe to capture an output stream through
+    ///
+    /// Returns the reading end and the inheritable writing end to hand to the process
+    pub(crate) fn create() -> Result<(Self, HANDLE), Error> {
+        let (handle, write_handle) = create_inheritable_pipe()?;
+        Ok((Self { handle }, write_handle))
+    }
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        read_pipe(self.handle, buf).map_err(std::io::Error::other)
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        // Nothing sensible can be done if closing fails here
+        let _ = close_handle(self.handle);
+    }
+}
+
+/// The writing end of a pipe the standard input of the process reads from, closed on drop
+pub(crate) struct PipeWriter {
+    handle: HANDLE,
+}
+
+impl PipeWriter {
+    /// Creates a pipe to feed the standard input through
+    ///
+    /// Returns the writing end and the inheritable reading end to hand to the process
+    pub(crate) fn create() -> Result<(Self, HANDLE), Error> {
+        let (read_handle, handle) = create_inheritable_input_pipe()?;
+        Ok((Self { handle }, read_handle))
+    }
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        write_pipe(self.handle, buf).map_err(std::io::Error::other)
+    }
+
+    /// Pipes are not buffered by the service, what was written is already in the pipe
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        // Nothing sensible can be done if closing fails here
+        let _ = close_handle(self.handle);
+    }
+}
+
+/// Copies a captured stream into sinks on a thread of its own
+///
+/// The thread ends once the process and every process that inherited the stream closed it.
+/// Output a sink fails to take is dropped for that sink only, so the process never blocks on a full pipe.
+pub(crate) fn pump(
+    mut reader: PipeReader,
+    mut sinks: Vec<Box<dyn Write + Send>>,
+) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut buffer = [0u8; CHUNK_SIZE];
+        loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => read,
+            };
+            for sink in &mut sinks {
+                let _ = sink.write_all(&buffer[..read]);
+            }
+        }
+        for sink in &mut sinks {
+            let _ = sink.flush();
+        }
+    })
+}
+
+/// How many bytes a stream has to start with before its encoding is settled
+const ENCODING_PROBE: usize = 4;
+
+/// Splits a captured stream into lines for a callback
+pub(crate) struct LineSplitter {
+    callback: LineCallback,
+    /// The encoding of the stream, settled from its first bytes
+    encoding: Encoding,
+    /// Whether the encoding has been settled
+    resolved: bool,
+    /// The start of a line whose end has not arrived yet
+    pending: Vec<u8>,
+}
+
+impl LineSplitter {
+    pub(crate) fn new(callback: LineCallback, encoding: Encoding) -> Self {
+        Self {
+            callback,
+            encoding,
+            resolved: false,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Settles the encoding and skips its byte order mark
+    fn resolve(&mut self) {
+        let (encoding, bom) = self.encoding.resolve(&self.pending);
+        self.encoding = encoding;
+        self.pending.drain(..bom);
+        self.resolved = true;
+    }
+
+    /// Passes a line to the callback without its line ending
+    fn emit(&self, line: &[u8]) {
+        let line = self.encoding.decode(line);
+        self.callback.call(line.strip_suffix('\r').unwrap_or(&line));
+    }
+}
+
+impl Write for LineSplitter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        if !self.resolved {
+            if self.pending.len() < ENCODING_PROBE {
+                return Ok(buf.len());
+            }
+            self.resolve();
+        }
+
+        // Line endings are whole character units, e.g. 0A 00 in UTF-16LE
+        let unit_size = self.encoding.unit_size();
+        let mut start = 0;
+        let mut index = 0;
+        while index + unit_size <= self.pending.len() {
+            let unit = &self.pending[index..index + unit_size];
+            if unit[0] == b'\n' && unit[1..].iter().all(|byte| *byte == 0) {
+                self.emit(&self.pending[start..index]);
+                start = index + unit_size;
+            }
+            index += unit_size;
+        }
+        self.pending.drain(..start);
+
+        Ok(buf.len())
+    }
+
+    /// Passes on the last line even if it has no line ending, only called once the stream ended
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.resolved {
+            self.resolve();
+        }
+        if !self.pending.is_empty() {
+            let last = std::mem::take(&mut self.pending);
+            self.emit(&last);
+        }
+        Ok(())
+    }
+}
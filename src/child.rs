@@ -0,0 +1,610 @@
+use crate::error::{Error, Stage};
+use crate::identity::{AdminToken, ProcessIdentity, TokenOrigin};
+use crate::processes::{descendants_of, terminate_processes, ProcessInfo};
+use crate::profile::LoadedProfile;
+#[cfg(feature = "window")]
+use crate::safe_windows_bindings::high_level::get_all_windows;
+use crate::safe_windows_bindings::high_level::{
+    close_handle, copy_handle, get_exit_code, job_active_processes, kill_job, kill_process,
+    open_process_for_child, resume_process_thread, suspend_process_thread, wait_for_process,
+    wait_for_process_timeout,
+};
+use crate::safe_windows_bindings::types::HANDLE;
+use crate::stdio::{ChildStderr, ChildStdin, ChildStdout};
+#[cfg(feature = "window")]
+use crate::window::WindowInfo;
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::Duration;
+#[cfg(feature = "window")]
+use std::time::Instant;
+
+#[cfg(feature = "window")]
+/// How often to look for the windows of a child
+const WINDOW_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often to check whether the processes in the job of a child have exited
+const TREE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A process launched by a [`ProcessBuilder`](crate::ProcessBuilder)
+///
+/// Keeps a handle to the process open until dropped
+#[derive(Debug)]
+pub struct Child {
+    /// The id of the process
+    pub(crate) pid: u32,
+    /// Handle to the process
+    pub(crate) handle: HANDLE,
+    /// The identity of the process, if it was verified at launch
+    pub(crate) identity: Option<ProcessIdentity>,
+    /// Where the launch token came from, if the process was launched by the builder
+    pub(crate) token_origin: Option<TokenOrigin>,
+    /// The single-instance mutex, if it could not be handed over to the process
+    pub(crate) instance: Option<HANDLE>,
+    /// The primary thread, if the builder kept it
+    pub(crate) primary_thread: Option<PrimaryThread>,
+    /// The job the process and its descendants run in, if the builder tracks them
+    pub(crate) job: Option<HANDLE>,
+    /// The correlation id of the launch, if the builder correlates its launches
+    pub(crate) correlation_id: Option<String>,
+    /// The writing end of the standard input, if it was piped and not taken yet
+    pub(crate) stdin: Option<ChildStdin>,
+    /// The reading end of the standard output, if it was piped and not taken yet
+    pub(crate) stdout: Option<ChildStdout>,
+    /// The reading end of the standard error, if it was piped and not taken yet
+    pub(crate) stderr: Option<ChildStderr>,
+    /// The profile loaded for the process, unloaded once it exits
+    pub(crate) loaded_profile: Option<LoadedProfile>,
+    /// The timeout of the builder and whether the process was terminated once it elapsed
+    pub(crate) timeout: Option<(Duration, Arc<AtomicBool>)>,
+    /// The threads copying output into targets of the service, joined once the process exits
+    pub(crate) pumps: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Child {
+    /// Adopts a running process that was not launched through this crate
+    ///
+    /// Useful for managing processes again after the service restarted.
+    pub fn from_pid(pid: u32) -> Result<Self, Error> {
+        let handle = open_process_for_child(pid)?;
+        Ok(Self::from_handle(pid, handle))
+    }
+
+    /// Takes ownership of a handle to a process, with nothing else attached to it
+    fn from_handle(pid: u32, handle: HANDLE) -> Self {
+        Self {
+            pid,
+            handle,
+            identity: None,
+            token_origin: None,
+            instance: None,
+            primary_thread: None,
+            job: None,
+            correlation_id: None,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            loaded_profile: None,
+            timeout: None,
+            pumps: Mutex::default(),
+        }
+    }
+
+    /// The id of the process
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+
+    /// The identity the process was verified to run with, if verification was enabled
+    pub fn identity(&self) -> Option<&ProcessIdentity> {
+        self.identity.as_ref()
+    }
+
+    /// Where the token of a launch as [`Elevation::Admin`](crate::Elevation::Admin) came from
+    ///
+    /// `None` for other elevations, adopted processes and backends that could not tell.
+    pub fn admin_token(&self) -> Option<AdminToken> {
+        match &self.token_origin {
+            Some(TokenOrigin::SessionAdmin { admin_token, .. }) => *admin_token,
+            _ => None,
+        }
+    }
+
+    /// Where the token the process was launched with came from, `None` for adopted processes
+    pub fn token_origin(&self) -> Option<&TokenOrigin> {
+        self.token_origin.as_ref()
+    }
+
+    /// The correlation id of the launch, see [`ProcessBuilder::correlate`](crate::ProcessBuilder::correlate)
+    ///
+    /// `None` for adopted processes and builders that do not correlate their launches.
+    pub fn correlation_id(&self) -> Option<&str> {
+        self.correlation_id.as_deref()
+    }
+
+    /// Lets a process [started suspended](crate::ProcessBuilder::create_suspended) run
+    ///
+    /// Meant for setting the process up first, e.g. assigning it to a job of the caller or
+    /// writing its configuration to a [piped](crate::Stdio::Piped) input before it reads it.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use std::io::Write;
+    /// use win_run::{win, Stdio};
+    ///
+    /// let mut child = win("C:\\Tools\\agent.exe")
+    ///     .stdin(Stdio::Piped)
+    ///     .create_suspended(true)
+    ///     .spawn()?;
+    /// if let Some(mut stdin) = child.take_stdin() {
+    ///     stdin.write_all(b"{\"mode\": \"tray\"}\r\n").expect("Could not configure the agent");
+    /// }
+    /// child.resume()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Fails if the child has no primary thread, as for adopted processes, [`LaunchBackend`](crate::LaunchBackend)s
+    /// of the caller and threads [taken](Child::take_primary_thread) out of the child.
+    pub fn resume(&self) -> Result<(), Error> {
+        let Some(primary_thread) = &self.primary_thread else {
+            return Err(Error::other(
+                Stage::ProcessSetup,
+                "The child has no primary thread to resume",
+            ));
+        };
+        primary_thread.resume().map(|_| ())
+    }
+
+    /// The primary thread of the process, if the builder was set to [keep it](crate::ProcessBuilder::keep_primary_thread)
+    pub fn primary_thread(&self) -> Option<&PrimaryThread> {
+        self.primary_thread.as_ref()
+    }
+
+    /// Takes the primary thread out of the child, to keep its handle open past the child's
+    pub fn take_primary_thread(&mut self) -> Option<PrimaryThread> {
+        self.primary_thread.take()
+    }
+
+    /// Takes the writing end of the standard input, if it was [piped](crate::Stdio::Piped)
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use std::io::Write;
+    /// use win_run::{win, Stdio};
+    ///
+    /// let mut child = win("C:\\Tools\\installer.exe")
+    ///     .stdin(Stdio::Piped)
+    ///     .spawn()?;
+    /// if let Some(mut stdin) = child.take_stdin() {
+    ///     stdin.write_all(b"y\r\n").expect("Could not answer the installer");
+    ///     // Dropping the pipe ends the input of the installer
+    /// }
+    /// child.wait()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn take_stdin(&mut self) -> Option<ChildStdin> {
+        self.stdin.take()
+    }
+
+    /// Takes the reading end of the standard output, if it was [piped](crate::Stdio::Piped)
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use std::io::Read;
+    /// use win_run::{win, Stdio};
+    ///
+    /// let mut child = win("C:\\Windows\\System32\\ipconfig.exe")
+    ///     .stdout(Stdio::Piped)
+    ///     .spawn()?;
+    /// let mut output = Vec::new();
+    /// if let Some(mut stdout) = child.take_stdout() {
+    ///     stdout.read_to_end(&mut output).expect("Could not read the output");
+    /// }
+    /// child.wait()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn take_stdout(&mut self) -> Option<ChildStdout> {
+        self.stdout.take()
+    }
+
+    /// Takes the reading end of the standard error, if it was [piped](crate::Stdio::Piped)
+    pub fn take_stderr(&mut self) -> Option<ChildStderr> {
+        self.stderr.take()
+    }
+
+    /// Lists the processes the process started, and those they started in turn, that are still running
+    ///
+    /// Each process is listed after the one that started it. Processes whose parent already exited
+    /// cannot be traced back to the process and are not listed.
+    pub fn descendants(&self) -> Vec<ProcessInfo> {
+        descendants_of(self.pid)
+    }
+
+    /// Terminates the process right away, making it exit with code 1
+    pub fn kill(&self) -> Result<(), Error> {
+        if self.handle.is_invalid() {
+            return Err(Error::other(
+                Stage::Termination,
+                "The backend provided no handle to the process",
+            ));
+        }
+        kill_process(self.handle, 1)
+    }
+
+    /// Terminates the process and everything it started right away, making them exit with code 1
+    ///
+    /// Children [tracking their descendants](crate::ProcessBuilder::track_descendants) terminate
+    /// everything in their job, including processes whose parent already exited. Others terminate
+    /// the [descendants](Child::descendants) they find, after the process itself so it cannot
+    /// start any more, and miss those that cannot be traced back to the process.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use win_run::win;
+    ///
+    /// let child = win("C:\\Windows\\System32\\cmd.exe")
+    ///     .args(["/c", "C:\\Tools\\build.cmd"])
+    ///     .track_descendants(true)
+    ///     .spawn()?;
+    /// // The build hangs, stop the compilers it started along with it
+    /// child.kill_tree()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn kill_tree(&self) -> Result<(), Error> {
+        if let Some(job) = self.job {
+            return kill_job(job, 1);
+        }
+
+        // Listed first, as the process has to be running for its children to be traced back to it
+        let descendants: Vec<u32> = self
+            .descendants()
+            .iter()
+            .map(|process| process.pid)
+            .collect();
+        let mut errors = Vec::new();
+        let exited = matches!(self.try_wait(), Ok(Some(_)));
+        if !exited {
+            if let Err(err) = self.kill() {
+                errors.push(err);
+            }
+        }
+        if let Err(err) = terminate_processes(&descendants) {
+            errors.push(err);
+        }
+
+        if !errors.is_empty() {
+            return Err(Error::combine(errors));
+        }
+        Ok(())
+    }
+
+    /// Blocks until the process exits and returns its exit code
+    ///
+    /// Output redirected to a [`Stdio::Writer`](crate::Stdio::Writer), [`Stdio::Log`](crate::Stdio::Log),
+    /// [`Stdio::Lines`](crate::Stdio::Lines) or [`Stdio::Tee`](crate::Stdio::Tee) has reached it by
+    /// the time this returns, as it has for the other waits once the process exited.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use win_run::win;
+    ///
+    /// let child = win("C:\\Windows\\System32\\cmd.exe").args(["/c", "exit", "3"]).spawn()?;
+    /// assert_eq!(child.wait()?, 3);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Fails with [`Error::Timeout`] if the process was terminated for running longer than the
+    /// [timeout](crate::ProcessBuilder::timeout) of its builder, as do [`Child::try_wait`],
+    /// [`Child::wait_timeout`] and [`Child::wait_with_output`].
+    pub fn wait(&self) -> Result<u32, Error> {
+        self.check_waitable()?;
+        wait_for_process(self.handle)?;
+        self.join_pumps();
+        self.exit_code()
+    }
+
+    /// Blocks until the process exits or the timeout elapses, returning the exit code if it exited
+    ///
+    /// The process keeps running once the timeout elapses, see [`ProcessBuilder::timeout`](crate::ProcessBuilder::timeout)
+    /// for terminating it.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use std::time::Duration;
+    /// use win_run::win;
+    ///
+    /// let child = win("C:\\Tools\\report.exe").spawn()?;
+    /// if child.wait_timeout(Duration::from_secs(30))?.is_none() {
+    ///     child.kill()?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<Option<u32>, Error> {
+        self.check_waitable()?;
+        // INFINITE would wait forever
+        let milliseconds = timeout.as_millis().min(u32::MAX as u128 - 1) as u32;
+        if !wait_for_process_timeout(self.handle, milliseconds)? {
+            return Ok(None);
+        }
+        self.join_pumps();
+        self.exit_code().map(Some)
+    }
+
+    /// Returns the exit code of the process if it has exited, without blocking
+    pub fn try_wait(&self) -> Result<Option<u32>, Error> {
+        self.wait_timeout(Duration::ZERO)
+    }
+
+    /// Closes the standard input, reads the output streams to their end and waits for the process
+    ///
+    /// Only [piped](crate::Stdio::Piped) streams that were not taken are read, the others come back empty.
+    pub fn wait_with_output(mut self) -> Result<Output, Error> {
+        // The process may wait for its input to end before it exits
+        drop(self.stdin.take());
+
+        // Both pipes are read at once, a process blocked on a full one would never finish the other
+        let stderr = self.stderr.take().map(|mut stderr| {
+            spawn(move || {
+                let mut output = Vec::new();
+                stderr.read_to_end(&mut output).map(|_| output)
+            })
+        });
+        let mut stdout = Vec::new();
+        if let Some(pipe) = self.stdout.as_mut() {
+            pipe.read_to_end(&mut stdout).map_err(read_error)?;
+        }
+        let stderr = match stderr {
+            Some(reader) => reader
+                .join()
+                .unwrap_or_else(|_| Err(std::io::Error::other("The reading thread panicked")))
+                .map_err(read_error)?,
+            None => Vec::new(),
+        };
+
+        Ok(Output {
+            status: self.wait()?,
+            stdout,
+            stderr,
+        })
+    }
+
+    /// The exit code of a process that has exited, unless it was terminated for running too long
+    pub(crate) fn exit_code(&self) -> Result<u32, Error> {
+        if let Some((timeout, timed_out)) = &self.timeout {
+            if timed_out.load(Ordering::SeqCst) {
+                return Err(Error::timeout(self.pid, *timeout));
+            }
+        }
+        get_exit_code(self.handle)
+    }
+
+    /// Takes the threads copying output into targets of the service
+    pub(crate) fn take_pumps(&self) -> Vec<JoinHandle<()>> {
+        let mut pumps = self.pumps.lock().unwrap_or_else(|err| err.into_inner());
+        std::mem::take(&mut *pumps)
+    }
+
+    /// Waits for the output of an exited process to reach the targets of the service
+    ///
+    /// The copying threads end once the last handle to the writing end of the pipe is closed,
+    /// which includes those of processes the process started.
+    fn join_pumps(&self) {
+        for pump in self.take_pumps() {
+            // A panicking target lost the rest of the output already, there is nothing to wait for
+            let _ = pump.join();
+        }
+    }
+
+    /// Fails if the backend provided no handle to wait on
+    pub(crate) fn check_waitable(&self) -> Result<(), Error> {
+        if self.handle.is_invalid() {
+            return Err(Error::other(
+                Stage::Waiting,
+                "The backend provided no handle to the process",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Blocks until the process exits
+    ///
+    /// Returns right away if the backend provided no handle to wait on.
+    pub(crate) fn wait_for_exit(&self) -> Result<(), Error> {
+        if self.handle.is_invalid() {
+            return Ok(());
+        }
+        wait_for_process(self.handle)
+    }
+
+    /// Terminates the process once it has run for the timeout, from a thread of its own
+    ///
+    /// Returns whether the process was terminated, which is set before it is.
+    pub(crate) fn terminate_after(&self, timeout: Duration) -> Arc<AtomicBool> {
+        let timed_out = Arc::new(AtomicBool::new(false));
+        // The handle is copied rather than the process reopened by its id, which Windows may have
+        // given to another process by then. A process without a handle, e.g. a fake of a test
+        // backend, is left alone
+        if self.handle.is_invalid() {
+            return timed_out;
+        }
+        let Ok(handle) = copy_handle(self.handle) else {
+            return timed_out;
+        };
+        let child = Child::from_handle(self.pid, handle);
+        let terminated = timed_out.clone();
+        spawn(move || {
+            if !matches!(child.wait_timeout(timeout), Ok(None)) {
+                return;
+            }
+            // Set first, so those woken by the exit see it
+            terminated.store(true, Ordering::SeqCst);
+            if child.kill().is_err() {
+                // The process exited on its own meanwhile
+                terminated.store(false, Ordering::SeqCst);
+            }
+        });
+        timed_out
+    }
+
+    /// Blocks until the process and every process it started have exited
+    ///
+    /// Only the processes of launches with [`ProcessBuilder::track_descendants`](crate::ProcessBuilder::track_descendants)
+    /// can be waited for this way, others fail at [`Stage::Waiting`].
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use win_run::win;
+    ///
+    /// let setup = win("C:\\Windows\\System32\\cmd.exe")
+    ///     .args(["/c", "install.bat"])
+    ///     .track_descendants(true)
+    ///     .spawn()?;
+    /// // Returns once the installers started by the script are done as well
+    /// setup.wait_tree()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn wait_tree(&self) -> Result<(), Error> {
+        let Some(job) = self.job else {
+            return Err(Error::other(
+                Stage::Waiting,
+                "The descendants of the process were not tracked",
+            ));
+        };
+        while job_active_processes(job)? > 0 {
+            sleep(TREE_POLL_INTERVAL);
+        }
+        Ok(())
+    }
+
+    /// Waits until the process shows a visible top-level window and returns it
+    ///
+    /// Returns `None` if no window appeared within the timeout.
+    ///
+    /// Only windows on the desktop of the calling thread can be enumerated,
+    /// so a service must call this from a thread attached to the child's desktop.
+    #[cfg(feature = "window")]
+    pub fn main_window(&self, timeout: Duration) -> Result<Option<WindowInfo>, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let window = get_all_windows()?
+                .into_iter()
+                .find(|window| window.pid == self.pid && window.visible);
+            if window.is_some() {
+                return Ok(window);
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            sleep(WINDOW_POLL_INTERVAL);
+        }
+    }
+}
+
+/// Describes a failure to read an output stream of the process
+fn read_error(err: std::io::Error) -> Error {
+    Error::other(
+        Stage::Redirection,
+        format!("Unable to read the output of the process: {err}"),
+    )
+}
+
+/// The exit code and the output of a finished process, see [`ProcessBuilder::output`](crate::ProcessBuilder::output)
+///
+/// The output is left in the encoding the process wrote it in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    /// The exit code of the process
+    pub status: u32,
+    /// Everything the process wrote to its standard output
+    pub stdout: Vec<u8>,
+    /// Everything the process wrote to its standard error
+    pub stderr: Vec<u8>,
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        if let Some(instance) = self.instance.take() {
+            let _ = close_handle(instance);
+        }
+        // The processes in the job keep running once it is closed
+        if let Some(job) = self.job.take() {
+            let _ = close_handle(job);
+        }
+        // The process may still be using the profile
+        if let Some(loaded_profile) = self.loaded_profile.take() {
+            loaded_profile.unload_after_exit(self.pid);
+        }
+        // Backends may not provide a handle
+        if self.handle.is_invalid() {
+            return;
+        }
+        // Nothing sensible can be done if closing fails here
+        let _ = close_handle(self.handle);
+    }
+}
+
+/// The primary thread of a launched process, kept with [`ProcessBuilder::keep_primary_thread`](crate::ProcessBuilder::keep_primary_thread)
+///
+/// Keeps a handle to the thread open until dropped, with all access rights, e.g. for queuing
+/// APCs to it or attaching a debugger before letting it run.
+///
+/// ```no_run
+/// # fn main() -> Result<(), win_run::Error> {
+/// use win_run::win;
+///
+/// let child = win("/path/to/agent").keep_primary_thread(true).spawn()?;
+/// if let Some(thread) = child.primary_thread() {
+///     thread.suspend()?;
+///     // Inspect the process while it cannot run
+///     thread.resume()?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct PrimaryThread {
+    /// The id of the thread
+    pub(crate) tid: u32,
+    /// Handle to the thread
+    pub(crate) handle: HANDLE,
+}
+
+impl PrimaryThread {
+    /// The id of the thread
+    pub fn id(&self) -> u32 {
+        self.tid
+    }
+
+    /// The handle to the thread, valid as long as this is not dropped
+    pub fn handle(&self) -> HANDLE {
+        self.handle
+    }
+
+    /// Suspends the thread, returning how many times it was suspended before
+    pub fn suspend(&self) -> Result<u32, Error> {
+        suspend_process_thread(self.handle)
+    }
+
+    /// Resumes the thread, returning how many times it was suspended before
+    ///
+    /// The thread only runs again once it was resumed as many times as it was suspended.
+    pub fn resume(&self) -> Result<u32, Error> {
+        resume_process_thread(self.handle)
+    }
+}
+
+impl Drop for PrimaryThread {
+    fn drop(&mut self) {
+        // Nothing sensible can be done if closing fails here
+        let _ = close_handle(self.handle);
+    }
+}
@@ -0,0 +1,36 @@
+use crate::environment::service_environment;
+use crate::error::{Error, Stage};
+use crate::safe_windows_bindings::types::GUID;
+use std::ffi::OsString;
+
+/// The environment variable holding the correlation id of a launch in the launched process
+///
+/// Processes launched by the service can read it to tag their own logs, and pass it on to
+/// the processes they start, so a workflow can be followed across the privilege boundary.
+pub const CORRELATION_ID_VARIABLE: &str = "WIN_RUN_CORRELATION_ID";
+
+/// Generates a fresh correlation id, a GUID in its lowercase hyphenated form
+pub(crate) fn new_correlation_id() -> Result<String, Error> {
+    let guid = GUID::new().map_err(|err| {
+        Error::windows(
+            Stage::EnvironmentBuild,
+            "Unable to generate a correlation id",
+            err,
+        )
+    })?;
+    Ok(format!("{guid:?}").to_lowercase())
+}
+
+/// Adds the correlation id to the environment of a launch, the service's if it has none
+pub(crate) fn with_correlation_id(
+    environment: Option<Vec<(OsString, OsString)>>,
+    correlation_id: &str,
+) -> Vec<(OsString, OsString)> {
+    let mut environment: Vec<(OsString, OsString)> = environment
+        .unwrap_or_else(service_environment)
+        .into_iter()
+        .filter(|(name, _)| !name.eq_ignore_ascii_case(CORRELATION_ID_VARIABLE))
+        .collect();
+    environment.push((CORRELATION_ID_VARIABLE.into(), correlation_id.into()));
+    environment
+}
@@ -0,0 +1,139 @@
+use crate::safe_windows_bindings::types::{
+    CREATE_PROCESS_LOGON_FLAGS, LOGON32_LOGON, LOGON32_LOGON_BATCH, LOGON32_LOGON_INTERACTIVE,
+    LOGON32_LOGON_NETWORK, LOGON32_LOGON_NETWORK_CLEARTEXT, LOGON32_LOGON_NEW_CREDENTIALS,
+    LOGON32_LOGON_SERVICE, LOGON_NETCREDENTIALS_ONLY, LOGON_WITH_PROFILE,
+};
+use std::fmt::{Debug, Formatter};
+
+/// How an account given by credentials is logged on
+///
+/// The logon type affects whether the user profile and group policy apply
+/// and whether the resulting token can access network resources.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum LogonType {
+    /// Like a user typing in their credentials at the console
+    #[default]
+    Interactive,
+    /// For accounts running scheduled work without user interaction
+    Batch,
+    /// For accessing a server, the token cannot reach further network resources
+    Network,
+    /// For accounts running services
+    Service,
+    /// Like network, but keeps the credentials so the token can reach further network resources
+    NetworkCleartext,
+    /// Copies the token of the service, using the credentials only for network access like `runas /netonly`
+    ///
+    /// The process runs as the service locally, e.g. as Local System, and reaches shares as the account.
+    /// The credentials are not checked until the first network access.
+    NewCredentials,
+}
+
+impl LogonType {
+    /// Maps the logon type to its native value
+    pub(crate) fn to_native(self) -> LOGON32_LOGON {
+        match self {
+            LogonType::Interactive => LOGON32_LOGON_INTERACTIVE,
+            LogonType::Batch => LOGON32_LOGON_BATCH,
+            LogonType::Network => LOGON32_LOGON_NETWORK,
+            LogonType::Service => LOGON32_LOGON_SERVICE,
+            LogonType::NetworkCleartext => LOGON32_LOGON_NETWORK_CLEARTEXT,
+            LogonType::NewCredentials => LOGON32_LOGON_NEW_CREDENTIALS,
+        }
+    }
+}
+
+/// How the secondary logon service uses the credentials of an account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogonFlags {
+    /// Logs the account on and loads its profile before starting the process
+    WithProfile,
+    /// Logs the account on without loading its profile, e.g. for throwaway runs that should not touch it
+    ///
+    /// The registry of the account is not available to the process, programs relying on it may fail.
+    WithoutProfile,
+    /// Runs the process as the caller, using the credentials only for network access like `runas /netonly`
+    NetCredentialsOnly,
+}
+
+impl LogonFlags {
+    /// Maps the logon flags to their native value
+    pub(crate) fn to_native(self) -> CREATE_PROCESS_LOGON_FLAGS {
+        match self {
+            LogonFlags::WithProfile => LOGON_WITH_PROFILE,
+            LogonFlags::WithoutProfile => CREATE_PROCESS_LOGON_FLAGS(0),
+            LogonFlags::NetCredentialsOnly => LOGON_NETCREDENTIALS_ONLY,
+        }
+    }
+}
+
+/// The credentials of an account to run an executable as
+#[derive(Clone)]
+pub struct Credentials {
+    /// Domain of the account, `.` for local accounts and empty for user principal names
+    pub(crate) domain: String,
+    /// Name of the account
+    pub(crate) username: String,
+    /// Password of the account
+    pub(crate) password: String,
+    /// How to log the account on
+    pub(crate) logon_type: LogonType,
+    /// How the secondary logon service uses the credentials, if it launches the process
+    pub(crate) logon_flags: Option<LogonFlags>,
+}
+
+impl Credentials {
+    /// Creates credentials logged on as **LogonType::Interactive**
+    ///
+    /// # Arguments
+    ///
+    /// * `domain` - Domain of the account, `.` for local accounts and empty for user principal names.
+    /// * `username` - Name of the account.
+    /// * `password` - Password of the account.
+    pub fn new(
+        domain: impl AsRef<str>,
+        username: impl AsRef<str>,
+        password: impl AsRef<str>,
+    ) -> Self {
+        let domain = domain.as_ref().to_string();
+        let username = username.as_ref().to_string();
+        let password = password.as_ref().to_string();
+        let logon_type = LogonType::default();
+        let logon_flags = None;
+
+        Self {
+            domain,
+            username,
+            password,
+            logon_type,
+            logon_flags,
+        }
+    }
+
+    /// Sets how the account is logged on, default is **LogonType::Interactive**
+    pub fn logon_type(mut self, logon_type: LogonType) -> Self {
+        self.logon_type = logon_type;
+        self
+    }
+
+    /// Launches through the secondary logon service with CreateProcessWithLogonW instead of LogonUserW, default is **None**
+    ///
+    /// The logon type does not apply to such launches.
+    /// The secondary logon service refuses requests from processes running as Local System.
+    pub fn logon_flags(mut self, logon_flags: LogonFlags) -> Self {
+        self.logon_flags = Some(logon_flags);
+        self
+    }
+}
+
+impl Debug for Credentials {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("domain", &self.domain)
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .field("logon_type", &self.logon_type)
+            .field("logon_flags", &self.logon_flags)
+            .finish()
+    }
+}
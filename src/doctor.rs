@@ -0,0 +1,129 @@
+use crate::error::Error;
+use crate::identity::{process_identity, ProcessIdentity};
+use crate::privileges::current_privileges;
+use crate::processes::{select_process_pid, MatchStrategy};
+use crate::safe_windows_bindings::high_level::{
+    close_handle, get_active_session_id, get_machine_dword, get_process_token, is_wow64,
+};
+use crate::sessions::{list_sessions, SessionInfo};
+use std::fmt::{Debug, Display, Formatter};
+
+/// The registry key holding the UAC policy
+const UAC_POLICY_KEY: &str = r"SOFTWARE\Microsoft\Windows\CurrentVersion\Policies\System";
+
+/// The UAC policy of the machine, with values that are not set left empty
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UacPolicy {
+    /// Whether UAC is enabled at all (`EnableLUA`)
+    pub enabled: Option<bool>,
+    /// How administrators are prompted for elevation (`ConsentPromptBehaviorAdmin`)
+    pub consent_prompt_behavior_admin: Option<u32>,
+    /// Whether the built-in Administrator runs in admin approval mode (`FilterAdministratorToken`)
+    pub filter_administrator_token: Option<bool>,
+}
+
+/// A snapshot of everything launches depend on, see [`doctor`]
+///
+/// Every part is gathered independently, so a failure in one does not hide the others.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostics {
+    /// The account, session and integrity of the current process
+    pub service: Result<ProcessIdentity, Error>,
+    /// Whether the current process is 32-bit on 64-bit Windows, seeing SysWOW64 as System32
+    pub wow64: Result<bool, Error>,
+    /// The privileges of the current process, with whether each is enabled
+    pub privileges: Result<Vec<(String, bool)>, Error>,
+    /// The session attached to the physical console
+    pub console_session: Result<u32, Error>,
+    /// All sessions with the accounts logged on to them
+    pub sessions: Result<Vec<SessionInfo>, Error>,
+    /// The UAC policy of the machine
+    pub uac: Result<UacPolicy, Error>,
+    /// Whether the token of winlogon in the console session can be taken
+    pub winlogon: Result<(), Error>,
+    /// Whether the token of lsass can be taken
+    pub lsass: Result<(), Error>,
+}
+
+/// Gathers a diagnostic snapshot of the current process and the machine
+///
+/// Print it with `{}` and attach it to bug reports when launches fail.
+pub fn doctor() -> Diagnostics {
+    Diagnostics {
+        service: process_identity(std::process::id()),
+        wow64: is_wow64(),
+        privileges: current_privileges(),
+        console_session: get_active_session_id(),
+        sessions: list_sessions(),
+        uac: uac_policy(),
+        winlogon: check_token_access("winlogon", MatchStrategy::InTargetSession),
+        lsass: check_token_access("lsass", MatchStrategy::First),
+    }
+}
+
+/// Reads the UAC policy from the registry
+fn uac_policy() -> Result<UacPolicy, Error> {
+    let enabled = get_machine_dword(UAC_POLICY_KEY, "EnableLUA")?;
+    let consent_prompt_behavior_admin =
+        get_machine_dword(UAC_POLICY_KEY, "ConsentPromptBehaviorAdmin")?;
+    let filter_administrator_token = get_machine_dword(UAC_POLICY_KEY, "FilterAdministratorToken")?;
+
+    Ok(UacPolicy {
+        enabled: enabled.map(|value| value != 0),
+        consent_prompt_behavior_admin,
+        filter_administrator_token: filter_administrator_token.map(|value| value != 0),
+    })
+}
+
+/// Checks that the token of a process can be taken
+fn check_token_access(process_name: &str, strategy: MatchStrategy) -> Result<(), Error> {
+    let pid = select_process_pid(process_name, strategy, None)?;
+    let token = get_process_token(pid)?;
+    close_handle(token)
+}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        fn line<T: Debug>(
+            f: &mut Formatter<'_>,
+            name: &str,
+            value: &Result<T, Error>,
+        ) -> std::fmt::Result {
+            match value {
+                Ok(value) => writeln!(f, "{name}: {value:?}"),
+                Err(err) => writeln!(f, "{name}: error: {err}"),
+            }
+        }
+
+        line(f, "service", &self.service)?;
+        line(f, "wow64", &self.wow64)?;
+        match &self.privileges {
+            Ok(privileges) => {
+                writeln!(f, "privileges:")?;
+                for (name, enabled) in privileges {
+                    let state = if *enabled { "enabled" } else { "disabled" };
+                    writeln!(f, "    {name} ({state})")?;
+                }
+            }
+            Err(err) => writeln!(f, "privileges: error: {err}")?,
+        }
+        line(f, "console session", &self.console_session)?;
+        match &self.sessions {
+            Ok(sessions) => {
+                writeln!(f, "sessions:")?;
+                for session in sessions {
+                    writeln!(f, "    {session:?}")?;
+                }
+            }
+            Err(err) => writeln!(f, "sessions: error: {err}")?,
+        }
+        line(f, "uac", &self.uac)?;
+        for (name, reachable) in [("winlogon", &self.winlogon), ("lsass", &self.lsass)] {
+            match reachable {
+                Ok(()) => writeln!(f, "{name}: reachable")?,
+                Err(err) => writeln!(f, "{name}: unreachable: {err}")?,
+            }
+        }
+        Ok(())
+    }
+}
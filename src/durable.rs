@@ -0,0 +1,526 @@
+use crate::error::{Error, Stage};
+use crate::record::{LaunchRecord, RecordKind};
+use crate::{Elevation, ProcessBuilder};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{spawn, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How queued launches are reported in errors
+const QUEUED: RecordKind = RecordKind {
+    stage: Stage::Persistence,
+    name: "queued launch",
+    no_credentials:
+        "Launching with credentials cannot be queued, as they would be stored in plain text",
+};
+
+/// A launch kept in the file of a [`DurableQueue`] until it is due
+///
+/// Only what can be written to a file is kept, so launching with credentials cannot be queued
+/// and no value can contain a line break.
+#[derive(Debug, Clone, Default)]
+pub struct DurableLaunch {
+    /// Path to the executable
+    pub path: String,
+    /// Arguments for the executable
+    pub args: String,
+    /// Working directory of the process, the service's if missing
+    pub directory: Option<String>,
+    /// Elevation of the process, one of `User`, `Admin` and `LocalSystem`
+    pub elevation: Elevation,
+    /// Session to launch into, the active console session if missing
+    pub session: Option<u32>,
+    /// The earliest time to launch at, right away if missing
+    pub not_before: Option<SystemTime>,
+}
+
+impl DurableLaunch {
+    /// Creates a launch of the executable as User in the active console session, due right away
+    pub fn new(path: impl AsRef<str>) -> Self {
+        Self {
+            path: path.as_ref().to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Whether the time to launch has come
+    fn is_due(&self, now: SystemTime) -> bool {
+        self.not_before.is_none_or(|not_before| not_before <= now)
+    }
+
+    /// The launch without its time
+    fn record(&self) -> LaunchRecord {
+        LaunchRecord {
+            path: self.path.clone(),
+            args: self.args.clone(),
+            directory: self.directory.clone(),
+            elevation: self.elevation.clone(),
+            session: self.session,
+        }
+    }
+
+    /// Creates the builder the launch is run with
+    fn builder(&self) -> ProcessBuilder {
+        self.record().builder()
+    }
+
+    /// Serializes the launch into its `key=value` lines
+    fn encode(&self, id: u64) -> Result<String, Error> {
+        let mut extra = vec![("id", id.to_string())];
+        if let Some(not_before) = self.not_before {
+            let seconds = not_before
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            extra.push(("not_before", seconds.to_string()));
+        }
+        self.record().encode(&QUEUED, extra)
+    }
+
+    /// Parses a launch and its id from its `key=value` lines
+    fn decode(text: &str) -> Result<(u64, Self), Error> {
+        let invalid = |message: String| Error::other(Stage::Persistence, message);
+
+        let mut id = None;
+        let mut not_before = None;
+        let record = LaunchRecord::decode(text, &QUEUED, |key, value| match key {
+            "id" => {
+                let parsed = value
+                    .parse()
+                    .map_err(|_| invalid(format!("Invalid launch id: {value}")))?;
+                id = Some(parsed);
+                Ok(true)
+            }
+            "not_before" => {
+                let seconds = value
+                    .parse()
+                    .map_err(|_| invalid(format!("Invalid launch time: {value}")))?;
+                not_before = Some(UNIX_EPOCH + Duration::from_secs(seconds));
+                Ok(true)
+            }
+            _ => Ok(false),
+        })?;
+
+        let id = id.ok_or_else(|| invalid("A queued launch has no id".to_string()))?;
+        let launch = Self {
+            path: record.path,
+            args: record.args,
+            directory: record.directory,
+            elevation: record.elevation,
+            session: record.session,
+            not_before,
+        };
+        Ok((id, launch))
+    }
+}
+
+/// What the thread of a queue has been asked to do
+#[derive(Default)]
+struct Signal {
+    /// Check the launches right away instead of at the next interval
+    refresh: bool,
+    /// Stop checking the launches
+    stop: bool,
+}
+
+/// The launches of a queue, as they are stored in its file
+struct Launches {
+    /// The id the next pushed launch gets
+    next_id: u64,
+    /// The pending launches by id, in the order they were pushed
+    pending: Vec<(u64, DurableLaunch)>,
+    /// The ids of pending launches the thread is launching, which can no longer be cancelled
+    in_flight: Vec<u64>,
+    /// Whether the pending launches changed since the file was last written
+    unsaved: bool,
+}
+
+/// The state a queue shares with its thread
+struct Shared {
+    file: PathBuf,
+    launches: Mutex<Launches>,
+    /// Why launches that will not be tried again failed, by id
+    failures: Mutex<Vec<(u64, Error)>>,
+    signal: Mutex<Signal>,
+    /// Wakes the thread when the signal changes
+    wake: Condvar,
+}
+
+/// Keeps launches in a file until they are due, so they survive restarts of the service
+///
+/// A thread checks the pending launches at every interval and launches those whose time
+/// has come. Launches that fail because no user is logged on yet, or for other reasons
+/// that go away on their own, stay pending and are tried again at the next check.
+/// Launches that fail otherwise are dropped and reported by [`DurableQueue::failures`].
+///
+/// A launch is removed from the file once it was launched, so a service stopped in between
+/// the two launches it again after restarting.
+///
+/// Dropping the queue stops the thread and leaves the pending launches in the file.
+///
+/// ```no_run
+/// # fn main() -> Result<(), win_run::Error> {
+/// use std::time::{Duration, SystemTime};
+/// use win_run::{DurableLaunch, DurableQueue};
+///
+/// let queue = DurableQueue::open("C:\\ProgramData\\Vendor\\launches", Duration::from_secs(30))?;
+///
+/// let mut report = DurableLaunch::new("/path/to/report");
+/// report.not_before = Some(SystemTime::now() + Duration::from_secs(60 * 60));
+/// queue.push(report)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct DurableQueue {
+    shared: Arc<Shared>,
+    /// The thread checking the launches, taken when the queue is dropped
+    worker: Option<JoinHandle<()>>,
+}
+
+impl DurableQueue {
+    /// Opens the queue kept in a file, restoring its pending launches, and checks them at every interval
+    ///
+    /// The file is created once the first launch is pushed.
+    pub fn open(file: impl AsRef<Path>, interval: Duration) -> Result<Self, Error> {
+        let file = file.as_ref().to_path_buf();
+        let pending = load(&file)?;
+        let next_id = pending.iter().map(|(id, _)| id + 1).max().unwrap_or(1);
+
+        let shared = Arc::new(Shared {
+            file,
+            launches: Mutex::new(Launches {
+                next_id,
+                pending,
+                in_flight: Vec::new(),
+                unsaved: false,
+            }),
+            failures: Mutex::new(Vec::new()),
+            signal: Mutex::new(Signal::default()),
+            wake: Condvar::new(),
+        });
+
+        let worker = {
+            let shared = Arc::clone(&shared);
+            spawn(move || shared.work(interval))
+        };
+
+        Ok(Self {
+            shared,
+            worker: Some(worker),
+        })
+    }
+
+    /// Adds a launch to the queue and its file, returning its id
+    pub fn push(&self, launch: DurableLaunch) -> Result<u64, Error> {
+        // Fail before the launch is queued if it cannot be stored
+        launch.encode(0)?;
+
+        let id = {
+            let mut launches = lock(&self.shared.launches);
+            let id = launches.next_id;
+            launches.next_id += 1;
+            launches.pending.push((id, launch));
+            // A launch that is not in the file would not survive a restart, so it is not queued
+            if let Err(err) = self.shared.save(&mut launches) {
+                launches.pending.pop();
+                return Err(err);
+            }
+            id
+        };
+
+        self.refresh();
+        Ok(id)
+    }
+
+    /// Removes a pending launch from the queue and its file
+    ///
+    /// Returns whether the launch was still pending, which it no longer is while being launched.
+    pub fn cancel(&self, id: u64) -> Result<bool, Error> {
+        let mut launches = lock(&self.shared.launches);
+        if launches.in_flight.contains(&id) {
+            return Ok(false);
+        }
+        let count = launches.pending.len();
+        launches.pending.retain(|(pending, _)| *pending != id);
+        if launches.pending.len() == count {
+            return Ok(false);
+        }
+        self.shared.save(&mut launches)?;
+        Ok(true)
+    }
+
+    /// The launches that are still pending, with their ids, in the order they were pushed
+    pub fn pending(&self) -> Vec<(u64, DurableLaunch)> {
+        lock(&self.shared.launches).pending.clone()
+    }
+
+    /// The launches that failed and will not be tried again, with why, by id
+    pub fn failures(&self) -> Vec<(u64, Error)> {
+        lock(&self.shared.failures).clone()
+    }
+
+    /// Checks the launches right away instead of at the next interval
+    ///
+    /// E.g. when the service is told about a logon through SERVICE_CONTROL_SESSIONCHANGE.
+    pub fn refresh(&self) {
+        lock(&self.shared.signal).refresh = true;
+        self.shared.wake.notify_one();
+    }
+}
+
+impl Drop for DurableQueue {
+    fn drop(&mut self) {
+        lock(&self.shared.signal).stop = true;
+        self.shared.wake.notify_one();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Shared {
+    /// Checks the launches until asked to stop
+    fn work(&self, interval: Duration) {
+        loop {
+            self.launch_due();
+
+            let signal = lock(&self.signal);
+            let (mut signal, _) = self
+                .wake
+                .wait_timeout_while(signal, interval, |signal| !signal.refresh && !signal.stop)
+                .unwrap_or_else(|err| err.into_inner());
+            if signal.stop {
+                return;
+            }
+            signal.refresh = false;
+        }
+    }
+
+    /// Launches the pending launches whose time has come
+    fn launch_due(&self) {
+        let now = SystemTime::now();
+        let due: Vec<(u64, DurableLaunch)> = {
+            let mut launches = lock(&self.launches);
+            let due: Vec<(u64, DurableLaunch)> = launches
+                .pending
+                .iter()
+                .filter(|(_, launch)| launch.is_due(now))
+                .cloned()
+                .collect();
+            // Marked under the lock, so a launch cancelled from now on is reported as not cancelled
+            launches.in_flight = due.iter().map(|(id, _)| *id).collect();
+            due
+        };
+
+        // Launch without holding the lock, so pushing is not held up by slow launches
+        let mut done = Vec::new();
+        for (id, launch) in due {
+            match launch.builder().spawn() {
+                // The process is not managed by the queue, dropping the child leaves it running
+                Ok(_) => done.push(id),
+                Err(err) if err.is_retryable() => {}
+                Err(err) => {
+                    lock(&self.failures).push((id, err));
+                    done.push(id);
+                }
+            }
+        }
+
+        let mut launches = lock(&self.launches);
+        launches.in_flight.clear();
+        if !done.is_empty() {
+            launches.pending.retain(|(id, _)| !done.contains(id));
+            launches.unsaved = true;
+        }
+        // A file that could not be written is tried again at the next check
+        if launches.unsaved {
+            let _ = self.save(&mut launches);
+        }
+    }
+
+    /// Writes the pending launches to the file, replacing it as a whole
+    fn save(&self, launches: &mut Launches) -> Result<(), Error> {
+        launches.unsaved = true;
+
+        let mut text = String::new();
+        for (id, launch) in &launches.pending {
+            text.push_str(&launch.encode(*id)?);
+            text.push('\n');
+        }
+
+        // Write next to the file first, so a crash never leaves it half written
+        let mut temporary = self.file.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, text)
+            .and_then(|()| fs::rename(&temporary, &self.file))
+            .map_err(|err| {
+                Error::other(
+                    Stage::Persistence,
+                    format!(
+                        "Unable to write the launch queue to {}: {err}",
+                        self.file.display()
+                    ),
+                )
+            })?;
+
+        launches.unsaved = false;
+        Ok(())
+    }
+}
+
+/// Reads the pending launches from a file, none if it does not exist yet
+fn load(file: &Path) -> Result<Vec<(u64, DurableLaunch)>, Error> {
+    let text = match fs::read_to_string(file) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => {
+            return Err(Error::other(
+                Stage::Persistence,
+                format!(
+                    "Unable to read the launch queue from {}: {err}",
+                    file.display()
+                ),
+            ))
+        }
+    };
+
+    // Launches are separated by empty lines
+    text.replace("\r\n", "\n")
+        .split("\n\n")
+        .filter(|record| !record.trim().is_empty())
+        .map(DurableLaunch::decode)
+        .collect()
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // A thread that panicked while holding the lock leaves the data as usable as before
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elevation_name;
+
+    /// A directory of its own for every test, removed once the test is done
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("win_run-durable-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn queue(&self) -> PathBuf {
+            self.0.join("launches")
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn report() -> DurableLaunch {
+        let mut launch = DurableLaunch::new("C:\\Tools\\report.exe");
+        launch.args = "--out=C:\\Reports \"a = b\"".to_string();
+        launch.directory = Some("C:\\Work=Dir".to_string());
+        launch.elevation = Elevation::LocalSystem;
+        launch.session = Some(2);
+        launch.not_before = Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        launch
+    }
+
+    fn assert_same(decoded: &DurableLaunch, launch: &DurableLaunch) {
+        assert_eq!(decoded.path, launch.path);
+        assert_eq!(decoded.args, launch.args);
+        assert_eq!(decoded.directory, launch.directory);
+        assert_eq!(
+            elevation_name(&decoded.elevation),
+            elevation_name(&launch.elevation)
+        );
+        assert_eq!(decoded.session, launch.session);
+        assert_eq!(decoded.not_before, launch.not_before);
+    }
+
+    #[test]
+    fn round_trips_launches() {
+        let launch = report();
+        let (id, decoded) = DurableLaunch::decode(&launch.encode(7).unwrap()).unwrap();
+        assert_eq!(id, 7);
+        assert_same(&decoded, &launch);
+
+        let launch = DurableLaunch::new("notepad.exe");
+        let (id, decoded) = DurableLaunch::decode(&launch.encode(1).unwrap()).unwrap();
+        assert_eq!(id, 1);
+        assert_same(&decoded, &launch);
+    }
+
+    #[test]
+    fn refuses_to_store_credentials_and_line_breaks() {
+        let mut launch = DurableLaunch::new("notepad.exe");
+        launch.elevation = Elevation::FromProcessName("explorer.exe".to_string());
+        assert_eq!(launch.encode(1).unwrap_err().stage(), Stage::Persistence);
+
+        let mut launch = DurableLaunch::new("notepad.exe");
+        launch.args = "a\nb".to_string();
+        assert_eq!(launch.encode(1).unwrap_err().stage(), Stage::Persistence);
+    }
+
+    #[test]
+    fn rejects_malformed_launches() {
+        let cases = [
+            "path=notepad.exe\n",
+            "id=1\n",
+            "id=one\npath=notepad.exe\n",
+            "id=1\npath=notepad.exe\nnot_before=soon\n",
+            "id=1\npath=notepad.exe\nuser=admin\n",
+            "id=1\npath=notepad.exe\nargs\n",
+        ];
+        for text in cases {
+            let err = DurableLaunch::decode(text).unwrap_err();
+            assert_eq!(err.stage(), Stage::Persistence, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn loads_nothing_from_a_missing_file() {
+        let dir = TestDir::new("missing");
+        assert!(load(&dir.queue()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn loads_the_launches_of_a_file() {
+        let dir = TestDir::new("load");
+        let first = report();
+        let second = DurableLaunch::new("notepad.exe");
+        // Written with Windows line endings, as an editor may leave them
+        let text = format!(
+            "{}\n{}",
+            first.encode(3).unwrap(),
+            second.encode(5).unwrap()
+        );
+        fs::write(dir.queue(), text.replace('\n', "\r\n")).unwrap();
+
+        let loaded = load(&dir.queue()).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].0, 3);
+        assert_same(&loaded[0].1, &first);
+        assert_eq!(loaded[1].0, 5);
+        assert_same(&loaded[1].1, &second);
+    }
+
+    #[test]
+    fn fails_to_load_a_malformed_file() {
+        let dir = TestDir::new("malformed");
+        let text = format!("{}\nid=4\nelevation=root\n", report().encode(3).unwrap());
+        fs::write(dir.queue(), text).unwrap();
+
+        assert_eq!(load(&dir.queue()).unwrap_err().stage(), Stage::Persistence);
+    }
+}
@@ -0,0 +1,192 @@
+use crate::safe_windows_bindings::high_level::{
+    decode_code_page, get_ansi_code_page, get_oem_code_page,
+};
+
+/// The byte order mark starting UTF-8 text
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+/// The byte order mark starting UTF-16LE text
+const UTF16LE_BOM: &[u8] = &[0xFF, 0xFE];
+/// How much of the start of a stream is looked at to detect UTF-16LE without a byte order mark
+const DETECTION_SAMPLE: usize = 64;
+
+/// The encoding a process writes its output in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// UTF-16LE if the output starts with its byte order mark or looks like ASCII text in it,
+    /// otherwise every line is decoded as UTF-8 if it is valid and in the OEM code page if it is not
+    #[default]
+    Auto,
+    /// UTF-8, e.g. of programs that set their console to code page 65001
+    Utf8,
+    /// UTF-16LE, e.g. of `cmd /u` and programs writing wide characters
+    Utf16Le,
+    /// The OEM code page, which console programs write in unless they pick another one
+    Oem,
+    /// The ANSI code page, which programs that are not Unicode aware write in
+    Ansi,
+    /// An explicit code page, e.g. 1252
+    CodePage(u32),
+}
+
+impl Encoding {
+    /// Settles the encoding from the first bytes of a stream
+    ///
+    /// Returns the encoding and the length of the byte order mark to skip
+    pub(crate) fn resolve(self, start: &[u8]) -> (Encoding, usize) {
+        match self {
+            Encoding::Auto if start.starts_with(UTF16LE_BOM) => {
+                (Encoding::Utf16Le, UTF16LE_BOM.len())
+            }
+            Encoding::Auto if start.starts_with(UTF8_BOM) => (Encoding::Utf8, UTF8_BOM.len()),
+            Encoding::Auto if looks_like_utf16le(start) => (Encoding::Utf16Le, 0),
+            Encoding::Utf8 if start.starts_with(UTF8_BOM) => (Encoding::Utf8, UTF8_BOM.len()),
+            Encoding::Utf16Le if start.starts_with(UTF16LE_BOM) => {
+                (Encoding::Utf16Le, UTF16LE_BOM.len())
+            }
+            encoding => (encoding, 0),
+        }
+    }
+
+    /// The number of bytes of a character unit, which line endings are made of
+    pub(crate) fn unit_size(self) -> usize {
+        match self {
+            Encoding::Utf16Le => 2,
+            _ => 1,
+        }
+    }
+
+    /// Decodes text, replacing what is invalid in the encoding
+    pub(crate) fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Auto => match std::str::from_utf8(bytes) {
+                Ok(text) => text.to_string(),
+                Err(_) => decode_lossy(get_oem_code_page(), bytes),
+            },
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Utf16Le => decode_utf16le(bytes),
+            Encoding::Oem => decode_lossy(get_oem_code_page(), bytes),
+            Encoding::Ansi => decode_lossy(get_ansi_code_page(), bytes),
+            Encoding::CodePage(code_page) => decode_lossy(code_page, bytes),
+        }
+    }
+}
+
+/// Whether the bytes look like ASCII text in UTF-16LE, i.e. every other byte is zero
+fn looks_like_utf16le(start: &[u8]) -> bool {
+    let sample = &start[..start.len().min(DETECTION_SAMPLE) & !1];
+    if sample.is_empty() {
+        return false;
+    }
+    let mut pairs = sample.chunks(2);
+    pairs.all(|pair| pair[0] != 0 && pair[1] == 0)
+}
+
+/// Decodes UTF-16LE text, replacing unpaired surrogates and reading a dangling byte as a whole unit
+fn decode_utf16le(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Decodes text in a code page, falling back to UTF-8 if the system cannot convert it
+fn decode_lossy(code_page: u32, bytes: &[u8]) -> String {
+    decode_code_page(code_page, bytes)
+        .unwrap_or_else(|_| String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    #[test]
+    fn detects_byte_order_marks() {
+        let utf16 = [UTF16LE_BOM, &utf16le("text")].concat();
+        assert_eq!(Encoding::Auto.resolve(&utf16), (Encoding::Utf16Le, 2));
+
+        let utf8 = [UTF8_BOM, "text".as_bytes()].concat();
+        assert_eq!(Encoding::Auto.resolve(&utf8), (Encoding::Utf8, 3));
+    }
+
+    #[test]
+    fn detects_utf16le_without_a_byte_order_mark() {
+        assert_eq!(
+            Encoding::Auto.resolve(&utf16le("Volume in drive C")),
+            (Encoding::Utf16Le, 0)
+        );
+        // An odd byte at the end of a sample is left out of the detection
+        let mut odd = utf16le("dir");
+        odd.push(b'x');
+        assert_eq!(Encoding::Auto.resolve(&odd), (Encoding::Utf16Le, 0));
+    }
+
+    #[test]
+    fn keeps_auto_for_other_text() {
+        for start in [
+            b"".as_slice(),
+            b"x",
+            b"plain text",
+            "Grüße".as_bytes(),
+            b"\0a",
+        ] {
+            assert_eq!(
+                Encoding::Auto.resolve(start),
+                (Encoding::Auto, 0),
+                "{start:?}"
+            );
+        }
+        // A line break of a single byte encoding is not mistaken for a zero high byte
+        assert_eq!(Encoding::Auto.resolve(b"a\nb\n"), (Encoding::Auto, 0));
+    }
+
+    #[test]
+    fn skips_byte_order_marks_of_the_explicit_encoding_only() {
+        let utf8 = [UTF8_BOM, b"text"].concat();
+        assert_eq!(Encoding::Utf8.resolve(&utf8), (Encoding::Utf8, 3));
+        assert_eq!(Encoding::Utf16Le.resolve(&utf8), (Encoding::Utf16Le, 0));
+
+        let utf16 = [UTF16LE_BOM, &utf16le("text")].concat();
+        assert_eq!(Encoding::Utf16Le.resolve(&utf16), (Encoding::Utf16Le, 2));
+        assert_eq!(Encoding::Utf8.resolve(&utf16), (Encoding::Utf8, 0));
+        assert_eq!(Encoding::Oem.resolve(&utf16), (Encoding::Oem, 0));
+        assert_eq!(
+            Encoding::CodePage(1252).resolve(&utf8),
+            (Encoding::CodePage(1252), 0)
+        );
+    }
+
+    #[test]
+    fn line_endings_of_utf16le_take_two_bytes() {
+        assert_eq!(Encoding::Utf16Le.unit_size(), 2);
+        for encoding in [
+            Encoding::Auto,
+            Encoding::Utf8,
+            Encoding::Oem,
+            Encoding::CodePage(437),
+        ] {
+            assert_eq!(encoding.unit_size(), 1, "{encoding:?}");
+        }
+    }
+
+    // Decoding reaches the code page conversion of the system, which only links on Windows
+    #[cfg(windows)]
+    #[test]
+    fn decodes_utf8_replacing_invalid_bytes() {
+        assert_eq!(Encoding::Utf8.decode("Grüße".as_bytes()), "Grüße");
+        assert_eq!(Encoding::Utf8.decode(b"a\xFFb"), "a\u{FFFD}b");
+        assert_eq!(Encoding::Auto.decode("Grüße 🚀".as_bytes()), "Grüße 🚀");
+    }
+
+    #[test]
+    fn decodes_utf16le_replacing_invalid_units() {
+        assert_eq!(decode_utf16le(&utf16le("Grüße 🚀")), "Grüße 🚀");
+        // An unpaired surrogate and a dangling byte
+        assert_eq!(decode_utf16le(&[0x00, 0xD8, b'a', 0]), "\u{FFFD}a");
+        assert_eq!(decode_utf16le(&[b'a', 0, b'b']), "ab");
+    }
+}
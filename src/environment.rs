@@ -0,0 +1,178 @@
+use std::ffi::{OsStr, OsString};
+use widestring::U16String;
+
+/// The UTF-16 code unit around the names of variables
+const PERCENT: u16 = b'%' as u16;
+
+/// The changes a builder makes to the environment the process starts with
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EnvironmentChanges {
+    /// Whether the process starts without any of the variables it would otherwise get
+    clear: bool,
+    /// Variables to set, or to remove if they have no value, in the order they were given
+    changes: Vec<(OsString, Option<OsString>)>,
+}
+
+impl EnvironmentChanges {
+    /// Sets a variable, replacing an earlier change to it
+    pub(crate) fn set(&mut self, name: &OsStr, value: &OsStr) {
+        self.forget(name);
+        self.changes
+            .push((name.to_os_string(), Some(value.to_os_string())));
+    }
+
+    /// Removes a variable, replacing an earlier change to it
+    pub(crate) fn remove(&mut self, name: &OsStr) {
+        self.forget(name);
+        self.changes.push((name.to_os_string(), None));
+    }
+
+    /// Starts from an empty environment, dropping the changes made so far
+    pub(crate) fn clear(&mut self) {
+        self.clear = true;
+        self.changes.clear();
+    }
+
+    /// Applies the changes to the environment of a launch, the service's if it has none
+    ///
+    /// Returns the environment unchanged if there are no changes to make.
+    pub(crate) fn apply(
+        &self,
+        environment: Option<Vec<(OsString, OsString)>>,
+    ) -> Option<Vec<(OsString, OsString)>> {
+        if !self.clear && self.changes.is_empty() {
+            return environment;
+        }
+
+        let mut environment = if self.clear {
+            Vec::new()
+        } else {
+            environment.unwrap_or_else(service_environment)
+        };
+        for (name, value) in &self.changes {
+            // Windows treats variable names case-insensitively
+            environment.retain(|(existing, _)| !existing.eq_ignore_ascii_case(name));
+            if let Some(value) = value {
+                environment.push((name.clone(), value.clone()));
+            }
+        }
+        Some(environment)
+    }
+
+    /// Drops an earlier change to a variable
+    fn forget(&mut self, name: &OsStr) {
+        self.changes
+            .retain(|(changed, _)| !changed.eq_ignore_ascii_case(name));
+    }
+}
+
+/// The variables of the service, which a launch without an environment of its own gets
+///
+/// Hidden entries like `=C:` keep per-drive directories of shells, not variables, and are left out.
+pub(crate) fn service_environment() -> Vec<(OsString, OsString)> {
+    std::env::vars_os()
+        .filter(|(name, _)| !name.to_string_lossy().starts_with('='))
+        .collect()
+}
+
+/// Expands `%VAR%` references in a value against the variables of an environment, the way
+/// `ExpandEnvironmentStringsW` does against those of the calling process
+///
+/// Names are compared ignoring case, and references to variables that are not set are kept.
+pub(crate) fn expand_variables(value: &OsStr, environment: &[(OsString, OsString)]) -> OsString {
+    let units = U16String::from_os_str(value).into_vec();
+    let mut expanded = Vec::with_capacity(units.len());
+    let mut rest = units.as_slice();
+    while let Some(start) = rest.iter().position(|unit| *unit == PERCENT) {
+        expanded.extend_from_slice(&rest[..start]);
+        let after = &rest[start + 1..];
+        let Some(end) = after.iter().position(|unit| *unit == PERCENT) else {
+            // A percent sign without a closing one is kept as is
+            rest = &rest[start..];
+            break;
+        };
+        match lookup(environment, &after[..end]) {
+            Some(variable) => expanded.extend(U16String::from_os_str(variable).into_vec()),
+            None => expanded.extend_from_slice(&rest[start..start + end + 2]),
+        }
+        rest = &after[end + 1..];
+    }
+    expanded.extend_from_slice(rest);
+    U16String::from_vec(expanded).to_os_string()
+}
+
+/// The value of a variable by the UTF-16 code units of its name
+fn lookup<'a>(environment: &'a [(OsString, OsString)], name: &[u16]) -> Option<&'a OsStr> {
+    if name.is_empty() {
+        return None;
+    }
+    let name = U16String::from_vec(name).to_os_string();
+    environment
+        .iter()
+        .find(|(variable, _)| variable.eq_ignore_ascii_case(&name))
+        .map(|(_, value)| value.as_os_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(value: &str) -> String {
+        let environment = [
+            (
+                OsString::from("APPDATA"),
+                OsString::from("C:\\Users\\alice\\AppData\\Roaming"),
+            ),
+            (OsString::from("UserName"), OsString::from("alice")),
+            (OsString::from("EMPTY"), OsString::new()),
+        ];
+        expand_variables(OsStr::new(value), &environment)
+            .into_string()
+            .unwrap()
+    }
+
+    #[test]
+    fn changes_replace_variables_ignoring_case() {
+        let mut changes = EnvironmentChanges::default();
+        changes.set(OsStr::new("Path"), OsStr::new("C:\\tools"));
+        changes.remove(OsStr::new("TEMP"));
+        let environment = vec![
+            (OsString::from("PATH"), OsString::from("C:\\Windows")),
+            (OsString::from("temp"), OsString::from("C:\\Temp")),
+            (OsString::from("USERNAME"), OsString::from("alice")),
+        ];
+        assert_eq!(
+            changes.apply(Some(environment)).unwrap(),
+            [
+                (OsString::from("USERNAME"), OsString::from("alice")),
+                (OsString::from("Path"), OsString::from("C:\\tools")),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_service_environment_leaves_out_hidden_entries() {
+        assert!(service_environment()
+            .iter()
+            .all(|(name, _)| !name.to_string_lossy().starts_with('=')));
+    }
+
+    #[test]
+    fn expands_variables_ignoring_case() {
+        assert_eq!(
+            expand("%AppData%\\tool"),
+            "C:\\Users\\alice\\AppData\\Roaming\\tool"
+        );
+        assert_eq!(expand("%USERNAME%-%username%"), "alice-alice");
+        assert_eq!(expand("[%EMPTY%]"), "[]");
+    }
+
+    #[test]
+    fn keeps_references_to_unset_variables() {
+        assert_eq!(expand("%MISSING%\\tool"), "%MISSING%\\tool");
+        assert_eq!(expand("%MISSING%USERNAME%"), "%MISSING%USERNAME%");
+        assert_eq!(expand("%MISSING%%USERNAME%"), "%MISSING%alice");
+        assert_eq!(expand("100% of %USERNAME"), "100% of %USERNAME");
+        assert_eq!(expand("%%"), "%%");
+    }
+}
@@ -0,0 +1,685 @@
+use crate::safe_windows_bindings::types::{
+    WindowsError, ERROR_ACCESS_DENIED, ERROR_NOT_LOGGED_ON, ERROR_NO_SUCH_LOGON_SESSION,
+    ERROR_NO_TOKEN, ERROR_PRIVILEGE_NOT_HELD, ERROR_USER_PROFILE_LOAD, WIN32_ERROR,
+};
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+/// The RPC server is unavailable, e.g. Terminal Services has not started yet
+const RPC_S_SERVER_UNAVAILABLE: WIN32_ERROR = WIN32_ERROR(1722);
+/// The RPC server is too busy to complete this operation
+const RPC_S_SERVER_TOO_BUSY: WIN32_ERROR = WIN32_ERROR(1723);
+
+/// OS error codes describing conditions that are expected to go away on their own
+const RETRYABLE_CODES: [WIN32_ERROR; 6] = [
+    // No user is logged on to the session yet
+    ERROR_NO_TOKEN,
+    ERROR_NOT_LOGGED_ON,
+    // The user's profile is not loaded yet, e.g. while the logon is still in progress
+    ERROR_USER_PROFILE_LOAD,
+    // The logon session is being created or torn down
+    ERROR_NO_SUCH_LOGON_SESSION,
+    // Services the launch depends on are not up yet
+    RPC_S_SERVER_UNAVAILABLE,
+    RPC_S_SERVER_TOO_BUSY,
+];
+
+/// The stage of a launch at which an error occurred
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    /// Finding the active console session
+    SessionQuery,
+    /// Logging off, disconnecting or messaging a session
+    SessionControl,
+    /// Obtaining the token of the user logged on to a session
+    UserTokenQuery,
+    /// Enabling or removing privileges on a token
+    PrivilegeAdjustment,
+    /// Logging an account on with credentials
+    Logon,
+    /// Obtaining the elevated token linked to a filtered one
+    LinkedTokenFetch,
+    /// Looking up a running process by name
+    ProcessLookup,
+    /// Enumerating top-level windows
+    WindowLookup,
+    /// Opening a handle to a running process
+    ProcessOpen,
+    /// Opening the token of a process
+    TokenOpen,
+    /// Querying information about a token
+    TokenQuery,
+    /// Resolving account names and SIDs
+    AccountLookup,
+    /// Duplicating a token into a primary one
+    Duplication,
+    /// Expanding environment variables in the builder's values
+    EnvironmentExpansion,
+    /// Resolving the path of the executable past file system redirection
+    PathResolution,
+    /// Reading system policy from the registry
+    PolicyQuery,
+    /// Building the environment block of the new process
+    EnvironmentBuild,
+    /// Converting values into their native representation
+    Conversion,
+    /// Connecting the standard streams of the new process to files or pipes
+    Redirection,
+    /// Preparing the settings of the console of the new process
+    ConsoleSetup,
+    /// Serving or requesting launches over a named pipe
+    LaunchServer,
+    /// Reading or writing the file of a durable launch queue
+    Persistence,
+    /// The call creating the process, to whichever CreateProcess function the backend uses
+    CreateProcess,
+    /// Preparing a temporary profile for the new process
+    ProfileSetup,
+    /// Mapping network drives for the user of the launch token
+    DriveMapping,
+    /// Granting the user of the launch token access to the window station and desktop
+    DesktopAccess,
+    /// Adjusting the launched process, e.g. its power throttling
+    ProcessSetup,
+    /// Checking that the launched process runs with the expected identity
+    Verification,
+    /// Checking that AppLocker and Software Restriction Policies let the executable run
+    ExecutionPolicy,
+    /// Looking for an instance of the executable that is already running
+    InstanceCheck,
+    /// Holding a launch back to respect a throttle
+    Throttling,
+    /// Waiting for the time of a scheduled launch
+    Scheduling,
+    /// Calling off a launch whose cancellation token was cancelled
+    Cancellation,
+    /// Waiting for a process
+    Waiting,
+    /// Terminating a process
+    Termination,
+    /// Suspending or resuming the primary thread of a process
+    ThreadControl,
+    /// Closing handles that are no longer needed
+    Cleanup,
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Stage::SessionQuery => "session query",
+            Stage::SessionControl => "session control",
+            Stage::UserTokenQuery => "user token query",
+            Stage::PrivilegeAdjustment => "privilege adjustment",
+            Stage::Logon => "logon",
+            Stage::LinkedTokenFetch => "linked token fetch",
+            Stage::ProcessLookup => "process lookup",
+            Stage::WindowLookup => "window lookup",
+            Stage::ProcessOpen => "process open",
+            Stage::TokenOpen => "token open",
+            Stage::TokenQuery => "token query",
+            Stage::AccountLookup => "account lookup",
+            Stage::Duplication => "token duplication",
+            Stage::EnvironmentExpansion => "environment expansion",
+            Stage::PathResolution => "path resolution",
+            Stage::PolicyQuery => "policy query",
+            Stage::EnvironmentBuild => "environment build",
+            Stage::Conversion => "conversion",
+            Stage::Redirection => "redirection",
+            Stage::ConsoleSetup => "console setup",
+            Stage::LaunchServer => "launch server",
+            Stage::Persistence => "persistence",
+            Stage::CreateProcess => "process creation",
+            Stage::ProfileSetup => "profile setup",
+            Stage::DriveMapping => "drive mapping",
+            Stage::DesktopAccess => "desktop access",
+            Stage::ProcessSetup => "process setup",
+            Stage::Verification => "verification",
+            Stage::ExecutionPolicy => "execution policy",
+            Stage::InstanceCheck => "instance check",
+            Stage::Throttling => "throttling",
+            Stage::Scheduling => "scheduling",
+            Stage::Cancellation => "cancellation",
+            Stage::Waiting => "waiting",
+            Stage::Termination => "termination",
+            Stage::ThreadControl => "thread control",
+            Stage::Cleanup => "cleanup",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// An error that occurred while launching a process
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// A Windows API call failed with an OS error code
+    Os {
+        stage: Stage,
+        code: i32,
+        message: String,
+    },
+    /// A failure that did not originate from a Windows API call
+    Other { stage: Stage, message: String },
+    /// The launch was skipped because an instance of the executable is already running
+    AlreadyRunning {
+        /// The running instance, if it is known
+        pid: Option<u32>,
+        message: String,
+    },
+    /// No user is logged on interactively to launch for, e.g. while the machine is still booting
+    NoInteractiveUser {
+        /// The session without a user, `None` if no session is attached to the physical console
+        session_id: Option<u32>,
+        message: String,
+    },
+    /// The service lacks a privilege launching as another user requires, e.g. when it does not run as LocalSystem
+    PrivilegeNotHeld {
+        stage: Stage,
+        /// The OS error code, `ERROR_PRIVILEGE_NOT_HELD`
+        code: i32,
+        message: String,
+    },
+    /// Windows denied the launch, e.g. the target user cannot read the executable or use the desktop
+    AccessDenied {
+        stage: Stage,
+        /// The OS error code, `ERROR_ACCESS_DENIED`
+        code: i32,
+        message: String,
+    },
+    /// The command line is longer than Windows accepts
+    CommandLineTooLong {
+        /// The length of the command line in UTF-16 code units
+        length: usize,
+        message: String,
+    },
+    /// The process ran longer than the [timeout](crate::ProcessBuilder::timeout) of its builder and was terminated
+    Timeout {
+        /// The process that was terminated
+        pid: u32,
+        /// How long the process was allowed to run
+        timeout: Duration,
+        message: String,
+    },
+    /// A failure after which releasing the acquired resources failed as well
+    Aggregate {
+        /// The failure that interrupted the launch
+        primary: Box<Error>,
+        /// The failures that occurred while cleaning up after it
+        cleanup: Vec<Error>,
+    },
+}
+
+impl Error {
+    /// Creates an error from the calling thread's last OS error
+    pub(crate) fn last_os_error(stage: Stage, context: &str) -> Self {
+        let last_error = std::io::Error::last_os_error();
+        let code = last_error.raw_os_error().unwrap_or_default();
+        let message = format!("{context}: {last_error}");
+        Error::Os {
+            stage,
+            code,
+            message,
+        }
+    }
+
+    /// Creates an error from an error returned by the windows crate
+    pub(crate) fn windows(stage: Stage, context: &str, err: WindowsError) -> Self {
+        // Unwrap win32 error codes that were turned into HRESULTs
+        let hresult = err.code().0;
+        let code = if (hresult as u32) & 0xFFFF_0000 == 0x8007_0000 {
+            hresult & 0xFFFF
+        } else {
+            hresult
+        };
+        let message = format!("{context}: {err}");
+        Error::Os {
+            stage,
+            code,
+            message,
+        }
+    }
+
+    /// Creates an error that did not originate from a Windows API call
+    pub(crate) fn other(stage: Stage, message: impl Into<String>) -> Self {
+        let message = message.into();
+        Error::Other { stage, message }
+    }
+
+    /// Creates an error for a launch skipped in favor of a running instance
+    pub(crate) fn already_running(pid: Option<u32>) -> Self {
+        let message = match pid {
+            Some(pid) => format!("The executable is already running as process {pid}"),
+            None => "An instance of the executable is already running".to_string(),
+        };
+        Error::AlreadyRunning { pid, message }
+    }
+
+    /// Creates an error for a launch that found no user logged on
+    pub(crate) fn no_interactive_user(session_id: Option<u32>) -> Self {
+        let message = match session_id {
+            Some(session_id) => format!("No user is logged on to session {session_id}"),
+            None => "No session is attached to the physical console".to_string(),
+        };
+        Error::NoInteractiveUser {
+            session_id,
+            message,
+        }
+    }
+
+    /// Turns the OS errors process creation commonly fails with into errors saying what to do about them
+    ///
+    /// The session is the one the process was launched into, if it is known
+    pub(crate) fn with_hint(self, session_id: Option<u32>) -> Self {
+        let Error::Os {
+            stage,
+            code,
+            message,
+        } = self
+        else {
+            return self;
+        };
+        if stage != Stage::CreateProcess {
+            return Error::Os {
+                stage,
+                code,
+                message,
+            };
+        }
+        match WIN32_ERROR(code as u32) {
+            ERROR_PRIVILEGE_NOT_HELD => Error::PrivilegeNotHeld {
+                stage,
+                code,
+                message: format!(
+                    "{message}. The service needs SeAssignPrimaryTokenPrivilege and \
+                    SeIncreaseQuotaPrivilege to launch as another user, which LocalSystem holds"
+                ),
+            },
+            ERROR_ACCESS_DENIED => Error::AccessDenied {
+                stage,
+                code,
+                message: format!(
+                    "{message}. The target user may lack access to the executable, its \
+                    directory or the desktop, see ProcessBuilder::grant_desktop_access"
+                ),
+            },
+            // The user logged off between acquiring the token and launching
+            ERROR_NO_SUCH_LOGON_SESSION if session_id.is_some() => {
+                Error::no_interactive_user(session_id)
+            }
+            _ => Error::Os {
+                stage,
+                code,
+                message,
+            },
+        }
+    }
+
+    /// Creates an error for a process terminated for running longer than it was allowed to
+    pub(crate) fn timeout(pid: u32, timeout: Duration) -> Self {
+        let message = format!("Process {pid} did not exit within {timeout:?} and was terminated");
+        Error::Timeout {
+            pid,
+            timeout,
+            message,
+        }
+    }
+
+    /// Creates an error for a command line longer than Windows accepts
+    pub(crate) fn command_line_too_long(length: usize, limit: usize) -> Self {
+        let message = format!(
+            "The command line is {length} characters long, Windows accepts at most {limit}"
+        );
+        Error::CommandLineTooLong { length, message }
+    }
+
+    /// Combines several errors, treating the first one as the primary and the rest as cleanup failures
+    pub(crate) fn combine(errors: Vec<Error>) -> Self {
+        let mut errors = errors.into_iter();
+        let primary = errors
+            .next()
+            .expect("Cannot combine an empty list of errors");
+        // Keep a single level of aggregation
+        let (primary, mut cleanup) = match primary {
+            Error::Aggregate { primary, cleanup } => (*primary, cleanup),
+            primary => (primary, Vec::new()),
+        };
+        cleanup.extend(errors);
+        if cleanup.is_empty() {
+            return primary;
+        }
+        let primary = Box::new(primary);
+        Error::Aggregate { primary, cleanup }
+    }
+
+    /// Replaces the stage and prefixes the message with additional context
+    pub(crate) fn context(self, stage: Stage, context: &str) -> Self {
+        let message = format!("{context}: {}", self.message());
+        match self {
+            Error::Os { code, .. } => Error::Os {
+                stage,
+                code,
+                message,
+            },
+            Error::Other { .. } => Error::Other { stage, message },
+            Error::PrivilegeNotHeld { code, .. } => Error::PrivilegeNotHeld {
+                stage,
+                code,
+                message,
+            },
+            Error::AccessDenied { code, .. } => Error::AccessDenied {
+                stage,
+                code,
+                message,
+            },
+            // Callers match on these, which must not get lost
+            Error::AlreadyRunning { .. }
+            | Error::NoInteractiveUser { .. }
+            | Error::CommandLineTooLong { .. }
+            | Error::Timeout { .. } => self,
+            Error::Aggregate { primary, cleanup } => {
+                let primary = Box::new(primary.context(stage, context));
+                Error::Aggregate { primary, cleanup }
+            }
+        }
+    }
+
+    /// The failure that interrupted the launch, which is the error itself unless it is an aggregate
+    pub fn primary(&self) -> &Error {
+        match self {
+            Error::Aggregate { primary, .. } => primary.primary(),
+            error => error,
+        }
+    }
+
+    /// The failures that occurred while cleaning up after the primary one
+    pub fn cleanup_errors(&self) -> &[Error] {
+        match self {
+            Error::Aggregate { cleanup, .. } => cleanup,
+            _ => &[],
+        }
+    }
+
+    /// The message describing the primary failure, without the stage
+    pub fn message(&self) -> &str {
+        match self.primary() {
+            Error::Os { message, .. } => message,
+            Error::Other { message, .. } => message,
+            Error::PrivilegeNotHeld { message, .. } => message,
+            Error::AccessDenied { message, .. } => message,
+            Error::AlreadyRunning { message, .. } => message,
+            Error::NoInteractiveUser { message, .. } => message,
+            Error::CommandLineTooLong { message, .. } => message,
+            Error::Timeout { message, .. } => message,
+            Error::Aggregate { .. } => unreachable!("The primary error is never an aggregate"),
+        }
+    }
+
+    /// The stage at which the primary failure occurred
+    pub fn stage(&self) -> Stage {
+        match self.primary() {
+            Error::Os { stage, .. } => *stage,
+            Error::Other { stage, .. } => *stage,
+            Error::PrivilegeNotHeld { stage, .. } => *stage,
+            Error::AccessDenied { stage, .. } => *stage,
+            Error::AlreadyRunning { .. } => Stage::InstanceCheck,
+            Error::NoInteractiveUser { session_id, .. } => no_user_stage(*session_id),
+            Error::CommandLineTooLong { .. } => Stage::Conversion,
+            Error::Timeout { .. } => Stage::Waiting,
+            Error::Aggregate { .. } => unreachable!("The primary error is never an aggregate"),
+        }
+    }
+
+    /// Whether the launch may succeed if attempted again later
+    ///
+    /// Conditions like no user being logged on yet, their profile not being loaded yet or system
+    /// services still starting are retryable, while missing executables, denied access and other
+    /// configuration problems are not
+    pub fn is_retryable(&self) -> bool {
+        match self.primary() {
+            Error::Os { code, .. } => RETRYABLE_CODES
+                .iter()
+                .any(|retryable| retryable.0 as i32 == *code),
+            // No active session or no donor process yet, both appear once a user logs on,
+            // and throttled launches are let through once earlier ones are done
+            Error::Other { stage, .. } => {
+                matches!(
+                    stage,
+                    Stage::SessionQuery | Stage::ProcessLookup | Stage::Throttling
+                )
+            }
+            Error::PrivilegeNotHeld { .. } => false,
+            Error::AccessDenied { .. } => false,
+            Error::AlreadyRunning { .. } => false,
+            Error::NoInteractiveUser { .. } => true,
+            Error::CommandLineTooLong { .. } => false,
+            Error::Timeout { .. } => false,
+            Error::Aggregate { .. } => unreachable!("The primary error is never an aggregate"),
+        }
+    }
+
+    /// Whether the launch was skipped because an instance is already running
+    pub fn is_already_running(&self) -> bool {
+        matches!(self.primary(), Error::AlreadyRunning { .. })
+    }
+
+    /// The pid of the running instance the launch was skipped for, if that is why it failed and it is known
+    pub fn already_running_pid(&self) -> Option<u32> {
+        match self.primary() {
+            Error::AlreadyRunning { pid, .. } => *pid,
+            _ => None,
+        }
+    }
+
+    /// Whether the launch failed because no user is logged on to launch for
+    pub fn is_no_interactive_user(&self) -> bool {
+        matches!(self.primary(), Error::NoInteractiveUser { .. })
+    }
+
+    /// Whether the service lacks a privilege the launch requires
+    pub fn is_privilege_not_held(&self) -> bool {
+        matches!(self.primary(), Error::PrivilegeNotHeld { .. })
+    }
+
+    /// Whether Windows denied the launch access to something it needs
+    pub fn is_access_denied(&self) -> bool {
+        matches!(self.primary(), Error::AccessDenied { .. })
+    }
+
+    /// Whether the process was terminated for running longer than its builder allowed
+    pub fn is_timeout(&self) -> bool {
+        matches!(self.primary(), Error::Timeout { .. })
+    }
+
+    /// The OS error code of the primary failure, if it originated from a Windows API call
+    pub fn code(&self) -> Option<i32> {
+        match self.primary() {
+            Error::Os { code, .. } => Some(*code),
+            Error::PrivilegeNotHeld { code, .. } => Some(*code),
+            Error::AccessDenied { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Os { stage, message, .. } => write!(f, "[{stage}] {message}"),
+            Error::Other { stage, message } => write!(f, "[{stage}] {message}"),
+            Error::PrivilegeNotHeld { stage, message, .. } => write!(f, "[{stage}] {message}"),
+            Error::AccessDenied { stage, message, .. } => write!(f, "[{stage}] {message}"),
+            Error::AlreadyRunning { message, .. } => {
+                write!(f, "[{}] {message}", Stage::InstanceCheck)
+            }
+            Error::NoInteractiveUser {
+                session_id,
+                message,
+            } => write!(f, "[{}] {message}", no_user_stage(*session_id)),
+            Error::CommandLineTooLong { message, .. } => {
+                write!(f, "[{}] {message}", Stage::Conversion)
+            }
+            Error::Timeout { message, .. } => write!(f, "[{}] {message}", Stage::Waiting),
+            Error::Aggregate { primary, cleanup } => {
+                write!(f, "{primary}")?;
+                for error in cleanup {
+                    write!(f, "\nwhile cleaning up: {error}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Aggregate { primary, .. } => Some(primary.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// The stage at which a launch finds no user, which is looking for the session when there is none
+fn no_user_stage(session_id: Option<u32>) -> Stage {
+    match session_id {
+        Some(_) => Stage::UserTokenQuery,
+        None => Stage::SessionQuery,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn other(message: &str) -> Error {
+        Error::other(Stage::Cleanup, message)
+    }
+
+    #[test]
+    fn combining_one_error_returns_it() {
+        let error = Error::other(Stage::Logon, "primary");
+        assert_eq!(Error::combine(vec![error.clone()]), error);
+    }
+
+    #[test]
+    fn combining_keeps_the_first_error_as_primary() {
+        let error = Error::combine(vec![
+            Error::other(Stage::Logon, "primary"),
+            other("first cleanup"),
+            other("second cleanup"),
+        ]);
+
+        assert_eq!(error.primary(), &Error::other(Stage::Logon, "primary"));
+        assert_eq!(error.stage(), Stage::Logon);
+        assert_eq!(error.message(), "primary");
+        assert_eq!(
+            error.cleanup_errors(),
+            [other("first cleanup"), other("second cleanup")].as_slice()
+        );
+        assert_eq!(
+            error.to_string(),
+            "[logon] primary\nwhile cleaning up: [cleanup] first cleanup\n\
+            while cleaning up: [cleanup] second cleanup"
+        );
+    }
+
+    #[test]
+    fn combining_an_aggregate_flattens_it() {
+        let aggregate = Error::combine(vec![
+            Error::other(Stage::Logon, "primary"),
+            other("first cleanup"),
+        ]);
+        let error = Error::combine(vec![aggregate, other("second cleanup")]);
+
+        let Error::Aggregate { primary, cleanup } = &error else {
+            panic!("Expected an aggregate, got {error:?}");
+        };
+        assert_eq!(**primary, Error::other(Stage::Logon, "primary"));
+        assert_eq!(cleanup, &[other("first cleanup"), other("second cleanup")]);
+    }
+
+    #[test]
+    fn combining_an_aggregate_alone_keeps_it() {
+        let aggregate = Error::combine(vec![
+            Error::other(Stage::Logon, "primary"),
+            other("cleanup"),
+        ]);
+        assert_eq!(Error::combine(vec![aggregate.clone()]), aggregate);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot combine an empty list of errors")]
+    fn combining_nothing_panics() {
+        Error::combine(Vec::new());
+    }
+
+    fn os(code: WIN32_ERROR) -> Error {
+        Error::Os {
+            stage: Stage::UserTokenQuery,
+            code: code.0 as i32,
+            message: "failed".to_string(),
+        }
+    }
+
+    #[test]
+    fn retryable_codes_are_retryable() {
+        for code in RETRYABLE_CODES {
+            assert!(os(code).is_retryable(), "{code:?}");
+        }
+    }
+
+    #[test]
+    fn profile_not_loaded_yet_is_retryable() {
+        let err = Error::Os {
+            stage: Stage::ProfileSetup,
+            code: ERROR_USER_PROFILE_LOAD.0 as i32,
+            message: "User profile cannot be loaded".to_string(),
+        };
+        assert!(err.is_retryable());
+    }
+
+    #[test]
+    fn other_codes_are_not_retryable() {
+        let codes = [
+            ERROR_ACCESS_DENIED,
+            ERROR_PRIVILEGE_NOT_HELD,
+            // ERROR_FILE_NOT_FOUND and ERROR_BAD_EXE_FORMAT, which no retry fixes
+            WIN32_ERROR(2),
+            WIN32_ERROR(193),
+        ];
+        for code in codes {
+            assert!(!os(code).is_retryable(), "{code:?}");
+        }
+    }
+
+    #[test]
+    fn retryability_follows_the_primary_error() {
+        let retryable = Error::combine(vec![os(ERROR_NO_TOKEN), os(ERROR_ACCESS_DENIED)]);
+        assert!(retryable.is_retryable());
+
+        let permanent = Error::combine(vec![os(ERROR_ACCESS_DENIED), os(ERROR_NO_TOKEN)]);
+        assert!(!permanent.is_retryable());
+    }
+
+    #[test]
+    fn retryability_of_failures_without_codes() {
+        assert!(Error::no_interactive_user(None).is_retryable());
+        assert!(Error::other(Stage::SessionQuery, "no session").is_retryable());
+        assert!(Error::other(Stage::Throttling, "throttled").is_retryable());
+        assert!(!Error::other(Stage::Conversion, "interior nul").is_retryable());
+        assert!(!Error::already_running(Some(4)).is_retryable());
+        assert!(!Error::timeout(4, Duration::from_secs(1)).is_retryable());
+        assert!(!Error::command_line_too_long(40000, 32767).is_retryable());
+    }
+
+    #[test]
+    fn classification_looks_at_the_primary_error() {
+        let error = Error::combine(vec![
+            Error::no_interactive_user(Some(1)),
+            Error::already_running(None),
+        ]);
+        assert!(error.is_no_interactive_user());
+        assert!(!error.is_already_running());
+        assert_eq!(error.already_running_pid(), None);
+    }
+}
@@ -0,0 +1,130 @@
+use crate::child::Child;
+use crate::safe_windows_bindings::high_level::get_exit_code;
+use crate::stdio::LineCallback;
+use std::sync::mpsc::Sender;
+use std::thread::spawn;
+use std::time::Duration;
+
+/// Which output stream of a process a line was written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStream {
+    /// The standard output
+    Stdout,
+    /// The standard error
+    Stderr,
+}
+
+/// Something that happened to a launch, reported to the sender set with [`ProcessBuilder::events`](crate::ProcessBuilder::events)
+///
+/// Every event carries the path of the executable, as configured on the builder, to tell
+/// launches of different builders sharing a sender apart. The events of a launch that was
+/// given a correlation id, see [`ProcessBuilder::correlate`](crate::ProcessBuilder::correlate),
+/// carry it as well, to tell launches of the same builder apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LaunchEvent {
+    /// The launch was pushed to a [`LaunchQueue`](crate::LaunchQueue) or scheduled, and waits to start
+    LaunchQueued { path: String },
+    /// The token the process is launched with was acquired, for the session it belongs to
+    TokenAcquired {
+        path: String,
+        session_id: Option<u32>,
+        correlation_id: Option<String>,
+    },
+    /// The process was launched
+    Started {
+        path: String,
+        pid: u32,
+        correlation_id: Option<String>,
+    },
+    /// The process wrote a line to a redirected output stream
+    OutputLine {
+        path: String,
+        stream: OutputStream,
+        line: String,
+        correlation_id: Option<String>,
+    },
+    /// The process exited, with its exit code if it could be read
+    Exited {
+        path: String,
+        pid: u32,
+        status: Option<u32>,
+        correlation_id: Option<String>,
+    },
+    /// A [`RecurringLaunch`](crate::RecurringLaunch) ran and will run again after the delay
+    RestartScheduled { path: String, delay: Duration },
+}
+
+/// Where a builder reports the events of its launches
+#[derive(Debug, Clone)]
+pub(crate) struct EventSink {
+    path: String,
+    sender: Sender<LaunchEvent>,
+    /// The correlation id of the launch the events are reported for, if it has one
+    correlation_id: Option<String>,
+}
+
+impl EventSink {
+    pub(crate) fn new(path: String, sender: Sender<LaunchEvent>) -> Self {
+        Self {
+            path,
+            sender,
+            correlation_id: None,
+        }
+    }
+
+    /// A sink reporting the events of a single launch, with its correlation id
+    pub(crate) fn for_launch(&self, correlation_id: Option<String>) -> Self {
+        Self {
+            correlation_id,
+            ..self.clone()
+        }
+    }
+
+    /// Reports an event, ignoring a receiver that went away
+    pub(crate) fn send(&self, event: LaunchEvent) {
+        let _ = self.sender.send(event);
+    }
+
+    /// The path the events are reported for
+    pub(crate) fn path(&self) -> String {
+        self.path.clone()
+    }
+
+    /// The correlation id of the launch the events are reported for
+    pub(crate) fn correlation_id(&self) -> Option<String> {
+        self.correlation_id.clone()
+    }
+
+    /// A line callback reporting the lines of an output stream
+    pub(crate) fn lines(&self, stream: OutputStream) -> LineCallback {
+        let sink = self.clone();
+        LineCallback::new(move |line| {
+            sink.send(LaunchEvent::OutputLine {
+                path: sink.path(),
+                stream,
+                line: line.to_string(),
+                correlation_id: sink.correlation_id(),
+            })
+        })
+    }
+
+    /// Reports the exit of a launched process, from a thread of its own waiting for it
+    pub(crate) fn watch_exit(&self, pid: u32) {
+        // A process the crate cannot open, e.g. a fake of a test backend, is not watched
+        let Ok(child) = Child::from_pid(pid) else {
+            return;
+        };
+        let sink = self.clone();
+        spawn(move || {
+            if child.wait_for_exit().is_err() {
+                return;
+            }
+            sink.send(LaunchEvent::Exited {
+                path: sink.path(),
+                pid,
+                status: get_exit_code(child.handle).ok(),
+                correlation_id: sink.correlation_id(),
+            });
+        });
+    }
+}
@@ -0,0 +1,289 @@
+use crate::error::{Error, Stage};
+use crate::safe_windows_bindings::high_level::{
+    close_handle, get_account_sid, get_process_query_token, get_session_user_member_of,
+    get_sid_account, get_token_elevated, get_token_integrity_rid, get_token_session_id,
+    get_token_user_account, get_token_user_sid,
+};
+use crate::safe_windows_bindings::types::HANDLE;
+use crate::Elevation;
+use std::fmt::{Display, Formatter};
+
+/// SID of the built-in Administrators group
+const ADMINISTRATORS_SID: &str = "S-1-5-32-544";
+
+/// The mandatory integrity level of a token
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IntegrityLevel {
+    Untrusted,
+    Low,
+    Medium,
+    MediumPlus,
+    High,
+    System,
+    Protected,
+    /// A level without a well-known name, holding its RID
+    Other(u32),
+}
+
+impl IntegrityLevel {
+    /// Maps the RID of an integrity level SID to its level
+    pub(crate) fn from_rid(rid: u32) -> Self {
+        match rid {
+            0x0000 => IntegrityLevel::Untrusted,
+            0x1000 => IntegrityLevel::Low,
+            0x2000 => IntegrityLevel::Medium,
+            0x2100 => IntegrityLevel::MediumPlus,
+            0x3000 => IntegrityLevel::High,
+            0x4000 => IntegrityLevel::System,
+            0x5000 => IntegrityLevel::Protected,
+            rid => IntegrityLevel::Other(rid),
+        }
+    }
+}
+
+impl Display for IntegrityLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IntegrityLevel::Untrusted => write!(f, "untrusted"),
+            IntegrityLevel::Low => write!(f, "low"),
+            IntegrityLevel::Medium => write!(f, "medium"),
+            IntegrityLevel::MediumPlus => write!(f, "medium plus"),
+            IntegrityLevel::High => write!(f, "high"),
+            IntegrityLevel::System => write!(f, "system"),
+            IntegrityLevel::Protected => write!(f, "protected"),
+            IntegrityLevel::Other(rid) => write!(f, "0x{rid:04X}"),
+        }
+    }
+}
+
+/// Who a process runs as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessIdentity {
+    /// The name of the user owning the process, if the SID could be resolved
+    pub user: Option<String>,
+    /// The domain of the user owning the process, if the SID could be resolved
+    pub domain: Option<String>,
+    /// The SID of the user owning the process, e.g. `S-1-5-18`
+    pub sid: String,
+    /// The session the process runs in
+    pub session_id: u32,
+    /// The integrity level of the process
+    pub integrity: IntegrityLevel,
+    /// Whether the process token is elevated
+    pub elevated: bool,
+}
+
+/// Reads the identity described by a token
+pub(crate) fn token_identity(token: HANDLE) -> Result<ProcessIdentity, Error> {
+    let (user, domain) = match get_token_user_account(token) {
+        Ok((user, domain)) => (Some(user), Some(domain)),
+        Err(_) => (None, None),
+    };
+    let sid = get_token_user_sid(token)?;
+    let session_id = get_token_session_id(token)?;
+    let integrity = IntegrityLevel::from_rid(get_token_integrity_rid(token)?);
+    let elevated = get_token_elevated(token)?;
+    Ok(ProcessIdentity {
+        user,
+        domain,
+        sid,
+        session_id,
+        integrity,
+        elevated,
+    })
+}
+
+/// Gets who a running process runs as
+///
+/// # Arguments
+///
+/// * `pid` - Id of the process.
+pub fn process_identity(pid: u32) -> Result<ProcessIdentity, Error> {
+    let token = get_process_query_token(pid)?;
+    let identity = token_identity(token);
+    let closed = close_handle(token);
+    match (identity, closed) {
+        (Ok(identity), Ok(())) => Ok(identity),
+        (Ok(_), Err(err)) => Err(err),
+        (Err(err), Ok(())) => Err(err),
+        (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
+    }
+}
+
+/// Looks up the account a SID belongs to
+///
+/// Returns the account name and its domain, e.g. `("SYSTEM", "NT AUTHORITY")` for `S-1-5-18`.
+///
+/// # Arguments
+///
+/// * `sid` - The SID in its string form, e.g. `S-1-5-21-...-1001`.
+///
+/// ```no_run
+/// # fn main() -> Result<(), win_run::Error> {
+/// use win_run::sid_to_account;
+///
+/// let (name, domain) = sid_to_account("S-1-5-32-544")?;
+/// println!("{domain}\\{name}");
+/// # Ok(())
+/// # }
+/// ```
+pub fn sid_to_account(sid: &str) -> Result<(String, String), Error> {
+    get_sid_account(sid)
+}
+
+/// Looks up the SID of an account, returning it in its string form
+///
+/// # Arguments
+///
+/// * `account` - Name of the account, either bare, as `DOMAIN\name` or as `name@domain`.
+///
+/// ```no_run
+/// # fn main() -> Result<(), win_run::Error> {
+/// use win_run::account_to_sid;
+///
+/// let sid = account_to_sid("BUILTIN\\Administrators")?;
+/// assert_eq!(sid, "S-1-5-32-544");
+/// # Ok(())
+/// # }
+/// ```
+pub fn account_to_sid(account: &str) -> Result<String, Error> {
+    get_account_sid(account)
+}
+
+/// Whether the user logged on to a session is a member of Administrators
+///
+/// With UAC on, the token of the session only keeps Administrators to deny access,
+/// which still counts, as launching with [`Elevation::Admin`] uses its linked elevated token.
+/// A `false` means launching as Admin into the session cannot work.
+///
+/// # Arguments
+///
+/// * `session_id` - Id of the session.
+///
+/// ```no_run
+/// # fn main() -> Result<(), win_run::Error> {
+/// use win_run::{is_user_admin, win, Elevation};
+///
+/// let elevation = if is_user_admin(1)? {
+///     Elevation::Admin
+/// } else {
+///     Elevation::User
+/// };
+/// win("/path/to/settings").session(1).elevation(elevation).run()?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn is_user_admin(session_id: u32) -> Result<bool, Error> {
+    get_session_user_member_of(session_id, ADMINISTRATORS_SID)
+}
+
+/// Where the token of a launch as [`Elevation::Admin`] came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminToken {
+    /// The elevated token UAC links to the filtered token of the user
+    Linked,
+    /// The token of the user itself, already elevated as UAC did not split it,
+    /// e.g. for the built-in Administrator or with admin approval mode off
+    Primary,
+}
+
+/// Where the token a process was launched with came from, see [`Child::token_origin`](crate::Child::token_origin)
+///
+/// Tokens shared through a [`TokenPool`](crate::TokenPool) report where the pool acquired them from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenOrigin {
+    /// WTSQueryUserToken, for the user logged on to the session
+    SessionUser { session_id: u32 },
+    /// The elevated token of the user logged on to the session, `None` if it could not be told which one
+    SessionAdmin {
+        session_id: u32,
+        admin_token: Option<AdminToken>,
+    },
+    /// The token of a winlogon process, running as Local System
+    Winlogon { pid: u32 },
+    /// A logon with the credentials of the account
+    Credentials { username: String, domain: String },
+    /// A service logon of a built-in service account, by its SID
+    ServiceAccount { sid: String },
+    /// The token of a process the elevation named, by its pid or its name
+    Process { pid: u32 },
+    /// A launch backend of the caller, which came by the token on its own
+    CallerSupplied { backend: String },
+}
+
+/// What a launch intended the identity of the process to be, taken from the launch token when there is one
+#[derive(Debug, Default)]
+pub(crate) struct ExpectedIdentity {
+    /// SID of the user the launch token belongs to
+    pub(crate) sid: Option<String>,
+    /// Session the launch token belongs to
+    pub(crate) session_id: Option<u32>,
+}
+
+/// Checks the identity of a launched process against what the launch intended
+pub(crate) fn verify_identity(
+    identity: &ProcessIdentity,
+    elevation: &Elevation,
+    expected: &ExpectedIdentity,
+) -> Result<(), Error> {
+    let mut mismatches: Vec<String> = Vec::new();
+
+    if let Some(expected_sid) = &expected.sid {
+        if &identity.sid != expected_sid {
+            mismatches.push(format!(
+                "runs as {} instead of {expected_sid}",
+                identity.sid
+            ));
+        }
+    }
+
+    if let Some(expected_session_id) = expected.session_id {
+        if identity.session_id != expected_session_id {
+            mismatches.push(format!(
+                "runs in session {} instead of {expected_session_id}",
+                identity.session_id
+            ));
+        }
+    }
+
+    // Only built-in service accounts, accounts given by credentials and those of other processes,
+    // which may be services, are expected to run without a desktop
+    let interactive = !matches!(
+        elevation,
+        Elevation::LocalService
+            | Elevation::NetworkService
+            | Elevation::Credentials(_)
+            | Elevation::FromPid(_)
+            | Elevation::FromProcessName(_)
+    );
+    if interactive && identity.session_id == 0 {
+        mismatches.push("runs in session 0 where its UI can never be seen".to_string());
+    }
+
+    let integrity_matches = match elevation {
+        Elevation::User => identity.integrity < IntegrityLevel::System,
+        Elevation::Admin => {
+            identity.integrity >= IntegrityLevel::High
+                && identity.integrity < IntegrityLevel::System
+        }
+        // The built-in service accounts run with system integrity as well
+        Elevation::LocalSystem | Elevation::LocalService | Elevation::NetworkService => {
+            identity.integrity == IntegrityLevel::System
+        }
+        Elevation::Credentials(_) => identity.integrity < IntegrityLevel::System,
+        // The process runs with whatever integrity the other one has
+        Elevation::FromPid(_) | Elevation::FromProcessName(_) => true,
+    };
+    if !integrity_matches {
+        mismatches.push(format!("runs with {} integrity", identity.integrity));
+    }
+
+    if !mismatches.is_empty() {
+        return Err(Error::other(
+            Stage::Verification,
+            format!("The launched process {}", mismatches.join(", ")),
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use crate::error::Error;
+use crate::safe_windows_bindings::high_level::{
+    close_handle, copy_handle_to_process, create_shared_mutex,
+};
+use crate::safe_windows_bindings::types::HANDLE;
+
+/// Where the name of a single-instance object is visible
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InstanceScope {
+    /// Only in the target session, allowing one instance per session
+    #[default]
+    Session,
+    /// In all sessions, allowing one instance on the machine
+    Global,
+}
+
+/// Creates the named mutex marking a running instance, failing if it already exists
+///
+/// The target user is allowed to open the mutex too, so the executable can check for it itself.
+pub(crate) fn claim_instance(
+    name: &str,
+    scope: InstanceScope,
+    sid: &str,
+    session_id: u32,
+) -> Result<HANDLE, Error> {
+    let name = match scope {
+        InstanceScope::Session => format!("Session\\{session_id}\\{name}"),
+        InstanceScope::Global => format!("Global\\{name}"),
+    };
+
+    let (mutex, already_exists) = create_shared_mutex(&name, sid)?;
+
+    if already_exists {
+        let err = Error::already_running(None);
+        if let Err(cleanup) = close_handle(mutex) {
+            return Err(Error::combine(vec![err, cleanup]));
+        }
+        return Err(err);
+    }
+
+    Ok(mutex)
+}
+
+/// Ties the lifetime of the instance mutex to the launched process
+///
+/// Returns the mutex if it could not be handed over, to be held by the caller instead.
+pub(crate) fn hand_over_instance(mutex: HANDLE, process_handle: HANDLE) -> Option<HANDLE> {
+    if process_handle.is_invalid() {
+        return Some(mutex);
+    }
+    // The mutex disappears once the process exits and closes its copy
+    match copy_handle_to_process(mutex, process_handle) {
+        Ok(()) => {
+            let _ = close_handle(mutex);
+            None
+        }
+        Err(_) => Some(mutex),
+    }
+}
+
+/// Removes the instance mutex after a failed launch, as nothing runs that it could mark
+pub(crate) fn release_instance(err: Error, mutex: Option<HANDLE>) -> Error {
+    let Some(mutex) = mutex else {
+        return err;
+    };
+    match close_handle(mutex) {
+        Ok(()) => err,
+        Err(cleanup) => Error::combine(vec![err, cleanup]),
+    }
+}
@@ -0,0 +1,155 @@
+use crate::child::Child;
+use crate::error::{Error, Stage};
+use crate::safe_windows_bindings::high_level::{
+    assign_process_to_job, close_handle, create_kill_on_close_job, job_active_processes,
+};
+use crate::safe_windows_bindings::types::HANDLE;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// What the processes of a [`Job`] are allowed to use
+///
+/// ```no_run
+/// # fn main() -> Result<(), win_run::Error> {
+/// use std::time::Duration;
+/// use win_run::{win, Job, JobLimits};
+///
+/// let sandbox = Job::with_limits(
+///     JobLimits::new()
+///         .memory(512 * 1024 * 1024)
+///         .active_processes(4)
+///         .process_time(Duration::from_secs(60)),
+/// )?;
+///
+/// win("/path/to/converter").job(&sandbox).run()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct JobLimits {
+    /// The memory all processes in the job may commit together, in bytes
+    pub(crate) memory: Option<usize>,
+    /// The memory each process in the job may commit, in bytes
+    pub(crate) process_memory: Option<usize>,
+    /// How many processes may run in the job at once
+    pub(crate) active_processes: Option<u32>,
+    /// How much user mode CPU time each process in the job may use
+    pub(crate) process_time: Option<Duration>,
+}
+
+impl JobLimits {
+    /// Limits nothing, processes are only terminated once the job is closed
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the memory all processes in the job may commit together, in bytes, default is **no limit**
+    ///
+    /// Allocations that would exceed it fail.
+    pub fn memory(mut self, bytes: usize) -> Self {
+        self.memory = Some(bytes);
+        self
+    }
+
+    /// Sets the memory each process in the job may commit, in bytes, default is **no limit**
+    ///
+    /// Allocations that would exceed it fail.
+    pub fn process_memory(mut self, bytes: usize) -> Self {
+        self.process_memory = Some(bytes);
+        self
+    }
+
+    /// Sets how many processes may run in the job at once, default is **no limit**
+    ///
+    /// Processes that would exceed it are terminated as they start, and launches put in the
+    /// job fail.
+    pub fn active_processes(mut self, active_processes: u32) -> Self {
+        self.active_processes = Some(active_processes);
+        self
+    }
+
+    /// Sets how much user mode CPU time each process in the job may use, default is **no limit**
+    ///
+    /// Processes that use it up are terminated.
+    pub fn process_time(mut self, process_time: Duration) -> Self {
+        self.process_time = Some(process_time);
+        self
+    }
+}
+
+/// Closes the job once the last clone of it is dropped
+#[derive(Debug)]
+struct JobHandle(HANDLE);
+
+impl Drop for JobHandle {
+    fn drop(&mut self) {
+        // Nothing sensible can be done if closing fails here
+        let _ = close_handle(self.0);
+    }
+}
+
+/// A job object the processes of launches are put in, terminating them once it is closed
+///
+/// Give the job to builders through [`ProcessBuilder::job`](crate::ProcessBuilder::job).
+/// Clones share the job, which is closed once the last of them is dropped, or by the system
+/// once the service exits, crashes included. Everything in the job is terminated then, along
+/// with whatever those processes started in turn.
+///
+/// ```no_run
+/// # fn main() -> Result<(), win_run::Error> {
+/// use win_run::{win, Job};
+///
+/// let helpers = Job::new()?;
+///
+/// win("/path/to/indexer").job(&helpers).spawn()?;
+/// win("/path/to/uploader").job(&helpers).spawn()?;
+///
+/// // On SERVICE_CONTROL_STOP, or whenever the service goes away
+/// drop(helpers);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Job {
+    handle: Arc<JobHandle>,
+}
+
+impl Job {
+    /// Creates a job that terminates its processes once it is closed
+    pub fn new() -> Result<Self, Error> {
+        Self::with_limits(JobLimits::new())
+    }
+
+    /// Creates a job that terminates its processes once it is closed and limits what they use
+    pub fn with_limits(limits: JobLimits) -> Result<Self, Error> {
+        let handle = create_kill_on_close_job(&limits)?;
+        Ok(Self {
+            handle: Arc::new(JobHandle(handle)),
+        })
+    }
+
+    /// Puts a running process in the job
+    ///
+    /// What the process started before that stays outside of the job, launch with
+    /// [`ProcessBuilder::job`](crate::ProcessBuilder::job) to have it in the job from the start.
+    pub fn assign(&self, child: &Child) -> Result<(), Error> {
+        self.assign_handle(child.handle)
+    }
+
+    /// Counts the processes in the job that are still running
+    pub fn active_processes(&self) -> Result<u32, Error> {
+        job_active_processes(self.handle.0)
+    }
+
+    /// Puts the process of a handle in the job
+    pub(crate) fn assign_handle(&self, handle: HANDLE) -> Result<(), Error> {
+        // Backends may not provide a handle
+        if handle.is_invalid() {
+            return Err(Error::other(
+                Stage::ProcessSetup,
+                "The backend provided no handle to put the process in a job",
+            ));
+        }
+        assign_process_to_job(self.handle.0, handle)
+    }
+}
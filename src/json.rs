@@ -0,0 +1,423 @@
+use std::fmt::{Display, Formatter, Write};
+
+/// How deeply arrays and objects may nest, so a hostile message cannot exhaust the stack
+const MAX_DEPTH: usize = 32;
+
+/// A JSON value, just enough of it for the launch server's protocol
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// Members in the order they appeared
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Parses a complete JSON text, returning why it is malformed if it is
+    pub(crate) fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser {
+            bytes: text.as_bytes(),
+            position: 0,
+        };
+        let value = parser.value(0)?;
+        parser.skip_whitespace();
+        if parser.position != parser.bytes.len() {
+            return Err(format!("Unexpected data at offset {}", parser.position));
+        }
+        Ok(value)
+    }
+
+    /// Creates an object from its members
+    pub(crate) fn object<const N: usize>(members: [(&str, Json); N]) -> Json {
+        Json::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// The member of an object, if this is an object having it
+    pub(crate) fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members
+                .iter()
+                .find(|(member, _)| member == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// The value as a `u32`, if it is a whole number in range
+    pub(crate) fn as_u32(&self) -> Option<u32> {
+        match self {
+            Json::Number(number)
+                if number.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(number) =>
+            {
+                Some(*number as u32)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for Json {
+    fn from(text: &str) -> Self {
+        Json::String(text.to_string())
+    }
+}
+
+impl From<u32> for Json {
+    fn from(number: u32) -> Self {
+        Json::Number(number as f64)
+    }
+}
+
+impl Display for Json {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(value) => write!(f, "{value}"),
+            Json::Number(number) if number.is_finite() => write!(f, "{number}"),
+            Json::Number(_) => f.write_str("null"),
+            Json::String(text) => write_string(f, text),
+            Json::Array(values) => {
+                f.write_char('[')?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                f.write_char(']')
+            }
+            Json::Object(members) => {
+                f.write_char('{')?;
+                for (index, (key, value)) in members.iter().enumerate() {
+                    if index > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+/// Writes a string literal, escaping what JSON requires
+fn write_string(f: &mut Formatter<'_>, text: &str) -> std::fmt::Result {
+    f.write_char('"')?;
+    for character in text.chars() {
+        match character {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            character if (character as u32) < 0x20 => write!(f, "\\u{:04x}", character as u32)?,
+            character => f.write_char(character)?,
+        }
+    }
+    f.write_char('"')
+}
+
+/// Reads a JSON value from the bytes of a text
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+
+    /// Consumes the expected byte, failing if another one comes
+    fn expect(&mut self, expected: u8) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.peek() != Some(expected) {
+            return Err(format!(
+                "Expected '{}' at offset {}",
+                expected as char, self.position
+            ));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    /// Consumes a keyword, e.g. `true`
+    fn keyword(&mut self, keyword: &str, value: Json) -> Result<Json, String> {
+        if !self.bytes[self.position..].starts_with(keyword.as_bytes()) {
+            return Err(format!("Unexpected data at offset {}", self.position));
+        }
+        self.position += keyword.len();
+        Ok(value)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, String> {
+        if depth > MAX_DEPTH {
+            return Err(format!("Values cannot nest deeper than {MAX_DEPTH} levels"));
+        }
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'n') => self.keyword("null", Json::Null),
+            Some(b't') => self.keyword("true", Json::Bool(true)),
+            Some(b'f') => self.keyword("false", Json::Bool(false)),
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b'[') => self.array(depth),
+            Some(b'{') => self.object(depth),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(format!("Unexpected data at offset {}", self.position)),
+            None => Err("Unexpected end of data".to_string()),
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Json, String> {
+        self.expect(b'[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(Json::Array(values));
+        }
+        loop {
+            values.push(self.value(depth + 1)?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(Json::Array(values));
+                }
+                _ => return Err(format!("Expected ',' or ']' at offset {}", self.position)),
+            }
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Json, String> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(b':')?;
+            members.push((key, self.value(depth + 1)?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(Json::Object(members));
+                }
+                _ => return Err(format!("Expected ',' or '}}' at offset {}", self.position)),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        while matches!(
+            self.peek(),
+            Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')
+        ) {
+            self.position += 1;
+        }
+        // The bytes are all ASCII, so they are valid UTF-8
+        let text = std::str::from_utf8(&self.bytes[start..self.position]).unwrap_or_default();
+        text.parse()
+            .map(Json::Number)
+            .map_err(|_| format!("Invalid number at offset {start}"))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut text = String::new();
+        loop {
+            let start = self.position;
+            while !matches!(self.peek(), Some(b'"' | b'\\') | None) {
+                self.position += 1;
+            }
+            // The text came from a `&str` and the run stops at ASCII bytes, so it is valid UTF-8
+            text.push_str(
+                std::str::from_utf8(&self.bytes[start..self.position]).unwrap_or_default(),
+            );
+
+            match self.peek() {
+                Some(b'"') => {
+                    self.position += 1;
+                    return Ok(text);
+                }
+                Some(b'\\') => {
+                    self.position += 1;
+                    text.push(self.escape()?);
+                }
+                _ => return Err("Unterminated string".to_string()),
+            }
+        }
+    }
+
+    /// Reads what follows a backslash in a string
+    fn escape(&mut self) -> Result<char, String> {
+        let escaped = self.peek().ok_or("Unterminated string")?;
+        self.position += 1;
+        match escaped {
+            b'"' => Ok('"'),
+            b'\\' => Ok('\\'),
+            b'/' => Ok('/'),
+            b'b' => Ok('\u{8}'),
+            b'f' => Ok('\u{c}'),
+            b'n' => Ok('\n'),
+            b'r' => Ok('\r'),
+            b't' => Ok('\t'),
+            b'u' => {
+                let unit = self.hex_unit()?;
+                if !(0xD800..0xDC00).contains(&unit) {
+                    return char::from_u32(unit as u32).ok_or("Invalid unicode escape".to_string());
+                }
+                // A high surrogate has to be followed by the escape of a low one
+                if !self.bytes[self.position..].starts_with(b"\\u") {
+                    return Err("Unpaired surrogate in unicode escape".to_string());
+                }
+                self.position += 2;
+                let low = self.hex_unit()?;
+                char::decode_utf16([unit, low])
+                    .next()
+                    .and_then(Result::ok)
+                    .ok_or("Invalid surrogate pair in unicode escape".to_string())
+            }
+            _ => Err(format!("Invalid escape at offset {}", self.position - 1)),
+        }
+    }
+
+    /// Reads the four hex digits of a unicode escape
+    fn hex_unit(&mut self) -> Result<u16, String> {
+        let digits = self
+            .bytes
+            .get(self.position..self.position + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u16::from_str_radix(digits, 16).ok())
+            .ok_or(format!(
+                "Invalid unicode escape at offset {}",
+                self.position
+            ))?;
+        self.position += 4;
+        Ok(digits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nested(depth: usize) -> String {
+        format!("{}{}", "[".repeat(depth), "]".repeat(depth))
+    }
+
+    #[test]
+    fn limits_nesting_depth() {
+        assert!(Json::parse(&nested(MAX_DEPTH + 1)).is_ok());
+        assert!(Json::parse(&nested(MAX_DEPTH + 2)).is_err());
+
+        let objects = format!(
+            "{}1{}",
+            "{\"a\":".repeat(MAX_DEPTH + 1),
+            "}".repeat(MAX_DEPTH + 1)
+        );
+        assert!(Json::parse(&objects).is_err());
+    }
+
+    #[test]
+    fn decodes_escapes() {
+        let parsed = Json::parse(r#""\"\\\/\b\f\n\r\tAé""#).unwrap();
+        assert_eq!(parsed, Json::String("\"\\/\u{8}\u{c}\n\r\tAé".to_string()));
+
+        assert!(Json::parse(r#""\x""#).is_err());
+        assert!(Json::parse(r#""\u12""#).is_err());
+        assert!(Json::parse(r#""\u12G4""#).is_err());
+        assert!(Json::parse(r#""unterminated\""#).is_err());
+    }
+
+    #[test]
+    fn decodes_surrogate_pairs() {
+        let parsed = Json::parse(r#""\ud83d\ude80""#).unwrap();
+        assert_eq!(parsed, Json::String("\u{1F680}".to_string()));
+
+        // Unpaired, reversed and mismatched surrogates
+        for text in [
+            r#""\ud83d""#,
+            r#""\ud83dx""#,
+            r#""\ude80""#,
+            r#""\ude80\ud83d""#,
+            r#""\ud83dA""#,
+            r#""\ud83d\u0041""#,
+        ] {
+            assert!(Json::parse(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn keeps_unescaped_unicode() {
+        let parsed = Json::parse("\"Grüße 🚀\"").unwrap();
+        assert_eq!(parsed, Json::String("Grüße 🚀".to_string()));
+    }
+
+    #[test]
+    fn rejects_trailing_data() {
+        assert!(Json::parse(" {\"a\": 1} \n").is_ok());
+        for text in ["{} {}", "[1] x", "null,", "truex", "1 2", "\"a\"\"b\""] {
+            assert!(Json::parse(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_values() {
+        for text in ["", "[", "[1,", "{\"a\"", "{\"a\":", "{\"a\":1,}", "nul"] {
+            assert!(Json::parse(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn round_trips_through_display() {
+        let value = Json::object([
+            ("text", "line\n\"quoted\"\u{1}".into()),
+            ("number", 42.into()),
+            (
+                "list",
+                Json::Array(vec![Json::Null, Json::Bool(true), Json::Number(-1.5)]),
+            ),
+        ]);
+        assert_eq!(Json::parse(&value.to_string()).unwrap(), value);
+    }
+
+    #[test]
+    fn reads_whole_numbers_as_u32() {
+        assert_eq!(Json::parse("4242").unwrap().as_u32(), Some(4242));
+        assert_eq!(Json::parse("4294967295").unwrap().as_u32(), Some(u32::MAX));
+        for text in ["-1", "1.5", "4294967296", "\"1\""] {
+            assert_eq!(Json::parse(text).unwrap().as_u32(), None, "{text}");
+        }
+    }
+}
@@ -11,51 +11,425 @@ To use this crate, add `win_run` as a dependency to your project's `Cargo.toml`:
 win_run = "0.2.1"
 ```
 
+# Features
+The core of the crate, acquiring a token for an elevation and creating a process with it,
+is always available. Everything else can be turned off with `default-features = false`,
+as long as one version of the `windows` crate stays selected:
+* `windows-0-48` *(default)* - Builds against `windows` 0.48.
+* `windows-0-52` - Builds against `windows` 0.52, e.g. to share it with other dependencies.
+  Turn off default features to use it, as `windows-0-48` takes precedence.
+* `sysinfo` *(default)* - Enumerates processes through `sysinfo`. Without it the crate
+  uses a Toolhelp snapshot and depends on nothing but `windows` and `widestring`.
+* `window` *(default)* - `find_process_by_window` and `Child::main_window`.
+* `diagnostics` *(default)* - `preflight`, `doctor` and the session types they report.
+* `server` - `LaunchServer`, a named pipe through which unprivileged processes ask the
+  service for launches, the `LaunchPolicy` limiting them, and `request_launch` to ask it.
+* `json-rpc` - Lets clients in any language talk JSON-RPC 2.0 to the `LaunchServer`
+  to launch, query and kill processes. Enables `server`.
+* `durable` - `DurableQueue`, keeping pending and scheduled launches in a file so they
+  survive restarts of the service.
+* `test-util` - Provides `MockBackend` for unit testing launch orchestration without Win32.
+
 # Example: Start a process as Admin on the Default desktop:
-```
+```no_run
+# fn main() -> Result<(), win_run::Error> {
 use win_run::{Desktop, Elevation, win};
 
 win("/path/to/executable")
-    .args("-flag")
+    .arg("-flag")
     .directory("/parent/directory")
     .desktop(Desktop::Default)
     .elevation(Elevation::Admin)
     .run()?;
+# Ok(())
+# }
 ```
 
 # Example: Start a process as Local System on the Secure desktop:
-```
+```no_run
+# fn main() -> Result<(), win_run::Error> {
 use win_run::{Desktop, Elevation, win};
 
 win("/path/to/executable")
-    .args("-flag")
+    .arg("-flag")
     .directory("/parent/directory")
     .desktop(Desktop::Secure)
     .elevation(Elevation::LocalSystem)
     .run()?;
+# Ok(())
+# }
+```
+
+# Example: Start a process as Local System logging to a file:
+```no_run
+# fn main() -> Result<(), win_run::Error> {
+use win_run::{Elevation, Stdio, win};
+
+win("/path/to/executable")
+    .elevation(Elevation::LocalSystem)
+    .stdout(Stdio::Append("C:\\ProgramData\\worker.log".to_string()))
+    .stderr(Stdio::Append("C:\\ProgramData\\worker.log".to_string()))
+    .run()?;
+# Ok(())
+# }
+```
+
+# Example: Start a process as a local account logged on for batch work:
+```no_run
+# fn main() -> Result<(), win_run::Error> {
+use win_run::{Credentials, Elevation, LogonType, win};
+
+let credentials = Credentials::new(".", "worker", "password").logon_type(LogonType::Batch);
+
+win("/path/to/executable")
+    .arg("-flag")
+    .elevation(Elevation::Credentials(credentials))
+    .run()?;
+# Ok(())
+# }
 ```
  */
 
+#[cfg(not(any(feature = "windows-0-48", feature = "windows-0-52")))]
+compile_error!("Enable one of the `windows-0-48` and `windows-0-52` features");
+
+#[cfg(all(feature = "windows-0-52", not(feature = "windows-0-48")))]
+extern crate windows_0_52 as windows;
+
+mod arguments;
+#[cfg(feature = "tokio")]
+mod asynchronous;
+mod backend;
+mod cancellation;
+mod capture;
+mod child;
+mod correlation;
+mod credentials;
+#[cfg(feature = "diagnostics")]
+mod doctor;
+#[cfg(feature = "durable")]
+mod durable;
+mod encoding;
+mod environment;
+mod error;
+mod events;
+mod identity;
+mod instance;
+mod job;
+#[cfg(feature = "json-rpc")]
+mod json;
+#[cfg(feature = "test-util")]
+mod mock;
+mod per_session;
+#[cfg(feature = "server")]
+mod policy;
+mod pool;
+#[cfg(feature = "diagnostics")]
+mod preflight;
+mod privileges;
+mod processes;
+mod profile;
+mod queue;
+#[cfg(any(feature = "server", feature = "durable"))]
+mod record;
+mod rotation;
+#[cfg(feature = "json-rpc")]
+mod rpc;
 mod safe_windows_bindings;
+mod schedule;
+mod sequence;
+#[cfg(feature = "server")]
+mod server;
+mod session_control;
+#[cfg(feature = "diagnostics")]
+mod sessions;
+mod stdio;
+mod throttle;
+mod token;
+mod validation;
+#[cfg(feature = "window")]
+mod window;
+
+#[cfg(feature = "tokio")]
+pub use crate::asynchronous::AsyncPipeReader;
+pub use crate::backend::{Backend, LaunchBackend, LaunchRequest, Launched};
+pub use crate::cancellation::CancellationToken;
+pub use crate::child::{Child, Output, PrimaryThread};
+pub use crate::correlation::CORRELATION_ID_VARIABLE;
+pub use crate::credentials::{Credentials, LogonFlags, LogonType};
+#[cfg(feature = "diagnostics")]
+pub use crate::doctor::{doctor, Diagnostics, UacPolicy};
+#[cfg(feature = "durable")]
+pub use crate::durable::{DurableLaunch, DurableQueue};
+pub use crate::encoding::Encoding;
+pub use crate::error::{Error, Stage};
+pub use crate::events::{LaunchEvent, OutputStream};
+pub use crate::identity::{
+    account_to_sid, is_user_admin, process_identity, sid_to_account, AdminToken, IntegrityLevel,
+    ProcessIdentity, TokenOrigin,
+};
+pub use crate::instance::InstanceScope;
+pub use crate::job::{Job, JobLimits};
+#[cfg(feature = "test-util")]
+pub use crate::mock::{MockBackend, RecordedLaunch};
+pub use crate::per_session::{ManagedChild, PerSessionLauncher};
+#[cfg(feature = "server")]
+pub use crate::policy::LaunchPolicy;
+pub use crate::pool::TokenPool;
+#[cfg(feature = "diagnostics")]
+pub use crate::preflight::{preflight, Check, Report, Requirement, SessionPolicy};
+pub use crate::processes::{get_all_process_pids, kill_in_session, MatchStrategy, ProcessInfo};
+pub use crate::queue::LaunchQueue;
+pub use crate::rotation::RotatingLog;
+pub use crate::safe_windows_bindings::types::HANDLE;
+pub use crate::schedule::{Recurrence, RecurringLaunch, ScheduledLaunch};
+pub use crate::sequence::{OnFailure, Sequence};
+#[cfg(feature = "server")]
+pub use crate::server::{request_launch, LaunchMessage, LaunchServer};
+pub use crate::session_control::{
+    disconnect_session, is_session_locked, logoff_session, send_message, session_idle_time,
+    MessageButtons, MessageResponse,
+};
+#[cfg(feature = "diagnostics")]
+pub use crate::sessions::{SessionInfo, SessionState};
+pub use crate::stdio::{ChildStderr, ChildStdin, ChildStdout, LineCallback, SharedWriter, Stdio};
+pub use crate::throttle::{Overflow, Throttle};
+pub use crate::token::Token;
+pub use crate::validation::{Field, Problem};
+#[cfg(feature = "window")]
+pub use crate::window::{find_process_by_window, WindowInfo};
 
+use crate::arguments::{append_argument, command_line};
+use crate::cancellation::cancelled;
+use crate::correlation::{new_correlation_id, with_correlation_id};
+use crate::environment::{expand_variables, EnvironmentChanges};
+use crate::events::EventSink;
+use crate::identity::verify_identity;
+use crate::instance::{claim_instance, hand_over_instance, release_instance};
+use crate::processes::{find_running_instance, select_process_pid, shut_down_process};
+use crate::profile::{AccountProfile, LoadedProfile, TemporaryProfile};
 use crate::safe_windows_bindings::high_level::{
-    add_admin_privileges_to_token, create_process_with_token, get_current_user_token,
-    get_process_pid, get_process_token,
+    add_admin_privileges_to_token, assign_process_to_new_job, close_handle,
+    execution_allowed_for_token, expand_environment_os_strings, get_active_session_id,
+    get_logon_user_token, get_process_token, get_session_user_token, get_sid_account,
+    get_token_is_linked_elevated, get_token_session_id, get_token_user_sid, get_user_environment,
+    grant_desktop_access_to_sid, kill_process, protect_process, resolve_system_path,
+    set_process_affinity, set_process_efficiency_mode, set_user_dword,
+};
+use crate::safe_windows_bindings::types::{
+    ABOVE_NORMAL_PRIORITY_CLASS, BELOW_NORMAL_PRIORITY_CLASS, HIGH_PRIORITY_CLASS,
+    IDLE_PRIORITY_CLASS, NORMAL_PRIORITY_CLASS, PROCESS_CREATION_FLAGS, REALTIME_PRIORITY_CLASS,
+    SHOW_WINDOW_CMD, SW_HIDE, SW_SHOWMAXIMIZED, SW_SHOWMINIMIZED, SW_SHOWMINNOACTIVE,
+    SW_SHOWNOACTIVATE, SW_SHOWNORMAL,
 };
+use crate::schedule::When;
+use crate::stdio::StdHandles;
+use crate::validation::{check_command_line_length, command_line_length, MAX_COMMAND_LINE_LENGTH};
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often a launch waiting for a logon tries again
+const LOGON_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
-#[derive(Default)]
+/// The well-known SID of the LocalService account, whose name is localized
+const LOCAL_SERVICE_SID: &str = "S-1-5-19";
+/// The well-known SID of the NetworkService account, whose name is localized
+const NETWORK_SERVICE_SID: &str = "S-1-5-20";
+
+#[derive(Debug, Clone, Default)]
 pub enum Elevation {
     #[default]
     User,
     Admin,
     LocalSystem,
+    /// The built-in LocalService account, with the rights of a user locally and anonymous on the network
+    LocalService,
+    /// The built-in NetworkService account, with the rights of a user locally and the machine
+    /// account's on the network
+    NetworkService,
+    /// An explicit account, logged on with its credentials
+    Credentials(Credentials),
+    /// The user of a running process by its pid, whose token is copied, e.g. of an agent running
+    /// in the session to launch into
+    FromPid(u32),
+    /// The user of a running process whose name contains this one, e.g. `explorer.exe`, picked
+    /// among several with the [`MatchStrategy`]
+    FromProcessName(String),
+}
+
+#[cfg(any(feature = "server", feature = "durable"))]
+/// The name an elevation is asked for and stored by, if it can be
+pub(crate) fn elevation_name(elevation: &Elevation) -> Option<&'static str> {
+    match elevation {
+        Elevation::User => Some("user"),
+        Elevation::Admin => Some("admin"),
+        Elevation::LocalSystem => Some("system"),
+        Elevation::LocalService
+        | Elevation::NetworkService
+        | Elevation::Credentials(_)
+        | Elevation::FromPid(_)
+        | Elevation::FromProcessName(_) => None,
+    }
+}
+
+#[cfg(any(feature = "server", feature = "durable"))]
+/// The elevation asked for by a name, if there is one by it
+pub(crate) fn parse_elevation(name: &str) -> Option<Elevation> {
+    match name {
+        "user" => Some(Elevation::User),
+        "admin" => Some(Elevation::Admin),
+        "system" => Some(Elevation::LocalSystem),
+        _ => None,
+    }
 }
 
-#[derive(Default)]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub enum Desktop {
     #[default]
     Default,
     Secure,
+    /// A desktop of any window station of the session, e.g. ones the caller created to keep
+    /// automated processes away from the interactive desktop
+    Custom {
+        window_station: String,
+        desktop: String,
+    },
+}
+
+impl Desktop {
+    /// Grants a user access to the window station and this desktop of the service's session
+    ///
+    /// The SID is given as a string, e.g. the one of [`Token::user_sid`]. Processes of users who
+    /// cannot open the window station and desktop they are launched onto fail to initialize.
+    /// Access granted before is kept, so granting it again changes nothing.
+    pub fn grant_access(&self, sid: &str) -> Result<(), Error> {
+        let (window_station, desktop) = self.names();
+        grant_desktop_access_to_sid(sid, window_station, desktop)
+    }
+
+    /// Creates a desktop of a window station other than the default ones
+    pub fn custom(window_station: impl AsRef<str>, desktop: impl AsRef<str>) -> Self {
+        Desktop::Custom {
+            window_station: window_station.as_ref().to_string(),
+            desktop: desktop.as_ref().to_string(),
+        }
+    }
+
+    /// The names of the window station and of the desktop within it
+    fn names(&self) -> (&str, &str) {
+        match self {
+            Desktop::Default => ("WinSta0", "Default"),
+            Desktop::Secure => ("WinSta0", "Winlogon"),
+            Desktop::Custom {
+                window_station,
+                desktop,
+            } => (window_station, desktop),
+        }
+    }
+
+    /// The desktop in `WindowStation\Desktop` form, empty for the default one
+    fn startup_name(&self) -> String {
+        match self {
+            Desktop::Default => String::new(),
+            _ => {
+                let (window_station, desktop) = self.names();
+                format!("{window_station}\\{desktop}")
+            }
+        }
+    }
+
+    /// Describes what is wrong with the names of a custom desktop, if anything
+    fn problem(&self) -> Option<String> {
+        let Desktop::Custom {
+            window_station,
+            desktop,
+        } = self
+        else {
+            return None;
+        };
+        for (kind, name) in [("window station", window_station), ("desktop", desktop)] {
+            if name.is_empty() {
+                return Some(format!("The {kind} name is empty"));
+            }
+            if name.contains(['\\', '\0']) {
+                return Some(format!(
+                    "The {kind} name {name:?} contains a backslash or null character"
+                ));
+            }
+        }
+        None
+    }
+}
+
+/// How the first window of a process is shown
+///
+/// Programs that pass their own show state to their first window ignore it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShowWindow {
+    /// Shown and activated at its usual size and position
+    #[default]
+    Normal,
+    /// Not shown at all, e.g. for programs that should only run in the tray
+    Hidden,
+    /// Shown minimized and activated
+    Minimized,
+    /// Shown maximized and activated
+    Maximized,
+    /// Shown minimized, leaving the active window as it is
+    MinimizedNoActivate,
+    /// Shown at its usual size and position, leaving the active window as it is
+    NoActivate,
+}
+
+impl ShowWindow {
+    /// Maps the show state to its native value
+    pub(crate) fn to_native(self) -> SHOW_WINDOW_CMD {
+        match self {
+            ShowWindow::Normal => SW_SHOWNORMAL,
+            ShowWindow::Hidden => SW_HIDE,
+            ShowWindow::Minimized => SW_SHOWMINIMIZED,
+            ShowWindow::Maximized => SW_SHOWMAXIMIZED,
+            ShowWindow::MinimizedNoActivate => SW_SHOWMINNOACTIVE,
+            ShowWindow::NoActivate => SW_SHOWNOACTIVATE,
+        }
+    }
+}
+
+/// The priority class the threads of a process are scheduled with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PriorityClass {
+    /// Only runs when the system is idle, e.g. for indexing or cleanup
+    Idle,
+    /// Yields to normal processes, e.g. for maintenance tasks in user sessions
+    BelowNormal,
+    /// The priority class of most processes
+    #[default]
+    Normal,
+    /// Preferred over normal processes
+    AboveNormal,
+    /// For time critical work, starves normal processes while it runs
+    High,
+    /// Preempts even the system, needs SeIncreaseBasePriorityPrivilege and is lowered to
+    /// [`PriorityClass::High`] without it
+    Realtime,
+}
+
+impl PriorityClass {
+    /// Maps the priority class to its creation flag
+    pub(crate) fn to_native(self) -> PROCESS_CREATION_FLAGS {
+        match self {
+            PriorityClass::Idle => IDLE_PRIORITY_CLASS,
+            PriorityClass::BelowNormal => BELOW_NORMAL_PRIORITY_CLASS,
+            PriorityClass::Normal => NORMAL_PRIORITY_CLASS,
+            PriorityClass::AboveNormal => ABOVE_NORMAL_PRIORITY_CLASS,
+            PriorityClass::High => HIGH_PRIORITY_CLASS,
+            PriorityClass::Realtime => REALTIME_PRIORITY_CLASS,
+        }
+    }
 }
 
 /// Creates a process builder with default settings
@@ -63,38 +437,175 @@ pub enum Desktop {
 /// # Arguments
 ///
 /// * `path` - Path to an executable.
-pub fn win(path: impl AsRef<str>) -> ProcessBuilder {
+pub fn win(path: impl AsRef<OsStr>) -> ProcessBuilder {
     ProcessBuilder::new(path)
 }
 
 /// Utility process builder
+///
+/// Clones share the backend, token pool and throttle of the original
+#[derive(Clone)]
 pub struct ProcessBuilder {
     /// The path to the executable
-    pub(crate) path: String,
-    /// Arguments with which to execute.
-    pub(crate) args: String,
+    pub(crate) path: OsString,
+    /// Arguments with which to execute, quoted and joined into the tail of the command line
+    pub(crate) args: OsString,
     /// Directory from which to run the executable
-    pub(crate) directory: String,
+    pub(crate) directory: OsString,
     /// Desktop on which the executable should run
     pub(crate) desktop: Desktop,
     /// User as which to run the executable
     pub(crate) elevation: Elevation,
+    /// The session to run the executable in, if not the active console session
+    pub(crate) session: Option<u32>,
+    /// How to pick the process to take a token from when several match
+    pub(crate) match_strategy: MatchStrategy,
+    /// Strategy used to create the process
+    pub(crate) backend: Arc<dyn LaunchBackend>,
+    /// Whether to expand `%VAR%` references in the path, arguments and directory
+    pub(crate) expand_env: bool,
+    /// Whether to check the identity of the process right after launching it
+    pub(crate) verify_identity: bool,
+    /// Pool to share the launch token through, if any
+    pub(crate) token_pool: Option<TokenPool>,
+    /// Limits shared with other launches, if any
+    pub(crate) throttle: Option<Throttle>,
+    /// Whether to skip the launch if the executable already runs for the target user and session
+    pub(crate) unless_running: bool,
+    /// How long a running instance gets to close before it is terminated, if it is to be replaced
+    pub(crate) replace_existing: Option<Duration>,
+    /// The name and scope of the object marking a running instance, if only one may run
+    pub(crate) single_instance: Option<(String, InstanceScope)>,
+    /// What the standard input of the executable reads from
+    pub(crate) stdin: Stdio,
+    /// What the standard output of the executable writes to
+    pub(crate) stdout: Stdio,
+    /// What the standard error of the executable writes to
+    pub(crate) stderr: Stdio,
+    /// The encoding output passed to line callbacks is decoded from
+    pub(crate) output_encoding: Encoding,
+    /// The code page of the console of the executable, if it is forced
+    pub(crate) console_code_page: Option<u32>,
+    /// The title of the console window of the process, the path of the executable if missing
+    pub(crate) window_title: Option<String>,
+    /// The token calling off the launch and terminating the process, if there is one
+    pub(crate) cancellation: Option<CancellationToken>,
+    /// How long the process may run before it is terminated, if it is limited
+    pub(crate) timeout: Option<Duration>,
+    /// Where the events of the launches are reported, if anywhere
+    pub(crate) events: Option<EventSink>,
+    /// Where the first window of the executable opens, in virtual screen coordinates, if it is forced
+    pub(crate) window_position: Option<(i32, i32)>,
+    /// The width and height of the first window of the executable, if they are forced
+    pub(crate) window_size: Option<(u32, u32)>,
+    /// How the first window of the process is shown, the program's choice if missing
+    pub(crate) show_window: Option<ShowWindow>,
+    /// Whether a console process runs without a console window
+    pub(crate) no_window: bool,
+    /// Whether a console process gets a new console window
+    pub(crate) new_console: bool,
+    /// Whether the process waits to be resumed before running
+    pub(crate) create_suspended: bool,
+    /// The raw creation flags added to those the builder sets
+    pub(crate) creation_flags: u32,
+    /// The priority class of the process, the one Windows picks if missing
+    pub(crate) priority: Option<PriorityClass>,
+    /// The processors the process may run on, any if missing
+    pub(crate) affinity: Option<u64>,
+    /// Whether the process runs in efficiency mode
+    pub(crate) efficiency_mode: bool,
+    /// Whether users who are not administrators are kept from terminating the process
+    pub(crate) protect_from_termination: bool,
+    /// The drive letters, e.g. `Z:`, mapped to shares for the launch token before launching
+    pub(crate) drive_mappings: Vec<(String, String)>,
+    /// Whether the process gets a throwaway profile instead of the user's real one
+    pub(crate) temporary_profile: bool,
+    /// Whether the process searches System32 first and loads no DLLs from shares or low integrity locations
+    pub(crate) harden_dll_search: bool,
+    /// Whether the child keeps a handle to the primary thread of the process
+    pub(crate) keep_primary_thread: bool,
+    /// Whether System32 paths resolve to the native system directory for a 32-bit service
+    pub(crate) disable_redirection: bool,
+    /// Whether the user of the launch token is granted access to the window station and desktop
+    pub(crate) grant_desktop_access: bool,
+    /// Whether the process and everything it starts run in a job the child waits on
+    pub(crate) track_descendants: bool,
+    /// The job of the caller the process and everything it starts run in, if there is one
+    pub(crate) job: Option<Job>,
+    /// Whether the profile of an account launched with credentials is created if it never logged on
+    pub(crate) create_profile: bool,
+    /// Whether a profile created for a launch is deleted once the process exits
+    pub(crate) delete_profile_after_exit: bool,
+    /// Whether the profile of the user of the launch token is loaded for the process until it exits
+    pub(crate) load_user_profile: bool,
+    /// Whether the process starts with the environment of the user of the launch token instead of the service's
+    pub(crate) inherit_user_environment: bool,
+    /// The variables set on and removed from the environment of the process
+    pub(crate) environment: EnvironmentChanges,
+    /// Whether each launch gets a correlation id, injected into the environment of the process
+    pub(crate) correlate: bool,
+    /// The correlation id of the launches, a fresh one is generated for each launch if missing
+    pub(crate) correlation_id: Option<String>,
 }
 
 impl ProcessBuilder {
     /// Initialized a new process builder
-    pub fn new(path: impl AsRef<str>) -> Self {
-        let path = path.as_ref().to_string();
-        let args = "".to_owned();
+    pub fn new(path: impl AsRef<OsStr>) -> Self {
+        let path = path.as_ref().to_os_string();
+        let args = OsString::new();
         let directory = std::env::current_exe()
             .expect("Could not get path to the current executable")
             .parent()
             .expect("Could not get path to the directory of the current executable")
-            .to_str()
-            .expect("Could not get the current directory path as utf-8")
-            .to_string();
+            .as_os_str()
+            .to_os_string();
         let desktop = Desktop::default();
         let elevation = Elevation::default();
+        let session = None;
+        let match_strategy = MatchStrategy::default();
+        let backend: Arc<dyn LaunchBackend> = Arc::new(Backend::default());
+        let expand_env = false;
+        let verify_identity = false;
+        let token_pool = None;
+        let throttle = None;
+        let unless_running = false;
+        let replace_existing = None;
+        let single_instance = None;
+        let stdin = Stdio::default();
+        let stdout = Stdio::default();
+        let stderr = Stdio::default();
+        let output_encoding = Encoding::default();
+        let console_code_page = None;
+        let window_title = None;
+        let cancellation = None;
+        let timeout = None;
+        let events = None;
+        let window_position = None;
+        let window_size = None;
+        let show_window = None;
+        let no_window = false;
+        let new_console = false;
+        let create_suspended = false;
+        let creation_flags = 0;
+        let priority = None;
+        let affinity = None;
+        let efficiency_mode = false;
+        let protect_from_termination = false;
+        let drive_mappings = Vec::new();
+        let temporary_profile = false;
+        let harden_dll_search = false;
+        let keep_primary_thread = false;
+        let disable_redirection = false;
+        let grant_desktop_access = false;
+        let track_descendants = false;
+        let job = None;
+        let create_profile = false;
+        let delete_profile_after_exit = false;
+        let load_user_profile = false;
+        let inherit_user_environment = false;
+        let environment = EnvironmentChanges::default();
+        let correlate = false;
+        let correlation_id = None;
 
         Self {
             path,
@@ -102,18 +613,491 @@ impl ProcessBuilder {
             directory,
             desktop,
             elevation,
+            session,
+            match_strategy,
+            backend,
+            expand_env,
+            verify_identity,
+            token_pool,
+            throttle,
+            unless_running,
+            replace_existing,
+            single_instance,
+            stdin,
+            stdout,
+            stderr,
+            output_encoding,
+            console_code_page,
+            window_title,
+            cancellation,
+            timeout,
+            events,
+            window_position,
+            window_size,
+            show_window,
+            no_window,
+            new_console,
+            create_suspended,
+            creation_flags,
+            priority,
+            affinity,
+            efficiency_mode,
+            protect_from_termination,
+            drive_mappings,
+            temporary_profile,
+            harden_dll_search,
+            keep_primary_thread,
+            disable_redirection,
+            grant_desktop_access,
+            track_descendants,
+            job,
+            create_profile,
+            delete_profile_after_exit,
+            load_user_profile,
+            inherit_user_environment,
+            environment,
+            correlate,
+            correlation_id,
+        }
+    }
+
+    /// Adds an argument with which to run the executable, default is **no arguments**
+    ///
+    /// The argument is quoted as `CommandLineToArgvW` and the MSVC runtime expect, so paths with
+    /// spaces and embedded quotes reach the executable as they were given.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use win_run::win;
+    ///
+    /// // Runs `"C:\Tools\tool.exe" --input "C:\Program Files\App\data.txt"`
+    /// win("C:\\Tools\\tool.exe")
+    ///     .arg("--input")
+    ///     .arg("C:\\Program Files\\App\\data.txt")
+    ///     .run()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        append_argument(&mut self.args, arg.as_ref());
+        self
+    }
+
+    /// Adds arguments with which to run the executable, quoted like [`ProcessBuilder::arg`]
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        for arg in args {
+            append_argument(&mut self.args, arg.as_ref());
         }
+        self
     }
 
-    /// Sets the arguments with which to run the executable, default is **empty string**
-    pub fn args(mut self, args: impl AsRef<str>) -> Self {
-        self.args = args.as_ref().to_string();
+    /// Adds a fragment of the command line as it is, without quoting it
+    ///
+    /// For executables that split their command line differently from the MSVC runtime, most
+    /// notably `cmd.exe`, whose `/c` takes the rest of the line with quotes of its own.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use win_run::win;
+    ///
+    /// win("C:\\Windows\\System32\\cmd.exe")
+    ///     .raw_arg(r#"/c ""C:\Program Files\App\run.bat" --quiet""#)
+    ///     .run()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn raw_arg(mut self, raw_arg: impl AsRef<OsStr>) -> Self {
+        if !self.args.is_empty() {
+            self.args.push(" ");
+        }
+        self.args.push(raw_arg);
         self
     }
 
     /// Sets the directory from which to run the executable, default is **std::env::current_exe().parent()**
-    pub fn directory(mut self, directory: impl AsRef<str>) -> Self {
-        self.directory = directory.as_ref().to_string();
+    pub fn directory(mut self, directory: impl AsRef<OsStr>) -> Self {
+        self.directory = directory.as_ref().to_os_string();
+        self
+    }
+
+    /// Sets where the first window of the executable opens, default is **where Windows places it**
+    ///
+    /// The coordinates are those of the virtual screen of the target session, whose primary
+    /// monitor starts at 0, 0, so the origin of a monitor opens the window on it, e.g. to drive
+    /// the displays of a kiosk or digital signage setup. Only applies to programs that leave
+    /// the position of their first window to Windows, as most GUI programs and consoles do.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use win_run::win;
+    ///
+    /// // Fill the second of two 1920x1080 monitors placed side by side
+    /// win("/path/to/signage")
+    ///     .window_position(1920, 0)
+    ///     .window_size(1920, 1080)
+    ///     .run()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn window_position(mut self, x: i32, y: i32) -> Self {
+        self.window_position = Some((x, y));
+        self
+    }
+
+    /// Sets the width and height of the first window of the executable, default is **what Windows picks**
+    ///
+    /// Like the position, only applies to programs that leave the size of their first window to Windows.
+    pub fn window_size(mut self, width: u32, height: u32) -> Self {
+        self.window_size = Some((width, height));
+        self
+    }
+
+    /// Sets how the first window of the executable is shown, default is **what the program picks**
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use win_run::{win, ShowWindow};
+    ///
+    /// win("C:\\Tools\\sync.exe")
+    ///     .show_window(ShowWindow::MinimizedNoActivate)
+    ///     .spawn()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn show_window(mut self, show_window: ShowWindow) -> Self {
+        self.show_window = Some(show_window);
+        self
+    }
+
+    /// Sets whether a console process runs without a console window, default is **false**
+    ///
+    /// Console tools launched from a service otherwise flash a console window on the desktop of
+    /// the user. Has no effect on GUI programs, and is ignored along with
+    /// [`ProcessBuilder::new_console`].
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use win_run::win;
+    ///
+    /// win("C:\\Windows\\System32\\ipconfig.exe")
+    ///     .arg("/flushdns")
+    ///     .no_window(true)
+    ///     .run()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn no_window(mut self, no_window: bool) -> Self {
+        self.no_window = no_window;
+        self
+    }
+
+    /// Sets whether a console process gets a new console window, default is **false**
+    ///
+    /// Console processes of a service have no console to share, so this mostly matters for
+    /// [`LaunchBackend`]s of the caller that launch from a console.
+    pub fn new_console(mut self, new_console: bool) -> Self {
+        self.new_console = new_console;
+        self
+    }
+
+    /// Sets whether the process starts suspended, default is **false**
+    ///
+    /// The primary thread of the process waits to be resumed, leaving time to set the process up
+    /// before it runs, which [`Child::resume`] then lets it do. Only applies to the built-in
+    /// backends, which return the thread.
+    pub fn create_suspended(mut self, create_suspended: bool) -> Self {
+        self.create_suspended = create_suspended;
+        self
+    }
+
+    /// Adds raw [process creation flags](https://learn.microsoft.com/en-us/windows/win32/procthread/process-creation-flags), default is **none**
+    ///
+    /// The flags are added to those of earlier calls and to those the builder sets itself, so they
+    /// can only switch things on.
+    /// `CREATE_SUSPENDED` leaves the process suspended without a way to resume it, use
+    /// [`ProcessBuilder::create_suspended`] instead.
+    pub fn creation_flags(mut self, creation_flags: u32) -> Self {
+        self.creation_flags |= creation_flags;
+        self
+    }
+
+    /// Sets the priority class of the process, default is **normal, or the service's if that is lower**
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use win_run::{win, PriorityClass};
+    ///
+    /// win("C:\\Tools\\defrag-cache.exe")
+    ///     .priority(PriorityClass::Idle)
+    ///     .run()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn priority(mut self, priority: PriorityClass) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// Sets the processors the process may run on, as a mask with a bit per processor, default is **any processor**
+    ///
+    /// The process is started suspended and restricted before it runs, so none of its threads
+    /// run elsewhere. Processes of [`LaunchBackend`]s of the caller are restricted once launched.
+    /// The mask has to name processors of the system, e.g. `0b11` for the first two.
+    pub fn affinity(mut self, affinity: u64) -> Self {
+        self.affinity = Some(affinity);
+        self
+    }
+
+    /// Sets whether the process runs in efficiency mode, also known as EcoQoS, default is **false**
+    ///
+    /// Windows then runs the process on the most power efficient cores at lower clock speeds,
+    /// which saves battery for background helpers that are not in a hurry. The mode is set right
+    /// after launching, and a process whose mode cannot be set is terminated, e.g. before
+    /// Windows 10 version 1709, which does not support it.
+    pub fn efficiency_mode(mut self, efficiency_mode: bool) -> Self {
+        self.efficiency_mode = efficiency_mode;
+        self
+    }
+
+    /// Sets whether users who are not administrators are kept from terminating the process, default is **false**
+    ///
+    /// Replaces the access rights of the process right after launching, so only the system and
+    /// elevated administrators keep full control, e.g. for a helper the user should not be able
+    /// to kill from the task manager. Everyone else can still see the process and wait for it.
+    /// A process that cannot be protected is terminated.
+    pub fn protect_from_termination(mut self, protect_from_termination: bool) -> Self {
+        self.protect_from_termination = protect_from_termination;
+        self
+    }
+
+    /// Maps a drive letter to a share for the user of the launch token before launching, default is **none**
+    ///
+    /// Drives mapped by the user are not visible to processes launched as Local System or as Admin,
+    /// whose tokens belong to other logon sessions, so tools launched that way often miss them.
+    /// The share is reached with the network credentials of the launch token, combine it with
+    /// [`LogonType::NewCredentials`] to reach it as another account. Letters already in use in the
+    /// logon session of the token are left as they are. Call it once per drive. Does not apply
+    /// to launches through CreateProcessWithLogonW.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use win_run::{win, Credentials, Elevation, LogonType};
+    ///
+    /// let credentials = Credentials::new("CORP", "backup", "password")
+    ///     .logon_type(LogonType::NewCredentials);
+    /// win("/path/to/backup")
+    ///     .elevation(Elevation::Credentials(credentials))
+    ///     .map_drive('Z', "\\\\fileserver\\backups")
+    ///     .run()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn map_drive(mut self, letter: char, share: impl AsRef<str>) -> Self {
+        let letter = format!("{}:", letter.to_ascii_uppercase());
+        self.drive_mappings
+            .push((letter, share.as_ref().to_string()));
+        self
+    }
+
+    /// Sets whether the process gets a throwaway profile instead of the user's real one, default is **false**
+    ///
+    /// The process is launched with USERPROFILE, APPDATA, LOCALAPPDATA, TEMP and TMP pointing into
+    /// a fresh directory in the temporary directory of the service, which only the system,
+    /// administrators and the target user can access. The directory is removed once the process
+    /// exits, or right away if the launch fails. Programs writing to the registry of the user
+    /// or to known folders they look up by other means still reach the real profile, so combine it
+    /// with [`LogonFlags::WithoutProfile`] for accounts whose profile should not be loaded at all.
+    /// Finding the target user takes an extra token acquisition, unless a token pool is set.
+    pub fn temporary_profile(mut self, temporary_profile: bool) -> Self {
+        self.temporary_profile = temporary_profile;
+        self
+    }
+
+    /// Sets whether the process is protected from DLL planting, default is **false**
+    ///
+    /// Launches the process with a mitigation policy that makes it search System32 before its own
+    /// directory, so copies of system DLLs planted next to an executable in a user-writable location
+    /// are not loaded, and that refuses DLLs on shares or written by low integrity processes.
+    /// The working directory is not searched for DLLs as long as safe DLL search mode is on, as it
+    /// is by default. DLLs the program ships itself are still loaded from its directory.
+    /// Only [`Backend::AsUser`] can apply the policy, other backends fail the launch.
+    pub fn harden_dll_search(mut self, harden_dll_search: bool) -> Self {
+        self.harden_dll_search = harden_dll_search;
+        self
+    }
+
+    /// Sets whether the child keeps the primary thread of the process, default is **false**
+    ///
+    /// The id of the thread and a handle to it are then available through [`Child::primary_thread`],
+    /// for suspending it, queuing APCs to it or attaching a debugger, instead of being closed right
+    /// after launching. Backends of the caller provide no thread.
+    pub fn keep_primary_thread(mut self, keep_primary_thread: bool) -> Self {
+        self.keep_primary_thread = keep_primary_thread;
+        self
+    }
+
+    /// Sets whether System32 paths escape file system redirection, default is **false**
+    ///
+    /// A 32-bit service on 64-bit Windows is redirected to SysWOW64 when it launches e.g.
+    /// `C:\Windows\System32\foo.exe`, running the 32-bit binary or failing if there is none.
+    /// Once set, such paths resolve to the native binary the caller meant, through the `Sysnative`
+    /// alias. Paths with `Sysnative` work for 64-bit services as well, resolving to System32.
+    pub fn disable_redirection(mut self, disable_redirection: bool) -> Self {
+        self.disable_redirection = disable_redirection;
+        self
+    }
+
+    /// Sets whether the profile of an account that never logged on is created, default is **false**
+    ///
+    /// Launches with [`Elevation::Credentials`] of accounts that never logged on to the machine
+    /// have no profile to point at, so programs land in the default or the service's one.
+    /// Once set, the profile is created before launching, and the process is pointed at it
+    /// through `USERPROFILE`, `APPDATA`, `LOCALAPPDATA`, `TEMP` and `TMP`.
+    /// Other elevations launch as users who are logged on and have their profiles.
+    pub fn create_profile(mut self, create_profile: bool) -> Self {
+        self.create_profile = create_profile;
+        self
+    }
+
+    /// Sets whether a profile created for the launch is deleted once the process exits, default is **false**
+    ///
+    /// Meant for temporary accounts, see [`ProcessBuilder::create_profile`]. Profiles that existed
+    /// before the launch are never deleted.
+    pub fn delete_profile_after_exit(mut self, delete_profile_after_exit: bool) -> Self {
+        self.delete_profile_after_exit = delete_profile_after_exit;
+        self
+    }
+
+    /// Sets whether the profile of the user is loaded for the process, default is **false**
+    ///
+    /// Processes of users who are not logged on, e.g. launched with [`Elevation::Credentials`],
+    /// otherwise find their registry hive unloaded, so `HKEY_CURRENT_USER` points at the default
+    /// user's and settings they write are lost. Once set, the profile is loaded before launching
+    /// and kept loaded by the [`Child`], which unloads it once the process exits after being dropped.
+    /// Loading the profile of a user who is logged on only keeps it loaded for longer.
+    pub fn load_user_profile(mut self, load_user_profile: bool) -> Self {
+        self.load_user_profile = load_user_profile;
+        self
+    }
+
+    /// Sets whether the process gets the environment of the user it runs as, default is **false**
+    ///
+    /// Processes otherwise inherit the environment of the service, so `USERPROFILE`, `APPDATA`,
+    /// `TEMP` and the user's additions to `PATH` point at LocalSystem's instead of the user's.
+    /// Once set, the environment is built from the launch token, as for a user logging on.
+    /// Variables set with [`ProcessBuilder::env`] and those of a profile apply on top of it.
+    pub fn inherit_user_environment(mut self, inherit_user_environment: bool) -> Self {
+        self.inherit_user_environment = inherit_user_environment;
+        self
+    }
+
+    /// Sets an environment variable of the process, default is **the environment of the service**
+    ///
+    /// The process otherwise inherits the environment of the service, which for LocalSystem
+    /// points at its own profile and temporary directory. Variables pointing at a
+    /// [temporary](ProcessBuilder::temporary_profile) or [created](ProcessBuilder::create_profile)
+    /// profile can be overridden as well.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use win_run::win;
+    ///
+    /// win("/path/to/agent")
+    ///     .env("AGENT_MODE", "tray")
+    ///     .env_remove("PYTHONPATH")
+    ///     .run()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn env(mut self, name: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Self {
+        self.environment.set(name.as_ref(), value.as_ref());
+        self
+    }
+
+    /// Sets several environment variables of the process, see [`ProcessBuilder::env`]
+    pub fn envs<I, K, V>(mut self, variables: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        for (name, value) in variables {
+            self.environment.set(name.as_ref(), value.as_ref());
+        }
+        self
+    }
+
+    /// Removes an environment variable the process would otherwise get
+    pub fn env_remove(mut self, name: impl AsRef<OsStr>) -> Self {
+        self.environment.remove(name.as_ref());
+        self
+    }
+
+    /// Starts the process without any environment variables but those set afterwards
+    ///
+    /// Programs generally need at least `SystemRoot` to start.
+    pub fn env_clear(mut self) -> Self {
+        self.environment.clear();
+        self
+    }
+
+    /// Sets whether each launch gets a correlation id, default is **false**
+    ///
+    /// A fresh GUID is generated for every launch and set as the
+    /// [`CORRELATION_ID_VARIABLE`] environment variable of the process, so the process
+    /// and the service can tag their logs with the same id. The id is carried by the
+    /// [events](ProcessBuilder::events) of the launch, the [`LaunchRequest`] handed to
+    /// the backend and the returned [`Child`].
+    pub fn correlate(mut self, correlate: bool) -> Self {
+        self.correlate = correlate;
+        self
+    }
+
+    /// Sets the correlation id of the launches, default is **a fresh one for each launch**
+    ///
+    /// For an id the caller already tracks the work with, e.g. of an incoming request.
+    /// Implies [`ProcessBuilder::correlate`].
+    pub fn correlation_id(mut self, correlation_id: impl AsRef<str>) -> Self {
+        self.correlate = true;
+        self.correlation_id = Some(correlation_id.as_ref().to_string());
+        self
+    }
+
+    /// Sets whether the processes the process starts are tracked, default is **false**
+    ///
+    /// The process is started suspended and put in a job object before it runs, so everything
+    /// it starts, and everything those start in turn, runs in the job as well.
+    /// [`Child::wait_tree`] then waits for all of them, as launching installers or scripts through
+    /// `cmd` requires. Processes of [`LaunchBackend`]s of the caller are put in the job once
+    /// launched, so what they start before that is not tracked.
+    pub fn track_descendants(mut self, track_descendants: bool) -> Self {
+        self.track_descendants = track_descendants;
+        self
+    }
+
+    /// Sets the job the process and everything it starts run in, default is **none**
+    ///
+    /// The process is started suspended and put in the job before it runs, like for
+    /// [`ProcessBuilder::track_descendants`], and is terminated once the job is closed.
+    /// The [limits](JobLimits) of the job apply to it and everything it starts.
+    /// Processes of [`LaunchBackend`]s of the caller are put in the job once launched.
+    pub fn job(mut self, job: &Job) -> Self {
+        self.job = Some(job.clone());
+        self
+    }
+
+    /// Sets whether the user of the launch token is granted access to the desktop, default is **false**
+    ///
+    /// Processes of users without access to the window station and the desktop they are
+    /// launched onto, e.g. of credentials launched into the service's session or onto the secure
+    /// desktop, fail to initialize. Once set, the user is granted that access before launching,
+    /// see [`Desktop::grant_access`].
+    pub fn grant_desktop_access(mut self, grant_desktop_access: bool) -> Self {
+        self.grant_desktop_access = grant_desktop_access;
         self
     }
 
@@ -129,38 +1113,982 @@ impl ProcessBuilder {
         self
     }
 
-    /// Runs the built process
-    pub fn run(&self) -> Result<(), String> {
-        let application_name = &self.path;
+    /// Sets the session to run the executable in, default is **the active console session**
+    ///
+    /// The user logged on to the session is the one `Elevation::User` and `Elevation::Admin` run as,
+    /// and `Elevation::LocalSystem` and `Elevation::FromProcessName` run in it when matching
+    /// **MatchStrategy::InTargetSession**. Does not apply to `Elevation::Credentials` and
+    /// `Elevation::FromPid`.
+    pub fn session(mut self, session_id: u32) -> Self {
+        self.session = Some(session_id);
+        self
+    }
+
+    /// Sets how to pick the process whose token is used when several match, default is **MatchStrategy::InTargetSession**
+    pub fn match_strategy(mut self, match_strategy: MatchStrategy) -> Self {
+        self.match_strategy = match_strategy;
+        self
+    }
+
+    /// Sets the strategy used to create the process, default is **Backend::AsUser**
+    ///
+    /// Use **Backend::Auto** to pick the strategy from the privileges the service holds
+    ///
+    /// Does not apply to credentials with logon flags, which always go through **Backend::WithLogon**
+    pub fn backend(mut self, backend: impl LaunchBackend + 'static) -> Self {
+        self.backend = Arc::new(backend);
+        self
+    }
+
+    /// Sets whether `%VAR%` references in the path, arguments and directory are expanded before launch, default is **false**
+    ///
+    /// Variables are expanded against the environment of the service, or against the user's with
+    /// [`ProcessBuilder::inherit_user_environment`], so `%APPDATA%` points where the process sees it.
+    /// Variables set with [`ProcessBuilder::env`] are not taken into account.
+    pub fn expand_env(mut self, expand_env: bool) -> Self {
+        self.expand_env = expand_env;
+        self
+    }
+
+    /// Sets whether the identity of the process is checked right after launching it, default is **false**
+    ///
+    /// The process must run as the user and in the session of the launch token, with an integrity level
+    /// matching the elevation. On a mismatch the process is terminated and an error is returned,
+    /// otherwise the identity is available through [`Child::identity`].
+    pub fn verify_identity(mut self, verify_identity: bool) -> Self {
+        self.verify_identity = verify_identity;
+        self
+    }
 
-        let command_line = format!("{} {}", self.path, self.args);
+    /// Sets a pool through which the launch token is shared with other launches, default is **none**
+    ///
+    /// Amortizes acquiring the token across many launches with the same elevation
+    pub fn token_pool(mut self, token_pool: TokenPool) -> Self {
+        self.token_pool = Some(token_pool);
+        self
+    }
 
-        let current_directory = &self.directory;
+    /// Sets the throttle that limits this launch together with others, default is **none**
+    pub fn throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = Some(throttle);
+        self
+    }
 
-        let desktop = match self.desktop {
-            Desktop::Default => "",
-            Desktop::Secure => "WinSta0\\Winlogon",
+    /// Skips the launch if the executable already runs in the target session as the target user, default is **launching anyway**
+    ///
+    /// A skipped launch fails with an error whose [`Error::already_running_pid`] is the running instance.
+    /// Finding the target user and session takes an extra token acquisition, unless a token pool is set.
+    pub fn unless_running(mut self) -> Self {
+        self.unless_running = true;
+        self
+    }
+
+    /// Stops the instance of the executable already running in the target session as the target user before launching, default is **keeping it**
+    ///
+    /// Its windows are asked to close first, and it is terminated if it has not exited once the timeout elapses.
+    /// Only windows on the desktop of the calling thread can be asked to close, so a service usually ends up
+    /// terminating instances in user sessions, and without the `window` feature they are terminated right away.
+    /// Finding the target user and session takes an extra token acquisition, unless a token pool is set.
+    pub fn replace_existing(mut self, timeout: Duration) -> Self {
+        self.replace_existing = Some(timeout);
+        self
+    }
+
+    /// Allows only one instance per target user and session, or per machine, default is **no limit**
+    ///
+    /// A named mutex marks the running instance for as long as the process lives, and the target
+    /// user can open it too. A launch while the mutex exists fails with an error for which
+    /// [`Error::is_already_running`] holds. Finding the target user and session takes an extra
+    /// token acquisition, unless a token pool is set.
+    pub fn single_instance(mut self, name: impl AsRef<str>, scope: InstanceScope) -> Self {
+        self.single_instance = Some((name.as_ref().to_string(), scope));
+        self
+    }
+
+    /// Sets what the standard input of the executable reads from, default is **Stdio::Console**
+    ///
+    /// Once any stream is redirected, the streams left at **Stdio::Console** are not connected at all
+    pub fn stdin(mut self, stdin: Stdio) -> Self {
+        self.stdin = stdin;
+        self
+    }
+
+    /// Sets what the standard output of the executable writes to, default is **Stdio::Console**
+    ///
+    /// Once any stream is redirected, the streams left at **Stdio::Console** are not connected at all
+    pub fn stdout(mut self, stdout: Stdio) -> Self {
+        self.stdout = stdout;
+        self
+    }
+
+    /// Sets what the standard error of the executable writes to, default is **Stdio::Console**
+    ///
+    /// Once any stream is redirected, the streams left at **Stdio::Console** are not connected at all
+    pub fn stderr(mut self, stderr: Stdio) -> Self {
+        self.stderr = stderr;
+        self
+    }
+
+    /// Calls the callback for every line the executable writes to its standard output, as it arrives
+    ///
+    /// Adds to the target set through [`ProcessBuilder::stdout`] so far, unless that is the console.
+    /// The callback runs on a thread of the service.
+    pub fn on_stdout_line(mut self, callback: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.stdout = self.stdout.with_lines(LineCallback::new(callback));
+        self
+    }
+
+    /// Calls the callback for every line the executable writes to its standard error, as it arrives
+    ///
+    /// Adds to the target set through [`ProcessBuilder::stderr`] so far, unless that is the console.
+    /// The callback runs on a thread of the service.
+    pub fn on_stderr_line(mut self, callback: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.stderr = self.stderr.with_lines(LineCallback::new(callback));
+        self
+    }
+
+    /// Sets the encoding output passed to line callbacks is decoded from, default is **Encoding::Auto**
+    ///
+    /// Output written to files, logs and writers is passed on unchanged
+    pub fn output_encoding(mut self, output_encoding: Encoding) -> Self {
+        self.output_encoding = output_encoding;
+        self
+    }
+
+    /// Forces the input and output code page of the console of the executable, e.g. 65001 for UTF-8, default is **the system's OEM code page**
+    ///
+    /// Consoles pick their settings from the registry of the user by their title, so the console
+    /// is given a title naming the code page, whose settings are written to the registry of the
+    /// target user first. Only applies to console programs that get a new console, and the
+    /// registry of the target user has to be loaded, as it is while the user is logged on.
+    /// Finding the target user takes an extra token acquisition, unless a token pool is set.
+    /// Along with a [window title](ProcessBuilder::window_title), the settings are stored under it.
+    pub fn console_code_page(mut self, code_page: u32) -> Self {
+        self.console_code_page = Some(code_page);
+        self
+    }
+
+    /// Sets the title of the console window of the executable, default is **the path of the executable**
+    ///
+    /// Tells apart the consoles of several launches of the same tool. Only applies to console
+    /// programs that get a new console, and programs may change their title once running.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use win_run::win;
+    ///
+    /// win("C:\\Tools\\worker.exe")
+    ///     .arg("--queue=billing")
+    ///     .window_title("Worker: billing")
+    ///     .spawn()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn window_title(mut self, window_title: impl AsRef<str>) -> Self {
+        self.window_title = Some(window_title.as_ref().to_string());
+        self
+    }
+
+    /// Sets the token that calls off the launch, default is **none**
+    ///
+    /// Once the token is cancelled, launches fail at [`Stage::Cancellation`] instead of starting,
+    /// including those waiting for their time, a user to log on or a queue.
+    /// Processes launched with the token are terminated by [`CancellationToken::cancel_and_kill`].
+    pub fn cancellation(mut self, token: &CancellationToken) -> Self {
+        self.cancellation = Some(token.clone());
+        self
+    }
+
+    /// Sets how long the process may run before it is terminated, default is **no limit**
+    ///
+    /// Once the timeout elapses the process is terminated, and waiting for it through the
+    /// [`Child`] fails with [`Error::Timeout`]. Counted from the launch, whether or not the
+    /// process is waited for. Processes the crate cannot open, e.g. of [`LaunchBackend`]s without
+    /// a handle, are not terminated.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use std::time::Duration;
+    /// use win_run::win;
+    ///
+    /// match win("C:\\Tools\\helper.exe").timeout(Duration::from_secs(60)).spawn()?.wait() {
+    ///     Ok(status) => println!("The helper exited with {status}"),
+    ///     Err(err) if err.is_timeout() => println!("The helper hung and was terminated"),
+    ///     Err(err) => return Err(err),
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets where the events of the launches are reported, default is **nowhere**
+    ///
+    /// Lines are only reported for output streams redirected away from the console, and
+    /// each launched process is watched by a thread of the service to report its exit.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use std::sync::mpsc::channel;
+    /// use win_run::{win, LaunchEvent, Stdio};
+    ///
+    /// let (sender, events) = channel();
+    /// win("/path/to/backup")
+    ///     .stdout(Stdio::Null)
+    ///     .events(sender)
+    ///     .run()?;
+    ///
+    /// for event in events {
+    ///     if let LaunchEvent::Exited { status, .. } = event {
+    ///         println!("The backup exited with {status:?}");
+    ///         break;
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn events(mut self, sender: Sender<LaunchEvent>) -> Self {
+        let path = self.path.to_string_lossy().into_owned();
+        self.events = Some(EventSink::new(path, sender));
+        self
+    }
+
+    /// Checks the builder's configuration without launching anything
+    ///
+    /// Returns every detected problem at once instead of stopping at the first one
+    pub fn validate(&self) -> Result<(), Vec<Problem>> {
+        let mut problems: Vec<Problem> = Vec::new();
+
+        // Expand the values the same way running would
+        let user_environment = if self.expand_env {
+            self.user_environment()
+        } else {
+            Ok(None)
+        };
+        let mut resolve = |field: Field, value: &OsStr| -> Option<OsString> {
+            if value.to_string_lossy().contains('\0') {
+                problems.push(Problem::InteriorNul(field));
+                return None;
+            }
+            if !self.expand_env {
+                return Some(value.to_os_string());
+            }
+            let expanded = match &user_environment {
+                Ok(environment) => expand_value(value, environment.as_deref()),
+                Err(err) => Err(err.clone()),
+            };
+            match expanded {
+                Ok(expanded) => Some(expanded),
+                Err(err) => {
+                    problems.push(Problem::ExpansionFailed(field, err));
+                    None
+                }
+            }
         };
 
-        let token = match self.elevation {
-            Elevation::User => get_current_user_token()?,
-            Elevation::Admin => {
-                let mut current_user_token = get_current_user_token()?;
-                current_user_token = add_admin_privileges_to_token(current_user_token)?;
-                current_user_token
+        let path = resolve(Field::Path, &self.path);
+        let args = resolve(Field::Args, &self.args);
+        let directory = resolve(Field::Directory, &self.directory);
+
+        if let (Some(path), Some(args)) = (&path, &args) {
+            let length = command_line_length(&command_line(path, args));
+            if length > MAX_COMMAND_LINE_LENGTH {
+                problems.push(Problem::CommandLineTooLong(length));
             }
-            Elevation::LocalSystem => {
-                let process_pid = get_process_pid("winlogon")?;
-                get_process_token(process_pid)?
+        }
+
+        if let Some(path) = path {
+            let executable = Path::new(&path);
+            let path = path.to_string_lossy().into_owned();
+            if path.trim().is_empty() {
+                problems.push(Problem::EmptyPath);
+            } else if !executable.exists() {
+                problems.push(Problem::ExecutableNotFound(path));
+            } else if !executable.is_file() {
+                problems.push(Problem::ExecutableNotAFile(path));
+            }
+        }
+
+        if let Some(problem) = self.desktop.problem() {
+            problems.push(Problem::InvalidDesktop(problem));
+        }
+
+        if let Some(directory) = directory {
+            let parent = Path::new(&directory);
+            let directory = directory.to_string_lossy().into_owned();
+            if !parent.exists() {
+                problems.push(Problem::DirectoryNotFound(directory));
+            } else if !parent.is_dir() {
+                problems.push(Problem::NotADirectory(directory));
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+
+        Ok(())
+    }
+
+    /// Runs the built process
+    pub fn run(&self) -> Result<(), Error> {
+        self.spawn()?;
+        Ok(())
+    }
+
+    /// Runs the built process to completion, capturing its standard output and error
+    ///
+    /// Both streams are [piped](Stdio::Piped) whatever the builder sets them to, and are not
+    /// reported as [events](ProcessBuilder::events).
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use win_run::{win, Elevation};
+    ///
+    /// let output = win("C:\\Windows\\System32\\whoami.exe")
+    ///     .elevation(Elevation::User)
+    ///     .output()?;
+    /// if output.status == 0 {
+    ///     println!("{}", String::from_utf8_lossy(&output.stdout));
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn output(&self) -> Result<Output, Error> {
+        let mut builder = self.clone();
+        builder.stdout = Stdio::Piped;
+        builder.stderr = Stdio::Piped;
+        builder.spawn()?.wait_with_output()
+    }
+
+    /// Runs the built process, waiting up to the timeout for a user to log on if none is
+    ///
+    /// Services starting at boot usually come up before anyone logs on, which fails launches
+    /// as User or Admin with [`Error::NoInteractiveUser`]. Other failures are returned right away.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use std::time::Duration;
+    /// use win_run::win;
+    ///
+    /// win("/path/to/tray").run_or_wait_for_logon(Duration::from_secs(10 * 60))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn run_or_wait_for_logon(&self, timeout: Duration) -> Result<(), Error> {
+        self.spawn_or_wait_for_logon(timeout)?;
+        Ok(())
+    }
+
+    /// Like [`ProcessBuilder::run_or_wait_for_logon`], returning a handle to the process
+    pub fn spawn_or_wait_for_logon(&self, timeout: Duration) -> Result<Child, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.spawn() {
+                Err(err) if err.is_no_interactive_user() => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Err(err);
+                    }
+                    let wait = remaining.min(LOGON_POLL_INTERVAL);
+                    match &self.cancellation {
+                        Some(token) => {
+                            if token.sleep(wait) {
+                                return Err(cancelled());
+                            }
+                        }
+                        None => sleep(wait),
+                    }
+                }
+                spawned => return spawned,
             }
+        }
+    }
+
+    /// Runs the built process once the delay has passed, on a thread of its own
+    pub fn run_after(&self, delay: Duration) -> ScheduledLaunch {
+        ScheduledLaunch::start(self.clone(), When::After(Instant::now() + delay))
+    }
+
+    /// Runs the built process at a time of the wall clock, on a thread of its own
+    ///
+    /// Follows changes to the wall clock, e.g. by time synchronization.
+    /// A time in the past runs the process right away.
+    pub fn run_at(&self, time: SystemTime) -> ScheduledLaunch {
+        ScheduledLaunch::start(self.clone(), When::At(time))
+    }
+
+    /// Runs the built process again and again according to the recurrence, on a thread of its own
+    ///
+    /// Runs stop once the returned handle is dropped.
+    pub fn run_every(&self, recurrence: Recurrence) -> RecurringLaunch {
+        RecurringLaunch::start(self.clone(), recurrence)
+    }
+
+    /// Checks that AppLocker and Software Restriction Policies let the target user run the executable
+    ///
+    /// Fails at [`Stage::ExecutionPolicy`] naming the executable if the policies block it, so a
+    /// service can report the block instead of the generic failure of a launch.
+    /// The token is acquired as for a launch, and rules for users and groups are evaluated for its user.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), win_run::Error> {
+    /// use win_run::win;
+    ///
+    /// let builder = win("C:\\Users\\Public\\tool.exe");
+    /// builder.check_execution_policy()?;
+    /// builder.spawn()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn check_execution_policy(&self) -> Result<(), Error> {
+        let application_name = if self.expand_env {
+            expand_value(&self.path, self.user_environment()?.as_deref())?
+        } else {
+            self.path.clone()
+        };
+        let application_name = resolve_system_path(&application_name, self.disable_redirection)?;
+
+        let (token, _) = self.acquire_token()?;
+        let allowed = execution_allowed_for_token(token, &application_name);
+        let closed = close_handle(token);
+        let allowed = match (allowed, closed) {
+            (Ok(allowed), Ok(())) => allowed,
+            (Ok(_), Err(err)) => return Err(err),
+            (Err(err), Ok(())) => return Err(err),
+            (Err(err), Err(cleanup)) => return Err(Error::combine(vec![err, cleanup])),
+        };
+
+        if !allowed {
+            return Err(Error::other(
+                Stage::ExecutionPolicy,
+                format!(
+                    "{} is blocked by AppLocker or Software Restriction Policies for the \
+                    target user",
+                    application_name.to_string_lossy()
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Runs the built process and returns a handle to it
+    pub fn spawn(&self) -> Result<Child, Error> {
+        if let Some(token) = &self.cancellation {
+            token.check()?;
+        }
+
+        let correlation_id = self.launch_correlation_id()?;
+        let events = self
+            .events
+            .as_ref()
+            .map(|events| events.for_launch(correlation_id.clone()));
+
+        let (application_name, args, current_directory) = if self.expand_env {
+            let environment = self.user_environment()?;
+            let environment = environment.as_deref();
+            (
+                expand_value(&self.path, environment)?,
+                expand_value(&self.args, environment)?,
+                expand_value(&self.directory, environment)?,
+            )
+        } else {
+            (self.path.clone(), self.args.clone(), self.directory.clone())
+        };
+        let application_name = resolve_system_path(&application_name, self.disable_redirection)?;
+
+        if let Some(timeout) = self.replace_existing {
+            self.stop_running(&application_name, timeout)?;
+        }
+
+        if self.unless_running {
+            self.check_not_running(&application_name)?;
+        }
+
+        let command_line = command_line(&application_name, &args);
+        check_command_line_length(&command_line)?;
+
+        if let Some(problem) = self.desktop.problem() {
+            return Err(Error::other(Stage::CreateProcess, problem));
+        }
+        let desktop = self.desktop.startup_name();
+
+        if self.grant_desktop_access {
+            let (sid, _) = self.target_user()?;
+            self.desktop.grant_access(&sid)?;
+        }
+
+        let console_title = self.prepare_console()?;
+
+        // Dropped along with the launch if it fails, which removes the directory
+        let profile = if self.temporary_profile {
+            let (sid, _) = self.target_user()?;
+            Some(TemporaryProfile::create(&sid)?)
+        } else {
+            None
         };
+        let account_profile = self.account_profile()?;
+        // Loaded before building the environment, which reads the variables of the user from their hive
+        let loaded_profile = self.loaded_profile()?;
+        let environment = self.user_environment()?;
+        // A throwaway profile stands in for the real one, whether or not that had to be created
+        let environment = match (&profile, &account_profile) {
+            (Some(profile), _) => Some(profile.environment(environment)?),
+            (None, Some(account_profile)) => Some(account_profile.environment(environment)),
+            (None, None) => environment,
+        };
+        let environment = self.environment.apply(environment);
+        let environment = match &correlation_id {
+            Some(correlation_id) => Some(with_correlation_id(environment, correlation_id)),
+            None => environment,
+        };
+
+        // The process gets its own copies, those of the service are closed once it has launched
+        let (stdout, stderr) = self.reported_output(events.as_ref());
+        let mut std_handles =
+            StdHandles::open(&self.stdin, &stdout, &stderr, self.output_encoding)?;
 
-        create_process_with_token(
-            token,
+        let request = LaunchRequest {
+            builder: self,
             application_name,
-            &command_line,
+            command_line,
             current_directory,
             desktop,
+            std_handles: std_handles.as_ref().map(StdHandles::handles),
+            console_title,
+            environment,
+            correlation_id: correlation_id.clone(),
+        };
+
+        // Credentials with logon flags are meant for the secondary logon service
+        let backend: &dyn LaunchBackend = match &self.elevation {
+            Elevation::Credentials(Credentials {
+                logon_flags: Some(_),
+                ..
+            }) => &Backend::WithLogon,
+            _ => self.backend.as_ref(),
+        };
+
+        if let Some(throttle) = &self.throttle {
+            throttle.admit()?;
+        }
+
+        let launched =
+            self.claim_single_instance()
+                .and_then(|instance| match backend.launch(&request) {
+                    Ok(launched) => Ok((launched, instance)),
+                    Err(err) => Err(release_instance(err, instance)),
+                });
+
+        if let Some(throttle) = &self.throttle {
+            throttle.release(launched.as_ref().ok().map(|(launched, _)| launched.pid));
+        }
+
+        let (
+            Launched {
+                pid,
+                handle,
+                expected,
+                origin,
+                primary_thread,
+            },
+            instance,
+        ) = launched?;
+
+        let mut child = Child {
+            pid,
+            handle,
+            identity: None,
+            token_origin: Some(origin.unwrap_or_else(|| TokenOrigin::CallerSupplied {
+                backend: backend.name().to_string(),
+            })),
+            instance: instance.and_then(|instance| hand_over_instance(instance, handle)),
+            primary_thread,
+            job: None,
+            correlation_id,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            loaded_profile,
+            timeout: None,
+            pumps: Mutex::default(),
+        };
+        if let Some(std_handles) = &mut std_handles {
+            (child.stdin, child.stdout, child.stderr) = std_handles.take_pipes();
+            child.pumps = Mutex::new(std_handles.take_pumps());
+        }
+
+        if let Some(profile) = profile {
+            profile.remove_after_exit(pid);
+        }
+        if let Some(account_profile) = account_profile {
+            account_profile.delete_after_exit(pid);
+        }
+
+        if let Err(err) = self.set_up_process(child.handle) {
+            // Do not leave a misconfigured process running
+            if let Err(cleanup) = kill_process(child.handle, 1) {
+                return Err(Error::combine(vec![err, cleanup]));
+            }
+            return Err(err);
+        }
+
+        if self.verify_identity {
+            let verified = process_identity(pid).and_then(|identity| {
+                verify_identity(&identity, &self.elevation, &expected)?;
+                Ok(identity)
+            });
+            match verified {
+                Ok(identity) => child.identity = Some(identity),
+                Err(err) => {
+                    // Do not leave a misconfigured process running
+                    if let Err(cleanup) = kill_process(child.handle, 1) {
+                        return Err(Error::combine(vec![err, cleanup]));
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        if self.track_descendants || self.job.is_some() {
+            if let Err(err) = self.start_in_job(&mut child) {
+                // Do not leave a misconfigured process running
+                if let Err(cleanup) = kill_process(child.handle, 1) {
+                    return Err(Error::combine(vec![err, cleanup]));
+                }
+                return Err(err);
+            }
+        }
+
+        if let Err(err) = self.let_run(&mut child) {
+            // Do not leave a process behind that never runs
+            if let Err(cleanup) = kill_process(child.handle, 1) {
+                return Err(Error::combine(vec![err, cleanup]));
+            }
+            return Err(err);
+        }
+
+        if let Some(timeout) = self.timeout {
+            child.timeout = Some((timeout, child.terminate_after(timeout)));
+        }
+
+        if let Some(token) = &self.cancellation {
+            token.track(pid);
+        }
+
+        if let Some(events) = &events {
+            events.send(LaunchEvent::Started {
+                path: events.path(),
+                pid,
+                correlation_id: events.correlation_id(),
+            });
+            events.watch_exit(pid);
+        }
+
+        Ok(child)
+    }
+
+    /// The correlation id of a launch, if the builder correlates its launches
+    fn launch_correlation_id(&self) -> Result<Option<String>, Error> {
+        if !self.correlate {
+            return Ok(None);
+        }
+        match &self.correlation_id {
+            Some(correlation_id) => Ok(Some(correlation_id.clone())),
+            None => new_correlation_id().map(Some),
+        }
+    }
+
+    /// The output streams to launch with, reporting their lines as events if they are redirected
+    fn reported_output(&self, events: Option<&EventSink>) -> (Stdio, Stdio) {
+        let (stdout, stderr) = (self.stdout.clone(), self.stderr.clone());
+        let Some(events) = events else {
+            return (stdout, stderr);
+        };
+        let report = |stdio: Stdio, stream: OutputStream| {
+            if stdio.is_redirected() {
+                stdio.with_lines(events.lines(stream))
+            } else {
+                stdio
+            }
+        };
+        (
+            report(stdout, OutputStream::Stdout),
+            report(stderr, OutputStream::Stderr),
         )
     }
+
+    /// Applies the settings that can only be made once the process is running
+    fn set_up_process(&self, handle: HANDLE) -> Result<(), Error> {
+        // Backends without a handle cannot have their processes adjusted
+        if handle.is_invalid() {
+            return Ok(());
+        }
+        if let Some(affinity) = self.affinity {
+            set_process_affinity(handle, affinity)?;
+        }
+        if self.efficiency_mode {
+            set_process_efficiency_mode(handle, true)?;
+        }
+        if self.protect_from_termination {
+            protect_process(handle)?;
+        }
+        Ok(())
+    }
+
+    /// Creates the profile of the account the credentials log on, if asked to and it does not exist yet
+    fn account_profile(&self) -> Result<Option<AccountProfile>, Error> {
+        let Elevation::Credentials(credentials) = &self.elevation else {
+            return Ok(None);
+        };
+        if !self.create_profile {
+            return Ok(None);
+        }
+
+        let (token, _) = self.acquire_token()?;
+        let profile =
+            AccountProfile::ensure(token, &credentials.username, self.delete_profile_after_exit);
+        let closed = close_handle(token);
+        match (profile, closed) {
+            (Ok(profile), Ok(())) => Ok(Some(profile)),
+            (Ok(_), Err(err)) => Err(err),
+            (Err(err), Ok(())) => Err(err),
+            (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
+        }
+    }
+
+    /// Loads the profile of the user of the launch token, if asked to
+    fn loaded_profile(&self) -> Result<Option<LoadedProfile>, Error> {
+        if !self.load_user_profile {
+            return Ok(None);
+        }
+
+        // The profile keeps the token, which it needs to be unloaded
+        let (token, _) = self.acquire_token()?;
+        LoadedProfile::load(token).map(Some)
+    }
+
+    /// The environment of the user of the launch token, if the process gets it instead of the service's
+    fn user_environment(&self) -> Result<Option<Vec<(OsString, OsString)>>, Error> {
+        if !self.inherit_user_environment {
+            return Ok(None);
+        }
+
+        let (token, _) = self.acquire_token()?;
+        let environment = get_user_environment(token);
+        let closed = close_handle(token);
+        match (environment, closed) {
+            (Ok(environment), Ok(())) => Ok(Some(environment)),
+            (Ok(_), Err(err)) => Err(err),
+            (Err(err), Ok(())) => Err(err),
+            (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
+        }
+    }
+
+    /// Puts the process in the job of the caller and in a job of its own, as asked to
+    fn start_in_job(&self, child: &mut Child) -> Result<(), Error> {
+        if child.handle.is_invalid() {
+            return Err(Error::other(
+                Stage::ProcessSetup,
+                "The backend provided no handle to put the process in a job",
+            ));
+        }
+        if let Some(job) = &self.job {
+            job.assign_handle(child.handle)?;
+        }
+        // Nested in the job of the caller, so closing that one still terminates the process
+        if self.track_descendants {
+            child.job = Some(assign_process_to_new_job(child.handle)?);
+        }
+        Ok(())
+    }
+
+    /// Whether the process is started suspended, to be set up before it runs
+    pub(crate) fn starts_suspended(&self) -> bool {
+        self.track_descendants
+            || self.job.is_some()
+            || self.affinity.is_some()
+            || self.create_suspended
+    }
+
+    /// Lets a process the builder started suspended run, unless the caller is to resume it
+    fn let_run(&self, child: &mut Child) -> Result<(), Error> {
+        if !self.starts_suspended() || self.create_suspended {
+            return Ok(());
+        }
+        // The built-in backends started the thread suspended, resuming one of a backend of the
+        // caller that runs already changes nothing
+        let thread = child.primary_thread.take();
+        if let Some(thread) = &thread {
+            thread.resume()?;
+        }
+        if self.keep_primary_thread {
+            child.primary_thread = thread;
+        }
+        Ok(())
+    }
+
+    /// Gets the SID of the user and the session of the launch token
+    fn target_user(&self) -> Result<(String, u32), Error> {
+        let (token, _) = self.acquire_token()?;
+        let target =
+            get_token_user_sid(token).and_then(|sid| Ok((sid, get_token_session_id(token)?)));
+        let closed = close_handle(token);
+        match (target, closed) {
+            (Ok(target), Ok(())) => Ok(target),
+            (Ok(_), Err(err)) => Err(err),
+            (Err(err), Ok(())) => Err(err),
+            (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
+        }
+    }
+
+    /// Writes the console settings of the target user for the forced code page
+    ///
+    /// Returns the console title, which the settings are stored under if the code page is forced
+    fn prepare_console(&self) -> Result<Option<String>, Error> {
+        let Some(code_page) = self.console_code_page else {
+            return Ok(self.window_title.clone());
+        };
+        let (sid, _) = self.target_user()?;
+        let console_title = match &self.window_title {
+            Some(window_title) => window_title.clone(),
+            None => format!("win_run code page {code_page}"),
+        };
+        // Consoles store the settings of titles with backslashes under their names with underscores
+        set_user_dword(
+            &sid,
+            &format!("Console\\{}", console_title.replace('\\', "_")),
+            "CodePage",
+            code_page,
+        )?;
+        Ok(Some(console_title))
+    }
+
+    /// Creates the object marking the running instance, if only one may run
+    fn claim_single_instance(&self) -> Result<Option<HANDLE>, Error> {
+        let Some((name, scope)) = &self.single_instance else {
+            return Ok(None);
+        };
+        let (sid, session_id) = self.target_user()?;
+        Ok(Some(claim_instance(name, *scope, &sid, session_id)?))
+    }
+
+    /// Fails if the executable already runs as the user and in the session of the launch token
+    fn check_not_running(&self, application_name: &OsStr) -> Result<(), Error> {
+        let (sid, session_id) = self.target_user()?;
+
+        match find_running_instance(application_name, &sid, session_id) {
+            Some(pid) => Err(Error::already_running(Some(pid))),
+            None => Ok(()),
+        }
+    }
+
+    /// Stops the executable if it runs as the user and in the session of the launch token
+    fn stop_running(&self, application_name: &OsStr, timeout: Duration) -> Result<(), Error> {
+        let (sid, session_id) = self.target_user()?;
+
+        match find_running_instance(application_name, &sid, session_id) {
+            Some(pid) => shut_down_process(pid, timeout),
+            None => Ok(()),
+        }
+    }
+
+    /// The session to launch into
+    fn target_session(&self) -> Result<u32, Error> {
+        match self.session {
+            Some(session_id) => Ok(session_id),
+            None => get_active_session_id(),
+        }
+    }
+
+    /// Acquires the token to run the executable with and where it came from, through the token pool if there is one
+    pub(crate) fn acquire_token(&self) -> Result<(HANDLE, TokenOrigin), Error> {
+        match &self.token_pool {
+            Some(token_pool) => token_pool.acquire(self),
+            None => self.acquire_fresh_token(),
+        }
+    }
+
+    /// Acquires the token to run the executable with and where it came from, according to the elevation
+    pub(crate) fn acquire_fresh_token(&self) -> Result<(HANDLE, TokenOrigin), Error> {
+        let acquired = match &self.elevation {
+            Elevation::User => {
+                let session_id = self.target_session()?;
+                (
+                    get_session_user_token(session_id)?,
+                    TokenOrigin::SessionUser { session_id },
+                )
+            }
+            Elevation::Admin => {
+                let session_id = self.target_session()?;
+                let current_user_token = get_session_user_token(session_id)?;
+                let token = add_admin_privileges_to_token(current_user_token)?;
+                let admin_token = get_token_is_linked_elevated(token).ok().map(|linked| {
+                    if linked {
+                        AdminToken::Linked
+                    } else {
+                        AdminToken::Primary
+                    }
+                });
+                (
+                    token,
+                    TokenOrigin::SessionAdmin {
+                        session_id,
+                        admin_token,
+                    },
+                )
+            }
+            Elevation::LocalSystem => {
+                let pid = select_process_pid("winlogon", self.match_strategy, self.session)?;
+                (get_process_token(pid)?, TokenOrigin::Winlogon { pid })
+            }
+            Elevation::LocalService | Elevation::NetworkService => {
+                let sid = match &self.elevation {
+                    Elevation::LocalService => LOCAL_SERVICE_SID,
+                    _ => NETWORK_SERVICE_SID,
+                };
+                // Looked up by SID, as the names differ between languages of Windows
+                let (username, domain) = get_sid_account(sid)?;
+                (
+                    get_logon_user_token(&username, &domain, "", LogonType::Service.to_native())?,
+                    TokenOrigin::ServiceAccount {
+                        sid: sid.to_string(),
+                    },
+                )
+            }
+            Elevation::FromPid(pid) => {
+                (get_process_token(*pid)?, TokenOrigin::Process { pid: *pid })
+            }
+            Elevation::FromProcessName(process_name) => {
+                let pid = select_process_pid(process_name, self.match_strategy, self.session)?;
+                (get_process_token(pid)?, TokenOrigin::Process { pid })
+            }
+            Elevation::Credentials(credentials) => (
+                get_logon_user_token(
+                    &credentials.username,
+                    &credentials.domain,
+                    &credentials.password,
+                    credentials.logon_type.to_native(),
+                )?,
+                TokenOrigin::Credentials {
+                    username: credentials.username.clone(),
+                    domain: credentials.domain.clone(),
+                },
+            ),
+        };
+        Ok(acquired)
+    }
+}
+
+/// Expands `%VAR%` references in a value against the user's environment, or the service's without one
+fn expand_value(
+    value: &OsStr,
+    user_environment: Option<&[(OsString, OsString)]>,
+) -> Result<OsString, Error> {
+    match user_environment {
+        Some(environment) => Ok(expand_variables(value, environment)),
+        None => expand_environment_os_strings(value),
+    }
 }
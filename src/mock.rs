@@ -0,0 +1,129 @@
+use crate::backend::{LaunchBackend, LaunchRequest, Launched};
+use crate::error::{Error, Stage};
+use crate::Elevation;
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::sync::{Arc, Mutex};
+
+/// The first pid handed out by a [`MockBackend`] when none was scripted
+const FIRST_MOCK_PID: u32 = 10_000;
+
+/// A launch recorded by a [`MockBackend`]
+#[derive(Debug, Clone)]
+pub struct RecordedLaunch {
+    /// The path to the executable
+    pub application_name: OsString,
+    /// The full command line, starting with the executable
+    pub command_line: OsString,
+    /// The directory the executable would run from
+    pub current_directory: OsString,
+    /// The desktop the executable would run on
+    pub desktop: String,
+    /// The user the executable would run as
+    pub elevation: Elevation,
+    /// The correlation id of the launch, if the builder correlates its launches
+    pub correlation_id: Option<String>,
+}
+
+/// The state shared between the clones of a mock
+#[derive(Default)]
+struct MockState {
+    /// Every launch in the order it was requested
+    launches: Vec<RecordedLaunch>,
+    /// Outcomes to return for the next launches, in order
+    script: VecDeque<Result<u32, Error>>,
+    /// The pid handed out by the previous unscripted launch
+    last_pid: Option<u32>,
+}
+
+/// A backend that records launch requests and returns scripted results without touching Win32
+///
+/// Clones share their recordings and script, so keep one clone to inspect after handing
+/// another to a builder. Unscripted launches succeed with increasing fake pids.
+///
+/// The returned children have no process handle, so do not combine the mock with
+/// identity verification, which queries the real process.
+///
+/// ```no_run
+/// # fn main() -> Result<(), win_run::Error> {
+/// use win_run::{win, MockBackend, Stage};
+///
+/// let mock = MockBackend::new();
+/// mock.push_pid(42);
+/// mock.push_error(Stage::CreateProcess, "Scripted failure");
+///
+/// let child = win("C:\\tool.exe").backend(mock.clone()).spawn()?;
+/// assert_eq!(child.id(), 42);
+/// assert!(win("C:\\tool.exe").backend(mock.clone()).spawn().is_err());
+/// assert_eq!(mock.launches().len(), 2);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct MockBackend {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockBackend {
+    /// Creates a mock with an empty script
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes the next unscripted launch succeed with a pid
+    pub fn push_pid(&self, pid: u32) {
+        self.lock().script.push_back(Ok(pid));
+    }
+
+    /// Makes the next unscripted launch fail at a stage
+    pub fn push_error(&self, stage: Stage, message: impl Into<String>) {
+        self.lock()
+            .script
+            .push_back(Err(Error::other(stage, message)));
+    }
+
+    /// The launches requested so far, in order
+    pub fn launches(&self) -> Vec<RecordedLaunch> {
+        self.lock().launches.clone()
+    }
+
+    /// Forgets the recorded launches and the remaining script
+    pub fn reset(&self) {
+        *self.lock() = MockState::default();
+    }
+
+    /// Locks the shared state, ignoring poisoning by a panicking test
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl LaunchBackend for MockBackend {
+    fn name(&self) -> &str {
+        "Mock"
+    }
+
+    fn launch(&self, request: &LaunchRequest) -> Result<Launched, Error> {
+        let mut state = self.lock();
+
+        state.launches.push(RecordedLaunch {
+            application_name: request.application_name().to_os_string(),
+            command_line: request.command_line().to_os_string(),
+            current_directory: request.current_directory().to_os_string(),
+            desktop: request.desktop().to_string(),
+            elevation: request.elevation().clone(),
+            correlation_id: request.correlation_id().map(str::to_string),
+        });
+
+        let pid = match state.script.pop_front() {
+            Some(outcome) => outcome?,
+            None => {
+                let pid = state.last_pid.map_or(FIRST_MOCK_PID, |pid| pid + 1);
+                state.last_pid = Some(pid);
+                pid
+            }
+        };
+
+        Ok(Launched::from_pid(pid))
+    }
+}
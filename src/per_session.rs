@@ -0,0 +1,254 @@
+use crate::child::Child;
+use crate::error::Error;
+use crate::safe_windows_bindings::high_level::{
+    get_logged_on_session_ids, get_session_user_sid, has_exited,
+};
+use crate::ProcessBuilder;
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
+
+/// The session services run in, which no user logs on to
+const SERVICES_SESSION: u32 = 0;
+
+/// What the thread of a launcher has been asked to do
+#[derive(Default)]
+struct Signal {
+    /// Check the sessions right away instead of at the next interval
+    refresh: bool,
+    /// Stop checking the sessions
+    stop: bool,
+}
+
+/// A process a launcher keeps running, with who it was launched for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManagedChild {
+    /// The session the process runs in
+    pub session_id: u32,
+    /// SID of the user logged on to the session when the process was launched
+    pub user_sid: String,
+    /// The id of the process
+    pub pid: u32,
+}
+
+/// A launched process with the user it was launched for
+struct Managed {
+    user_sid: String,
+    child: Child,
+}
+
+/// The state a launcher shares with its thread
+struct Shared {
+    template: ProcessBuilder,
+    /// Whether a user logged on to several sessions gets a single process
+    per_user: bool,
+    /// The process launched into each session
+    children: Mutex<HashMap<u32, Managed>>,
+    /// Why the last launch into each session failed, for sessions it did
+    failures: Mutex<HashMap<u32, Error>>,
+    signal: Mutex<Signal>,
+    /// Wakes the thread when the signal changes
+    wake: Condvar,
+}
+
+/// Keeps a process running in every session a user is logged on to
+///
+/// A thread checks the sessions at every interval: it launches the process into sessions
+/// users logged on to, launches it again where it exited, and forgets the processes of
+/// sessions users logged off from, killing them should they outlive the logoff.
+/// Disconnected sessions keep their process, so it is still there when the user reconnects.
+/// Should another user log on to a session id that was freed in between two checks, the
+/// process of the previous user is killed and one is launched for the new user.
+///
+/// Dropping the launcher stops the thread and leaves the processes running.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use win_run::{win, PerSessionLauncher};
+///
+/// let launcher = PerSessionLauncher::start(win("/path/to/tray"), Duration::from_secs(5));
+///
+/// // E.g. when the service is told about a logon through SERVICE_CONTROL_SESSIONCHANGE
+/// launcher.refresh();
+/// ```
+pub struct PerSessionLauncher {
+    shared: Arc<Shared>,
+    /// The thread checking the sessions, taken when the launcher is dropped
+    worker: Option<JoinHandle<()>>,
+}
+
+impl PerSessionLauncher {
+    /// Starts keeping the process of the template running in every session, checking them at every interval
+    ///
+    /// The template is launched with its session set to each of the sessions in turn.
+    pub fn start(template: ProcessBuilder, interval: Duration) -> Self {
+        Self::launch(template, interval, false)
+    }
+
+    /// Like [`PerSessionLauncher::start`], but with a single process per user
+    ///
+    /// A user logged on to several sessions, e.g. through fast user switching or a second
+    /// remote desktop connection, keeps the process in the session it was launched into.
+    /// Once that process exits, the next check launches it into one of the user's sessions.
+    pub fn start_per_user(template: ProcessBuilder, interval: Duration) -> Self {
+        Self::launch(template, interval, true)
+    }
+
+    fn launch(template: ProcessBuilder, interval: Duration, per_user: bool) -> Self {
+        let shared = Arc::new(Shared {
+            template,
+            per_user,
+            children: Mutex::new(HashMap::new()),
+            failures: Mutex::new(HashMap::new()),
+            signal: Mutex::new(Signal::default()),
+            wake: Condvar::new(),
+        });
+
+        let worker = {
+            let shared = Arc::clone(&shared);
+            spawn(move || shared.work(interval))
+        };
+
+        Self {
+            shared,
+            worker: Some(worker),
+        }
+    }
+
+    /// Checks the sessions right away instead of at the next interval
+    pub fn refresh(&self) {
+        lock(&self.shared.signal).refresh = true;
+        self.shared.wake.notify_one();
+    }
+
+    /// The processes kept running, one per session they run in, ordered by session
+    pub fn children(&self) -> Vec<ManagedChild> {
+        let mut children: Vec<ManagedChild> = lock(&self.shared.children)
+            .iter()
+            .map(|(session_id, managed)| ManagedChild {
+                session_id: *session_id,
+                user_sid: managed.user_sid.clone(),
+                pid: managed.child.id(),
+            })
+            .collect();
+        children.sort_unstable_by_key(|child| child.session_id);
+        children
+    }
+
+    /// The process kept running for a user in a session, if there is one
+    pub fn child_of(&self, user_sid: &str, session_id: u32) -> Option<ManagedChild> {
+        self.children()
+            .into_iter()
+            .find(|child| child.session_id == session_id && child.user_sid == user_sid)
+    }
+
+    /// The sessions the last launch failed in, with why, retried at every check
+    pub fn failures(&self) -> Vec<(u32, Error)> {
+        let mut failures: Vec<(u32, Error)> = lock(&self.shared.failures)
+            .iter()
+            .map(|(session_id, err)| (*session_id, err.clone()))
+            .collect();
+        failures.sort_unstable_by_key(|(session_id, _)| *session_id);
+        failures
+    }
+}
+
+impl Drop for PerSessionLauncher {
+    fn drop(&mut self) {
+        lock(&self.shared.signal).stop = true;
+        self.shared.wake.notify_one();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Shared {
+    /// Checks the sessions until asked to stop
+    fn work(&self, interval: Duration) {
+        loop {
+            self.check_sessions();
+
+            let signal = lock(&self.signal);
+            let (mut signal, _) = self
+                .wake
+                .wait_timeout_while(signal, interval, |signal| !signal.refresh && !signal.stop)
+                .unwrap_or_else(|err| err.into_inner());
+            if signal.stop {
+                return;
+            }
+            signal.refresh = false;
+        }
+    }
+
+    /// Brings the launched processes in line with the sessions users are logged on to
+    fn check_sessions(&self) {
+        // The sessions are checked again at the next interval
+        let Ok(sessions) = get_logged_on_session_ids() else {
+            return;
+        };
+        // Sessions whose user cannot be told right now are left as they are until the next check
+        let sessions: HashMap<u32, Option<String>> = sessions
+            .into_iter()
+            .filter(|session_id| *session_id != SERVICES_SESSION)
+            .map(|session_id| (session_id, get_session_user_sid(session_id).ok()))
+            .collect();
+
+        let mut children = lock(&self.children);
+        children.retain(|session_id, managed| {
+            let running = is_running(&managed.child);
+            let logged_on = match sessions.get(session_id) {
+                Some(Some(user_sid)) => *user_sid == managed.user_sid,
+                Some(None) => true,
+                None => false,
+            };
+            if running && !logged_on {
+                // The process outlived the logoff, nothing sensible can be done if killing fails
+                let _ = managed.child.kill();
+                return false;
+            }
+            running
+        });
+
+        let mut failures = lock(&self.failures);
+        failures.retain(|session_id, _| sessions.contains_key(session_id));
+
+        let mut sessions: Vec<(u32, String)> = sessions
+            .into_iter()
+            .filter_map(|(session_id, user_sid)| Some((session_id, user_sid?)))
+            .collect();
+        sessions.sort_unstable();
+        for (session_id, user_sid) in sessions {
+            if children.contains_key(&session_id) {
+                continue;
+            }
+            if self.per_user
+                && children
+                    .values()
+                    .any(|managed| managed.user_sid == user_sid)
+            {
+                continue;
+            }
+            match self.template.clone().session(session_id).spawn() {
+                Ok(child) => {
+                    failures.remove(&session_id);
+                    children.insert(session_id, Managed { user_sid, child });
+                }
+                Err(err) => {
+                    failures.insert(session_id, err);
+                }
+            }
+        }
+    }
+}
+
+/// Whether a process is still running, assuming it is if the backend provided no handle to check
+fn is_running(child: &Child) -> bool {
+    child.handle.is_invalid() || !has_exited(child.handle).unwrap_or(false)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // A thread that panicked while holding the lock leaves the data as usable as before
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
@@ -0,0 +1,352 @@
+use crate::error::{Error, Stage};
+use crate::safe_windows_bindings::high_level::{
+    close_handle, get_pipe_client_token, get_token_group_sids, get_token_session_id,
+    get_token_user_sid,
+};
+use crate::safe_windows_bindings::types::HANDLE;
+use crate::server::LaunchMessage;
+use crate::Elevation;
+use std::fmt::{Debug, Formatter};
+use std::mem::discriminant;
+use std::sync::Arc;
+
+/// Decides whether the arguments of a launch are allowed, given its path and arguments
+type ArgumentCheck = Arc<dyn Fn(&str, &str) -> bool + Send + Sync>;
+
+/// What the clients of a launch server may ask for, checked against the token of each client
+///
+/// ```no_run
+/// # fn main() -> Result<(), win_run::Error> {
+/// use win_run::{Elevation, LaunchPolicy, LaunchServer};
+///
+/// // Members of Administrators may launch the updater as Admin in their own session
+/// let policy = LaunchPolicy::new()
+///     .allow_client("S-1-5-32-544")
+///     .allow_executable("C:\\Program Files\\Vendor\\updater.exe")
+///     .allow_elevation(Elevation::Admin)
+///     .check_arguments(|_, args| !args.contains('&'));
+///
+/// LaunchServer::new("my-service-launcher")
+///     .policy(policy)
+///     .serve()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct LaunchPolicy {
+    /// SIDs of the users and groups whose members may ask for launches, anyone if empty
+    pub(crate) clients: Vec<String>,
+    /// Paths of the executables that may be launched, any if empty
+    pub(crate) executables: Vec<String>,
+    /// Elevations that may be asked for
+    pub(crate) elevations: Vec<Elevation>,
+    /// Whether clients may launch into sessions other than their own
+    pub(crate) other_sessions: bool,
+    /// Decides whether the arguments of a launch are allowed, if set
+    pub(crate) arguments: Option<ArgumentCheck>,
+}
+
+impl LaunchPolicy {
+    /// Creates a policy letting any client launch anything as User in its own session
+    pub fn new() -> Self {
+        let clients = Vec::new();
+        let executables = Vec::new();
+        let elevations = vec![Elevation::User];
+        let other_sessions = false;
+        let arguments = None;
+
+        Self {
+            clients,
+            executables,
+            elevations,
+            other_sessions,
+            arguments,
+        }
+    }
+
+    /// Allows a user, or the members of a group, to ask for launches, default is **anyone**
+    ///
+    /// The SID is given as a string, e.g. `S-1-5-32-544` for Administrators.
+    /// Once a client is allowed, only the allowed ones are.
+    pub fn allow_client(mut self, sid: impl AsRef<str>) -> Self {
+        self.clients.push(sid.as_ref().to_string());
+        self
+    }
+
+    /// Allows an executable to be launched, default is **any**
+    ///
+    /// Paths are compared as given by the client, ignoring case and the kind of slashes.
+    /// Once an executable is allowed, only the allowed ones are.
+    pub fn allow_executable(mut self, path: impl AsRef<str>) -> Self {
+        self.executables.push(normalize_path(path.as_ref()));
+        self
+    }
+
+    /// Allows an elevation to be asked for, default is **User only**
+    pub fn allow_elevation(mut self, elevation: Elevation) -> Self {
+        self.elevations.push(elevation);
+        self
+    }
+
+    /// Sets whether clients may launch into sessions other than their own, default is **false**
+    ///
+    /// Requests without a session launch into the client's session unless this is allowed.
+    pub fn allow_other_sessions(mut self, other_sessions: bool) -> Self {
+        self.other_sessions = other_sessions;
+        self
+    }
+
+    /// Sets a check of the arguments of every launch, given its path and arguments, default is **none**
+    pub fn check_arguments(
+        mut self,
+        check: impl Fn(&str, &str) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.arguments = Some(Arc::new(check));
+        self
+    }
+
+    /// Checks a request against the policy, pinning it to the client's session when it has to stay there
+    pub(crate) fn authorize(
+        &self,
+        client: &PipeClient,
+        request: &mut LaunchMessage,
+    ) -> Result<(), Error> {
+        let denied = |reason: String| Error::other(Stage::LaunchServer, reason);
+
+        let is_allowed_client = self.clients.iter().any(|sid| {
+            sid.eq_ignore_ascii_case(&client.sid)
+                || client
+                    .groups
+                    .iter()
+                    .any(|group| sid.eq_ignore_ascii_case(group))
+        });
+        if !self.clients.is_empty() && !is_allowed_client {
+            return Err(denied(format!(
+                "{} is not allowed to ask for launches",
+                client.sid
+            )));
+        }
+
+        let path = normalize_path(&request.path);
+        if !self.executables.is_empty() && !self.executables.contains(&path) {
+            return Err(denied(format!(
+                "{} is not allowed to be launched",
+                request.path
+            )));
+        }
+
+        let is_allowed_elevation = self
+            .elevations
+            .iter()
+            .any(|elevation| discriminant(elevation) == discriminant(&request.elevation));
+        if !is_allowed_elevation {
+            return Err(denied(format!(
+                "{:?} is not allowed to be asked for",
+                request.elevation
+            )));
+        }
+
+        if !self.other_sessions {
+            match request.session {
+                Some(session) if session != client.session_id => {
+                    return Err(denied(format!(
+                        "Launching into session {session} is not allowed from session {}",
+                        client.session_id
+                    )))
+                }
+                Some(_) => {}
+                None => request.session = Some(client.session_id),
+            }
+        }
+
+        if let Some(check) = &self.arguments {
+            if !check(&request.path, &request.args) {
+                return Err(denied(format!(
+                    "The arguments {} are not allowed for {}",
+                    request.args, request.path
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for LaunchPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for LaunchPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LaunchPolicy")
+            .field("clients", &self.clients)
+            .field("executables", &self.executables)
+            .field("elevations", &self.elevations)
+            .field("other_sessions", &self.other_sessions)
+            .field("arguments", &self.arguments.is_some())
+            .finish()
+    }
+}
+
+/// Who is connected to a pipe instance, taken from the token the client connected with
+#[derive(Debug)]
+pub(crate) struct PipeClient {
+    /// SID of the user the client runs as
+    pub(crate) sid: String,
+    /// SIDs of the groups the client is an enabled member of
+    pub(crate) groups: Vec<String>,
+    /// Session the client runs in
+    pub(crate) session_id: u32,
+}
+
+impl PipeClient {
+    /// Identifies the process connected to a pipe instance
+    pub(crate) fn identify(pipe: HANDLE) -> Result<Self, Error> {
+        let token = get_pipe_client_token(pipe)?;
+        let client = Self::from_token(token);
+        let closed = close_handle(token);
+        match (client, closed) {
+            (Ok(client), Ok(())) => Ok(client),
+            (Ok(_), Err(err)) => Err(err),
+            (Err(err), Ok(())) => Err(err),
+            (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
+        }
+    }
+
+    fn from_token(token: HANDLE) -> Result<Self, Error> {
+        Ok(Self {
+            sid: get_token_user_sid(token)?,
+            groups: get_token_group_sids(token)?,
+            session_id: get_token_session_id(token)?,
+        })
+    }
+}
+
+/// Brings a path into the form executables are compared in
+fn normalize_path(path: &str) -> String {
+    path.trim().replace('/', "\\").to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADMINISTRATORS: &str = "S-1-5-32-544";
+    const USER: &str = "S-1-5-21-1-2-3-1001";
+
+    fn client(session_id: u32) -> PipeClient {
+        PipeClient {
+            sid: USER.to_string(),
+            groups: vec!["S-1-5-32-545".to_string()],
+            session_id,
+        }
+    }
+
+    fn request(elevation: Elevation, session: Option<u32>) -> LaunchMessage {
+        let mut request = LaunchMessage::new("C:\\Tools\\sync.exe");
+        request.elevation = elevation;
+        request.session = session;
+        request
+    }
+
+    #[test]
+    fn default_allows_only_user_elevation() {
+        let policy = LaunchPolicy::new();
+
+        assert!(policy
+            .authorize(&client(1), &mut request(Elevation::User, None))
+            .is_ok());
+        for elevation in [Elevation::Admin, Elevation::LocalSystem] {
+            let err = policy
+                .authorize(&client(1), &mut request(elevation, None))
+                .unwrap_err();
+            assert_eq!(err.stage(), Stage::LaunchServer);
+        }
+    }
+
+    #[test]
+    fn allowed_elevations_are_added_to_user() {
+        let policy = LaunchPolicy::new().allow_elevation(Elevation::Admin);
+
+        assert!(policy
+            .authorize(&client(1), &mut request(Elevation::User, None))
+            .is_ok());
+        assert!(policy
+            .authorize(&client(1), &mut request(Elevation::Admin, None))
+            .is_ok());
+        assert!(policy
+            .authorize(&client(1), &mut request(Elevation::LocalSystem, None))
+            .is_err());
+    }
+
+    #[test]
+    fn pins_requests_without_a_session_to_the_client_session() {
+        let mut request = request(Elevation::User, None);
+        LaunchPolicy::new()
+            .authorize(&client(2), &mut request)
+            .unwrap();
+        assert_eq!(request.session, Some(2));
+    }
+
+    #[test]
+    fn denies_other_sessions_unless_allowed() {
+        assert!(LaunchPolicy::new()
+            .authorize(&client(2), &mut request(Elevation::User, Some(2)))
+            .is_ok());
+        assert!(LaunchPolicy::new()
+            .authorize(&client(2), &mut request(Elevation::User, Some(3)))
+            .is_err());
+
+        let policy = LaunchPolicy::new().allow_other_sessions(true);
+        let mut unpinned = request(Elevation::User, None);
+        assert!(policy.authorize(&client(2), &mut unpinned).is_ok());
+        assert_eq!(unpinned.session, None);
+        assert!(policy
+            .authorize(&client(2), &mut request(Elevation::User, Some(3)))
+            .is_ok());
+    }
+
+    #[test]
+    fn allows_clients_by_user_or_group() {
+        let by_user = LaunchPolicy::new().allow_client(USER.to_lowercase());
+        assert!(by_user
+            .authorize(&client(1), &mut request(Elevation::User, None))
+            .is_ok());
+
+        let by_group = LaunchPolicy::new().allow_client("S-1-5-32-545");
+        assert!(by_group
+            .authorize(&client(1), &mut request(Elevation::User, None))
+            .is_ok());
+
+        let others = LaunchPolicy::new().allow_client(ADMINISTRATORS);
+        assert!(others
+            .authorize(&client(1), &mut request(Elevation::User, None))
+            .is_err());
+    }
+
+    #[test]
+    fn compares_executables_ignoring_case_and_slashes() {
+        let policy = LaunchPolicy::new().allow_executable("c:/tools/SYNC.exe");
+
+        assert!(policy
+            .authorize(&client(1), &mut request(Elevation::User, None))
+            .is_ok());
+        let mut other = LaunchMessage::new("C:\\Tools\\other.exe");
+        assert!(policy.authorize(&client(1), &mut other).is_err());
+    }
+
+    #[test]
+    fn checks_arguments() {
+        let policy = LaunchPolicy::new().check_arguments(|_, args| !args.contains('&'));
+
+        let mut allowed = request(Elevation::User, None);
+        allowed.args = "/sync now".to_string();
+        assert!(policy.authorize(&client(1), &mut allowed).is_ok());
+
+        let mut denied = request(Elevation::User, None);
+        denied.args = "/sync & calc".to_string();
+        assert!(policy.authorize(&client(1), &mut denied).is_err());
+    }
+}
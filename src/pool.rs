@@ -0,0 +1,174 @@
+use crate::credentials::LogonType;
+use crate::error::Error;
+use crate::identity::TokenOrigin;
+use crate::processes::MatchStrategy;
+use crate::safe_windows_bindings::high_level::{
+    close_handle, copy_primary_token, get_active_session_id,
+};
+use crate::safe_windows_bindings::types::HANDLE;
+use crate::{Elevation, ProcessBuilder};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// The elevation a pooled token was acquired for
+///
+/// Credentials are compared with their password, so a wrong password never reuses a token.
+/// Elevations finding a process by name keep the strategy picking among the matches, as
+/// another strategy may pick a process of another user.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum PoolKey {
+    User,
+    Admin,
+    LocalSystem(MatchStrategy),
+    LocalService,
+    NetworkService,
+    Credentials {
+        domain: String,
+        username: String,
+        password: String,
+        logon_type: LogonType,
+    },
+    FromPid(u32),
+    FromProcessName(String, MatchStrategy),
+}
+
+impl PoolKey {
+    fn new(elevation: &Elevation, match_strategy: MatchStrategy) -> Self {
+        match elevation {
+            Elevation::User => PoolKey::User,
+            Elevation::Admin => PoolKey::Admin,
+            Elevation::LocalSystem => PoolKey::LocalSystem(match_strategy),
+            Elevation::LocalService => PoolKey::LocalService,
+            Elevation::NetworkService => PoolKey::NetworkService,
+            Elevation::Credentials(credentials) => PoolKey::Credentials {
+                domain: credentials.domain.clone(),
+                username: credentials.username.clone(),
+                password: credentials.password.clone(),
+                logon_type: credentials.logon_type,
+            },
+            Elevation::FromPid(pid) => PoolKey::FromPid(*pid),
+            Elevation::FromProcessName(process_name) => {
+                PoolKey::FromProcessName(process_name.clone(), match_strategy)
+            }
+        }
+    }
+}
+
+/// A token kept by a pool, closed when evicted
+struct PooledToken {
+    /// The token, never handed out directly
+    token: HANDLE,
+    /// Where the token was acquired from
+    origin: TokenOrigin,
+    /// When the token was acquired
+    acquired: Instant,
+}
+
+impl Drop for PooledToken {
+    fn drop(&mut self) {
+        let _ = close_handle(self.token);
+    }
+}
+
+/// The state shared between the clones of a pool
+#[derive(Default)]
+struct PoolState {
+    /// The active console session the tokens were acquired in
+    session_id: Option<u32>,
+    /// The tokens by elevation and the session the launch asked for, if not the active console one
+    tokens: HashMap<(PoolKey, Option<u32>), PooledToken>,
+}
+
+/// Shares acquired tokens between launches, for services spawning many short-lived processes
+///
+/// Tokens are kept per elevation, [match strategy](crate::ProcessBuilder::match_strategy) and
+/// target session. Each launch gets its own copy, so only the first launch pays for acquiring
+/// the token. Tokens are dropped once they are older than the time to live, and all of them are
+/// dropped when the active console session changes. Clones share their tokens.
+///
+/// ```no_run
+/// # fn main() -> Result<(), win_run::Error> {
+/// use std::time::Duration;
+/// use win_run::{win, TokenPool};
+///
+/// let pool = TokenPool::new(Duration::from_secs(30));
+///
+/// for _ in 0..10 {
+///     win("/path/to/scanner").token_pool(pool.clone()).run()?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct TokenPool {
+    /// How long a token is reused for
+    ttl: Duration,
+    state: Arc<Mutex<PoolState>>,
+}
+
+impl TokenPool {
+    /// Creates an empty pool whose tokens are reused for at most `ttl`
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            state: Arc::new(Mutex::new(PoolState::default())),
+        }
+    }
+
+    /// Drops all pooled tokens, e.g. after the user's group membership changed
+    pub fn clear(&self) {
+        self.lock().tokens.clear();
+    }
+
+    /// Gets a copy of the pooled token for the builder's elevation and where it came from, acquiring it if needed
+    ///
+    /// The pool is not locked while acquiring a token, so a slow acquisition holds up no other launch.
+    pub(crate) fn acquire(&self, builder: &ProcessBuilder) -> Result<(HANDLE, TokenOrigin), Error> {
+        let session_id = get_active_session_id().ok();
+        let key = (
+            PoolKey::new(&builder.elevation, builder.match_strategy),
+            builder.session,
+        );
+
+        {
+            let mut state = self.lock();
+
+            // Tokens of the previous console user must not leak into the next one's session
+            if state.session_id != session_id {
+                state.tokens.clear();
+                state.session_id = session_id;
+            }
+
+            let ttl = self.ttl;
+            state
+                .tokens
+                .retain(|_, pooled| pooled.acquired.elapsed() < ttl);
+
+            if let Some(pooled) = state.tokens.get(&key) {
+                return Ok((copy_primary_token(pooled.token)?, pooled.origin.clone()));
+            }
+        }
+
+        let (token, origin) = builder.acquire_fresh_token()?;
+        let pooled = PooledToken {
+            token,
+            origin,
+            acquired: Instant::now(),
+        };
+        let acquired = (copy_primary_token(pooled.token)?, pooled.origin.clone());
+
+        // Only kept if the console session did not change meanwhile, and a token another launch
+        // acquired at the same time is kept instead of this one
+        let mut state = self.lock();
+        if state.session_id == session_id {
+            state.tokens.entry(key).or_insert(pooled);
+        }
+        Ok(acquired)
+    }
+
+    /// Locks the shared state, ignoring poisoning by a panicking launch
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
@@ -0,0 +1,190 @@
+use crate::error::{Error, Stage};
+use crate::privileges::{
+    current_privileges, SE_ASSIGN_PRIMARY_TOKEN, SE_DEBUG, SE_INCREASE_QUOTA, SE_TCB,
+};
+use crate::processes::{get_all_process_pids, select_process_pid, MatchStrategy};
+use crate::safe_windows_bindings::high_level::{
+    close_handle, get_active_session_id, get_logged_on_session_ids, get_process_token,
+};
+use crate::Elevation;
+use std::fmt::{Display, Formatter};
+
+/// Which session a launch targets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SessionPolicy {
+    /// The session attached to the physical console
+    #[default]
+    ActiveConsole,
+    /// A session by its id
+    Id(u32),
+}
+
+/// Something a launch needs from the current process or the machine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Requirement {
+    /// A privilege of the current process, by name
+    Privilege(&'static str),
+    /// Access to the token of winlogon in the target session
+    WinlogonAccess,
+    /// A user logged on to the target session
+    LoggedOnSession,
+}
+
+impl Display for Requirement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Requirement::Privilege(name) => write!(f, "{name}"),
+            Requirement::WinlogonAccess => write!(f, "access to the token of winlogon"),
+            Requirement::LoggedOnSession => write!(f, "a user logged on to the target session"),
+        }
+    }
+}
+
+/// The outcome of checking a single requirement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    /// What was checked
+    pub requirement: Requirement,
+    /// Whether the requirement is met
+    pub satisfied: bool,
+    /// Why the requirement is not met, or a caveat when it is
+    pub detail: Option<String>,
+}
+
+/// The outcome of [`preflight`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// Every requirement of the launch with its outcome
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Whether every requirement is met
+    pub fn is_ready(&self) -> bool {
+        self.checks.iter().all(|check| check.satisfied)
+    }
+
+    /// The requirements that are not met
+    pub fn missing(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(|check| !check.satisfied)
+    }
+}
+
+/// Checks whether the current process could launch with an elevation into a session, without launching anything
+///
+/// The privileges checked are those of the default **Backend::AsUser**.
+///
+/// # Arguments
+///
+/// * `elevation` - The user the executable would run as.
+/// * `session` - The session the executable would run in.
+pub fn preflight(elevation: &Elevation, session: SessionPolicy) -> Report {
+    let mut checks = Vec::new();
+
+    let mut privileges = vec![SE_ASSIGN_PRIMARY_TOKEN, SE_INCREASE_QUOTA];
+    match elevation {
+        // Service logons of the built-in service accounts need to act as part of the operating system as well
+        Elevation::User
+        | Elevation::Admin
+        | Elevation::LocalService
+        | Elevation::NetworkService => privileges.push(SE_TCB),
+        // Processes of other users can only be opened with the debug privilege
+        Elevation::LocalSystem | Elevation::FromPid(_) | Elevation::FromProcessName(_) => {
+            privileges.push(SE_DEBUG)
+        }
+        Elevation::Credentials(_) => {}
+    }
+
+    match current_privileges() {
+        Ok(held) => {
+            for name in privileges {
+                let found = held.iter().find(|(privilege, _)| privilege == name);
+                checks.push(Check {
+                    requirement: Requirement::Privilege(name),
+                    satisfied: found.is_some(),
+                    detail: match found {
+                        None => Some("The privilege is not held".to_string()),
+                        Some((_, false)) => Some("The privilege is held but disabled".to_string()),
+                        Some((_, true)) => None,
+                    },
+                });
+            }
+        }
+        Err(err) => {
+            for name in privileges {
+                checks.push(Check {
+                    requirement: Requirement::Privilege(name),
+                    satisfied: false,
+                    detail: Some(err.to_string()),
+                });
+            }
+        }
+    }
+
+    // Built-in service accounts, accounts logged on with credentials and those of other processes
+    // do not need a session of their own
+    if let Elevation::LocalService
+    | Elevation::NetworkService
+    | Elevation::Credentials(_)
+    | Elevation::FromPid(_)
+    | Elevation::FromProcessName(_) = elevation
+    {
+        return Report { checks };
+    }
+
+    let outcome = check_logged_on_session(session);
+    checks.push(Check {
+        requirement: Requirement::LoggedOnSession,
+        satisfied: outcome.is_ok(),
+        detail: outcome.err().map(|err| err.to_string()),
+    });
+
+    if let Elevation::LocalSystem = elevation {
+        let outcome = check_winlogon_access(session);
+        checks.push(Check {
+            requirement: Requirement::WinlogonAccess,
+            satisfied: outcome.is_ok(),
+            detail: outcome.err().map(|err| err.to_string()),
+        });
+    }
+
+    Report { checks }
+}
+
+/// Resolves the target session to its id
+fn target_session_id(session: SessionPolicy) -> Result<u32, Error> {
+    match session {
+        SessionPolicy::ActiveConsole => get_active_session_id(),
+        SessionPolicy::Id(session_id) => Ok(session_id),
+    }
+}
+
+/// Checks that a user is logged on to the target session
+fn check_logged_on_session(session: SessionPolicy) -> Result<(), Error> {
+    let session_id = target_session_id(session)?;
+    if !get_logged_on_session_ids()?.contains(&session_id) {
+        return Err(Error::no_interactive_user(Some(session_id)));
+    }
+    Ok(())
+}
+
+/// Checks that the token of winlogon in the target session can be taken
+fn check_winlogon_access(session: SessionPolicy) -> Result<(), Error> {
+    let pid = match session {
+        SessionPolicy::ActiveConsole => {
+            select_process_pid("winlogon", MatchStrategy::InTargetSession, None)?
+        }
+        SessionPolicy::Id(session_id) => get_all_process_pids("winlogon")
+            .into_iter()
+            .find(|process| process.session_id == Some(session_id))
+            .map(|process| process.pid)
+            .ok_or_else(|| {
+                Error::other(
+                    Stage::ProcessLookup,
+                    format!("No winlogon process runs in session {session_id}"),
+                )
+            })?,
+    };
+    let token = get_process_token(pid)?;
+    close_handle(token)
+}
@@ -0,0 +1,35 @@
+use crate::error::Error;
+use crate::safe_windows_bindings::high_level::{
+    close_handle, get_current_process_query_token, get_token_privileges,
+};
+
+/// Allows creating processes with any primary token, used by CreateProcessAsUserW
+pub(crate) const SE_ASSIGN_PRIMARY_TOKEN: &str = "SeAssignPrimaryTokenPrivilege";
+#[cfg(feature = "diagnostics")]
+/// Allows adjusting memory quotas, needed by CreateProcessAsUserW
+pub(crate) const SE_INCREASE_QUOTA: &str = "SeIncreaseQuotaPrivilege";
+#[cfg(feature = "diagnostics")]
+/// Allows opening any process, needed to take the token of winlogon
+pub(crate) const SE_DEBUG: &str = "SeDebugPrivilege";
+/// Allows impersonating clients, used by CreateProcessWithTokenW
+pub(crate) const SE_IMPERSONATE: &str = "SeImpersonatePrivilege";
+/// Allows acting as part of the operating system, needed to get the token of the logged on user
+pub(crate) const SE_TCB: &str = "SeTcbPrivilege";
+
+/// Gets the privileges of the current process, with whether each is enabled
+pub(crate) fn current_privileges() -> Result<Vec<(String, bool)>, Error> {
+    let token = get_current_process_query_token()?;
+    let privileges = get_token_privileges(token);
+    let closed = close_handle(token);
+    match (privileges, closed) {
+        (Ok(privileges), Ok(())) => Ok(privileges),
+        (Ok(_), Err(err)) => Err(err),
+        (Err(err), Ok(())) => Err(err),
+        (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
+    }
+}
+
+/// Whether a privilege is present in a list of privileges, enabled or not
+pub(crate) fn holds(privileges: &[(String, bool)], name: &str) -> bool {
+    privileges.iter().any(|(privilege, _)| privilege == name)
+}
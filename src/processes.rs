@@ -0,0 +1,254 @@
+use crate::error::{Error, Stage};
+use crate::identity::process_identity;
+use crate::safe_windows_bindings::high_level::{
+    close_handle, get_active_session_id, get_all_processes, get_session_user_sid, kill_process,
+    open_process_for_termination, wait_for_process,
+};
+#[cfg(feature = "window")]
+use crate::safe_windows_bindings::high_level::{
+    close_windows_of_process, wait_for_process_timeout,
+};
+use crate::safe_windows_bindings::types::{ERROR_INVALID_PARAMETER, HANDLE};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Information about a running process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessInfo {
+    /// The id of the process
+    pub pid: u32,
+    /// The id of the process that created it, if known
+    pub parent_pid: Option<u32>,
+    /// The name of the process
+    pub name: String,
+    /// The path to the executable of the process, if it could be read
+    pub exe: Option<PathBuf>,
+    /// The time the process started at, in seconds since the unix epoch
+    pub start_time: u64,
+    /// The terminal services session the process runs in, if it could be queried
+    pub session_id: Option<u32>,
+}
+
+/// How to pick one process when several match a name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MatchStrategy {
+    /// The first match reported by the system, whose order is unspecified
+    First,
+    /// The most recently started match
+    Newest,
+    /// A match running in the session the launch targets, the active console session by default
+    #[default]
+    InTargetSession,
+}
+
+/// Gets all running processes whose name contains the provided one
+pub fn get_all_process_pids(process_name: &str) -> Vec<ProcessInfo> {
+    get_all_processes()
+        .into_iter()
+        .filter(|process| process.name.contains(process_name))
+        .collect()
+}
+
+/// Gets the pid of a process by name, picking among multiple matches with a strategy
+///
+/// The target session is the active console session unless another one is given
+pub(crate) fn select_process_pid(
+    process_name: &str,
+    strategy: MatchStrategy,
+    target_session: Option<u32>,
+) -> Result<u32, Error> {
+    let processes = get_all_process_pids(process_name);
+
+    let selected = match strategy {
+        MatchStrategy::First => processes.first(),
+        MatchStrategy::Newest => processes.iter().max_by_key(|process| process.start_time),
+        MatchStrategy::InTargetSession => {
+            let session_id = match target_session {
+                Some(session_id) => session_id,
+                None => get_active_session_id()?,
+            };
+            processes
+                .iter()
+                .find(|process| process.session_id == Some(session_id))
+        }
+    };
+
+    match selected {
+        None => Err(Error::other(
+            Stage::ProcessLookup,
+            format!("No running processes by the name: {process_name}"),
+        )),
+        Some(process) => Ok(process.pid),
+    }
+}
+
+/// Gets the pid of a process running an executable in a session as a user, if there is one
+pub(crate) fn find_running_instance(executable: &OsStr, sid: &str, session_id: u32) -> Option<u32> {
+    let executable = normalize_path(Path::new(executable));
+    get_all_processes()
+        .into_iter()
+        .filter(|process| process.session_id == Some(session_id))
+        .filter(|process| {
+            process
+                .exe
+                .as_deref()
+                .is_some_and(|exe| normalize_path(exe) == executable)
+        })
+        // Processes whose owner cannot be read are not counted, as they cannot be attributed
+        .find(|process| process_identity(process.pid).is_ok_and(|identity| identity.sid == sid))
+        .map(|process| process.pid)
+}
+
+/// Lists the running processes descending from a process, each after the one that started it
+///
+/// Processes whose parent pid was reused are told apart by their start time, as a child cannot
+/// start before its parent. Children whose parent already exited are no longer found.
+pub(crate) fn descendants_of(pid: u32) -> Vec<ProcessInfo> {
+    let mut processes = get_all_processes();
+    let Some(root) = processes.iter().find(|process| process.pid == pid) else {
+        return Vec::new();
+    };
+
+    let mut parents = vec![(root.pid, root.start_time)];
+    let mut descendants = Vec::new();
+    while let Some((parent_pid, parent_start_time)) = parents.pop() {
+        let (children, others): (Vec<_>, Vec<_>) = processes.into_iter().partition(|process| {
+            process.parent_pid == Some(parent_pid)
+                && process.pid != parent_pid
+                && process.start_time >= parent_start_time
+        });
+        processes = others;
+        parents.extend(children.iter().map(|child| (child.pid, child.start_time)));
+        descendants.extend(children);
+    }
+    descendants
+}
+
+/// Stops the processes by a name running in a session as the user logged on to it
+///
+/// Returns the pids of the processes that were stopped. Processes of other accounts, e.g. a
+/// system process by the same name, and processes whose owner cannot be read are left alone.
+///
+/// # Arguments
+///
+/// * `session_id` - Id of the session.
+/// * `process_name` - Name of the processes, e.g. `app.exe`, compared ignoring case.
+/// * `grace` - How long to let the processes close their windows before terminating them, if at all.
+///   Only windows on the desktop of the calling thread can be asked to close, so a service usually
+///   ends up terminating processes in user sessions once the grace period elapses.
+///
+/// ```no_run
+/// # fn main() -> Result<(), win_run::Error> {
+/// use std::time::Duration;
+/// use win_run::kill_in_session;
+///
+/// let stopped = kill_in_session(2, "tray.exe", Some(Duration::from_secs(5)))?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn kill_in_session(
+    session_id: u32,
+    process_name: &str,
+    grace: Option<Duration>,
+) -> Result<Vec<u32>, Error> {
+    let sid = get_session_user_sid(session_id)?;
+
+    let pids: Vec<u32> = get_all_processes()
+        .into_iter()
+        .filter(|process| process.session_id == Some(session_id))
+        .filter(|process| process.name.eq_ignore_ascii_case(process_name))
+        .filter(|process| process_identity(process.pid).is_ok_and(|identity| identity.sid == sid))
+        .map(|process| process.pid)
+        .collect();
+
+    let mut errors = Vec::new();
+    for pid in &pids {
+        let stopped = match grace {
+            Some(timeout) => shut_down_process(*pid, timeout),
+            None => terminate_process(*pid),
+        };
+        if let Err(err) = stopped {
+            errors.push(err);
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(Error::combine(errors));
+    }
+    Ok(pids)
+}
+
+/// Terminates processes by pid right away and waits for them to exit
+///
+/// Processes that exited already are skipped.
+pub(crate) fn terminate_processes(pids: &[u32]) -> Result<(), Error> {
+    let errors: Vec<Error> = pids
+        .iter()
+        .filter_map(|pid| terminate_process(*pid).err())
+        // Opening a process that no longer exists fails with ERROR_INVALID_PARAMETER
+        .filter(|err| err.code() != Some(ERROR_INVALID_PARAMETER.0 as i32))
+        .collect();
+
+    if !errors.is_empty() {
+        return Err(Error::combine(errors));
+    }
+    Ok(())
+}
+
+/// Terminates a process by pid right away and waits for it to exit
+fn terminate_process(pid: u32) -> Result<(), Error> {
+    let process_handle = open_process_for_termination(pid)?;
+    let stopped = kill_process(process_handle, 1).and_then(|()| wait_for_process(process_handle));
+    match (stopped, close_handle(process_handle)) {
+        (Ok(()), Ok(())) => Ok(()),
+        (Ok(()), Err(err)) => Err(err),
+        (Err(err), Ok(())) => Err(err),
+        (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
+    }
+}
+
+/// Makes paths comparable the way Windows compares them, resolving them where possible
+fn normalize_path(path: &Path) -> String {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    path.to_string_lossy().to_lowercase()
+}
+
+/// Stops a process, asking its windows to close first and terminating it if it does not exit in time
+///
+/// Only windows on the desktop of the calling thread can be asked to close, so processes on other
+/// desktops, like those of user sessions seen from a service, are terminated once the timeout elapses.
+pub(crate) fn shut_down_process(pid: u32, timeout: Duration) -> Result<(), Error> {
+    let process_handle = open_process_for_termination(pid)?;
+
+    let stopped = close_gracefully(pid, process_handle, timeout).and_then(|closed| {
+        if closed {
+            return Ok(());
+        }
+        kill_process(process_handle, 1)?;
+        wait_for_process(process_handle)
+    });
+
+    match (stopped, close_handle(process_handle)) {
+        (Ok(()), Ok(())) => Ok(()),
+        (Ok(()), Err(err)) => Err(err),
+        (Err(err), Ok(())) => Err(err),
+        (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
+    }
+}
+
+/// Asks the windows of a process to close and waits for it to exit, returning whether it did
+#[cfg(feature = "window")]
+fn close_gracefully(pid: u32, process_handle: HANDLE, timeout: Duration) -> Result<bool, Error> {
+    if close_windows_of_process(pid)? == 0 {
+        return Ok(false);
+    }
+    let milliseconds = timeout.as_millis().min(u32::MAX as u128 - 1) as u32;
+    wait_for_process_timeout(process_handle, milliseconds)
+}
+
+/// Without window lookup there is no way to ask a process to close
+#[cfg(not(feature = "window"))]
+fn close_gracefully(_pid: u32, _process_handle: HANDLE, _timeout: Duration) -> Result<bool, Error> {
+    Ok(false)
+}
@@ -0,0 +1,259 @@
+use crate::child::Child;
+use crate::environment::service_environment;
+use crate::error::{Error, Stage};
+use crate::safe_windows_bindings::high_level::{
+    close_handle, create_account_profile, delete_account_profile, get_profile_directory,
+    get_token_user_account, get_token_user_sid, load_account_profile, restrict_directory_to_user,
+    unload_account_profile,
+};
+use crate::safe_windows_bindings::types::HANDLE;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How many times removing a profile is attempted, as processes the child started may still hold files in it
+const REMOVE_ATTEMPTS: u32 = 10;
+/// How long to wait between attempts to remove a profile
+const REMOVE_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tells apart the profiles created by the service within the same instant
+static PROFILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A throwaway profile directory standing in for the profile of the user, removed when dropped
+pub(crate) struct TemporaryProfile {
+    directory: PathBuf,
+}
+
+impl TemporaryProfile {
+    /// Creates an empty profile in the temporary directory of the service, only accessible to the user
+    pub(crate) fn create(user_sid: &str) -> Result<Self, Error> {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_nanos();
+        let counter = PROFILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let directory = std::env::temp_dir().join(format!(
+            "win_run-profile-{}-{created}-{counter}",
+            std::process::id()
+        ));
+
+        let profile = Self { directory };
+        for folder in profile.folders() {
+            fs::create_dir_all(&folder).map_err(|err| {
+                Error::other(
+                    Stage::ProfileSetup,
+                    format!(
+                        "Unable to create profile directory {}: {err}",
+                        folder.display()
+                    ),
+                )
+            })?;
+        }
+        restrict_directory_to_user(&profile.path()?, user_sid)?;
+        Ok(profile)
+    }
+
+    /// The variables pointing the process at the profile instead of the user's real one
+    ///
+    /// The other variables are those of the launch, the service's if it has none.
+    pub(crate) fn environment(
+        &self,
+        environment: Option<Vec<(OsString, OsString)>>,
+    ) -> Result<Vec<(OsString, OsString)>, Error> {
+        Ok(profile_environment(&self.path()?, environment))
+    }
+
+    /// Removes the profile once the process exits, from a thread of its own
+    pub(crate) fn remove_after_exit(self, pid: u32) {
+        // A process the crate cannot wait for, e.g. a fake of a test backend, has the profile removed right away
+        let child = Child::from_pid(pid).ok();
+        spawn(move || {
+            if let Some(child) = child {
+                let _ = child.wait_for_exit();
+            }
+            drop(self);
+        });
+    }
+
+    /// The directories of a profile that programs expect to exist
+    fn folders(&self) -> [PathBuf; 3] {
+        [
+            self.directory.join("AppData").join("Roaming"),
+            self.directory.join("AppData").join("Local").join("Temp"),
+            self.directory.join("Desktop"),
+        ]
+    }
+
+    fn path(&self) -> Result<String, Error> {
+        path_to_string(&self.directory)
+    }
+}
+
+impl Drop for TemporaryProfile {
+    fn drop(&mut self) {
+        for attempt in 1..=REMOVE_ATTEMPTS {
+            if fs::remove_dir_all(&self.directory).is_ok() || !self.directory.exists() {
+                return;
+            }
+            if attempt < REMOVE_ATTEMPTS {
+                sleep(REMOVE_RETRY_INTERVAL);
+            }
+        }
+    }
+}
+
+/// The real profile of an account, created for launches of accounts that never logged on
+///
+/// Deleted when dropped if it was created for the launch and is meant to be thrown away.
+pub(crate) struct AccountProfile {
+    sid: String,
+    directory: String,
+    /// Whether the profile is deleted when dropped
+    delete: bool,
+}
+
+impl AccountProfile {
+    /// Creates the profile of the user of the token if it does not exist yet
+    ///
+    /// Profiles that existed already are never deleted, whatever `delete` says.
+    pub(crate) fn ensure(token: HANDLE, username: &str, delete: bool) -> Result<Self, Error> {
+        let sid = get_token_user_sid(token)?;
+        let created = create_account_profile(&sid, username)?;
+        let mut profile = Self {
+            sid,
+            directory: String::new(),
+            delete: delete && created,
+        };
+        // Dropping the profile on failure deletes what was just created
+        profile.directory = get_profile_directory(token)?;
+        Ok(profile)
+    }
+
+    /// The variables pointing the process at the profile instead of the service's one
+    pub(crate) fn environment(
+        &self,
+        environment: Option<Vec<(OsString, OsString)>>,
+    ) -> Vec<(OsString, OsString)> {
+        profile_environment(&self.directory, environment)
+    }
+
+    /// Deletes the profile once the process exits if it is meant to be thrown away, from a thread of its own
+    pub(crate) fn delete_after_exit(self, pid: u32) {
+        if !self.delete {
+            return;
+        }
+        let child = Child::from_pid(pid).ok();
+        spawn(move || {
+            if let Some(child) = child {
+                let _ = child.wait_for_exit();
+            }
+            drop(self);
+        });
+    }
+}
+
+impl Drop for AccountProfile {
+    fn drop(&mut self) {
+        if !self.delete {
+            return;
+        }
+        // The profile stays loaded for a moment after the last process of the user exits
+        for attempt in 1..=REMOVE_ATTEMPTS {
+            if delete_account_profile(&self.sid).is_ok() {
+                return;
+            }
+            if attempt < REMOVE_ATTEMPTS {
+                sleep(REMOVE_RETRY_INTERVAL);
+            }
+        }
+    }
+}
+
+/// The profile of the user of a launch token, loaded for the process and unloaded when dropped
+///
+/// Keeps the token open, as unloading needs the one the profile was loaded with.
+#[derive(Debug)]
+pub(crate) struct LoadedProfile {
+    token: HANDLE,
+    profile: HANDLE,
+}
+
+impl LoadedProfile {
+    /// Loads the profile of the user of the token, taking ownership of the token
+    ///
+    /// The token is closed if the profile cannot be loaded.
+    pub(crate) fn load(token: HANDLE) -> Result<Self, Error> {
+        let profile = get_token_user_account(token)
+            .and_then(|(username, _)| load_account_profile(token, &username));
+        match profile {
+            Ok(profile) => Ok(Self { token, profile }),
+            Err(err) => match close_handle(token) {
+                Ok(()) => Err(err),
+                Err(cleanup) => Err(Error::combine(vec![err, cleanup])),
+            },
+        }
+    }
+
+    /// Unloads the profile once the process exits, from a thread of its own
+    pub(crate) fn unload_after_exit(self, pid: u32) {
+        // A process that already exited has the profile unloaded right away
+        let child = Child::from_pid(pid).ok();
+        spawn(move || {
+            if let Some(child) = child {
+                let _ = child.wait_for_exit();
+            }
+            drop(self);
+        });
+    }
+}
+
+impl Drop for LoadedProfile {
+    fn drop(&mut self) {
+        // Nothing sensible can be done if unloading fails here
+        let _ = unload_account_profile(self.token, self.profile);
+        let _ = close_handle(self.token);
+    }
+}
+
+/// The variables of a launch, the service's if it has none, with those pointing at the profile
+/// replaced by ones pointing at another
+fn profile_environment(
+    profile: &str,
+    environment: Option<Vec<(OsString, OsString)>>,
+) -> Vec<(OsString, OsString)> {
+    let overrides = [
+        ("USERPROFILE", profile.to_string()),
+        ("APPDATA", format!("{profile}\\AppData\\Roaming")),
+        ("LOCALAPPDATA", format!("{profile}\\AppData\\Local")),
+        ("TEMP", format!("{profile}\\AppData\\Local\\Temp")),
+        ("TMP", format!("{profile}\\AppData\\Local\\Temp")),
+    ];
+
+    let mut environment: Vec<(OsString, OsString)> = environment
+        .unwrap_or_else(service_environment)
+        .into_iter()
+        .filter(|(name, _)| {
+            !overrides
+                .iter()
+                .any(|(overridden, _)| name.eq_ignore_ascii_case(overridden))
+        })
+        .collect();
+    environment.extend(
+        overrides
+            .into_iter()
+            .map(|(name, value)| (name.into(), value.into())),
+    );
+    environment
+}
+
+fn path_to_string(path: &Path) -> Result<String, Error> {
+    path.to_str().map(str::to_string).ok_or_else(|| {
+        Error::other(
+            Stage::ProfileSetup,
+            format!("The profile path is not valid UTF-8: {}", path.display()),
+        )
+    })
+}
@@ -0,0 +1,109 @@
+use crate::child::Child;
+use crate::error::Error;
+use crate::events::LaunchEvent;
+use crate::ProcessBuilder;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{spawn, JoinHandle};
+
+/// A launch waiting in a queue, with where to deliver its result
+struct QueuedLaunch {
+    builder: ProcessBuilder,
+    result: Sender<Result<Child, Error>>,
+}
+
+/// Runs launches in the order they were pushed, at most a fixed number at a time
+///
+/// A launch occupies its slot until the launched process exits, so with a single slot
+/// no two queued processes ever run at the same time. The result of each launch is
+/// delivered through the receiver returned by [`LaunchQueue::push`] once its process
+/// has exited, or right away if the launch failed.
+///
+/// Dropping the queue waits for the launches that were already pushed.
+///
+/// ```no_run
+/// use win_run::{win, LaunchQueue};
+///
+/// let queue = LaunchQueue::new(1);
+///
+/// let cleanup = queue.push(win("/path/to/cleanup"));
+/// let defrag = queue.push(win("/path/to/defrag"));
+///
+/// // The defragmentation only starts once the cleanup has exited
+/// let _ = cleanup.recv();
+/// let _ = defrag.recv();
+/// ```
+pub struct LaunchQueue {
+    /// Where launches are pushed, taken when the queue is dropped
+    sender: Option<Sender<QueuedLaunch>>,
+    /// The threads running the launches, one per slot
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl LaunchQueue {
+    /// Creates a queue running at most `slots` launches at a time
+    ///
+    /// A queue needs at least one slot, fewer are treated as one.
+    pub fn new(slots: usize) -> Self {
+        let (sender, receiver) = channel::<QueuedLaunch>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..slots.max(1))
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                spawn(move || Self::work(&receiver))
+            })
+            .collect();
+
+        Self {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    /// Queues a launch and returns where its result will be delivered
+    pub fn push(&self, builder: ProcessBuilder) -> Receiver<Result<Child, Error>> {
+        let (result, receiver) = channel();
+        if let Some(events) = &builder.events {
+            events.send(LaunchEvent::LaunchQueued {
+                path: events.path(),
+            });
+        }
+        if let Some(sender) = &self.sender {
+            // The workers only stop once the queue is dropped
+            let _ = sender.send(QueuedLaunch { builder, result });
+        }
+        receiver
+    }
+
+    /// Takes launches off the queue one by one until it is closed
+    fn work(receiver: &Mutex<Receiver<QueuedLaunch>>) {
+        loop {
+            // Hold the lock only while taking a launch, so other slots can take the next one
+            let queued = match receiver.lock() {
+                Ok(receiver) => receiver.recv(),
+                Err(_) => return,
+            };
+            let Ok(QueuedLaunch { builder, result }) = queued else {
+                return;
+            };
+
+            let outcome = builder
+                .spawn()
+                .and_then(|child| child.wait_for_exit().map(|()| child));
+
+            // Nobody may be listening for the result anymore
+            let _ = result.send(outcome);
+        }
+    }
+}
+
+impl Drop for LaunchQueue {
+    fn drop(&mut self) {
+        // Closing the queue lets the workers finish what was pushed and stop
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
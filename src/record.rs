@@ -0,0 +1,128 @@
+use crate::error::{Error, Stage};
+use crate::{elevation_name, parse_elevation, win, Elevation, ProcessBuilder};
+
+/// How the `key=value` lines of a kind of launch are reported in errors
+pub(crate) struct RecordKind {
+    /// The stage errors are reported at
+    pub(crate) stage: Stage,
+    /// What the lines hold, e.g. `launch request`
+    pub(crate) name: &'static str,
+    /// Why launching with credentials cannot be written to the lines
+    pub(crate) no_credentials: &'static str,
+}
+
+/// A launch as it is written to `key=value` lines, by launch requests and queued launches
+///
+/// No value can contain a line break, and only elevations with a name can be written.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LaunchRecord {
+    /// Path to the executable
+    pub(crate) path: String,
+    /// Arguments for the executable, already quoted
+    pub(crate) args: String,
+    /// Working directory of the process
+    pub(crate) directory: Option<String>,
+    /// Elevation of the process, one of `User`, `Admin` and `LocalSystem`
+    pub(crate) elevation: Elevation,
+    /// Session to launch into
+    pub(crate) session: Option<u32>,
+}
+
+impl LaunchRecord {
+    /// Creates the builder the launch is run with
+    pub(crate) fn builder(&self) -> ProcessBuilder {
+        // The arguments arrive already joined, as the client quoted them
+        let mut builder = win(&self.path)
+            .raw_arg(&self.args)
+            .elevation(self.elevation.clone());
+        if let Some(directory) = &self.directory {
+            builder = builder.directory(directory);
+        }
+        if let Some(session) = self.session {
+            builder = builder.session(session);
+        }
+        builder
+    }
+
+    /// Serializes the launch into its `key=value` lines, along with fields of the kind
+    pub(crate) fn encode(
+        &self,
+        kind: &RecordKind,
+        extra: Vec<(&'static str, String)>,
+    ) -> Result<String, Error> {
+        let elevation = elevation_name(&self.elevation)
+            .ok_or_else(|| Error::other(kind.stage, kind.no_credentials))?;
+
+        let mut fields = vec![
+            ("path", self.path.clone()),
+            ("args", self.args.clone()),
+            ("elevation", elevation.to_string()),
+        ];
+        if let Some(directory) = &self.directory {
+            fields.push(("directory", directory.clone()));
+        }
+        if let Some(session) = self.session {
+            fields.push(("session", session.to_string()));
+        }
+        fields.extend(extra);
+
+        let mut text = String::new();
+        for (key, value) in fields {
+            if value.contains(['\r', '\n']) {
+                return Err(Error::other(
+                    kind.stage,
+                    format!("The {key} of a {} cannot contain line breaks", kind.name),
+                ));
+            }
+            text.push_str(&format!("{key}={value}\n"));
+        }
+        Ok(text)
+    }
+
+    /// Parses a launch from its `key=value` lines
+    ///
+    /// Fields of the kind are passed to `extra`, which returns whether it knew the field.
+    pub(crate) fn decode(
+        text: &str,
+        kind: &RecordKind,
+        mut extra: impl FnMut(&str, &str) -> Result<bool, Error>,
+    ) -> Result<Self, Error> {
+        let invalid = |message: String| Error::other(kind.stage, message);
+
+        let mut record = Self::default();
+        let mut has_path = false;
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("Malformed line in {}: {line}", kind.name)))?;
+            match key {
+                "path" => {
+                    record.path = value.to_string();
+                    has_path = true;
+                }
+                "args" => record.args = value.to_string(),
+                "directory" => record.directory = Some(value.to_string()),
+                "elevation" => {
+                    record.elevation = parse_elevation(value)
+                        .ok_or_else(|| invalid(format!("Unknown elevation: {value}")))?
+                }
+                "session" => {
+                    let session = value
+                        .parse()
+                        .map_err(|_| invalid(format!("Invalid session id: {value}")))?;
+                    record.session = Some(session);
+                }
+                _ => {
+                    if !extra(key, value)? {
+                        return Err(invalid(format!("Unknown field in {}: {key}", kind.name)));
+                    }
+                }
+            }
+        }
+
+        if !has_path {
+            return Err(invalid(format!("The {} has no path", kind.name)));
+        }
+        Ok(record)
+    }
+}
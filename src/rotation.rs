@@ -0,0 +1,308 @@
+use crate::error::{Error, Stage};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+/// A log file receiving an output stream of the process, rotated once it grows too large or too old
+///
+/// Rotated files get the suffixes `.1`, `.2` and so on, `.1` being the most recent one.
+/// Give every stream a log file of its own, as two streams writing one log rotate it independently.
+#[derive(Debug, Clone)]
+pub struct RotatingLog {
+    /// The path of the file currently written to
+    pub(crate) path: String,
+    /// The size above which the file is rotated
+    pub(crate) max_size: u64,
+    /// The age above which the file is rotated, if any
+    pub(crate) max_age: Option<Duration>,
+    /// How many rotated files are kept
+    pub(crate) keep: usize,
+}
+
+impl RotatingLog {
+    /// Creates a rotating log with default limits
+    pub fn new(path: impl AsRef<str>) -> Self {
+        let path = path.as_ref().to_string();
+        let max_size = 10 * 1024 * 1024;
+        let max_age = None;
+        let keep = 5;
+
+        Self {
+            path,
+            max_size,
+            max_age,
+            keep,
+        }
+    }
+
+    /// Sets the size in bytes above which the file is rotated, default is **10 MiB**
+    ///
+    /// A single write larger than the limit still goes to one file
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Sets the age above which the file is rotated, default is **no limit**
+    ///
+    /// The age of a file that already exists is counted from its creation
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Sets how many rotated files are kept before the oldest is deleted, default is **5**
+    pub fn keep(mut self, keep: usize) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    /// The path of the n-th most recent rotated file
+    fn rotated_path(&self, n: usize) -> String {
+        format!("{}.{n}", self.path)
+    }
+}
+
+/// Writes to a rotating log, rotating it between writes
+pub(crate) struct RotatingWriter {
+    log: RotatingLog,
+    /// The file currently written to, only missing while rotating
+    file: Option<File>,
+    /// The size of the current file
+    size: u64,
+    /// When the current file was started
+    started: SystemTime,
+}
+
+impl RotatingWriter {
+    /// Opens the log, appending to the file if it exists
+    pub(crate) fn open(log: RotatingLog) -> Result<Self, Error> {
+        let file = open_log_file(&log.path).map_err(|err| {
+            Error::other(
+                Stage::Redirection,
+                format!("Unable to open log file {}: {err}", log.path),
+            )
+        })?;
+
+        let metadata = file.metadata().ok();
+        let size = metadata.as_ref().map_or(0, |metadata| metadata.len());
+        let started = metadata
+            .and_then(|metadata| metadata.created().ok())
+            .unwrap_or_else(SystemTime::now);
+
+        Ok(Self {
+            log,
+            file: Some(file),
+            size,
+            started,
+        })
+    }
+
+    /// Whether the current file has to be rotated before writing the given number of bytes to it
+    fn needs_rotation(&self, incoming: usize) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        let too_large = self.size.saturating_add(incoming as u64) > self.log.max_size;
+        let too_old = self.log.max_age.is_some_and(|max_age| {
+            self.started
+                .elapsed()
+                .is_ok_and(|elapsed| elapsed >= max_age)
+        });
+        too_large || too_old
+    }
+
+    /// Moves every file one suffix up, dropping the oldest, and starts a new file
+    fn rotate(&mut self) -> std::io::Result<()> {
+        // Files cannot be renamed while open
+        self.file = None;
+
+        if self.log.keep == 0 {
+            std::fs::remove_file(&self.log.path)?;
+        } else {
+            let oldest = self.log.rotated_path(self.log.keep);
+            if std::path::Path::new(&oldest).exists() {
+                std::fs::remove_file(&oldest)?;
+            }
+            for n in (1..self.log.keep).rev() {
+                let path = self.log.rotated_path(n);
+                if std::path::Path::new(&path).exists() {
+                    std::fs::rename(&path, self.log.rotated_path(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.log.path, self.log.rotated_path(1))?;
+        }
+
+        self.file = Some(open_log_file(&self.log.path)?);
+        self.size = 0;
+        self.started = SystemTime::now();
+        Ok(())
+    }
+}
+
+impl Write for RotatingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.needs_rotation(buf.len()) {
+            self.rotate()?;
+        }
+        let file = match &mut self.file {
+            Some(file) => file,
+            // A failed rotation leaves no file, so try carrying on with whichever is at the path
+            None => {
+                let file = open_log_file(&self.log.path)?;
+                self.size = file.metadata().map_or(0, |metadata| metadata.len());
+                self.file.insert(file)
+            }
+        };
+        let written = file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Opens a log file for appending, creating it if it does not exist
+fn open_log_file(path: &str) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// A directory of its own for every test, removed once the test is done
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("win_run-rotation-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn log(&self) -> String {
+            self.0.join("output.log").to_string_lossy().into_owned()
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn read(path: &str) -> Option<String> {
+        fs::read_to_string(path).ok()
+    }
+
+    #[test]
+    fn names_rotated_files_by_recency() {
+        let log = RotatingLog::new("C:\\Logs\\worker.log");
+        assert_eq!(log.rotated_path(1), "C:\\Logs\\worker.log.1");
+        assert_eq!(log.rotated_path(12), "C:\\Logs\\worker.log.12");
+    }
+
+    #[test]
+    fn rotates_once_the_size_is_exceeded() {
+        let dir = TestDir::new("size");
+        let path = dir.log();
+        let mut writer = RotatingWriter::open(RotatingLog::new(&path).max_size(8).keep(2)).unwrap();
+
+        for line in ["one\n", "two\n", "three\n", "four\n", "five\n"] {
+            writer.write_all(line.as_bytes()).unwrap();
+        }
+        writer.flush().unwrap();
+
+        assert_eq!(read(&path).as_deref(), Some("five\n"));
+        assert_eq!(read(&format!("{path}.1")).as_deref(), Some("four\n"));
+        assert_eq!(read(&format!("{path}.2")).as_deref(), Some("three\n"));
+        // Only as many rotated files as asked for are kept
+        assert_eq!(read(&format!("{path}.3")), None);
+    }
+
+    #[test]
+    fn writes_that_fit_go_to_the_same_file() {
+        let dir = TestDir::new("fit");
+        let path = dir.log();
+        let mut writer = RotatingWriter::open(RotatingLog::new(&path).max_size(8)).unwrap();
+
+        writer.write_all(b"one\n").unwrap();
+        writer.write_all(b"two\n").unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(read(&path).as_deref(), Some("one\ntwo\n"));
+        assert_eq!(read(&format!("{path}.1")), None);
+    }
+
+    #[test]
+    fn never_rotates_an_empty_file() {
+        let dir = TestDir::new("empty");
+        let path = dir.log();
+        let mut writer = RotatingWriter::open(RotatingLog::new(&path).max_size(4)).unwrap();
+
+        writer.write_all(b"larger than the limit\n").unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(read(&path).as_deref(), Some("larger than the limit\n"));
+        assert_eq!(read(&format!("{path}.1")), None);
+    }
+
+    #[test]
+    fn keeping_no_files_starts_over() {
+        let dir = TestDir::new("keep-none");
+        let path = dir.log();
+        let log = RotatingLog::new(&path).max_size(4).keep(0);
+        let mut writer = RotatingWriter::open(log).unwrap();
+
+        writer.write_all(b"old\n").unwrap();
+        writer.write_all(b"new\n").unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(read(&path).as_deref(), Some("new\n"));
+        assert_eq!(read(&format!("{path}.1")), None);
+    }
+
+    #[test]
+    fn rotates_once_the_age_is_exceeded() {
+        let dir = TestDir::new("age");
+        let path = dir.log();
+        let log = RotatingLog::new(&path).max_age(Duration::ZERO);
+        let mut writer = RotatingWriter::open(log).unwrap();
+
+        writer.write_all(b"first\n").unwrap();
+        writer.write_all(b"second\n").unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(read(&path).as_deref(), Some("second\n"));
+        assert_eq!(read(&format!("{path}.1")).as_deref(), Some("first\n"));
+    }
+
+    #[test]
+    fn appends_to_an_existing_file_counting_its_size() {
+        let dir = TestDir::new("existing");
+        let path = dir.log();
+        fs::write(&path, "earlier\n").unwrap();
+
+        let log = RotatingLog::new(&path).max_size(12);
+        let mut writer = RotatingWriter::open(log).unwrap();
+        writer.write_all(b"now\n").unwrap();
+        writer.write_all(b"later\n").unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(read(&path).as_deref(), Some("later\n"));
+        assert_eq!(
+            read(&format!("{path}.1")).as_deref(),
+            Some("earlier\nnow\n")
+        );
+    }
+}
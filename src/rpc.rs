@@ -0,0 +1,290 @@
+use crate::child::Child;
+use crate::error::Error;
+use crate::json::Json;
+use crate::parse_elevation;
+use crate::policy::PipeClient;
+use crate::safe_windows_bindings::high_level::has_exited;
+use crate::safe_windows_bindings::types::HANDLE;
+use crate::server::{LaunchMessage, LaunchServer};
+use std::sync::MutexGuard;
+
+/// The version of the JSON-RPC protocol the server speaks
+///
+/// Only ever raised for changes that older clients would misunderstand,
+/// new optional members and methods keep the version.
+pub(crate) const PROTOCOL_VERSION: u32 = 1;
+
+/// The request is not valid JSON
+const PARSE_ERROR: i32 = -32700;
+/// The request is not a JSON-RPC 2.0 request
+const INVALID_REQUEST: i32 = -32600;
+/// There is no method by the name
+const METHOD_NOT_FOUND: i32 = -32601;
+/// The parameters are missing or of the wrong type
+const INVALID_PARAMS: i32 = -32602;
+/// Launching, querying or killing failed, or the policy denied it
+const SERVER_ERROR: i32 = -32000;
+/// The pid is not of a process the client launched through the server
+const UNKNOWN_CHILD: i32 = -32001;
+/// The client asked for a protocol version the server does not speak
+const UNSUPPORTED_PROTOCOL: i32 = -32002;
+
+/// A process launched through the server
+pub(crate) struct ServedChild {
+    pub(crate) child: Child,
+    /// The path the process was launched from
+    pub(crate) path: String,
+    /// SID of the client that asked for the launch
+    pub(crate) owner: String,
+}
+
+/// A failed call, answered with a JSON-RPC error object
+struct RpcError {
+    code: i32,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<Error> for RpcError {
+    fn from(err: Error) -> Self {
+        Self::new(SERVER_ERROR, err.to_string())
+    }
+}
+
+/// Keeps a launched process for clients to query and kill, forgetting the ones that have exited
+///
+/// Processes nobody asks about would otherwise hold on to their handles while the server runs
+pub(crate) fn track(server: &LaunchServer, child: Child, path: String, owner: String) {
+    let mut children = lock_children(server);
+    // Keep the processes whose state cannot be told, as they may still be running
+    children.retain(|served| !has_exited(served.child.handle).unwrap_or(false));
+    children.push(ServedChild { child, path, owner });
+}
+
+/// Answers a JSON-RPC 2.0 request
+pub(crate) fn reply(server: &LaunchServer, pipe: HANDLE, request: &[u8]) -> String {
+    let request = std::str::from_utf8(request)
+        .map_err(|_| "The request is not UTF-8".to_string())
+        .and_then(Json::parse);
+
+    let (id, result) = match request {
+        Ok(request) => {
+            let id = request.get("id").cloned().unwrap_or(Json::Null);
+            (id, call(server, pipe, &request))
+        }
+        Err(message) => (Json::Null, Err(RpcError::new(PARSE_ERROR, message))),
+    };
+
+    let outcome = match result {
+        Ok(result) => ("result", result),
+        Err(err) => (
+            "error",
+            Json::object([
+                ("code", Json::Number(err.code as f64)),
+                ("message", Json::String(err.message)),
+            ]),
+        ),
+    };
+    Json::object([("jsonrpc", "2.0".into()), ("id", id), outcome]).to_string()
+}
+
+/// Checks the envelope of a request and calls its method
+fn call(server: &LaunchServer, pipe: HANDLE, request: &Json) -> Result<Json, RpcError> {
+    if request.get("jsonrpc").and_then(Json::as_str) != Some("2.0") {
+        return Err(RpcError::new(
+            INVALID_REQUEST,
+            "The request is not a JSON-RPC 2.0 request",
+        ));
+    }
+    let method = request
+        .get("method")
+        .and_then(Json::as_str)
+        .ok_or_else(|| RpcError::new(INVALID_REQUEST, "The request has no method"))?;
+
+    let no_params = Json::Object(Vec::new());
+    let params = request.get("params").unwrap_or(&no_params);
+    if !matches!(params, Json::Object(_)) {
+        return Err(RpcError::new(
+            INVALID_PARAMS,
+            "The parameters must be an object",
+        ));
+    }
+    if let Some(protocol) = params.get("protocol") {
+        if !protocol
+            .as_u32()
+            .is_some_and(|protocol| (1..=PROTOCOL_VERSION).contains(&protocol))
+        {
+            return Err(RpcError::new(
+                UNSUPPORTED_PROTOCOL,
+                format!("The server speaks protocol versions 1 to {PROTOCOL_VERSION}"),
+            ));
+        }
+    }
+
+    match method {
+        "version" => Ok(Json::object([("protocol", PROTOCOL_VERSION.into())])),
+        "launch" => launch(server, pipe, params),
+        "status" => status(server, pipe, params),
+        "kill" => kill(server, pipe, params),
+        "list_children" => list_children(server, pipe),
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("There is no method {method}"),
+        )),
+    }
+}
+
+/// `launch {path, args?, directory?, elevation?, session?}` -> `{pid}`
+fn launch(server: &LaunchServer, pipe: HANDLE, params: &Json) -> Result<Json, RpcError> {
+    let mut message = LaunchMessage::new(
+        string_param(params, "path")?
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, "The parameter path is missing"))?,
+    );
+    if let Some(args) = string_param(params, "args")? {
+        message.args = args.to_string();
+    }
+    message.directory = string_param(params, "directory")?.map(str::to_string);
+    if let Some(elevation) = string_param(params, "elevation")? {
+        message.elevation = parse_elevation(elevation).ok_or_else(|| {
+            RpcError::new(INVALID_PARAMS, format!("Unknown elevation: {elevation}"))
+        })?;
+    }
+    message.session = u32_param(params, "session")?;
+
+    let pid = server.launch(pipe, message)?;
+    Ok(Json::object([("pid", pid.into())]))
+}
+
+/// `status {pid}` -> `{pid, path, running}`
+///
+/// A process is forgotten once it was reported to have exited
+fn status(server: &LaunchServer, pipe: HANDLE, params: &Json) -> Result<Json, RpcError> {
+    let pid = pid_param(params)?;
+    let owner = client_filter(pipe)?;
+
+    let mut children = lock_children(server);
+    let index = find_child(&children, pid, &owner)?;
+    let report = report_child(&children[index])?;
+    if !is_running(&report) {
+        children.remove(index);
+    }
+    Ok(report)
+}
+
+/// `kill {pid}` -> `{pid}`
+fn kill(server: &LaunchServer, pipe: HANDLE, params: &Json) -> Result<Json, RpcError> {
+    let pid = pid_param(params)?;
+    let owner = client_filter(pipe)?;
+
+    let children = lock_children(server);
+    let index = find_child(&children, pid, &owner)?;
+    children[index].child.kill()?;
+    Ok(Json::object([("pid", pid.into())]))
+}
+
+/// `list_children` -> `[{pid, path, running}]`
+///
+/// The processes reported to have exited are forgotten
+fn list_children(server: &LaunchServer, pipe: HANDLE) -> Result<Json, RpcError> {
+    let owner = client_filter(pipe)?;
+
+    let mut children = lock_children(server);
+    let mut reports = Vec::new();
+    let mut index = 0;
+    while index < children.len() {
+        if !is_visible(&children[index], &owner) {
+            index += 1;
+            continue;
+        }
+        let report = report_child(&children[index])?;
+        if is_running(&report) {
+            index += 1;
+        } else {
+            children.remove(index);
+        }
+        reports.push(report);
+    }
+    Ok(Json::Array(reports))
+}
+
+/// The SID whose processes the client may see
+fn client_filter(pipe: HANDLE) -> Result<String, RpcError> {
+    Ok(PipeClient::identify(pipe)?.sid)
+}
+
+fn is_visible(served: &ServedChild, owner: &str) -> bool {
+    served.owner == owner
+}
+
+/// The index of a process the client may see, by pid
+fn find_child(children: &[ServedChild], pid: u32, owner: &str) -> Result<usize, RpcError> {
+    children
+        .iter()
+        .position(|served| served.child.id() == pid && is_visible(served, owner))
+        .ok_or_else(|| {
+            RpcError::new(
+                UNKNOWN_CHILD,
+                format!("{pid} is not a process launched through the server for the client"),
+            )
+        })
+}
+
+fn report_child(served: &ServedChild) -> Result<Json, RpcError> {
+    let running = !has_exited(served.child.handle)?;
+    Ok(Json::object([
+        ("pid", served.child.id().into()),
+        ("path", served.path.as_str().into()),
+        ("running", Json::Bool(running)),
+    ]))
+}
+
+fn is_running(report: &Json) -> bool {
+    report.get("running") == Some(&Json::Bool(true))
+}
+
+fn lock_children(server: &LaunchServer) -> MutexGuard<'_, Vec<ServedChild>> {
+    // A thread that panicked while holding the lock leaves the list as usable as before
+    server
+        .children
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+}
+
+fn pid_param(params: &Json) -> Result<u32, RpcError> {
+    u32_param(params, "pid")?
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "The parameter pid is missing"))
+}
+
+/// An optional string parameter, failing if it is of another type
+fn string_param<'a>(params: &'a Json, name: &str) -> Result<Option<&'a str>, RpcError> {
+    match params.get(name) {
+        None | Some(Json::Null) => Ok(None),
+        Some(value) => value.as_str().map(Some).ok_or_else(|| {
+            RpcError::new(
+                INVALID_PARAMS,
+                format!("The parameter {name} must be a string"),
+            )
+        }),
+    }
+}
+
+/// An optional `u32` parameter, failing if it is of another type
+fn u32_param(params: &Json, name: &str) -> Result<Option<u32>, RpcError> {
+    match params.get(name) {
+        None | Some(Json::Null) => Ok(None),
+        Some(value) => value.as_u32().map(Some).ok_or_else(|| {
+            RpcError::new(
+                INVALID_PARAMS,
+                format!("The parameter {name} must be a whole number"),
+            )
+        }),
+    }
+}
@@ -0,0 +1,107 @@
+use std::ffi::c_void;
+
+use windows::Win32::Foundation::{BOOL, HANDLE, HLOCAL, WIN32_ERROR};
+use windows::Win32::Security::OBJECT_SECURITY_INFORMATION;
+use windows::Win32::Storage::FileSystem::ReadFile;
+
+/// Whether a call that reports failure through the last error succeeded
+///
+/// Older `windows` versions return such calls' `BOOL`, newer ones turn it into a `Result`.
+pub(crate) trait Succeeded {
+    fn succeeded(self) -> bool;
+}
+
+impl Succeeded for BOOL {
+    fn succeeded(self) -> bool {
+        self.as_bool()
+    }
+}
+
+impl Succeeded for windows::core::Result<()> {
+    fn succeeded(self) -> bool {
+        self.is_ok()
+    }
+}
+
+/// The outcome of a call that returns its error code
+///
+/// Older `windows` versions return the raw code, newer ones turn it into a `Result`.
+pub(crate) trait Status {
+    fn into_result(self) -> windows::core::Result<()>;
+}
+
+impl Status for WIN32_ERROR {
+    fn into_result(self) -> windows::core::Result<()> {
+        if self.is_err() {
+            return Err(windows::core::Error::from(self.to_hresult()));
+        }
+        Ok(())
+    }
+}
+
+impl Status for u32 {
+    fn into_result(self) -> windows::core::Result<()> {
+        WIN32_ERROR(self).into_result()
+    }
+}
+
+impl Status for windows::core::Result<()> {
+    fn into_result(self) -> windows::core::Result<()> {
+        self
+    }
+}
+
+/// The last error of the calling thread
+pub(crate) fn last_error() -> WIN32_ERROR {
+    // Read through std, as newer `windows` versions turn `GetLastError` into a `Result`
+    WIN32_ERROR(
+        std::io::Error::last_os_error()
+            .raw_os_error()
+            .unwrap_or_default() as u32,
+    )
+}
+
+/// Frees memory the system allocated with `LocalAlloc`
+pub(crate) unsafe fn local_free(memory: *mut c_void) -> windows::core::Result<()> {
+    #[cfg(feature = "windows-0-48")]
+    let freed = windows::Win32::System::Memory::LocalFree(HLOCAL(memory as isize));
+    #[cfg(not(feature = "windows-0-48"))]
+    let freed = windows::Win32::Foundation::LocalFree(HLOCAL(memory));
+
+    freed.map(|_| ())
+}
+
+/// The security information argument of `GetSecurityInfo` and `SetSecurityInfo`
+#[cfg(feature = "windows-0-48")]
+pub(crate) fn security_information(information: OBJECT_SECURITY_INFORMATION) -> u32 {
+    information.0
+}
+
+/// The security information argument of `GetSecurityInfo` and `SetSecurityInfo`
+#[cfg(not(feature = "windows-0-48"))]
+pub(crate) fn security_information(
+    information: OBJECT_SECURITY_INFORMATION,
+) -> OBJECT_SECURITY_INFORMATION {
+    information
+}
+
+/// Reads from a file into the buffer, storing the number of bytes read
+///
+/// Returns whether the read succeeded.
+pub(crate) unsafe fn read_file(handle: HANDLE, buffer: &mut [u8], read: &mut u32) -> bool {
+    let length = buffer.len().min(u32::MAX as usize);
+
+    #[cfg(feature = "windows-0-48")]
+    let success = ReadFile(
+        handle,
+        Some(buffer.as_mut_ptr() as *mut c_void),
+        length as u32,
+        Some(read),
+        None,
+    )
+    .succeeded();
+    #[cfg(not(feature = "windows-0-48"))]
+    let success = ReadFile(handle, Some(&mut buffer[..length]), Some(read), None).succeeded();
+
+    success
+}
@@ -1,33 +1,420 @@
+use crate::error::{Error, Stage};
+use crate::job::JobLimits;
+use crate::processes::ProcessInfo;
 use crate::safe_windows_bindings::low_level::{
-    create_process_as_user_w, duplicate_token_ex, get_token_information, open_process,
-    open_process_token, wts_get_active_console_session_id, wts_query_user_token,
+    assign_process_to_job_object, close_after_failure, close_desktop, close_token,
+    close_window_station, convert_sid_to_string_sid_w, convert_string_sid_to_sid_w,
+    create_environment_block, create_file_w, create_job_object_w, create_mutex_w, create_pipe,
+    create_process_as_user_w, create_process_with_logon_w, create_process_with_token_w,
+    create_profile, delete_proc_thread_attribute_list, delete_profile_w, duplicate_handle,
+    duplicate_token_ex, duplicate_token_ex_keep_existing, expand_environment_strings_w, get_acp,
+    get_current_process, get_exit_code_process, get_last_sid_sub_authority, get_local_time,
+    get_oem_cp, get_proc_thread_attribute_list_size, get_process_window_station,
+    get_token_information, get_user_profile_directory_w, grant_window_object_access,
+    impersonate_logged_on_user, initialize_proc_thread_attribute_list, is_wow64_process,
+    load_user_profile_w, logon_user_w, lookup_account_name_w, lookup_account_sid_w,
+    lookup_privilege_name_w, multi_byte_to_wide_char, open_desktop_w, open_process,
+    open_process_token, open_window_station_w, process_id_to_session_id,
+    query_job_active_processes, read_file, reg_close_key, reg_create_key_ex_w, reg_set_value_ex_w,
+    resume_thread, revert_to_self, safer_close_level, safer_get_level_id, safer_identify_level,
+    set_job_extended_limits, set_kernel_object_dacl, set_named_file_dacl,
+    set_process_affinity_mask, set_process_information, set_process_window_station, suspend_thread,
+    terminate_job_object, terminate_process, unload_user_profile, update_proc_thread_attribute,
+    wait_for_single_object, wnet_add_connection2_w, write_file, wts_disconnect_session,
+    wts_enumerate_sessions_w, wts_get_active_console_session_id, wts_logoff_session,
+    wts_query_session_info_ex, wts_query_user_token, wts_send_message_w,
 };
+#[cfg(feature = "server")]
+use crate::safe_windows_bindings::low_level::{
+    call_named_pipe_w, connect_named_pipe, create_named_pipe_w, disconnect_named_pipe,
+    flush_file_buffers, impersonate_named_pipe_client, open_thread_token,
+};
+#[cfg(not(feature = "sysinfo"))]
+use crate::safe_windows_bindings::low_level::{
+    create_toolhelp32_snapshot, get_process_times, query_full_process_image_name_w,
+};
+#[cfg(feature = "window")]
+use crate::safe_windows_bindings::low_level::{
+    enum_windows, get_class_name_w, get_window_text_w, get_window_thread_process_id,
+    is_window_visible, post_close_message,
+};
+#[cfg(feature = "diagnostics")]
+use crate::safe_windows_bindings::low_level::{reg_get_value_w, wts_query_session_information_w};
+#[cfg(feature = "diagnostics")]
+use crate::sessions::{SessionInfo, SessionState};
+#[cfg(feature = "window")]
+use crate::window::WindowInfo;
+use std::ffi::{OsStr, OsString};
 use std::os::raw::c_void;
-use sysinfo::{PidExt, Process, ProcessExt, System, SystemExt};
-use widestring::U16CString;
+#[cfg(not(feature = "sysinfo"))]
+use std::path::PathBuf;
+use std::time::Duration;
+#[cfg(feature = "sysinfo")]
+use sysinfo::{PidExt, ProcessExt, System, SystemExt};
+use widestring::{U16CStr, U16CString};
 use windows::core::{PCWSTR, PWSTR};
-use windows::Win32::Foundation::HANDLE;
+#[cfg(feature = "diagnostics")]
+use windows::Win32::Foundation::ERROR_FILE_NOT_FOUND;
+#[cfg(feature = "server")]
+use windows::Win32::Foundation::ERROR_MORE_DATA;
+use windows::Win32::Foundation::{ERROR_BROKEN_PIPE, HANDLE, PSID};
+use windows::Win32::Security::AppLocker::SAFER_LEVELID_DISALLOWED;
 use windows::Win32::Security::{
-    SecurityImpersonation, TokenLinkedToken, TokenPrimary, TOKEN_ASSIGN_PRIMARY, TOKEN_DUPLICATE,
-    TOKEN_LINKED_TOKEN, TOKEN_QUERY,
+    SecurityImpersonation, TokenElevation, TokenElevationType, TokenElevationTypeFull,
+    TokenElevationTypeLimited, TokenIntegrityLevel, TokenLinkedToken, TokenPrimary,
+    TokenPrivileges, TokenSessionId, TokenUser, LOGON32_LOGON, SE_PRIVILEGE_ENABLED,
+    TOKEN_ASSIGN_PRIMARY, TOKEN_DUPLICATE, TOKEN_ELEVATION, TOKEN_ELEVATION_TYPE,
+    TOKEN_INFORMATION_CLASS, TOKEN_LINKED_TOKEN, TOKEN_MANDATORY_LABEL, TOKEN_PRIVILEGES,
+    TOKEN_QUERY, TOKEN_USER,
+};
+use windows::Win32::Security::{TokenGroups, TOKEN_GROUPS};
+use windows::Win32::Storage::FileSystem::{
+    CREATE_ALWAYS, FILE_GENERIC_READ, FILE_GENERIC_WRITE, FILE_WRITE_DATA, OPEN_ALWAYS,
+    OPEN_EXISTING,
+};
+use windows::Win32::System::JobObjects::{
+    JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JOB_OBJECT_LIMIT_ACTIVE_PROCESS,
+    JOB_OBJECT_LIMIT_JOB_MEMORY, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+    JOB_OBJECT_LIMIT_PROCESS_MEMORY, JOB_OBJECT_LIMIT_PROCESS_TIME,
 };
+#[cfg(feature = "diagnostics")]
+use windows::Win32::System::Registry::HKEY_LOCAL_MACHINE;
+use windows::Win32::System::Registry::HKEY_USERS;
+use windows::Win32::System::RemoteDesktop::{
+    WTSActive, WTSDisconnected, WTS_SESSIONSTATE_LOCK, WTS_SESSIONSTATE_UNLOCK,
+};
+#[cfg(feature = "diagnostics")]
+use windows::Win32::System::RemoteDesktop::{WTSDomainName, WTSUserName};
+use windows::Win32::System::StationsAndDesktops::HWINSTA;
+use windows::Win32::System::SystemServices::{SE_GROUP_ENABLED, SE_GROUP_USE_FOR_DENY_ONLY};
 use windows::Win32::System::Threading::{
-    PROCESS_CREATION_FLAGS, PROCESS_INFORMATION, PROCESS_QUERY_INFORMATION, STARTUPINFOW,
+    ProcessPowerThrottling, CREATE_NEW_CONSOLE, CREATE_NO_WINDOW, CREATE_PROCESS_LOGON_FLAGS,
+    CREATE_SUSPENDED, CREATE_UNICODE_ENVIRONMENT, EXTENDED_STARTUPINFO_PRESENT, INFINITE,
+    LPPROC_THREAD_ATTRIBUTE_LIST, PROCESS_CREATION_FLAGS, PROCESS_INFORMATION,
+    PROCESS_POWER_THROTTLING_CURRENT_VERSION, PROCESS_POWER_THROTTLING_EXECUTION_SPEED,
+    PROCESS_POWER_THROTTLING_STATE, PROCESS_QUERY_INFORMATION, PROCESS_QUERY_LIMITED_INFORMATION,
+    PROCESS_SYNCHRONIZE, PROCESS_TERMINATE, PROC_THREAD_ATTRIBUTE_HANDLE_LIST,
+    PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY, STARTF_USEPOSITION, STARTF_USESHOWWINDOW,
+    STARTF_USESIZE, STARTF_USESTDHANDLES, STARTUPINFOEXW, STARTUPINFOW,
 };
+use windows::Win32::UI::WindowsAndMessaging::{MESSAGEBOX_STYLE, SHOW_WINDOW_CMD};
+
+/// Closes a handle that is no longer needed
+pub fn close_handle(handle: HANDLE) -> Result<(), Error> {
+    close_token(handle)
+}
+
+/// A handle that is closed once it is dropped, unless it was released to a new owner first
+///
+/// Keeps every early return from leaking the handle, while [`OwnedHandle::close`] still
+/// reports a failure to close it where that matters.
+#[derive(Debug)]
+pub struct OwnedHandle(HANDLE);
 
-/// Gets the pid of a process by name
-pub fn get_process_pid(process_name: &str) -> Result<u32, String> {
+impl OwnedHandle {
+    /// Takes ownership of a handle
+    pub fn new(handle: HANDLE) -> Self {
+        Self(handle)
+    }
+
+    /// The handle, which stays owned by the guard
+    pub fn get(&self) -> HANDLE {
+        self.0
+    }
+
+    /// Gives up ownership of the handle without closing it
+    pub fn release(self) -> HANDLE {
+        let handle = self.0;
+        std::mem::forget(self);
+        handle
+    }
+}
+
+impl Drop for OwnedHandle {
+    fn drop(&mut self) {
+        // Nothing sensible can be done if closing fails here
+        let _ = close_token(self.0);
+    }
+}
+
+/// Gets a snapshot of all running processes
+#[cfg(feature = "sysinfo")]
+pub fn get_all_processes() -> Vec<ProcessInfo> {
     let system = System::new_all();
-    let processes: Vec<&Process> = system.processes_by_name(process_name).collect();
-    let process_pid = processes.first().map(|process| process.pid().as_u32());
-    match process_pid {
-        None => Err(format!("No running processes by the name: {process_name}")),
-        Some(process_pid) => Ok(process_pid),
+    system
+        .processes()
+        .values()
+        .map(|process| {
+            let pid = process.pid().as_u32();
+            let parent_pid = process.parent().map(|parent| parent.as_u32());
+            let name = process.name().to_string();
+            let exe = Some(process.exe().to_path_buf()).filter(|exe| !exe.as_os_str().is_empty());
+            let start_time = process.start_time();
+            let session_id = process_id_to_session_id(pid).ok();
+            ProcessInfo {
+                pid,
+                parent_pid,
+                name,
+                exe,
+                start_time,
+                session_id,
+            }
+        })
+        .collect()
+}
+
+/// Gets a snapshot of all running processes
+///
+/// Processes that cannot be opened are still listed, without an executable path or start time
+#[cfg(not(feature = "sysinfo"))]
+pub fn get_all_processes() -> Vec<ProcessInfo> {
+    let Ok(processes) = create_toolhelp32_snapshot() else {
+        return Vec::new();
+    };
+    processes
+        .into_iter()
+        .map(|(pid, parent_pid, name)| {
+            let (exe, start_time) =
+                match open_process(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) {
+                    Ok(process_handle) => {
+                        let exe = query_full_process_image_name_w(process_handle)
+                            .ok()
+                            .map(PathBuf::from);
+                        // FILETIME counts 100ns intervals since 1601, the start time is in seconds since 1970
+                        let start_time = get_process_times(process_handle)
+                            .map(|creation_time| {
+                                (creation_time / 10_000_000).saturating_sub(11_644_473_600)
+                            })
+                            .unwrap_or_default();
+                        let _ = close_handle(process_handle);
+                        (exe, start_time)
+                    }
+                    Err(_) => (None, 0),
+                };
+            let parent_pid = Some(parent_pid).filter(|&parent_pid| parent_pid != 0);
+            let session_id = process_id_to_session_id(pid).ok();
+            ProcessInfo {
+                pid,
+                parent_pid,
+                name,
+                exe,
+                start_time,
+                session_id,
+            }
+        })
+        .collect()
+}
+
+/// Opens a process by pid only to wait for it to exit
+pub fn open_process_for_wait(pid: u32) -> Result<HANDLE, Error> {
+    open_process(PROCESS_SYNCHRONIZE, false, pid)
+}
+
+/// Checks whether a process has exited, without waiting
+pub fn has_exited(process_handle: HANDLE) -> Result<bool, Error> {
+    wait_for_single_object(process_handle, 0)
+}
+
+/// Blocks until a process exits
+pub fn wait_for_process(process_handle: HANDLE) -> Result<(), Error> {
+    wait_for_single_object(process_handle, INFINITE)?;
+    Ok(())
+}
+
+/// Gets the exit code of a process that has exited
+pub fn get_exit_code(process_handle: HANDLE) -> Result<u32, Error> {
+    get_exit_code_process(process_handle)
+}
+
+/// Turns the power throttling of a process, known as EcoQoS or efficiency mode, on or off
+pub fn set_process_efficiency_mode(process_handle: HANDLE, enabled: bool) -> Result<(), Error> {
+    let state = PROCESS_POWER_THROTTLING_STATE {
+        Version: PROCESS_POWER_THROTTLING_CURRENT_VERSION,
+        ControlMask: PROCESS_POWER_THROTTLING_EXECUTION_SPEED,
+        StateMask: if enabled {
+            PROCESS_POWER_THROTTLING_EXECUTION_SPEED
+        } else {
+            0
+        },
+    };
+    set_process_information(
+        process_handle,
+        ProcessPowerThrottling,
+        &state as *const PROCESS_POWER_THROTTLING_STATE as *const c_void,
+        std::mem::size_of::<PROCESS_POWER_THROTTLING_STATE>() as u32,
+    )
+}
+
+/// Maps drive letters to shares for the logon session of the token, leaving letters already in use as they are
+///
+/// Each mapping is a drive letter, e.g. `Z:`, and the share it maps to, e.g. `\\server\share`.
+pub fn map_drives_for_token(token: HANDLE, mappings: &[(String, String)]) -> Result<(), Error> {
+    // Convert all parameters before impersonating, so a conversion failure needs no reverting
+    let mut mappings = mappings
+        .iter()
+        .map(|(letter, share)| Ok((to_u16_cstring(letter)?, to_u16_cstring(share)?)))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    impersonate_logged_on_user(token)?;
+    let mut errors: Vec<Error> = Vec::new();
+    for (letter, share) in &mut mappings {
+        let mapped = wnet_add_connection2_w(
+            PWSTR::from_raw(letter.as_mut_ptr()),
+            PWSTR::from_raw(share.as_mut_ptr()),
+        );
+        if let Err(err) = mapped {
+            errors.push(err);
+            break;
+        }
+    }
+    // The thread must not go on acting as the user in any case
+    if let Err(err) = revert_to_self() {
+        errors.push(err);
+    }
+
+    if !errors.is_empty() {
+        return Err(Error::combine(errors));
+    }
+    Ok(())
+}
+
+/// Whether AppLocker and Software Restriction Policies let the user of the token run an executable
+pub fn execution_allowed_for_token(token: HANDLE, image_path: &OsStr) -> Result<bool, Error> {
+    let image_path = os_to_u16_cstring(image_path)?;
+
+    // Rules apply to users and groups, so the level is identified as the user of the token
+    impersonate_logged_on_user(token)?;
+    let level = safer_identify_level(PCWSTR::from_raw(image_path.as_ptr()));
+    // The thread must not go on acting as the user in any case
+    let reverted = revert_to_self();
+    let level = match (level, reverted) {
+        (Ok(level), Ok(())) => level,
+        (Ok(level), Err(err)) => {
+            return Err(match safer_close_level(level) {
+                Ok(()) => err,
+                Err(cleanup) => Error::combine(vec![err, cleanup]),
+            })
+        }
+        (Err(err), Ok(())) => return Err(err),
+        (Err(err), Err(cleanup)) => return Err(Error::combine(vec![err, cleanup])),
+    };
+
+    let level_id = safer_get_level_id(level);
+    let closed = safer_close_level(level);
+    match (level_id, closed) {
+        (Ok(level_id), Ok(())) => Ok(level_id != SAFER_LEVELID_DISALLOWED),
+        (Ok(_), Err(err)) => Err(err),
+        (Err(err), Ok(())) => Err(err),
+        (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
+    }
+}
+
+/// Everything a user needs of a window station to run processes on its desktops, WINSTA_ALL_ACCESS
+const WINDOW_STATION_ACCESS: u32 = 0x037F;
+
+/// Everything a user needs of a desktop to create and use windows on it, all the DESKTOP_ rights
+const DESKTOP_ACCESS: u32 = 0x01FF;
+
+/// Grants a user access to a window station, e.g. `WinSta0`, and one of its desktops, e.g. `Default`
+///
+/// The window station is one of the session of the current process.
+pub fn grant_desktop_access_to_sid(
+    sid: &str,
+    window_station: &str,
+    desktop: &str,
+) -> Result<(), Error> {
+    let sid = to_u16_cstring(sid)?;
+    let mut sid = convert_string_sid_to_sid_w(PCWSTR::from_raw(sid.as_ptr()))?;
+    let sid = PSID(sid.as_mut_ptr() as *mut c_void);
+    let window_station_name = to_u16_cstring(window_station)?;
+    let desktop = to_u16_cstring(desktop)?;
+
+    let window_station = open_window_station_w(PCWSTR::from_raw(window_station_name.as_ptr()))?;
+    let granted = grant_window_object_access(HANDLE(window_station.0), sid, WINDOW_STATION_ACCESS)
+        .and_then(|()| {
+            grant_access_to_desktop_of(window_station, PCWSTR::from_raw(desktop.as_ptr()), sid)
+        });
+    let closed = close_window_station(window_station);
+
+    match (granted, closed) {
+        (Ok(()), Ok(())) => Ok(()),
+        (Ok(()), Err(err)) => Err(err),
+        (Err(err), Ok(())) => Err(err),
+        (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
     }
 }
 
+/// Grants a user access to a desktop of a window station
+///
+/// Desktops are opened in the window station of the process, so the process switches to the
+/// given one while opening it and switches back afterwards.
+fn grant_access_to_desktop_of(
+    window_station: HWINSTA,
+    desktop: PCWSTR,
+    sid: PSID,
+) -> Result<(), Error> {
+    let previous = get_process_window_station()?;
+    set_process_window_station(window_station)?;
+
+    let granted = open_desktop_w(desktop).and_then(|desktop| {
+        let granted = grant_window_object_access(HANDLE(desktop.0), sid, DESKTOP_ACCESS);
+        let closed = close_desktop(desktop);
+        match (granted, closed) {
+            (Ok(()), Ok(())) => Ok(()),
+            (Ok(()), Err(err)) => Err(err),
+            (Err(err), Ok(())) => Err(err),
+            (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
+        }
+    });
+    // The service must not stay on the interactive window station in any case
+    let restored = set_process_window_station(previous);
+
+    match (granted, restored) {
+        (Ok(()), Ok(())) => Ok(()),
+        (Ok(()), Err(err)) => Err(err),
+        (Err(err), Ok(())) => Err(err),
+        (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
+    }
+}
+
+/// Access to a protected process: full for the system and administrators, query and wait for everyone else
+///
+/// The owner rights entry keeps the user owning the process from rewriting the DACL.
+const PROTECTED_PROCESS_SDDL: &str =
+    "D:P(A;;GA;;;SY)(A;;GA;;;BA)(A;;0x101000;;;OW)(A;;0x101000;;;WD)";
+
+/// Keeps users who are not administrators from terminating or otherwise tampering with a process
+pub fn protect_process(process_handle: HANDLE) -> Result<(), Error> {
+    let sddl = to_u16_cstring(PROTECTED_PROCESS_SDDL)?;
+    set_kernel_object_dacl(process_handle, PCWSTR::from_raw(sddl.as_ptr()))
+}
+
+/// Restricts the threads of a process to the processors whose bits are set in the mask
+pub fn set_process_affinity(process_handle: HANDLE, mask: u64) -> Result<(), Error> {
+    // 32-bit processes only address the first 32 processors
+    let mask = usize::try_from(mask).map_err(|_| {
+        Error::other(
+            Stage::ProcessSetup,
+            format!("The affinity mask {mask:#x} names processors a 32-bit service cannot address"),
+        )
+    })?;
+    set_process_affinity_mask(process_handle, mask)
+}
+
+/// Gives full control of a directory and its contents to the system, administrators and the user alone
+pub fn restrict_directory_to_user(path: &str, user_sid: &str) -> Result<(), Error> {
+    let path = to_u16_cstring(path)?;
+    let sddl = to_u16_cstring(&format!(
+        "D:P(A;OICI;FA;;;SY)(A;OICI;FA;;;BA)(A;OICI;FA;;;{user_sid})"
+    ))?;
+    set_named_file_dacl(
+        PCWSTR::from_raw(path.as_ptr()),
+        PCWSTR::from_raw(sddl.as_ptr()),
+    )
+}
+
 /// Gets the token of a process by pid
-pub fn get_process_token(pid: u32) -> Result<HANDLE, String> {
+pub fn get_process_token(pid: u32) -> Result<HANDLE, Error> {
     // Get the process handle by pid
     let process_handle = open_process(PROCESS_QUERY_INFORMATION, false, pid)?;
 
@@ -50,13 +437,116 @@ pub fn get_process_token(pid: u32) -> Result<HANDLE, String> {
     Ok(duplicated_token_handle)
 }
 
-/// Gets the current user token
-pub fn get_current_user_token() -> Result<HANDLE, String> {
-    // Get the active session id
+/// Gets the id of the session attached to the physical console
+pub fn get_active_session_id() -> Result<u32, Error> {
     let active_session_id = wts_get_active_console_session_id();
+    if active_session_id == u32::MAX {
+        return Err(Error::no_interactive_user(None));
+    }
+    Ok(active_session_id)
+}
+
+#[cfg(feature = "diagnostics")]
+/// Gets a snapshot of all sessions with the accounts logged on to them
+pub fn get_all_sessions() -> Result<Vec<SessionInfo>, Error> {
+    let sessions = wts_enumerate_sessions_w()?;
+    Ok(sessions
+        .into_iter()
+        .map(|(id, state)| {
+            // Sessions without a logged on user report empty names
+            let account = |info_class| {
+                wts_query_session_information_w(id, info_class)
+                    .ok()
+                    .filter(|value| !value.is_empty())
+            };
+            SessionInfo {
+                id,
+                state: SessionState::from_native(state.0),
+                user: account(WTSUserName),
+                domain: account(WTSDomainName),
+            }
+        })
+        .collect())
+}
+
+/// Logs the user of a session off, waiting for the logoff to finish if asked to
+pub fn log_off_session(session_id: u32, wait: bool) -> Result<(), Error> {
+    wts_logoff_session(session_id, wait)
+}
+
+/// Disconnects the client of a session, leaving the user logged on
+pub fn disconnect_session_client(session_id: u32, wait: bool) -> Result<(), Error> {
+    wts_disconnect_session(session_id, wait)
+}
+
+/// Shows a message box in a session and waits for the answer, returning the id of the button pressed
+///
+/// Returns `IDTIMEOUT` if the timeout in seconds elapsed first, zero waits indefinitely
+pub fn show_session_message(
+    session_id: u32,
+    title: &str,
+    text: &str,
+    style: u32,
+    timeout_s: u32,
+) -> Result<i32, Error> {
+    let title = to_u16_cstring(title)?;
+    let text = to_u16_cstring(text)?;
+    let response = wts_send_message_w(
+        session_id,
+        &title,
+        &text,
+        MESSAGEBOX_STYLE(style),
+        timeout_s,
+    )?;
+    Ok(response.0)
+}
+
+/// Gets how long no input has been given to a session, if the system tracks it for the session
+pub fn get_session_idle_time(session_id: u32) -> Result<Option<Duration>, Error> {
+    let info = wts_query_session_info_ex(session_id)?;
+    if info.LastInputTime == 0 {
+        return Ok(None);
+    }
+    // Both times are in 100 nanosecond intervals
+    let idle = info.CurrentTime.saturating_sub(info.LastInputTime).max(0) as u64;
+    Ok(Some(Duration::from_nanos(idle.saturating_mul(100))))
+}
+
+/// Gets whether a session is locked, if the system reports it for the session
+///
+/// Windows 7 and Server 2008 R2 report the state the wrong way around, which is not corrected for.
+pub fn get_session_locked(session_id: u32) -> Result<Option<bool>, Error> {
+    let info = wts_query_session_info_ex(session_id)?;
+    // The flags hold a single state, despite their name
+    match info.SessionFlags as u32 {
+        WTS_SESSIONSTATE_LOCK => Ok(Some(true)),
+        WTS_SESSIONSTATE_UNLOCK => Ok(Some(false)),
+        _ => Ok(None),
+    }
+}
+
+/// Gets how much of the local day has passed, e.g. 6 hours at 6 AM
+pub fn get_local_time_of_day() -> Duration {
+    let time = get_local_time();
+    let seconds =
+        u64::from(time.wHour) * 3600 + u64::from(time.wMinute) * 60 + u64::from(time.wSecond);
+    Duration::from_secs(seconds) + Duration::from_millis(u64::from(time.wMilliseconds))
+}
+
+/// Gets the ids of the sessions a user is logged on to, connected or not
+pub fn get_logged_on_session_ids() -> Result<Vec<u32>, Error> {
+    let sessions = wts_enumerate_sessions_w()?;
+    Ok(sessions
+        .into_iter()
+        .filter(|(_, state)| *state == WTSActive || *state == WTSDisconnected)
+        .map(|(session_id, _)| session_id)
+        .collect())
+}
 
+/// Gets the token of the user logged on to a session
+pub fn get_session_user_token(session_id: u32) -> Result<HANDLE, Error> {
     // Get the current user token
-    let current_user_token = wts_query_user_token(active_session_id)?;
+    let current_user_token = wts_query_user_token(session_id)?;
 
     // Specify access rights
     let access_flags = TOKEN_QUERY | TOKEN_DUPLICATE | TOKEN_ASSIGN_PRIMARY;
@@ -71,8 +561,380 @@ pub fn get_current_user_token() -> Result<HANDLE, String> {
     )
 }
 
+/// Gets the SID of the user logged on to a session, as a string
+pub fn get_session_user_sid(session_id: u32) -> Result<String, Error> {
+    let token = wts_query_user_token(session_id)?;
+    let sid = get_token_user_sid(token);
+    let closed = close_token(token);
+    match (sid, closed) {
+        (Ok(sid), Ok(())) => Ok(sid),
+        (Ok(_), Err(err)) => Err(err),
+        (Err(err), Ok(())) => Err(err),
+        (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
+    }
+}
+
+/// Makes a copy of a primary token that can be consumed by a launch, leaving the original open
+pub fn copy_primary_token(token: HANDLE) -> Result<HANDLE, Error> {
+    let access_flags = TOKEN_QUERY | TOKEN_DUPLICATE | TOKEN_ASSIGN_PRIMARY;
+    duplicate_token_ex_keep_existing(
+        token,
+        access_flags,
+        None,
+        SecurityImpersonation,
+        TokenPrimary,
+    )
+}
+
+/// Gets a variable sized piece of information about a token
+///
+/// The buffer is made of `usize` so the structures read from it are properly aligned
+fn get_token_information_buffer(
+    token: HANDLE,
+    token_information_class: TOKEN_INFORMATION_CLASS,
+) -> Result<Vec<usize>, Error> {
+    // Ask for the required size first, which is expected to fail
+    let mut return_length = 0u32;
+    let _ = get_token_information(token, token_information_class, None, 0, &mut return_length);
+
+    // Then read the information into a buffer of that size
+    let word = std::mem::size_of::<usize>();
+    let mut buffer = vec![0usize; (return_length as usize).div_ceil(word)];
+    get_token_information(
+        token,
+        token_information_class,
+        Some(buffer.as_mut_ptr() as *mut c_void),
+        (buffer.len() * word) as u32,
+        &mut return_length,
+    )?;
+
+    Ok(buffer)
+}
+
+/// Gets the SID of the user a token belongs to, as a string
+pub fn get_token_user_sid(token: HANDLE) -> Result<String, Error> {
+    let buffer = get_token_information_buffer(token, TokenUser)?;
+    let token_user = unsafe { &*(buffer.as_ptr() as *const TOKEN_USER) };
+    convert_sid_to_string_sid_w(token_user.User.Sid)
+}
+
+/// Gets the SIDs of the groups a token is an enabled member of, as strings
+///
+/// Groups that only serve to deny access are left out
+#[cfg(feature = "server")]
+pub fn get_token_group_sids(token: HANDLE) -> Result<Vec<String>, Error> {
+    let groups = get_token_groups(token)?;
+    Ok(groups
+        .into_iter()
+        .filter(|(_, attributes)| attributes & SE_GROUP_ENABLED as u32 != 0)
+        .map(|(sid, _)| sid)
+        .collect())
+}
+
+/// Gets the SIDs of the groups of a token, with their attributes
+fn get_token_groups(token: HANDLE) -> Result<Vec<(String, u32)>, Error> {
+    let buffer = get_token_information_buffer(token, TokenGroups)?;
+    let token_groups = unsafe { &*(buffer.as_ptr() as *const TOKEN_GROUPS) };
+
+    // The groups are laid out right after the count, past the declared array length
+    let groups = unsafe {
+        std::slice::from_raw_parts(
+            token_groups.Groups.as_ptr(),
+            token_groups.GroupCount as usize,
+        )
+    };
+
+    groups
+        .iter()
+        .map(|group| Ok((convert_sid_to_string_sid_w(group.Sid)?, group.Attributes)))
+        .collect()
+}
+
+/// Gets whether a token belongs to a group, counting groups a filtered token only keeps to deny access
+pub fn get_token_member_of(token: HANDLE, group_sid: &str) -> Result<bool, Error> {
+    let member_attributes = SE_GROUP_ENABLED as u32 | SE_GROUP_USE_FOR_DENY_ONLY as u32;
+    let groups = get_token_groups(token)?;
+    Ok(groups.iter().any(|(sid, attributes)| {
+        sid.eq_ignore_ascii_case(group_sid) && attributes & member_attributes != 0
+    }))
+}
+
+/// Gets whether the user logged on to a session belongs to a group
+pub fn get_session_user_member_of(session_id: u32, group_sid: &str) -> Result<bool, Error> {
+    let token = wts_query_user_token(session_id)?;
+    let member = get_token_member_of(token, group_sid);
+    let closed = close_token(token);
+    match (member, closed) {
+        (Ok(member), Ok(())) => Ok(member),
+        (Ok(_), Err(err)) => Err(err),
+        (Err(err), Ok(())) => Err(err),
+        (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
+    }
+}
+
+/// Logs an account on with credentials and returns its primary token
+pub fn get_logon_user_token(
+    username: &str,
+    domain: &str,
+    password: &str,
+    logon_type: LOGON32_LOGON,
+) -> Result<HANDLE, Error> {
+    // Convert all parameters to their native versions
+    let username = to_u16_cstring(username)?;
+    let domain = to_u16_cstring(domain)?;
+    let password = to_u16_cstring(password)?;
+
+    // Let the system pick the domain for user principal names
+    let domain = if domain.is_empty() {
+        PCWSTR::null()
+    } else {
+        PCWSTR::from_raw(domain.as_ptr())
+    };
+
+    let token = logon_user_w(
+        PCWSTR::from_raw(username.as_ptr()),
+        domain,
+        PCWSTR::from_raw(password.as_ptr()),
+        logon_type,
+    )?;
+
+    // Specify access rights
+    let access_flags = TOKEN_QUERY | TOKEN_DUPLICATE | TOKEN_ASSIGN_PRIMARY;
+
+    // Network logons produce impersonation tokens, so always duplicate into a primary one
+    duplicate_token_ex(
+        token,
+        access_flags,
+        None,
+        SecurityImpersonation,
+        TokenPrimary,
+    )
+}
+
+/// Converts a string into a null terminated wide string
+fn to_u16_cstring(value: &str) -> Result<U16CString, Error> {
+    U16CString::from_str(value).map_err(|err| {
+        Error::other(
+            Stage::Conversion,
+            format!("Cannot convert string to U16CString: {err}"),
+        )
+    })
+}
+
+/// Converts an OS string, e.g. a path that is not valid UTF-8, into a null terminated wide string
+fn os_to_u16_cstring(value: &OsStr) -> Result<U16CString, Error> {
+    U16CString::from_os_str(value).map_err(|err| {
+        Error::other(
+            Stage::Conversion,
+            format!("Cannot convert string to U16CString: {err}"),
+        )
+    })
+}
+
+/// Gets the account name and domain of the user a token belongs to
+pub fn get_token_user_account(token: HANDLE) -> Result<(String, String), Error> {
+    let buffer = get_token_information_buffer(token, TokenUser)?;
+    let token_user = unsafe { &*(buffer.as_ptr() as *const TOKEN_USER) };
+    lookup_account_sid_w(token_user.User.Sid)
+}
+
+/// Gets the account name and domain of a SID given in its string form
+pub fn get_sid_account(sid: &str) -> Result<(String, String), Error> {
+    let sid = to_u16_cstring(sid)?;
+    let mut sid = convert_string_sid_to_sid_w(PCWSTR::from_raw(sid.as_ptr()))?;
+    lookup_account_sid_w(PSID(sid.as_mut_ptr() as *mut c_void))
+}
+
+/// Gets the string form of the SID of an account
+pub fn get_account_sid(account: &str) -> Result<String, Error> {
+    let account = to_u16_cstring(account)?;
+    let (mut sid, _) = lookup_account_name_w(PCWSTR::from_raw(account.as_ptr()))?;
+    convert_sid_to_string_sid_w(PSID(sid.as_mut_ptr() as *mut c_void))
+}
+
+/// Gets the privileges held by a token, with whether each is enabled
+pub fn get_token_privileges(token: HANDLE) -> Result<Vec<(String, bool)>, Error> {
+    let buffer = get_token_information_buffer(token, TokenPrivileges)?;
+    let token_privileges = unsafe { &*(buffer.as_ptr() as *const TOKEN_PRIVILEGES) };
+
+    // The privileges are laid out right after the count, past the declared array length
+    let privileges = unsafe {
+        std::slice::from_raw_parts(
+            token_privileges.Privileges.as_ptr(),
+            token_privileges.PrivilegeCount as usize,
+        )
+    };
+
+    privileges
+        .iter()
+        .map(|privilege| {
+            let name = lookup_privilege_name_w(&privilege.Luid)?;
+            let enabled = privilege.Attributes.0 & SE_PRIVILEGE_ENABLED.0 != 0;
+            Ok((name, enabled))
+        })
+        .collect()
+}
+
+/// Gets the token of the current process for querying only
+pub fn get_current_process_query_token() -> Result<HANDLE, Error> {
+    // The pseudo handle of the current process is not affected by being closed
+    open_process_token(get_current_process(), TOKEN_QUERY)
+}
+
+/// Gets the id of the session a token belongs to
+pub fn get_token_session_id(token: HANDLE) -> Result<u32, Error> {
+    let mut session_id = 0u32;
+    let mut return_length = 0u32;
+    get_token_information(
+        token,
+        TokenSessionId,
+        Some((&mut session_id) as *mut u32 as *mut c_void),
+        std::mem::size_of::<u32>() as u32,
+        &mut return_length,
+    )?;
+    Ok(session_id)
+}
+
+/// Gets the RID of a token's mandatory integrity level
+pub fn get_token_integrity_rid(token: HANDLE) -> Result<u32, Error> {
+    let buffer = get_token_information_buffer(token, TokenIntegrityLevel)?;
+    let label = unsafe { &*(buffer.as_ptr() as *const TOKEN_MANDATORY_LABEL) };
+    Ok(get_last_sid_sub_authority(label.Label.Sid))
+}
+
+/// Gets whether a token is elevated
+pub fn get_token_elevated(token: HANDLE) -> Result<bool, Error> {
+    let mut elevation = TOKEN_ELEVATION::default();
+    let mut return_length = 0u32;
+    get_token_information(
+        token,
+        TokenElevation,
+        Some((&mut elevation) as *mut TOKEN_ELEVATION as *mut c_void),
+        std::mem::size_of::<TOKEN_ELEVATION>() as u32,
+        &mut return_length,
+    )?;
+    Ok(elevation.TokenIsElevated != 0)
+}
+
+/// Gets whether UAC split a token, and which half of the split it is
+fn get_token_elevation_type(token: HANDLE) -> Result<TOKEN_ELEVATION_TYPE, Error> {
+    let mut elevation_type = TOKEN_ELEVATION_TYPE::default();
+    let mut return_length = 0u32;
+    get_token_information(
+        token,
+        TokenElevationType,
+        Some((&mut elevation_type) as *mut TOKEN_ELEVATION_TYPE as *mut c_void),
+        std::mem::size_of::<TOKEN_ELEVATION_TYPE>() as u32,
+        &mut return_length,
+    )?;
+    Ok(elevation_type)
+}
+
+/// Gets whether a token is the elevated half of a token split by UAC
+pub fn get_token_is_linked_elevated(token: HANDLE) -> Result<bool, Error> {
+    Ok(get_token_elevation_type(token)? == TokenElevationTypeFull)
+}
+
+/// Opens the token of a process by pid for querying only
+pub fn get_process_query_token(pid: u32) -> Result<HANDLE, Error> {
+    let process_handle = open_process(PROCESS_QUERY_LIMITED_INFORMATION, false, pid)?;
+    open_process_token(process_handle, TOKEN_QUERY)
+}
+
+/// Terminates a process by its handle
+pub fn kill_process(process_handle: HANDLE, exit_code: u32) -> Result<(), Error> {
+    terminate_process(process_handle, exit_code)
+}
+
+/// Kills every process in a job with an exit code
+pub fn kill_job(job: HANDLE, exit_code: u32) -> Result<(), Error> {
+    terminate_job_object(job, exit_code)
+}
+
+/// Suspends a thread by its handle, returning its previous suspend count
+pub fn suspend_process_thread(thread_handle: HANDLE) -> Result<u32, Error> {
+    suspend_thread(thread_handle)
+}
+
+/// Creates a job object and assigns a process to it, returning the job
+pub fn assign_process_to_new_job(process_handle: HANDLE) -> Result<HANDLE, Error> {
+    let job = create_job_object_w()?;
+    if let Err(err) = assign_process_to_job_object(job, process_handle) {
+        return Err(close_after_failure(err, &[job]));
+    }
+    Ok(job)
+}
+
+/// Creates a job object that terminates its processes once its last handle is closed and
+/// limits what they use
+pub fn create_kill_on_close_job(job_limits: &JobLimits) -> Result<HANDLE, Error> {
+    let job = create_job_object_w()?;
+    let mut limits = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+    limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+    if let Some(memory) = job_limits.memory {
+        limits.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_JOB_MEMORY;
+        limits.JobMemoryLimit = memory;
+    }
+    if let Some(process_memory) = job_limits.process_memory {
+        limits.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_PROCESS_MEMORY;
+        limits.ProcessMemoryLimit = process_memory;
+    }
+    if let Some(active_processes) = job_limits.active_processes {
+        limits.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_ACTIVE_PROCESS;
+        limits.BasicLimitInformation.ActiveProcessLimit = active_processes;
+    }
+    if let Some(process_time) = job_limits.process_time {
+        limits.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_PROCESS_TIME;
+        // Counted in 100ns intervals
+        limits.BasicLimitInformation.PerProcessUserTimeLimit =
+            i64::try_from(process_time.as_nanos() / 100).unwrap_or(i64::MAX);
+    }
+    if let Err(err) = set_job_extended_limits(job, &limits) {
+        return Err(close_after_failure(err, &[job]));
+    }
+    Ok(job)
+}
+
+/// Assigns a process to a job
+pub fn assign_process_to_job(job: HANDLE, process_handle: HANDLE) -> Result<(), Error> {
+    assign_process_to_job_object(job, process_handle)
+}
+
+/// Counts the running processes in a job
+pub fn job_active_processes(job: HANDLE) -> Result<u32, Error> {
+    query_job_active_processes(job)
+}
+
+/// Resumes a thread by its handle, returning its previous suspend count
+pub fn resume_process_thread(thread_handle: HANDLE) -> Result<u32, Error> {
+    resume_thread(thread_handle)
+}
+
 /// Adds admin rights to a token handle
-pub fn add_admin_privileges_to_token(token: HANDLE) -> Result<HANDLE, String> {
+///
+/// Tokens UAC did not split, e.g. of the built-in Administrator or with admin approval mode off,
+/// have no linked token and are returned as they are if they are elevated already.
+pub fn add_admin_privileges_to_token(token: HANDLE) -> Result<HANDLE, Error> {
+    let elevated = match get_token_elevation_type(token) {
+        Ok(elevation_type) if elevation_type == TokenElevationTypeLimited => None,
+        Ok(_) => Some(get_token_elevated(token)),
+        Err(err) => Some(Err(err)),
+    };
+    if let Some(elevated) = elevated {
+        let err = match elevated {
+            Ok(true) => return Ok(token),
+            Ok(false) => Error::other(
+                Stage::LinkedTokenFetch,
+                "The user is not an administrator, its token has no elevated counterpart",
+            ),
+            Err(err) => err,
+        };
+        return match close_token(token) {
+            Ok(()) => Err(err),
+            Err(cleanup) => Err(Error::combine(vec![err, cleanup])),
+        };
+    }
+
     // Windows api magic
     let token_information_class = TokenLinkedToken;
     let mut token_linked: TOKEN_LINKED_TOKEN = unsafe { std::mem::zeroed() };
@@ -80,6 +942,8 @@ pub fn add_admin_privileges_to_token(token: HANDLE) -> Result<HANDLE, String> {
     let token_information_length = std::mem::size_of::<TOKEN_LINKED_TOKEN>() as u32;
     let mut return_length = 0u32;
 
+    let mut errors: Vec<Error> = Vec::new();
+
     // Magic elevation
     if let Err(err) = get_token_information(
         token,
@@ -88,69 +952,1069 @@ pub fn add_admin_privileges_to_token(token: HANDLE) -> Result<HANDLE, String> {
         token_information_length,
         &mut return_length,
     ) {
-        return Err(format!("Could not elevate process token: {err}"));
+        errors.push(err.context(Stage::LinkedTokenFetch, "Could not elevate process token"));
+    }
+
+    // The filtered token is not needed anymore in any case
+    if let Err(err) = close_token(token) {
+        errors.push(err);
+    }
+
+    if !errors.is_empty() {
+        return Err(Error::combine(errors));
     }
 
     Ok(token_linked.LinkedToken)
 }
 
+/// The parameters of a process to start, converted to their native versions
+pub struct NativeCommand {
+    application_name: U16CString,
+    command_line: U16CString,
+    current_directory: U16CString,
+    desktop: U16CString,
+    /// The inheritable input, output and error handles, if the streams are redirected
+    std_handles: Option<[HANDLE; 3]>,
+    /// The title of a new console window, which also picks the console settings from the registry
+    console_title: Option<U16CString>,
+    /// Where the first window of the process opens, in virtual screen coordinates
+    window_position: Option<(i32, i32)>,
+    /// The width and height of the first window of the process
+    window_size: Option<(u32, u32)>,
+    /// How the first window of the process is shown
+    show_window: Option<u16>,
+    /// The environment block of the process, the service's environment if missing
+    environment: Option<Vec<u16>>,
+    /// Whether the process searches System32 first and loads no DLLs from shares or low integrity locations
+    harden_dll_search: bool,
+    /// Whether the handle to the primary thread is returned instead of closed
+    keep_primary_thread: bool,
+    /// Whether the primary thread waits to be resumed before running
+    suspended: bool,
+    /// The creation flags asked for on top of those the command sets itself
+    extra_flags: PROCESS_CREATION_FLAGS,
+}
+
+impl NativeCommand {
+    /// Converts all parameters to their native versions
+    pub fn new(
+        application_name: &OsStr,
+        command_line: &OsStr,
+        current_directory: &OsStr,
+        desktop: &str,
+    ) -> Result<Self, Error> {
+        let application_name = os_to_u16_cstring(application_name)?;
+        let command_line = os_to_u16_cstring(command_line)?;
+        let current_directory = os_to_u16_cstring(current_directory)?;
+        let desktop = to_u16_cstring(desktop)?;
+
+        Ok(Self {
+            application_name,
+            command_line,
+            current_directory,
+            desktop,
+            std_handles: None,
+            console_title: None,
+            window_position: None,
+            window_size: None,
+            show_window: None,
+            environment: None,
+            harden_dll_search: false,
+            keep_primary_thread: false,
+            suspended: false,
+            extra_flags: PROCESS_CREATION_FLAGS(0),
+        })
+    }
+
+    /// Sets the title of a new console window
+    pub fn with_console_title(mut self, console_title: &str) -> Result<Self, Error> {
+        self.console_title = Some(to_u16_cstring(console_title)?);
+        Ok(self)
+    }
+
+    /// Sets where the first window of the process opens, in virtual screen coordinates
+    pub fn with_window_position(mut self, x: i32, y: i32) -> Self {
+        self.window_position = Some((x, y));
+        self
+    }
+
+    /// Sets the width and height of the first window of the process
+    pub fn with_window_size(mut self, width: u32, height: u32) -> Self {
+        self.window_size = Some((width, height));
+        self
+    }
+
+    /// Sets how the first window of the process is shown
+    pub fn with_show_window(mut self, show_window: SHOW_WINDOW_CMD) -> Self {
+        self.show_window = Some(show_window.0 as u16);
+        self
+    }
+
+    /// Makes the process search System32 first for DLLs and refuse those on shares or with a low label
+    pub fn with_hardened_dll_search(mut self) -> Self {
+        self.harden_dll_search = true;
+        self
+    }
+
+    /// Returns the id of the primary thread and a handle to it along with the process
+    pub fn with_primary_thread(mut self) -> Self {
+        self.keep_primary_thread = true;
+        self
+    }
+
+    /// Starts the process with its primary thread suspended, which is then returned to resume it
+    pub fn with_suspended_start(mut self) -> Self {
+        self.suspended = true;
+        self.keep_primary_thread = true;
+        self
+    }
+
+    /// Runs a console process without a console window
+    pub fn with_no_window(mut self) -> Self {
+        self.extra_flags |= CREATE_NO_WINDOW;
+        self
+    }
+
+    /// Gives a console process a new console instead of the one of its parent
+    pub fn with_new_console(mut self) -> Self {
+        self.extra_flags |= CREATE_NEW_CONSOLE;
+        self
+    }
+
+    /// Sets the priority class of the process through its creation flag
+    pub fn with_priority_class(mut self, priority_class: PROCESS_CREATION_FLAGS) -> Self {
+        self.extra_flags |= priority_class;
+        self
+    }
+
+    /// Adds raw creation flags to those the command sets itself
+    pub fn with_creation_flags(mut self, creation_flags: u32) -> Self {
+        self.extra_flags |= PROCESS_CREATION_FLAGS(creation_flags);
+        self
+    }
+
+    /// Sets the environment variables of the process instead of those of the service
+    pub fn with_environment(mut self, variables: &[(OsString, OsString)]) -> Result<Self, Error> {
+        let mut environment: Vec<u16> = Vec::new();
+        for (name, value) in variables {
+            if name.is_empty() || name.to_string_lossy().contains('=') {
+                return Err(Error::other(
+                    Stage::EnvironmentBuild,
+                    format!("Invalid environment variable name: {name:?}"),
+                ));
+            }
+            let mut variable = name.clone();
+            variable.push("=");
+            variable.push(value);
+            let variable = os_to_u16_cstring(&variable)?;
+            environment.extend_from_slice(variable.as_slice_with_nul());
+        }
+        // The block ends with an empty variable, so an empty block is two nuls
+        if environment.is_empty() {
+            environment.push(0);
+        }
+        environment.push(0);
+        self.environment = Some(environment);
+        Ok(self)
+    }
+
+    /// Redirects the standard streams to inheritable handles, invalid ones leave a stream unconnected
+    pub fn with_std_handles(mut self, std_handles: [HANDLE; 3]) -> Self {
+        self.std_handles = Some(std_handles);
+        self
+    }
+
+    fn application_name(&self) -> PCWSTR {
+        PCWSTR::from_raw(self.application_name.as_ptr())
+    }
+
+    /// The command line may be modified in place by the system
+    fn command_line(&mut self) -> PWSTR {
+        PWSTR::from_raw(self.command_line.as_mut_ptr())
+    }
+
+    fn environment(&self) -> Option<*const c_void> {
+        self.environment
+            .as_ref()
+            .map(|environment| environment.as_ptr() as *const c_void)
+    }
+
+    /// The creation flags telling the system how the environment block is encoded, whether to
+    /// start suspended and those asked for
+    fn creation_flags(&self) -> PROCESS_CREATION_FLAGS {
+        let environment = match self.environment {
+            Some(_) => CREATE_UNICODE_ENVIRONMENT,
+            None => PROCESS_CREATION_FLAGS(0),
+        };
+        if self.suspended {
+            return environment | CREATE_SUSPENDED | self.extra_flags;
+        }
+        environment | self.extra_flags
+    }
+
+    fn current_directory(&self) -> PCWSTR {
+        PCWSTR::from_raw(self.current_directory.as_ptr())
+    }
+
+    /// Creates the startup info pointing at the desktop and the redirected streams
+    fn startup_info(&mut self) -> STARTUPINFOW {
+        let mut startup_info = STARTUPINFOW::default();
+        startup_info.cb = std::mem::size_of_val(&startup_info) as u32;
+        startup_info.lpDesktop = PWSTR::from_raw(self.desktop.as_mut_ptr());
+        if let Some(console_title) = &mut self.console_title {
+            startup_info.lpTitle = PWSTR::from_raw(console_title.as_mut_ptr());
+        }
+        if let Some([input, output, error]) = self.std_handles {
+            startup_info.dwFlags |= STARTF_USESTDHANDLES;
+            startup_info.hStdInput = input;
+            startup_info.hStdOutput = output;
+            startup_info.hStdError = error;
+        }
+        if let Some((x, y)) = self.window_position {
+            startup_info.dwFlags |= STARTF_USEPOSITION;
+            // Monitors left of or above the primary one have negative coordinates, passed on as they are
+            startup_info.dwX = x as u32;
+            startup_info.dwY = y as u32;
+        }
+        if let Some((width, height)) = self.window_size {
+            startup_info.dwFlags |= STARTF_USESIZE;
+            startup_info.dwXSize = width;
+            startup_info.dwYSize = height;
+        }
+        if let Some(show_window) = self.show_window {
+            startup_info.dwFlags |= STARTF_USESHOWWINDOW;
+            startup_info.wShowWindow = show_window;
+        }
+        startup_info
+    }
+
+    /// The handles the process has to inherit, if the streams are redirected
+    fn inherited_handles(&self) -> Option<Vec<HANDLE>> {
+        let std_handles = self.std_handles?;
+        Some(
+            std_handles
+                .into_iter()
+                .filter(|handle| !handle.is_invalid())
+                .collect(),
+        )
+    }
+}
+
+/// Makes the process search System32 before its own directory for DLLs, keeping planted copies of system DLLs from loading
+const MITIGATION_PREFER_SYSTEM32: u64 = 0x1 << 60;
+/// Keeps the process from loading images from remote devices, e.g. shares
+const MITIGATION_NO_REMOTE_IMAGES: u64 = 0x1 << 52;
+/// Keeps the process from loading images with a low mandatory label, e.g. written by sandboxed processes
+const MITIGATION_NO_LOW_LABEL_IMAGES: u64 = 0x1 << 56;
+
+/// An attribute list of a new process: the handles it inherits and its mitigation policy
+struct AttributeList {
+    /// The memory of the attribute list, of pointer sized elements to keep it aligned
+    buffer: Vec<usize>,
+    /// The listed handles, which must stay in place for as long as the list exists
+    handles: Option<Vec<HANDLE>>,
+    /// The mitigation policy flags, which must stay in place for as long as the list exists
+    mitigation_policy: Option<Box<u64>>,
+}
+
+impl AttributeList {
+    /// Creates the attribute list, if the command needs one
+    fn new(command: &NativeCommand) -> Result<Option<Self>, Error> {
+        let handles = command.inherited_handles();
+        let mitigation_policy = command.harden_dll_search.then(|| {
+            Box::new(
+                MITIGATION_PREFER_SYSTEM32
+                    | MITIGATION_NO_REMOTE_IMAGES
+                    | MITIGATION_NO_LOW_LABEL_IMAGES,
+            )
+        });
+        let count = handles.is_some() as u32 + mitigation_policy.is_some() as u32;
+        if count == 0 {
+            return Ok(None);
+        }
+
+        let size = get_proc_thread_attribute_list_size(count)?;
+        let mut buffer = vec![0usize; size.div_ceil(std::mem::size_of::<usize>())];
+        initialize_proc_thread_attribute_list(
+            LPPROC_THREAD_ATTRIBUTE_LIST(buffer.as_mut_ptr() as *mut c_void),
+            count,
+            size,
+        )?;
+
+        // From here on the list is deleted on drop
+        let list = Self {
+            buffer,
+            handles,
+            mitigation_policy,
+        };
+        if let Some(handles) = &list.handles {
+            update_proc_thread_attribute(
+                list.attribute_list(),
+                PROC_THREAD_ATTRIBUTE_HANDLE_LIST,
+                handles.as_ptr() as *const c_void,
+                std::mem::size_of_val(handles.as_slice()),
+            )?;
+        }
+        if let Some(mitigation_policy) = &list.mitigation_policy {
+            update_proc_thread_attribute(
+                list.attribute_list(),
+                PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY,
+                mitigation_policy.as_ref() as *const u64 as *const c_void,
+                std::mem::size_of::<u64>(),
+            )?;
+        }
+
+        Ok(Some(list))
+    }
+
+    fn attribute_list(&self) -> LPPROC_THREAD_ATTRIBUTE_LIST {
+        LPPROC_THREAD_ATTRIBUTE_LIST(self.buffer.as_ptr() as *mut c_void)
+    }
+}
+
+impl Drop for AttributeList {
+    fn drop(&mut self) {
+        delete_proc_thread_attribute_list(self.attribute_list());
+    }
+}
+
+/// The id of a started process and a handle to it, with those of its primary thread if kept
+pub type Started = (u32, HANDLE, Option<(u32, HANDLE)>);
+
+/// Takes the handles of a started process, closing the token it was started with, if any,
+/// and its primary thread's handle unless it is kept
+///
+/// The process is running by then, so failing to close the others is ignored rather than
+/// reported, as a caller retrying on the error would start the process a second time
+fn started(
+    process_information: PROCESS_INFORMATION,
+    keep_primary_thread: bool,
+    token: Option<OwnedHandle>,
+) -> Started {
+    started_with(
+        process_information,
+        keep_primary_thread,
+        token.map(OwnedHandle::release),
+        close_token,
+    )
+}
+
+/// [`started`] closing the handles with the given function
+fn started_with(
+    process_information: PROCESS_INFORMATION,
+    keep_primary_thread: bool,
+    token: Option<HANDLE>,
+    close: impl Fn(HANDLE) -> Result<(), Error>,
+) -> Started {
+    if let Some(token) = token {
+        let _ = close(token);
+    }
+    let thread = if keep_primary_thread {
+        Some((process_information.dwThreadId, process_information.hThread))
+    } else {
+        let _ = close(process_information.hThread);
+        None
+    };
+    (
+        process_information.dwProcessId,
+        process_information.hProcess,
+        thread,
+    )
+}
+
 /// Starts a process with specified settings
+///
+/// Returns the pid of the new process and a handle to it, and those of its primary thread if kept
 pub fn create_process_with_token(
     token: HANDLE,
-    application_name: &str,
-    command_line: &str,
-    current_directory: &str,
-    desktop: &str,
-) -> Result<(), String> {
-    // Convert all parameters to their native versions
-    let application_name = U16CString::from_str(application_name)
-        .map_err(|err| format!("Cannot convert string to U16CString: {err}"))?;
-    let application_name = PCWSTR::from_raw(application_name.as_ptr());
+    command: &mut NativeCommand,
+) -> Result<Started, Error> {
+    // The token is consumed, whether starting succeeds or not
+    let token = OwnedHandle::new(token);
 
-    let mut command_line = U16CString::from_str(command_line)
-        .map_err(|err| format!("Cannot convert string to U16CString: {err}"))?;
-    let command_line = PWSTR::from_raw(command_line.as_mut_ptr());
+    // Create the startup info
+    let mut startup_info = STARTUPINFOEXW {
+        StartupInfo: command.startup_info(),
+        ..Default::default()
+    };
 
-    let current_directory = U16CString::from_str(current_directory)
-        .map_err(|err| format!("Cannot convert string to U16CString: {err}"))?;
-    let current_directory = PCWSTR::from_raw(current_directory.as_ptr());
+    // Only the redirected streams are inherited, not every inheritable handle of the service
+    let attributes = AttributeList::new(command)?;
+    let creation_flags = match &attributes {
+        Some(attributes) => {
+            startup_info.StartupInfo.cb = std::mem::size_of_val(&startup_info) as u32;
+            startup_info.lpAttributeList = attributes.attribute_list();
+            EXTENDED_STARTUPINFO_PRESENT
+        }
+        None => PROCESS_CREATION_FLAGS(0),
+    };
+    let inherit_handles = command.inherited_handles().is_some();
 
-    let mut lp_desktop = U16CString::from_str(desktop)
-        .map_err(|err| format!("Cannot convert string to U16CString: {err}"))?;
-    let lp_desktop = PWSTR::from_raw(lp_desktop.as_mut_ptr());
+    // Create the process information
+    let process_information = PROCESS_INFORMATION::default();
+
+    // Attempt starting
+    let process_information = create_process_as_user_w(
+        token.get(),
+        command.application_name(),
+        command.command_line(),
+        None,
+        None,
+        inherit_handles,
+        creation_flags | command.creation_flags(),
+        command.environment(),
+        command.current_directory(),
+        &startup_info as *const STARTUPINFOEXW as *const STARTUPINFOW,
+        process_information,
+    )?;
+
+    Ok(started(
+        process_information,
+        command.keep_primary_thread,
+        Some(token),
+    ))
+}
+
+/// Starts a process with specified settings through the secondary logon service
+///
+/// Returns the pid of the new process and a handle to it, and those of its primary thread if kept
+pub fn create_process_with_token_secondary(
+    token: HANDLE,
+    command: &mut NativeCommand,
+) -> Result<Started, Error> {
+    // The token is consumed, whether starting succeeds or not
+    let token = OwnedHandle::new(token);
 
     // Create the startup info
-    let mut startup_info = STARTUPINFOW::default();
-    startup_info.cb = std::mem::size_of_val(&startup_info) as u32;
-    startup_info.lpDesktop = lp_desktop;
+    let startup_info = command.startup_info();
 
     // Create the process information
     let process_information = PROCESS_INFORMATION::default();
 
-    let mut errors: Vec<String> = Vec::new();
+    // Attempt starting
+    let process_information = create_process_with_token_w(
+        token.get(),
+        CREATE_PROCESS_LOGON_FLAGS(0),
+        command.application_name(),
+        command.command_line(),
+        command.creation_flags(),
+        command.environment(),
+        command.current_directory(),
+        startup_info,
+        process_information,
+    )?;
+
+    Ok(started(
+        process_information,
+        command.keep_primary_thread,
+        Some(token),
+    ))
+}
+
+/// Logs an account on and starts a process as it through the secondary logon service
+///
+/// Returns the pid of the new process and a handle to it, and those of its primary thread if kept
+pub fn create_process_with_logon(
+    username: &str,
+    domain: &str,
+    password: &str,
+    logon_flags: CREATE_PROCESS_LOGON_FLAGS,
+    command: &mut NativeCommand,
+) -> Result<Started, Error> {
+    // Convert all parameters to their native versions
+    let username = to_u16_cstring(username)?;
+    let domain = to_u16_cstring(domain)?;
+    let password = to_u16_cstring(password)?;
+
+    // Let the system pick the domain for user principal names
+    let domain = if domain.is_empty() {
+        PCWSTR::null()
+    } else {
+        PCWSTR::from_raw(domain.as_ptr())
+    };
+
+    // Create the startup info
+    let startup_info = command.startup_info();
+
+    // Create the process information
+    let process_information = PROCESS_INFORMATION::default();
 
     // Attempt starting
-    if let Err(err) = create_process_as_user_w(
-        token,
-        application_name,
-        command_line,
-        None,
-        None,
-        false,
-        PROCESS_CREATION_FLAGS(0),
-        None,
-        current_directory,
+    let process_information = create_process_with_logon_w(
+        PCWSTR::from_raw(username.as_ptr()),
+        domain,
+        PCWSTR::from_raw(password.as_ptr()),
+        logon_flags,
+        command.application_name(),
+        command.command_line(),
+        command.creation_flags(),
+        command.environment(),
+        command.current_directory(),
         startup_info,
         process_information,
+    )?;
+
+    Ok(started(
+        process_information,
+        command.keep_primary_thread,
+        None,
+    ))
+}
+
+/// Creates the profile of an account that never logged on, returning `false` if it exists already
+pub fn create_account_profile(sid: &str, username: &str) -> Result<bool, Error> {
+    let sid = to_u16_cstring(sid)?;
+    let username = to_u16_cstring(username)?;
+    create_profile(
+        PCWSTR::from_raw(sid.as_ptr()),
+        PCWSTR::from_raw(username.as_ptr()),
+    )
+}
+
+/// Gets the directory of the profile of the user of a token
+pub fn get_profile_directory(token: HANDLE) -> Result<String, Error> {
+    get_user_profile_directory_w(token)
+}
+
+/// Deletes the profile of an account, its directory and its registry entries
+pub fn delete_account_profile(sid: &str) -> Result<(), Error> {
+    let sid = to_u16_cstring(sid)?;
+    delete_profile_w(PCWSTR::from_raw(sid.as_ptr()))
+}
+
+/// Loads the profile of the user of a token, returning the handle to unload it with
+pub fn load_account_profile(token: HANDLE, username: &str) -> Result<HANDLE, Error> {
+    let mut username = to_u16_cstring(username)?;
+    load_user_profile_w(token, PWSTR::from_raw(username.as_mut_ptr()))
+}
+
+/// Unloads a profile loaded with [`load_account_profile`]
+pub fn unload_account_profile(token: HANDLE, profile: HANDLE) -> Result<(), Error> {
+    unload_user_profile(token, profile)
+}
+
+/// Whether the current process is a 32-bit one running on 64-bit Windows
+pub fn is_wow64() -> Result<bool, Error> {
+    is_wow64_process(get_current_process())
+}
+
+/// Resolves an executable under the system directory to the one of the system's own bitness
+///
+/// A 32-bit process on 64-bit Windows sees the 32-bit binaries of SysWOW64 under System32, and
+/// reaches the native ones through the `Sysnative` alias, which other processes do not have.
+/// `Sysnative` paths are resolved to System32 for processes without the alias, and System32 paths
+/// to `Sysnative` for those with it if `native_system32` is set.
+pub fn resolve_system_path(path: &OsStr, native_system32: bool) -> Result<OsString, Error> {
+    let wow64 = is_wow64()?;
+    let (from, to) = match (wow64, native_system32) {
+        (true, true) => ("System32", "Sysnative"),
+        (true, false) => return Ok(path.to_os_string()),
+        (false, _) => ("Sysnative", "System32"),
+    };
+    // The system directory is valid UTF-8, so other paths cannot be in it
+    let Some(path) = path.to_str() else {
+        return Ok(path.to_os_string());
+    };
+
+    let system_root = expand_environment_strings("%SystemRoot%")?;
+    let prefix = format!("{system_root}\\{from}\\");
+    // Paths are compared case-insensitively, only ASCII matters for the system directory
+    match path.get(..prefix.len()) {
+        Some(start) if start.eq_ignore_ascii_case(&prefix) => {
+            Ok(format!("{system_root}\\{to}\\{}", &path[prefix.len()..]).into())
+        }
+        _ => Ok(path.into()),
+    }
+}
+
+/// Gets the environment variables a user logging on would get, without those of the service
+pub fn get_user_environment(token: HANDLE) -> Result<Vec<(OsString, OsString)>, Error> {
+    let entries = create_environment_block(token, false)?;
+    Ok(entries
+        .into_iter()
+        // Hidden entries like `=C:` keep per-drive directories of shells, not variables
+        .filter(|entry| !entry.starts_with('='))
+        .filter_map(|entry| {
+            let (name, value) = entry.split_once('=')?;
+            Some((name.into(), value.into()))
+        })
+        .collect())
+}
+
+/// Expands `%VAR%` references in a string using the environment of the current process
+pub fn expand_environment_strings(input: &str) -> Result<String, Error> {
+    expand_environment_os_strings(OsStr::new(input))?
+        .into_string()
+        .map_err(|_| Error::other(Stage::Conversion, "Cannot convert expanded string to utf-8"))
+}
+
+/// Expands `%VAR%` references in an OS string using the environment of the current process
+pub fn expand_environment_os_strings(input: &OsStr) -> Result<OsString, Error> {
+    let source = os_to_u16_cstring(input)?;
+    let source = PCWSTR::from_raw(source.as_ptr());
+
+    // Ask for the required buffer size first
+    let required = expand_environment_strings_w(source, None)?;
+
+    // Then expand into a buffer of that size
+    let mut buffer = vec![0u16; required as usize];
+    expand_environment_strings_w(source, Some(&mut buffer))?;
+
+    let expanded = U16CStr::from_slice_truncate(&buffer).map_err(|err| {
+        Error::other(
+            Stage::Conversion,
+            format!("Cannot convert expanded string from U16CStr: {err}"),
+        )
+    })?;
+
+    Ok(expanded.to_os_string())
+}
+
+#[cfg(feature = "window")]
+/// Gets all top-level windows on the desktop of the calling thread
+pub fn get_all_windows() -> Result<Vec<WindowInfo>, Error> {
+    let windows = enum_windows()?
+        .into_iter()
+        .map(|hwnd| {
+            let pid = get_window_thread_process_id(hwnd);
+            let session_id = process_id_to_session_id(pid).ok();
+            let title = get_window_text_w(hwnd);
+            let class = get_class_name_w(hwnd);
+            let visible = is_window_visible(hwnd);
+            WindowInfo {
+                hwnd: hwnd.0,
+                pid,
+                session_id,
+                title,
+                class,
+                visible,
+            }
+        })
+        .collect();
+    Ok(windows)
+}
+
+#[cfg(feature = "diagnostics")]
+/// Reads a DWORD value under `HKEY_LOCAL_MACHINE`, or none if it is not set
+pub fn get_machine_dword(subkey: &str, value: &str) -> Result<Option<u32>, Error> {
+    let subkey = to_u16_cstring(subkey)?;
+    let value = to_u16_cstring(value)?;
+
+    match reg_get_value_w(
+        HKEY_LOCAL_MACHINE,
+        PCWSTR::from_raw(subkey.as_ptr()),
+        PCWSTR::from_raw(value.as_ptr()),
     ) {
-        errors.push(err);
+        Ok(data) => Ok(Some(data)),
+        Err(err) if err.code() == Some(ERROR_FILE_NOT_FOUND.0 as i32) => Ok(None),
+        Err(err) => Err(err),
     }
+}
 
-    // On error format the errors and return
-    if !errors.is_empty() {
-        let errors_combined = errors.join("\n");
-        return Err(errors_combined);
+/// Sets a DWORD value in the registry hive of a user, creating the key if it does not exist
+///
+/// The hive is only loaded while the user is logged on, or has processes running.
+pub fn set_user_dword(sid: &str, subkey: &str, value: &str, data: u32) -> Result<(), Error> {
+    let subkey = to_u16_cstring(&format!("{sid}\\{subkey}"))?;
+    let value = to_u16_cstring(value)?;
+
+    let key = reg_create_key_ex_w(HKEY_USERS, PCWSTR::from_raw(subkey.as_ptr()))?;
+    let set = reg_set_value_ex_w(key, PCWSTR::from_raw(value.as_ptr()), data);
+    let closed = reg_close_key(key);
+
+    match (set, closed) {
+        (Ok(()), Ok(())) => Ok(()),
+        (Ok(()), Err(err)) => Err(err),
+        (Err(err), Ok(())) => Err(err),
+        (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
     }
+}
+
+/// Creates a named mutex that the system, administrators and a user can open, or opens the existing one
+///
+/// Returns the mutex and whether it already existed
+pub fn create_shared_mutex(name: &str, sid: &str) -> Result<(HANDLE, bool), Error> {
+    let name = to_u16_cstring(name)?;
+    let sddl = to_u16_cstring(&format!("D:(A;;GA;;;SY)(A;;GA;;;BA)(A;;GA;;;{sid})"))?;
+    create_mutex_w(
+        PCWSTR::from_raw(name.as_ptr()),
+        PCWSTR::from_raw(sddl.as_ptr()),
+    )
+}
 
+/// Gives another process its own copy of a handle, which it closes when it exits
+pub fn copy_handle_to_process(handle: HANDLE, target_process_handle: HANDLE) -> Result<(), Error> {
+    duplicate_handle(handle, target_process_handle, false)?;
     Ok(())
 }
+
+/// Opens an existing file to read from, as an inheritable handle
+pub fn open_file_for_reading(path: &str) -> Result<HANDLE, Error> {
+    let path = to_u16_cstring(path)?;
+    create_file_w(
+        PCWSTR::from_raw(path.as_ptr()),
+        FILE_GENERIC_READ.0,
+        OPEN_EXISTING,
+    )
+}
+
+/// Opens a file to write to, as an inheritable handle
+///
+/// The file is created if it does not exist. Otherwise it is truncated,
+/// or every write goes to its end when appending.
+pub fn open_file_for_writing(path: &str, append: bool) -> Result<HANDLE, Error> {
+    let path = to_u16_cstring(path)?;
+    let (desired_access, creation_disposition) = if append {
+        (FILE_GENERIC_WRITE.0 & !FILE_WRITE_DATA.0, OPEN_ALWAYS)
+    } else {
+        (FILE_GENERIC_WRITE.0, CREATE_ALWAYS)
+    };
+    create_file_w(
+        PCWSTR::from_raw(path.as_ptr()),
+        desired_access,
+        creation_disposition,
+    )
+}
+
+/// Duplicates a handle of the current process, to keep using it after the original is closed
+pub fn copy_handle(handle: HANDLE) -> Result<HANDLE, Error> {
+    duplicate_handle(handle, get_current_process(), false)
+}
+
+/// Duplicates a handle of the current process into an inheritable one
+pub fn copy_handle_inheritable(handle: HANDLE) -> Result<HANDLE, Error> {
+    duplicate_handle(handle, get_current_process(), true)
+        .map_err(|err| err.context(Stage::Redirection, "Unable to duplicate a stream handle"))
+}
+
+/// Creates a pipe whose writing end can be inherited by a new process
+///
+/// Returns the reading and the writing end
+pub fn create_inheritable_pipe() -> Result<(HANDLE, HANDLE), Error> {
+    let (read_handle, write_handle) = create_pipe()?;
+    let write_handle = replace_with_inheritable(write_handle, read_handle)?;
+    Ok((read_handle, write_handle))
+}
+
+/// Creates a pipe whose reading end can be inherited by a new process
+///
+/// Returns the reading and the writing end
+pub fn create_inheritable_input_pipe() -> Result<(HANDLE, HANDLE), Error> {
+    let (read_handle, write_handle) = create_pipe()?;
+    let read_handle = replace_with_inheritable(read_handle, write_handle)?;
+    Ok((read_handle, write_handle))
+}
+
+/// Replaces one end of a new pipe by an inheritable copy, closing both ends if that fails
+fn replace_with_inheritable(end: HANDLE, other_end: HANDLE) -> Result<HANDLE, Error> {
+    let inheritable = copy_handle_inheritable(end);
+    let closed = close_token(end);
+
+    let mut errors: Vec<Error> = Vec::new();
+    let cleanup = match (inheritable, closed) {
+        (Ok(inheritable), Ok(())) => return Ok(inheritable),
+        (Ok(inheritable), Err(err)) => {
+            errors.push(err);
+            close_token(inheritable)
+        }
+        (Err(err), closed) => {
+            errors.push(err);
+            closed
+        }
+    };
+    if let Err(err) = cleanup {
+        errors.push(err);
+    }
+    if let Err(err) = close_token(other_end) {
+        errors.push(err);
+    }
+
+    Err(Error::combine(errors))
+}
+
+/// Decodes text in a code page, replacing invalid characters
+pub fn decode_code_page(code_page: u32, bytes: &[u8]) -> Result<String, Error> {
+    let wide = multi_byte_to_wide_char(code_page, bytes)?;
+    Ok(String::from_utf16_lossy(&wide))
+}
+
+/// Gets the code page console programs write in unless they pick another one
+pub fn get_oem_code_page() -> u32 {
+    get_oem_cp()
+}
+
+/// Gets the code page of programs that are not Unicode aware
+pub fn get_ansi_code_page() -> u32 {
+    get_acp()
+}
+
+/// The security of the launch server's pipe: the system, administrators and interactive
+/// users can use it, network logons cannot
+#[cfg(feature = "server")]
+const SERVER_PIPE_SDDL: &str = "D:(D;;GA;;;NU)(A;;GA;;;SY)(A;;GA;;;BA)(A;;GRGW;;;IU)";
+
+/// Creates an instance of a launch server's pipe
+///
+/// Creating the first instance fails if another process already owns a pipe by the name
+#[cfg(feature = "server")]
+pub fn create_server_pipe(
+    name: &str,
+    first_instance: bool,
+    buffer_size: usize,
+) -> Result<HANDLE, Error> {
+    let name = to_u16_cstring(&format!("\\\\.\\pipe\\{name}"))?;
+    let sddl = to_u16_cstring(SERVER_PIPE_SDDL)?;
+    create_named_pipe_w(
+        PCWSTR::from_raw(name.as_ptr()),
+        PCWSTR::from_raw(sddl.as_ptr()),
+        first_instance,
+        buffer_size as u32,
+    )
+}
+
+/// Waits for a client to connect to a pipe instance
+#[cfg(feature = "server")]
+pub fn wait_for_pipe_client(pipe: HANDLE) -> Result<(), Error> {
+    connect_named_pipe(pipe)
+}
+
+/// Reads a whole message from a message mode pipe, failing if it is larger than the limit
+#[cfg(feature = "server")]
+pub fn read_pipe_message(pipe: HANDLE, max_size: usize) -> Result<Vec<u8>, Error> {
+    let mut message = vec![0u8; max_size];
+    match read_file(pipe, &mut message) {
+        Ok(read) => {
+            message.truncate(read);
+            Ok(message)
+        }
+        Err(err) if err.code() == Some(ERROR_MORE_DATA.0 as i32) => Err(Error::other(
+            Stage::LaunchServer,
+            format!("Pipe messages cannot exceed {max_size} bytes"),
+        )),
+        Err(err) => Err(err),
+    }
+}
+
+/// Writes a message to a pipe and waits until the client has read it
+#[cfg(feature = "server")]
+pub fn write_pipe_message(pipe: HANDLE, message: &[u8]) -> Result<(), Error> {
+    write_file(pipe, message)
+        .map_err(|err| err.context(Stage::LaunchServer, "Unable to send a message"))?;
+    flush_file_buffers(pipe)
+}
+
+/// Disconnects the client of a pipe instance
+#[cfg(feature = "server")]
+pub fn disconnect_pipe_client(pipe: HANDLE) -> Result<(), Error> {
+    disconnect_named_pipe(pipe)
+}
+
+/// Gets a query token of the client connected to a pipe instance, taken from the connection itself
+///
+/// Unlike looking the client up by its pid, this cannot be fooled by the pid being reused.
+#[cfg(feature = "server")]
+pub fn get_pipe_client_token(pipe: HANDLE) -> Result<HANDLE, Error> {
+    impersonate_named_pipe_client(pipe)?;
+    let token = open_thread_token(TOKEN_QUERY);
+    // The thread must not go on acting as the client in any case
+    match (token, revert_to_self()) {
+        (Ok(token), Ok(())) => Ok(token),
+        (Ok(token), Err(err)) => Err(close_after_failure(err, &[token])),
+        (Err(err), Ok(())) => Err(err),
+        (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
+    }
+}
+
+/// Sends a message to a launch server's pipe and returns its reply
+#[cfg(feature = "server")]
+pub fn call_server_pipe(
+    name: &str,
+    message: &[u8],
+    max_reply_size: usize,
+    timeout: Duration,
+) -> Result<Vec<u8>, Error> {
+    let name = to_u16_cstring(&format!("\\\\.\\pipe\\{name}"))?;
+    let mut reply = vec![0u8; max_reply_size];
+    let timeout_ms = timeout.as_millis().min(u32::MAX as u128 - 1) as u32;
+    let read = call_named_pipe_w(
+        PCWSTR::from_raw(name.as_ptr()),
+        message,
+        &mut reply,
+        timeout_ms,
+    )?;
+    reply.truncate(read);
+    Ok(reply)
+}
+
+/// Reads from a pipe, returning zero once every writing end is closed
+pub fn read_pipe(handle: HANDLE, buffer: &mut [u8]) -> Result<usize, Error> {
+    match read_file(handle, buffer) {
+        Err(err) if err.code() == Some(ERROR_BROKEN_PIPE.0 as i32) => Ok(0),
+        read => read,
+    }
+}
+
+/// Writes to a pipe, returning how much of the data was taken
+pub fn write_pipe(handle: HANDLE, data: &[u8]) -> Result<usize, Error> {
+    write_file(handle, data)
+}
+
+/// Asks every top-level window of a process on the desktop of the calling thread to close
+///
+/// Returns how many windows were asked
+#[cfg(feature = "window")]
+pub fn close_windows_of_process(pid: u32) -> Result<usize, Error> {
+    let mut asked = 0;
+    for hwnd in enum_windows()? {
+        if get_window_thread_process_id(hwnd) == pid {
+            post_close_message(hwnd)?;
+            asked += 1;
+        }
+    }
+    Ok(asked)
+}
+
+/// Opens a process by pid to manage it like a launched one
+pub fn open_process_for_child(pid: u32) -> Result<HANDLE, Error> {
+    open_process(
+        PROCESS_QUERY_LIMITED_INFORMATION | PROCESS_SYNCHRONIZE | PROCESS_TERMINATE,
+        false,
+        pid,
+    )
+}
+
+/// Opens a process by pid to stop it and wait for it to exit
+pub fn open_process_for_termination(pid: u32) -> Result<HANDLE, Error> {
+    open_process(PROCESS_TERMINATE | PROCESS_SYNCHRONIZE, false, pid)
+}
+
+/// Waits up to a number of milliseconds for a process to exit and returns whether it did
+pub fn wait_for_process_timeout(process_handle: HANDLE, milliseconds: u32) -> Result<bool, Error> {
+    wait_for_single_object(process_handle, milliseconds)
+}
+
+#[cfg(test)]
+mod started_tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    const TOKEN: HANDLE = HANDLE(1);
+    const PROCESS: HANDLE = HANDLE(2);
+    const THREAD: HANDLE = HANDLE(3);
+
+    fn process_information() -> PROCESS_INFORMATION {
+        PROCESS_INFORMATION {
+            hProcess: PROCESS,
+            hThread: THREAD,
+            dwProcessId: 10,
+            dwThreadId: 20,
+        }
+    }
+
+    /// Runs `started_with` with closing every handle failing, returning what it tried to close
+    fn started_failing_to_close(keep_primary_thread: bool) -> (Started, Vec<HANDLE>) {
+        let closed = RefCell::new(Vec::new());
+        let started = started_with(
+            process_information(),
+            keep_primary_thread,
+            Some(TOKEN),
+            |handle| {
+                closed.borrow_mut().push(handle);
+                Err(Error::other(Stage::Cleanup, "Unable to close the handle"))
+            },
+        );
+        (started, closed.into_inner())
+    }
+
+    #[test]
+    fn failing_to_close_the_token_and_thread_still_returns_the_process() {
+        let (started, closed) = started_failing_to_close(false);
+        assert_eq!(started, (10, PROCESS, None));
+        assert_eq!(closed, [TOKEN, THREAD]);
+    }
+
+    #[test]
+    fn failing_to_close_the_token_still_returns_the_kept_thread() {
+        let (started, closed) = started_failing_to_close(true);
+        assert_eq!(started, (10, PROCESS, Some((20, THREAD))));
+        assert_eq!(closed, [TOKEN]);
+    }
+}
+
+#[cfg(all(test, windows))]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use windows::Win32::System::Threading::GetProcessHandleCount;
+
+    /// How many launches each test fails, so a leak of one handle per launch cannot hide
+    const ATTEMPTS: u32 = 32;
+    /// Handles the process may open meanwhile on its own, e.g. for the thread pool
+    const SLACK: u32 = 4;
+
+    /// The handle count is per process, so the tests counting it cannot run side by side
+    static HANDLE_COUNT: Mutex<()> = Mutex::new(());
+
+    fn handle_count() -> u32 {
+        let mut count = 0;
+        assert!(unsafe { GetProcessHandleCount(get_current_process(), &mut count).as_bool() });
+        count
+    }
+
+    fn missing_command() -> NativeCommand {
+        let path = OsStr::new("C:\\win_run\\does\\not\\exist.exe");
+        NativeCommand::new(path, path, OsStr::new("C:\\"), "winsta0\\default").unwrap()
+    }
+
+    /// Fails a launch many times over, checking the handle count does not grow with them
+    fn assert_no_leak(launch: impl Fn() -> Result<Started, Error>) {
+        let _guard = HANDLE_COUNT.lock().unwrap_or_else(|err| err.into_inner());
+        // The first launch may load libraries that keep handles open for good
+        assert!(launch().is_err());
+
+        let before = handle_count();
+        for _ in 0..ATTEMPTS {
+            assert!(launch().is_err());
+        }
+        let after = handle_count();
+        assert!(
+            after <= before + SLACK,
+            "{ATTEMPTS} failed launches grew the handle count from {before} to {after}"
+        );
+    }
+
+    fn own_token() -> HANDLE {
+        get_process_token(std::process::id()).unwrap()
+    }
+
+    #[test]
+    fn failed_launches_with_token_close_it() {
+        assert_no_leak(|| create_process_with_token(own_token(), &mut missing_command()));
+    }
+
+    #[test]
+    fn failed_launches_with_attributes_close_the_token() {
+        assert_no_leak(|| {
+            let mut command = missing_command().with_hardened_dll_search();
+            create_process_with_token(own_token(), &mut command)
+        });
+    }
+
+    #[test]
+    fn failed_secondary_launches_close_the_token() {
+        assert_no_leak(|| create_process_with_token_secondary(own_token(), &mut missing_command()));
+    }
+
+    #[test]
+    fn failed_launches_with_logon_leave_nothing_open() {
+        assert_no_leak(|| {
+            create_process_with_logon(
+                "win_run_missing_user",
+                ".",
+                "not the password",
+                CREATE_PROCESS_LOGON_FLAGS(0),
+                &mut missing_command(),
+            )
+        });
+    }
+}
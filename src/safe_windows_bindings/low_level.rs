@@ -1,39 +1,156 @@
+use crate::error::{Error, Stage};
+use crate::safe_windows_bindings::compat::{
+    self, last_error, local_free, security_information, Status, Succeeded,
+};
 use std::ffi::c_void;
-use std::io::Error;
+use widestring::U16CStr;
 use windows::core::{PCWSTR, PWSTR};
-use windows::Win32::Foundation::{CloseHandle, HANDLE};
+#[cfg(feature = "server")]
+use windows::Win32::Foundation::ERROR_PIPE_CONNECTED;
+#[cfg(not(feature = "sysinfo"))]
+use windows::Win32::Foundation::FILETIME;
+use windows::Win32::Foundation::{
+    CloseHandle, DuplicateHandle, BOOL, DUPLICATE_SAME_ACCESS, ERROR_ALREADY_ASSIGNED,
+    ERROR_ALREADY_EXISTS, ERROR_INSUFFICIENT_BUFFER, ERROR_NO_TOKEN, HANDLE, LUID, MAX_PATH, PSID,
+    SYSTEMTIME, WAIT_OBJECT_0, WAIT_TIMEOUT,
+};
+#[cfg(feature = "window")]
+use windows::Win32::Foundation::{HWND, LPARAM, WPARAM};
+use windows::Win32::Globalization::{
+    GetACP, GetOEMCP, MultiByteToWideChar, MULTI_BYTE_TO_WIDE_CHAR_FLAGS,
+};
+use windows::Win32::NetworkManagement::WNet::{
+    WNetAddConnection2W, NETRESOURCEW, RESOURCETYPE_DISK,
+};
+use windows::Win32::Security::AppLocker::{
+    SaferCloseLevel, SaferGetLevelInformation, SaferIdentifyLevel, SaferObjectLevelId,
+    SAFER_CODE_PROPERTIES_V2, SAFER_CRITERIA_IMAGEHASH, SAFER_CRITERIA_IMAGEPATH,
+};
+use windows::Win32::Security::Authorization::{
+    ConvertSidToStringSidW, ConvertStringSecurityDescriptorToSecurityDescriptorW,
+    ConvertStringSidToSidW, GetSecurityInfo, SetEntriesInAclW, SetNamedSecurityInfoW,
+    SetSecurityInfo, EXPLICIT_ACCESS_W, GRANT_ACCESS, SDDL_REVISION_1, SE_FILE_OBJECT,
+    SE_KERNEL_OBJECT, SE_WINDOW_OBJECT, TRUSTEE_IS_SID, TRUSTEE_IS_USER, TRUSTEE_W,
+};
 use windows::Win32::Security::{
-    DuplicateTokenEx, GetTokenInformation, SECURITY_ATTRIBUTES, SECURITY_IMPERSONATION_LEVEL,
-    TOKEN_ACCESS_MASK, TOKEN_INFORMATION_CLASS, TOKEN_TYPE,
+    DuplicateTokenEx, GetLengthSid, GetSecurityDescriptorDacl, GetSidSubAuthority,
+    GetSidSubAuthorityCount, GetTokenInformation, ImpersonateLoggedOnUser, LogonUserW,
+    LookupAccountNameW, LookupAccountSidW, LookupPrivilegeNameW, RevertToSelf, ACL,
+    DACL_SECURITY_INFORMATION, LOGON32_LOGON, LOGON32_LOGON_NEW_CREDENTIALS,
+    LOGON32_PROVIDER_DEFAULT, LOGON32_PROVIDER_WINNT50, NO_INHERITANCE,
+    PROTECTED_DACL_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR, SAFER_LEVEL_HANDLE,
+    SECURITY_ATTRIBUTES, SECURITY_IMPERSONATION_LEVEL, SID_NAME_USE, TOKEN_ACCESS_MASK,
+    TOKEN_INFORMATION_CLASS, TOKEN_TYPE,
+};
+use windows::Win32::Storage::FileSystem::{
+    CreateFileW, WriteFile, FILE_ATTRIBUTE_NORMAL, FILE_CREATION_DISPOSITION, FILE_SHARE_READ,
+    FILE_SHARE_WRITE,
+};
+#[cfg(feature = "server")]
+use windows::Win32::Storage::FileSystem::{
+    FlushFileBuffers, FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX,
+};
+#[cfg(not(feature = "sysinfo"))]
+use windows::Win32::System::Diagnostics::ToolHelp::{
+    CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS,
+};
+use windows::Win32::System::Environment::{
+    CreateEnvironmentBlock, DestroyEnvironmentBlock, ExpandEnvironmentStringsW,
+};
+use windows::Win32::System::JobObjects::{
+    AssignProcessToJobObject, CreateJobObjectW, JobObjectBasicAccountingInformation,
+    JobObjectExtendedLimitInformation, QueryInformationJobObject, SetInformationJobObject,
+    TerminateJobObject, JOBOBJECT_BASIC_ACCOUNTING_INFORMATION,
+    JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+};
+use windows::Win32::System::Pipes::CreatePipe;
+#[cfg(feature = "server")]
+use windows::Win32::System::Pipes::{
+    CallNamedPipeW, ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe,
+    ImpersonateNamedPipeClient, PIPE_READMODE_MESSAGE, PIPE_REJECT_REMOTE_CLIENTS,
+    PIPE_TYPE_MESSAGE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+};
+use windows::Win32::System::Registry::{
+    RegCloseKey, RegCreateKeyExW, RegSetValueExW, HKEY, KEY_SET_VALUE, REG_DWORD,
+    REG_OPTION_NON_VOLATILE,
+};
+#[cfg(feature = "diagnostics")]
+use windows::Win32::System::Registry::{RegGetValueW, RRF_RT_REG_DWORD};
+#[cfg(feature = "diagnostics")]
+use windows::Win32::System::RemoteDesktop::WTS_INFO_CLASS;
+use windows::Win32::System::RemoteDesktop::{
+    ProcessIdToSessionId, WTSDisconnectSession, WTSEnumerateSessionsW, WTSFreeMemory,
+    WTSGetActiveConsoleSessionId, WTSLogoffSession, WTSQuerySessionInformationW, WTSQueryUserToken,
+    WTSSendMessageW, WTSSessionInfoEx, WTSINFOEXW, WTSINFOEX_LEVEL1_W, WTS_CONNECTSTATE_CLASS,
+    WTS_CURRENT_SERVER_HANDLE, WTS_SESSION_INFOW,
+};
+use windows::Win32::System::StationsAndDesktops::{
+    CloseDesktop, CloseWindowStation, GetProcessWindowStation, OpenDesktopW, OpenWindowStationW,
+    SetProcessWindowStation, DESKTOP_CONTROL_FLAGS, HDESK, HWINSTA,
+};
+use windows::Win32::System::SystemInformation::GetLocalTime;
+use windows::Win32::System::Threading::{
+    CreateMutexW, CreateProcessAsUserW, CreateProcessWithLogonW, CreateProcessWithTokenW,
+    DeleteProcThreadAttributeList, GetCurrentProcess, GetExitCodeProcess,
+    InitializeProcThreadAttributeList, IsWow64Process, OpenProcess, OpenProcessToken, ResumeThread,
+    SetProcessAffinityMask, SetProcessInformation, SuspendThread, TerminateProcess,
+    UpdateProcThreadAttribute, WaitForSingleObject, CREATE_PROCESS_LOGON_FLAGS,
+    LPPROC_THREAD_ATTRIBUTE_LIST, PROCESS_ACCESS_RIGHTS, PROCESS_CREATION_FLAGS,
+    PROCESS_INFORMATION, PROCESS_INFORMATION_CLASS, STARTUPINFOW,
 };
-use windows::Win32::System::RemoteDesktop::{WTSGetActiveConsoleSessionId, WTSQueryUserToken};
+#[cfg(feature = "server")]
+use windows::Win32::System::Threading::{GetCurrentThread, OpenThreadToken};
+#[cfg(not(feature = "sysinfo"))]
 use windows::Win32::System::Threading::{
-    CreateProcessAsUserW, OpenProcess, OpenProcessToken, PROCESS_ACCESS_RIGHTS,
-    PROCESS_CREATION_FLAGS, PROCESS_INFORMATION, STARTUPINFOW,
+    GetProcessTimes, QueryFullProcessImageNameW, PROCESS_NAME_WIN32,
+};
+use windows::Win32::UI::Shell::{
+    CreateProfile, DeleteProfileW, GetUserProfileDirectoryW, LoadUserProfileW, UnloadUserProfile,
+    PROFILEINFOW,
 };
+#[cfg(feature = "window")]
+use windows::Win32::UI::WindowsAndMessaging::{
+    EnumWindows, GetClassNameW, GetWindowTextW, GetWindowThreadProcessId, IsWindowVisible,
+    PostMessageW, WM_CLOSE,
+};
+use windows::Win32::UI::WindowsAndMessaging::{MESSAGEBOX_RESULT, MESSAGEBOX_STYLE};
 
 /// Closes a token and returns an error if there was one
-pub fn close_token(h_object: HANDLE) -> Result<(), String> {
+pub fn close_token(h_object: HANDLE) -> Result<(), Error> {
     // Close the token
-    let closed = unsafe { CloseHandle(h_object).as_bool() };
+    let closed = unsafe { CloseHandle(h_object).succeeded() };
     // If it couldn't be closed, find out why and return the error
     if !closed {
-        let last_error = Error::last_os_error();
-        return Err(format!("Unable to close handle: {last_error}"));
+        return Err(Error::last_os_error(
+            Stage::Cleanup,
+            "Unable to close handle",
+        ));
     }
     Ok(())
 }
 
+/// Closes the handles a failed call was responsible for, keeping its failure as the primary error
+pub fn close_after_failure(error: Error, handles: &[HANDLE]) -> Error {
+    let mut errors = vec![error];
+    for handle in handles {
+        if let Err(err) = close_token(*handle) {
+            errors.push(err);
+        }
+    }
+    Error::combine(errors)
+}
+
 /// Gets a handle to a process using specific pid and access rights
 pub fn open_process(
     dw_desired_access: PROCESS_ACCESS_RIGHTS,
     b_inherit_handle: bool,
     pid: u32,
-) -> Result<HANDLE, String> {
+) -> Result<HANDLE, Error> {
     // Open the process
     let process_handle = unsafe { OpenProcess(dw_desired_access, b_inherit_handle, pid) };
     // Format error case
-    process_handle.map_err(|err| format!("Could not obtain process: {err}"))
+    process_handle
+        .map_err(|err| Error::windows(Stage::ProcessOpen, "Could not obtain process", err))
 }
 
 /// Gets the token of a process using its handle and desired access
@@ -42,29 +159,22 @@ pub fn open_process(
 pub fn open_process_token(
     process_handle: HANDLE,
     desired_access: TOKEN_ACCESS_MASK,
-) -> Result<HANDLE, String> {
+) -> Result<HANDLE, Error> {
     // Create empty token handle
     let mut token_handle: HANDLE = HANDLE::default();
     // Fill it with the process token
     let success =
-        unsafe { OpenProcessToken(process_handle, desired_access, &mut token_handle).as_bool() };
-    // If it couldn't be opened, close the empty token handle and the process handle and return
+        unsafe { OpenProcessToken(process_handle, desired_access, &mut token_handle).succeeded() };
+    // If it couldn't be opened, close the process handle and return
     if !success {
-        let mut errors: Vec<String> = Vec::new();
-        let last_error = Error::last_os_error();
-        errors.push(format!("Unable to open process token: {last_error}"));
-        if let Err(err) = close_token(process_handle) {
-            errors.push(err);
-        }
-        if let Err(err) = close_token(token_handle) {
-            errors.push(err);
-        }
-        let errors_combined = errors.join("\n");
-        return Err(errors_combined);
+        let error = Error::last_os_error(Stage::TokenOpen, "Unable to open process token");
+        return Err(close_after_failure(error, &[process_handle]));
     }
 
-    // If it could be opened close the process handle anyway
-    close_token(process_handle)?;
+    // If it could be opened close the process handle anyway, not leaking the token if that fails
+    if let Err(err) = close_token(process_handle) {
+        return Err(close_after_failure(err, &[token_handle]));
+    }
 
     // Return the token handle
     Ok(token_handle)
@@ -72,14 +182,14 @@ pub fn open_process_token(
 
 /// Duplicates a token
 ///
-/// Closes the initial token handle in any case
-pub fn duplicate_token_ex(
+/// Leaves the existing token open, the caller remains responsible for closing it
+pub fn duplicate_token_ex_keep_existing(
     h_existing_token: HANDLE,
     dw_desired_access: TOKEN_ACCESS_MASK,
     lp_token_attributes: Option<*const SECURITY_ATTRIBUTES>,
     impersonation_level: SECURITY_IMPERSONATION_LEVEL,
     token_type: TOKEN_TYPE,
-) -> Result<HANDLE, String> {
+) -> Result<HANDLE, Error> {
     // Create empty token handle
     let mut ph_new_token = HANDLE::default();
 
@@ -93,54 +203,273 @@ pub fn duplicate_token_ex(
             token_type,
             &mut ph_new_token,
         )
-        .as_bool()
+        .succeeded()
     };
 
-    // On failure close all provides handles
+    // No token was created on failure, so there is nothing to close
     if !duplicated {
-        let mut errors: Vec<String> = Vec::new();
-        let last_error = Error::last_os_error();
-        errors.push(format!("Unable to duplicate token: {last_error}"));
-        if let Err(err) = close_token(h_existing_token) {
-            errors.push(err);
-        }
-        if let Err(err) = close_token(ph_new_token) {
-            errors.push(err);
-        }
-        let errors_combined = errors.join("\n");
-        return Err(errors_combined);
+        return Err(Error::last_os_error(
+            Stage::Duplication,
+            "Unable to duplicate token",
+        ));
     }
 
-    // On success close the first token and return the new duplicated one
-    close_token(h_existing_token)?;
     Ok(ph_new_token)
 }
 
+/// Duplicates a token
+///
+/// Closes the initial token handle in any case
+pub fn duplicate_token_ex(
+    h_existing_token: HANDLE,
+    dw_desired_access: TOKEN_ACCESS_MASK,
+    lp_token_attributes: Option<*const SECURITY_ATTRIBUTES>,
+    impersonation_level: SECURITY_IMPERSONATION_LEVEL,
+    token_type: TOKEN_TYPE,
+) -> Result<HANDLE, Error> {
+    let duplicated = duplicate_token_ex_keep_existing(
+        h_existing_token,
+        dw_desired_access,
+        lp_token_attributes,
+        impersonation_level,
+        token_type,
+    );
+
+    // Close the first token in any case
+    match (duplicated, close_token(h_existing_token)) {
+        (Ok(ph_new_token), Ok(())) => Ok(ph_new_token),
+        (Ok(ph_new_token), Err(err)) => {
+            let mut errors = vec![err];
+            if let Err(err) = close_token(ph_new_token) {
+                errors.push(err);
+            }
+            Err(Error::combine(errors))
+        }
+        (Err(err), Ok(())) => Err(err),
+        (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
+    }
+}
+
+/// Safe binding to a windows api version of the function
+pub fn get_local_time() -> SYSTEMTIME {
+    unsafe { GetLocalTime() }
+}
+
 /// Safe binding to a windows api version of the function
 pub fn wts_get_active_console_session_id() -> u32 {
     unsafe { WTSGetActiveConsoleSessionId() }
 }
 
+/// Safe binding to a windows api version of the function
+pub fn wts_logoff_session(session_id: u32, wait: bool) -> Result<(), Error> {
+    let success =
+        unsafe { WTSLogoffSession(WTS_CURRENT_SERVER_HANDLE, session_id, wait).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::SessionControl,
+            "Unable to log off the session",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Safe binding to a windows api version of the function
+pub fn wts_disconnect_session(session_id: u32, wait: bool) -> Result<(), Error> {
+    let success =
+        unsafe { WTSDisconnectSession(WTS_CURRENT_SERVER_HANDLE, session_id, wait).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::SessionControl,
+            "Unable to disconnect the session",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Waits for the user to answer or the timeout in seconds to elapse, zero waiting indefinitely
+pub fn wts_send_message_w(
+    session_id: u32,
+    title: &U16CStr,
+    message: &U16CStr,
+    style: MESSAGEBOX_STYLE,
+    timeout_s: u32,
+) -> Result<MESSAGEBOX_RESULT, Error> {
+    let mut response = MESSAGEBOX_RESULT::default();
+    // The lengths are in bytes, without the terminating null
+    let unit = std::mem::size_of::<u16>() as u32;
+
+    let success = unsafe {
+        WTSSendMessageW(
+            WTS_CURRENT_SERVER_HANDLE,
+            session_id,
+            PCWSTR::from_raw(title.as_ptr()),
+            title.len() as u32 * unit,
+            PCWSTR::from_raw(message.as_ptr()),
+            message.len() as u32 * unit,
+            style,
+            timeout_s,
+            &mut response,
+            true,
+        )
+        .succeeded()
+    };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::SessionControl,
+            "Unable to show a message in the session",
+        ));
+    }
+
+    Ok(response)
+}
+
+/// Lists the sessions on the local machine with their connection states
+pub fn wts_enumerate_sessions_w() -> Result<Vec<(u32, WTS_CONNECTSTATE_CLASS)>, Error> {
+    let mut session_info: *mut WTS_SESSION_INFOW = std::ptr::null_mut();
+    let mut count = 0u32;
+
+    let success = unsafe {
+        WTSEnumerateSessionsW(
+            WTS_CURRENT_SERVER_HANDLE,
+            0,
+            1,
+            &mut session_info,
+            &mut count,
+        )
+        .succeeded()
+    };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::SessionQuery,
+            "Unable to enumerate sessions",
+        ));
+    }
+
+    // Copy the sessions out before the system allocated buffer is freed
+    let sessions = unsafe { std::slice::from_raw_parts(session_info, count as usize) }
+        .iter()
+        .map(|session| (session.SessionId, session.State))
+        .collect();
+
+    unsafe { WTSFreeMemory(session_info as *mut c_void) };
+
+    Ok(sessions)
+}
+
+#[cfg(feature = "diagnostics")]
+/// Gets a piece of textual information about a session, e.g. the name of the logged on user
+pub fn wts_query_session_information_w(
+    session_id: u32,
+    info_class: WTS_INFO_CLASS,
+) -> Result<String, Error> {
+    let mut buffer = PWSTR::null();
+    let mut bytes_returned = 0u32;
+
+    let success = unsafe {
+        WTSQuerySessionInformationW(
+            WTS_CURRENT_SERVER_HANDLE,
+            session_id,
+            info_class,
+            &mut buffer,
+            &mut bytes_returned,
+        )
+        .succeeded()
+    };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::SessionQuery,
+            "Unable to query session information",
+        ));
+    }
+
+    // Copy the value out before the system allocated buffer is freed
+    let value = unsafe { buffer.to_string() };
+
+    unsafe { WTSFreeMemory(buffer.as_ptr() as *mut c_void) };
+
+    value.map_err(|err| Error::other(Stage::Conversion, err.to_string()))
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Gets the extended information about a session, e.g. its lock state and last input time
+pub fn wts_query_session_info_ex(session_id: u32) -> Result<WTSINFOEX_LEVEL1_W, Error> {
+    let mut buffer = PWSTR::null();
+    let mut bytes_returned = 0u32;
+
+    let success = unsafe {
+        WTSQuerySessionInformationW(
+            WTS_CURRENT_SERVER_HANDLE,
+            session_id,
+            WTSSessionInfoEx,
+            &mut buffer,
+            &mut bytes_returned,
+        )
+        .succeeded()
+    };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::SessionQuery,
+            "Unable to query session information",
+        ));
+    }
+
+    // Copy the information out before the system allocated buffer is freed
+    let info = unsafe {
+        let info = &*(buffer.as_ptr() as *const WTSINFOEXW);
+        info.Data.WTSInfoExLevel1
+    };
+
+    unsafe { WTSFreeMemory(buffer.as_ptr() as *mut c_void) };
+
+    Ok(info)
+}
+
+/// Gets the id of the session a process runs in
+pub fn process_id_to_session_id(pid: u32) -> Result<u32, Error> {
+    let mut session_id = 0u32;
+
+    let success = unsafe { ProcessIdToSessionId(pid, &mut session_id).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::ProcessLookup,
+            "Unable to obtain the session of a process",
+        ));
+    }
+
+    Ok(session_id)
+}
+
 /// Get the current user token
-pub fn wts_query_user_token(session_id: u32) -> Result<HANDLE, String> {
+pub fn wts_query_user_token(session_id: u32) -> Result<HANDLE, Error> {
     // Create empty token handle
     let mut token_handle = HANDLE::default();
 
     // Obtain the user token
-    let success = unsafe { WTSQueryUserToken(session_id, &mut token_handle).as_bool() };
+    let success = unsafe { WTSQueryUserToken(session_id, &mut token_handle).succeeded() };
 
-    // Close it on failure
+    // No token was opened on failure, so there is nothing to close
     if !success {
-        let mut errors: Vec<String> = Vec::new();
-        let last_error = Error::last_os_error();
-        errors.push(format!(
-            "Unable to obtain current user handle: {last_error}"
-        ));
-        if let Err(err) = close_token(token_handle) {
-            errors.push(err);
+        let error = Error::last_os_error(
+            Stage::UserTokenQuery,
+            "Unable to obtain current user handle",
+        );
+        // The session exists, but nobody is logged on to it
+        if error.code() == Some(ERROR_NO_TOKEN.0 as i32) {
+            return Err(Error::no_interactive_user(Some(session_id)));
         }
-        let errors_combined = errors.join("\n");
-        return Err(errors_combined);
+        return Err(error);
     }
 
     Ok(token_handle)
@@ -153,7 +482,7 @@ pub fn get_token_information(
     token_information: Option<*mut c_void>,
     token_information_length: u32,
     return_length: &mut u32,
-) -> Result<(), String> {
+) -> Result<(), Error> {
     let success = unsafe {
         GetTokenInformation(
             token,
@@ -162,29 +491,22 @@ pub fn get_token_information(
             token_information_length,
             return_length,
         )
-        .as_bool()
+        .succeeded()
     };
 
-    let mut errors: Vec<String> = Vec::new();
-
     if !success {
-        let last_error = Error::last_os_error();
-        errors.push(format!("Unable to get token information: {last_error}"));
-    }
-
-    if let Err(err) = close_token(token) {
-        errors.push(err);
-    }
-
-    if !errors.is_empty() {
-        let errors_combined = errors.join("\n");
-        return Err(errors_combined);
+        return Err(Error::last_os_error(
+            Stage::TokenQuery,
+            "Unable to get token information",
+        ));
     }
 
     Ok(())
 }
 
 /// Safe binding to a windows api version of the function
+///
+/// Returns the process information, whose process and primary thread handles the caller has to close
 #[allow(clippy::too_many_arguments)]
 pub fn create_process_as_user_w(
     token: HANDLE,
@@ -196,9 +518,9 @@ pub fn create_process_as_user_w(
     creation_flags: PROCESS_CREATION_FLAGS,
     environment: Option<*const c_void>,
     current_directory: PCWSTR,
-    startup_info: STARTUPINFOW,
+    startup_info: *const STARTUPINFOW,
     mut process_information: PROCESS_INFORMATION,
-) -> Result<(), String> {
+) -> Result<PROCESS_INFORMATION, Error> {
     let created = unsafe {
         CreateProcessAsUserW(
             token,
@@ -210,36 +532,1889 @@ pub fn create_process_as_user_w(
             creation_flags,
             environment,
             current_directory,
-            &startup_info,
+            startup_info,
             &mut process_information,
         )
-        .as_bool()
+        .succeeded()
     };
 
-    let mut errors: Vec<String> = Vec::new();
-
     if !created {
-        let last_error = Error::last_os_error();
-        errors.push(format!("Unable to create process: {last_error}"));
-        return Err(format!("Unable to create process: {last_error}"));
+        return Err(Error::last_os_error(
+            Stage::CreateProcess,
+            "Unable to create process",
+        ));
     }
 
-    let mut errors: Vec<String> = Vec::new();
+    Ok(process_information)
+}
 
-    if let Err(err) = close_token(process_information.hThread) {
-        errors.push(err);
-    };
-    if let Err(err) = close_token(process_information.hProcess) {
-        errors.push(err);
+/// Safe binding to a windows api version of the function
+///
+/// Returns the number of characters required to hold the expanded string, including the terminating null
+pub fn expand_environment_strings_w(src: PCWSTR, dst: Option<&mut [u16]>) -> Result<u32, Error> {
+    let required = unsafe { ExpandEnvironmentStringsW(src, dst) };
+
+    if required == 0 {
+        return Err(Error::last_os_error(
+            Stage::EnvironmentExpansion,
+            "Unable to expand environment strings",
+        ));
+    }
+
+    Ok(required)
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Returns the `name=value` entries of the block, including the hidden ones starting with `=`
+pub fn create_environment_block(token: HANDLE, inherit: bool) -> Result<Vec<String>, Error> {
+    let mut block: *mut c_void = std::ptr::null_mut();
+
+    let success = unsafe { CreateEnvironmentBlock(&mut block, token, inherit).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::EnvironmentBuild,
+            "Unable to create the environment block of the user",
+        ));
+    }
+
+    // The block is a sequence of null terminated entries, ending with an empty one
+    let mut entries = Vec::new();
+    let mut entry = block as *const u16;
+    unsafe {
+        loop {
+            let length = (0..).take_while(|&i| *entry.add(i) != 0).count();
+            if length == 0 {
+                break;
+            }
+            let units = std::slice::from_raw_parts(entry, length);
+            entries.push(String::from_utf16_lossy(units));
+            entry = entry.add(length + 1);
+        }
+    }
+
+    let destroyed = unsafe { DestroyEnvironmentBlock(block).succeeded() };
+
+    if !destroyed {
+        return Err(Error::last_os_error(
+            Stage::Cleanup,
+            "Unable to destroy the environment block of the user",
+        ));
+    }
+
+    Ok(entries)
+}
+
+#[cfg(feature = "window")]
+/// Gets the handles of all top-level windows on the desktop of the calling thread
+pub fn enum_windows() -> Result<Vec<HWND>, Error> {
+    // Collect every window handle passed to the callback
+    unsafe extern "system" fn collect(hwnd: HWND, lparam: LPARAM) -> BOOL {
+        let windows = &mut *(lparam.0 as *mut Vec<HWND>);
+        windows.push(hwnd);
+        BOOL::from(true)
+    }
+
+    let mut windows: Vec<HWND> = Vec::new();
+
+    let success = unsafe {
+        EnumWindows(
+            Some(collect),
+            LPARAM((&mut windows) as *mut Vec<HWND> as isize),
+        )
+        .succeeded()
     };
-    if let Err(err) = close_token(token) {
-        errors.push(err);
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::WindowLookup,
+            "Unable to enumerate windows",
+        ));
     }
 
-    if !errors.is_empty() {
-        let errors_combined = errors.join("\n");
-        return Err(errors_combined);
+    Ok(windows)
+}
+
+#[cfg(feature = "window")]
+/// Gets the pid of the process that created a window
+pub fn get_window_thread_process_id(hwnd: HWND) -> u32 {
+    let mut pid = 0u32;
+    unsafe { GetWindowThreadProcessId(hwnd, Some(&mut pid)) };
+    pid
+}
+
+#[cfg(feature = "window")]
+/// Gets the title of a window, empty if it has none
+pub fn get_window_text_w(hwnd: HWND) -> String {
+    let mut buffer = [0u16; 512];
+    let length = unsafe { GetWindowTextW(hwnd, &mut buffer) };
+    String::from_utf16_lossy(&buffer[..length.max(0) as usize])
+}
+
+#[cfg(feature = "window")]
+/// Gets the class name of a window, empty if it could not be read
+pub fn get_class_name_w(hwnd: HWND) -> String {
+    let mut buffer = [0u16; 256];
+    let length = unsafe { GetClassNameW(hwnd, &mut buffer) };
+    String::from_utf16_lossy(&buffer[..length.max(0) as usize])
+}
+
+#[cfg(feature = "window")]
+/// Safe binding to a windows api version of the function
+pub fn is_window_visible(hwnd: HWND) -> bool {
+    unsafe { IsWindowVisible(hwnd).succeeded() }
+}
+
+/// Asks a window to close, without waiting for it to do so
+#[cfg(feature = "window")]
+pub fn post_close_message(hwnd: HWND) -> Result<(), Error> {
+    let success = unsafe { PostMessageW(hwnd, WM_CLOSE, WPARAM(0), LPARAM(0)).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::Termination,
+            "Unable to ask a window to close",
+        ));
     }
 
     Ok(())
 }
+
+/// Converts a SID into its string form, e.g. `S-1-5-18`
+pub fn convert_sid_to_string_sid_w(sid: PSID) -> Result<String, Error> {
+    let mut string_sid = PWSTR::null();
+
+    let success = unsafe { ConvertSidToStringSidW(sid, &mut string_sid).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::TokenQuery,
+            "Unable to convert SID to string",
+        ));
+    }
+
+    // Copy the string out before freeing the buffer allocated by the system
+    let converted = unsafe { string_sid.to_string() };
+    let freed = unsafe { local_free(string_sid.as_ptr() as *mut c_void) };
+
+    let converted = converted.map_err(|err| {
+        Error::other(
+            Stage::Conversion,
+            format!("Cannot convert SID string to utf-8: {err}"),
+        )
+    })?;
+    freed.map_err(|err| Error::windows(Stage::Cleanup, "Unable to free SID string", err))?;
+
+    Ok(converted)
+}
+
+/// Converts the string form of a SID, e.g. `S-1-5-18`, into a buffer holding the SID
+pub fn convert_string_sid_to_sid_w(string_sid: PCWSTR) -> Result<Vec<u8>, Error> {
+    let mut sid = PSID::default();
+
+    let success = unsafe { ConvertStringSidToSidW(string_sid, &mut sid).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::Conversion,
+            "Unable to convert string to SID",
+        ));
+    }
+
+    // Copy the SID out before freeing the buffer allocated by the system
+    let buffer = unsafe {
+        std::slice::from_raw_parts(sid.0 as *const u8, GetLengthSid(sid) as usize).to_vec()
+    };
+    let freed = unsafe { local_free(sid.0) };
+    freed.map_err(|err| Error::windows(Stage::Cleanup, "Unable to free SID", err))?;
+
+    Ok(buffer)
+}
+
+/// Gets the last sub-authority of a SID, which is the RID of well-known and integrity level SIDs
+///
+/// The SID must be valid
+pub fn get_last_sid_sub_authority(sid: PSID) -> u32 {
+    unsafe {
+        let count = *GetSidSubAuthorityCount(sid);
+        *GetSidSubAuthority(sid, (count as u32).saturating_sub(1))
+    }
+}
+
+/// Lists the running processes as pid, parent pid and executable name
+#[cfg(not(feature = "sysinfo"))]
+pub fn create_toolhelp32_snapshot() -> Result<Vec<(u32, u32, String)>, Error> {
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) }
+        .map_err(|err| Error::windows(Stage::ProcessLookup, "Unable to snapshot processes", err))?;
+
+    let mut entry = PROCESSENTRY32W {
+        dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32,
+        ..Default::default()
+    };
+    let mut processes = Vec::new();
+
+    let mut found = unsafe { Process32FirstW(snapshot, &mut entry).succeeded() };
+    while found {
+        let name_length = entry
+            .szExeFile
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(entry.szExeFile.len());
+        let name = String::from_utf16_lossy(&entry.szExeFile[..name_length]);
+        processes.push((entry.th32ProcessID, entry.th32ParentProcessID, name));
+        found = unsafe { Process32NextW(snapshot, &mut entry).succeeded() };
+    }
+
+    close_token(snapshot)?;
+
+    Ok(processes)
+}
+
+/// Gets the creation time of a process as a raw `FILETIME` value
+#[cfg(not(feature = "sysinfo"))]
+pub fn get_process_times(process_handle: HANDLE) -> Result<u64, Error> {
+    let mut creation_time = FILETIME::default();
+    let mut exit_time = FILETIME::default();
+    let mut kernel_time = FILETIME::default();
+    let mut user_time = FILETIME::default();
+
+    let success = unsafe {
+        GetProcessTimes(
+            process_handle,
+            &mut creation_time,
+            &mut exit_time,
+            &mut kernel_time,
+            &mut user_time,
+        )
+        .succeeded()
+    };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::ProcessLookup,
+            "Unable to obtain the times of a process",
+        ));
+    }
+
+    Ok(((creation_time.dwHighDateTime as u64) << 32) | creation_time.dwLowDateTime as u64)
+}
+
+/// Gets the full path to the executable of a process
+#[cfg(not(feature = "sysinfo"))]
+pub fn query_full_process_image_name_w(process_handle: HANDLE) -> Result<String, Error> {
+    let mut path = vec![0u16; 32_768];
+    let mut path_length = path.len() as u32;
+
+    let success = unsafe {
+        QueryFullProcessImageNameW(
+            process_handle,
+            PROCESS_NAME_WIN32,
+            PWSTR::from_raw(path.as_mut_ptr()),
+            &mut path_length,
+        )
+        .succeeded()
+    };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::ProcessLookup,
+            "Unable to obtain the executable of a process",
+        ));
+    }
+
+    Ok(String::from_utf16_lossy(&path[..path_length as usize]))
+}
+
+/// Waits for an object to be signaled, e.g. a process to exit
+///
+/// Returns whether the object was signaled before the timeout, in milliseconds, elapsed
+pub fn wait_for_single_object(handle: HANDLE, milliseconds: u32) -> Result<bool, Error> {
+    let event = unsafe { WaitForSingleObject(handle, milliseconds) };
+
+    match event {
+        WAIT_OBJECT_0 => Ok(true),
+        WAIT_TIMEOUT => Ok(false),
+        _ => Err(Error::last_os_error(
+            Stage::Waiting,
+            "Unable to wait for an object",
+        )),
+    }
+}
+
+/// Safe binding to a windows api version of the function
+pub fn get_exit_code_process(process_handle: HANDLE) -> Result<u32, Error> {
+    let mut exit_code = 0;
+    let success = unsafe { GetExitCodeProcess(process_handle, &mut exit_code).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::Waiting,
+            "Unable to get the exit code of the process",
+        ));
+    }
+
+    Ok(exit_code)
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Restricts the threads of a process to the processors whose bits are set in the mask
+pub fn set_process_affinity_mask(process_handle: HANDLE, mask: usize) -> Result<(), Error> {
+    let success = unsafe { SetProcessAffinityMask(process_handle, mask).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::ProcessSetup,
+            "Unable to set the processor affinity of the process",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Safe binding to a windows api version of the function
+pub fn set_process_information(
+    process_handle: HANDLE,
+    process_information_class: PROCESS_INFORMATION_CLASS,
+    process_information: *const c_void,
+    process_information_size: u32,
+) -> Result<(), Error> {
+    let success = unsafe {
+        SetProcessInformation(
+            process_handle,
+            process_information_class,
+            process_information,
+            process_information_size,
+        )
+        .succeeded()
+    };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::ProcessSetup,
+            "Unable to set information of the process",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Safe binding to a windows api version of the function
+pub fn terminate_process(process_handle: HANDLE, exit_code: u32) -> Result<(), Error> {
+    let success = unsafe { TerminateProcess(process_handle, exit_code).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::Termination,
+            "Unable to terminate process",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Terminates every process in a job
+pub fn terminate_job_object(job: HANDLE, exit_code: u32) -> Result<(), Error> {
+    let success = unsafe { TerminateJobObject(job, exit_code).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::Termination,
+            "Unable to terminate the processes of the job object",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Creates an unnamed job object, closing it does not terminate the processes in it
+pub fn create_job_object_w() -> Result<HANDLE, Error> {
+    unsafe { CreateJobObjectW(None, PCWSTR::null()) }
+        .map_err(|err| Error::windows(Stage::ProcessSetup, "Unable to create job object", err))
+}
+
+/// Safe binding to a windows api version of the function
+pub fn assign_process_to_job_object(job: HANDLE, process_handle: HANDLE) -> Result<(), Error> {
+    let success = unsafe { AssignProcessToJobObject(job, process_handle).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::ProcessSetup,
+            "Unable to assign process to job object",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Sets the limits of a job, replacing those it had
+pub fn set_job_extended_limits(
+    job: HANDLE,
+    limits: &JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+) -> Result<(), Error> {
+    let success = unsafe {
+        SetInformationJobObject(
+            job,
+            JobObjectExtendedLimitInformation,
+            limits as *const JOBOBJECT_EXTENDED_LIMIT_INFORMATION as *const c_void,
+            std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+        )
+        .succeeded()
+    };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::ProcessSetup,
+            "Unable to set the limits of the job object",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Returns how many processes in the job are still running
+pub fn query_job_active_processes(job: HANDLE) -> Result<u32, Error> {
+    let mut accounting = JOBOBJECT_BASIC_ACCOUNTING_INFORMATION::default();
+
+    let success = unsafe {
+        QueryInformationJobObject(
+            job,
+            JobObjectBasicAccountingInformation,
+            &mut accounting as *mut JOBOBJECT_BASIC_ACCOUNTING_INFORMATION as *mut c_void,
+            std::mem::size_of::<JOBOBJECT_BASIC_ACCOUNTING_INFORMATION>() as u32,
+            None,
+        )
+        .succeeded()
+    };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::Waiting,
+            "Unable to query the processes of the job object",
+        ));
+    }
+
+    Ok(accounting.ActiveProcesses)
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Returns the suspend count of the thread before the call
+pub fn suspend_thread(thread_handle: HANDLE) -> Result<u32, Error> {
+    let previous = unsafe { SuspendThread(thread_handle) };
+
+    if previous == u32::MAX {
+        return Err(Error::last_os_error(
+            Stage::ThreadControl,
+            "Unable to suspend thread",
+        ));
+    }
+
+    Ok(previous)
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Returns the suspend count of the thread before the call
+pub fn resume_thread(thread_handle: HANDLE) -> Result<u32, Error> {
+    let previous = unsafe { ResumeThread(thread_handle) };
+
+    if previous == u32::MAX {
+        return Err(Error::last_os_error(
+            Stage::ThreadControl,
+            "Unable to resume thread",
+        ));
+    }
+
+    Ok(previous)
+}
+
+/// Looks up the account name and domain of a SID on the local machine
+///
+/// Returns the account name and the domain name
+pub fn lookup_account_sid_w(sid: PSID) -> Result<(String, String), Error> {
+    let mut name = vec![0u16; 257];
+    let mut domain = vec![0u16; 257];
+
+    loop {
+        let mut name_length = name.len() as u32;
+        let mut domain_length = domain.len() as u32;
+        let mut sid_name_use = SID_NAME_USE::default();
+
+        let success = unsafe {
+            LookupAccountSidW(
+                PCWSTR::null(),
+                sid,
+                PWSTR::from_raw(name.as_mut_ptr()),
+                &mut name_length,
+                PWSTR::from_raw(domain.as_mut_ptr()),
+                &mut domain_length,
+                &mut sid_name_use,
+            )
+            .succeeded()
+        };
+
+        if success {
+            let name = String::from_utf16_lossy(&name[..name_length as usize]);
+            let domain = String::from_utf16_lossy(&domain[..domain_length as usize]);
+            return Ok((name, domain));
+        }
+
+        let error = Error::last_os_error(Stage::AccountLookup, "Unable to look up account");
+
+        // Retry with the buffer sizes the system asked for
+        if error.code() != Some(ERROR_INSUFFICIENT_BUFFER.0 as i32) {
+            return Err(error);
+        }
+        name.resize((name_length as usize).max(name.len()), 0);
+        domain.resize((domain_length as usize).max(domain.len()), 0);
+    }
+}
+
+/// Looks up the SID of an account on the local machine
+///
+/// Returns a buffer holding the SID and the domain the account was found in
+pub fn lookup_account_name_w(account: PCWSTR) -> Result<(Vec<u8>, String), Error> {
+    let mut sid = vec![0u8; 68];
+    let mut domain = vec![0u16; 257];
+
+    loop {
+        let mut sid_length = sid.len() as u32;
+        let mut domain_length = domain.len() as u32;
+        let mut sid_name_use = SID_NAME_USE::default();
+
+        let success = unsafe {
+            LookupAccountNameW(
+                PCWSTR::null(),
+                account,
+                PSID(sid.as_mut_ptr() as *mut c_void),
+                &mut sid_length,
+                PWSTR::from_raw(domain.as_mut_ptr()),
+                &mut domain_length,
+                &mut sid_name_use,
+            )
+            .succeeded()
+        };
+
+        if success {
+            sid.truncate(sid_length as usize);
+            let domain = String::from_utf16_lossy(&domain[..domain_length as usize]);
+            return Ok((sid, domain));
+        }
+
+        let error = Error::last_os_error(Stage::AccountLookup, "Unable to look up account");
+
+        // Retry with the buffer sizes the system asked for
+        if error.code() != Some(ERROR_INSUFFICIENT_BUFFER.0 as i32) {
+            return Err(error);
+        }
+        sid.resize((sid_length as usize).max(sid.len()), 0);
+        domain.resize((domain_length as usize).max(domain.len()), 0);
+    }
+}
+
+/// Logs an account on and returns its token
+pub fn logon_user_w(
+    username: PCWSTR,
+    domain: PCWSTR,
+    password: PCWSTR,
+    logon_type: LOGON32_LOGON,
+) -> Result<HANDLE, Error> {
+    // Create empty token handle
+    let mut token_handle = HANDLE::default();
+
+    // Only the negotiate provider supports logons for network credentials alone
+    let provider = if logon_type == LOGON32_LOGON_NEW_CREDENTIALS {
+        LOGON32_PROVIDER_WINNT50
+    } else {
+        LOGON32_PROVIDER_DEFAULT
+    };
+
+    let success = unsafe {
+        LogonUserW(
+            username,
+            domain,
+            password,
+            logon_type,
+            provider,
+            &mut token_handle,
+        )
+        .succeeded()
+    };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::Logon,
+            "Unable to log the account on",
+        ));
+    }
+
+    Ok(token_handle)
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Returns the process information, whose process and primary thread handles the caller has to close
+#[allow(clippy::too_many_arguments)]
+pub fn create_process_with_logon_w(
+    username: PCWSTR,
+    domain: PCWSTR,
+    password: PCWSTR,
+    logon_flags: CREATE_PROCESS_LOGON_FLAGS,
+    application_name: PCWSTR,
+    command_line: PWSTR,
+    creation_flags: PROCESS_CREATION_FLAGS,
+    environment: Option<*const c_void>,
+    current_directory: PCWSTR,
+    startup_info: STARTUPINFOW,
+    mut process_information: PROCESS_INFORMATION,
+) -> Result<PROCESS_INFORMATION, Error> {
+    let created = unsafe {
+        CreateProcessWithLogonW(
+            username,
+            domain,
+            password,
+            logon_flags,
+            application_name,
+            command_line,
+            creation_flags,
+            environment,
+            current_directory,
+            &startup_info,
+            &mut process_information,
+        )
+        .succeeded()
+    };
+
+    if !created {
+        return Err(Error::last_os_error(
+            Stage::CreateProcess,
+            "Unable to create process with logon",
+        ));
+    }
+
+    Ok(process_information)
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Returns the process information, whose process and primary thread handles the caller has to close
+#[allow(clippy::too_many_arguments)]
+pub fn create_process_with_token_w(
+    token: HANDLE,
+    logon_flags: CREATE_PROCESS_LOGON_FLAGS,
+    application_name: PCWSTR,
+    command_line: PWSTR,
+    creation_flags: PROCESS_CREATION_FLAGS,
+    environment: Option<*const c_void>,
+    current_directory: PCWSTR,
+    startup_info: STARTUPINFOW,
+    mut process_information: PROCESS_INFORMATION,
+) -> Result<PROCESS_INFORMATION, Error> {
+    let created = unsafe {
+        CreateProcessWithTokenW(
+            token,
+            logon_flags,
+            application_name,
+            command_line,
+            creation_flags,
+            environment,
+            current_directory,
+            &startup_info,
+            &mut process_information,
+        )
+        .succeeded()
+    };
+
+    if !created {
+        return Err(Error::last_os_error(
+            Stage::CreateProcess,
+            "Unable to create process with token",
+        ));
+    }
+
+    Ok(process_information)
+}
+
+/// Gets the name of a privilege by its locally unique id, e.g. `SeTcbPrivilege`
+pub fn lookup_privilege_name_w(luid: &LUID) -> Result<String, Error> {
+    let mut name = vec![0u16; 64];
+
+    loop {
+        let mut name_length = name.len() as u32;
+
+        let success = unsafe {
+            LookupPrivilegeNameW(
+                PCWSTR::null(),
+                luid,
+                PWSTR::from_raw(name.as_mut_ptr()),
+                &mut name_length,
+            )
+            .succeeded()
+        };
+
+        if success {
+            return Ok(String::from_utf16_lossy(&name[..name_length as usize]));
+        }
+
+        let error = Error::last_os_error(Stage::TokenQuery, "Unable to look up privilege name");
+
+        // Retry with the buffer size the system asked for
+        if error.code() != Some(ERROR_INSUFFICIENT_BUFFER.0 as i32) {
+            return Err(error);
+        }
+        name.resize((name_length as usize + 1).max(name.len() * 2), 0);
+    }
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Creates the profile of an account that never logged on, returning `false` if it exists already
+pub fn create_profile(sid: PCWSTR, username: PCWSTR) -> Result<bool, Error> {
+    let mut profile_path = vec![0u16; MAX_PATH as usize];
+
+    match unsafe { CreateProfile(sid, username, &mut profile_path) } {
+        Ok(()) => Ok(true),
+        Err(err) if err.code() == ERROR_ALREADY_EXISTS.to_hresult() => Ok(false),
+        Err(err) => Err(Error::windows(
+            Stage::ProfileSetup,
+            "Unable to create the profile of the account",
+            err,
+        )),
+    }
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Gets the directory of the profile of the user of a token
+pub fn get_user_profile_directory_w(token: HANDLE) -> Result<String, Error> {
+    let mut directory = vec![0u16; MAX_PATH as usize];
+
+    loop {
+        let mut directory_length = directory.len() as u32;
+
+        let success = unsafe {
+            GetUserProfileDirectoryW(
+                token,
+                PWSTR::from_raw(directory.as_mut_ptr()),
+                &mut directory_length,
+            )
+            .succeeded()
+        };
+
+        if success {
+            // The length includes the terminating null
+            let length = (directory_length as usize).saturating_sub(1);
+            return Ok(String::from_utf16_lossy(&directory[..length]));
+        }
+
+        let error = Error::last_os_error(
+            Stage::ProfileSetup,
+            "Unable to get the profile directory of the user",
+        );
+
+        // Retry with the buffer size the system asked for
+        if error.code() != Some(ERROR_INSUFFICIENT_BUFFER.0 as i32) {
+            return Err(error);
+        }
+        directory.resize((directory_length as usize).max(directory.len() * 2), 0);
+    }
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Deletes the profile of an account on this computer, which fails while it is loaded
+pub fn delete_profile_w(sid: PCWSTR) -> Result<(), Error> {
+    let success = unsafe { DeleteProfileW(sid, PCWSTR::null(), PCWSTR::null()).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::ProfileSetup,
+            "Unable to delete the profile of the account",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Loading a profile never shows a message box when it fails
+const PI_NOUI: u32 = 1;
+
+/// Safe binding to a windows api version of the function
+///
+/// Loads the profile of the user of a token, registry hive included, and returns a handle to its `HKEY_CURRENT_USER`
+pub fn load_user_profile_w(token: HANDLE, username: PWSTR) -> Result<HANDLE, Error> {
+    let mut profile_info = PROFILEINFOW {
+        dwSize: std::mem::size_of::<PROFILEINFOW>() as u32,
+        dwFlags: PI_NOUI,
+        lpUserName: username,
+        ..Default::default()
+    };
+
+    let success = unsafe { LoadUserProfileW(token, &mut profile_info).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::ProfileSetup,
+            "Unable to load the profile of the user",
+        ));
+    }
+
+    Ok(profile_info.hProfile)
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Unloads a profile loaded with [`load_user_profile_w`], which stays loaded while others use it
+pub fn unload_user_profile(token: HANDLE, profile: HANDLE) -> Result<(), Error> {
+    let success = unsafe { UnloadUserProfile(token, profile).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::ProfileSetup,
+            "Unable to unload the profile of the user",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Gets a pseudo handle to the current process, which does not need to be closed
+pub fn get_current_process() -> HANDLE {
+    unsafe { GetCurrentProcess() }
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Returns whether the process is a 32-bit one running on 64-bit Windows
+pub fn is_wow64_process(process_handle: HANDLE) -> Result<bool, Error> {
+    let mut wow64 = BOOL::default();
+    let success = unsafe { IsWow64Process(process_handle, &mut wow64).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::PathResolution,
+            "Unable to tell whether the process runs under WOW64",
+        ));
+    }
+
+    Ok(wow64.succeeded())
+}
+
+#[cfg(feature = "diagnostics")]
+/// Reads a DWORD value from the registry
+pub fn reg_get_value_w(key: HKEY, subkey: PCWSTR, value: PCWSTR) -> Result<u32, Error> {
+    let mut data = 0u32;
+    let mut data_length = std::mem::size_of::<u32>() as u32;
+
+    let status = unsafe {
+        RegGetValueW(
+            key,
+            subkey,
+            value,
+            RRF_RT_REG_DWORD,
+            None,
+            Some(&mut data as *mut u32 as *mut c_void),
+            Some(&mut data_length),
+        )
+    };
+
+    if let Err(err) = status.into_result() {
+        return Err(Error::windows(
+            Stage::PolicyQuery,
+            "Unable to read registry value",
+            err,
+        ));
+    }
+
+    Ok(data)
+}
+
+/// Creates a named mutex, or opens it if it already exists
+///
+/// A new mutex is secured with the security descriptor described by the SDDL string.
+/// Returns the mutex and whether it already existed.
+pub fn create_mutex_w(name: PCWSTR, sddl: PCWSTR) -> Result<(HANDLE, bool), Error> {
+    let mut security_descriptor = PSECURITY_DESCRIPTOR::default();
+
+    let success = unsafe {
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            sddl,
+            SDDL_REVISION_1,
+            &mut security_descriptor,
+            None,
+        )
+        .succeeded()
+    };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::InstanceCheck,
+            "Unable to build the security descriptor of a mutex",
+        ));
+    }
+
+    let security_attributes = SECURITY_ATTRIBUTES {
+        nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+        lpSecurityDescriptor: security_descriptor.0,
+        bInheritHandle: false.into(),
+    };
+
+    let mutex = unsafe { CreateMutexW(Some(&security_attributes), false, name) };
+    // Read right away, before anything else can overwrite the last error
+    let already_exists = last_error() == ERROR_ALREADY_EXISTS;
+    let freed = unsafe { local_free(security_descriptor.0) };
+
+    let mutex = mutex
+        .map_err(|err| Error::windows(Stage::InstanceCheck, "Unable to create a mutex", err))?;
+    if let Err(err) = freed {
+        let mut errors = vec![Error::windows(
+            Stage::Cleanup,
+            "Unable to free the security descriptor of a mutex",
+            err,
+        )];
+        if let Err(err) = close_token(mutex) {
+            errors.push(err);
+        }
+        return Err(Error::combine(errors));
+    }
+
+    Ok((mutex, already_exists))
+}
+
+/// Replaces the DACL of a kernel object, e.g. a process, with the one described by the SDDL string
+///
+/// The DACL is protected, so the object does not inherit entries from its parent.
+pub fn set_kernel_object_dacl(handle: HANDLE, sddl: PCWSTR) -> Result<(), Error> {
+    set_dacl_from_sddl(sddl, Stage::ProcessSetup, |dacl| unsafe {
+        SetSecurityInfo(
+            handle,
+            SE_KERNEL_OBJECT,
+            security_information(DACL_SECURITY_INFORMATION | PROTECTED_DACL_SECURITY_INFORMATION),
+            PSID::default(),
+            PSID::default(),
+            Some(dacl),
+            None,
+        )
+        .into_result()
+    })
+}
+
+/// Replaces the DACL of a file or directory with the one described by the SDDL string
+///
+/// The DACL is protected, so the file does not inherit entries from its parent.
+pub fn set_named_file_dacl(path: PCWSTR, sddl: PCWSTR) -> Result<(), Error> {
+    set_dacl_from_sddl(sddl, Stage::ProfileSetup, |dacl| unsafe {
+        SetNamedSecurityInfoW(
+            path,
+            SE_FILE_OBJECT,
+            DACL_SECURITY_INFORMATION | PROTECTED_DACL_SECURITY_INFORMATION,
+            PSID::default(),
+            PSID::default(),
+            Some(dacl),
+            None,
+        )
+        .into_result()
+    })
+}
+
+/// Reading and changing the DACL of a window station or desktop
+const WINDOW_OBJECT_DACL_ACCESS: u32 = 0x0002_0000 | 0x0004_0000; // READ_CONTROL | WRITE_DAC
+
+/// Safe binding to a windows api version of the function
+///
+/// Opens a window station of the session of the calling process, with access to its DACL
+pub fn open_window_station_w(name: PCWSTR) -> Result<HWINSTA, Error> {
+    unsafe { OpenWindowStationW(name, false, WINDOW_OBJECT_DACL_ACCESS) }
+        .map_err(|err| Error::windows(Stage::DesktopAccess, "Unable to open window station", err))
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Opens a desktop of the window station of the calling process, with access to its DACL
+pub fn open_desktop_w(name: PCWSTR) -> Result<HDESK, Error> {
+    unsafe {
+        OpenDesktopW(
+            name,
+            DESKTOP_CONTROL_FLAGS(0),
+            false,
+            WINDOW_OBJECT_DACL_ACCESS,
+        )
+    }
+    .map_err(|err| Error::windows(Stage::DesktopAccess, "Unable to open desktop", err))
+}
+
+/// Safe binding to a windows api version of the function
+pub fn get_process_window_station() -> Result<HWINSTA, Error> {
+    unsafe { GetProcessWindowStation() }.map_err(|err| {
+        Error::windows(
+            Stage::DesktopAccess,
+            "Unable to get the window station of the process",
+            err,
+        )
+    })
+}
+
+/// Safe binding to a windows api version of the function
+pub fn set_process_window_station(window_station: HWINSTA) -> Result<(), Error> {
+    let success = unsafe { SetProcessWindowStation(window_station).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::DesktopAccess,
+            "Unable to set the window station of the process",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Safe binding to a windows api version of the function
+pub fn close_window_station(window_station: HWINSTA) -> Result<(), Error> {
+    let success = unsafe { CloseWindowStation(window_station).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::Cleanup,
+            "Unable to close window station",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Safe binding to a windows api version of the function
+pub fn close_desktop(desktop: HDESK) -> Result<(), Error> {
+    let success = unsafe { CloseDesktop(desktop).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::Cleanup,
+            "Unable to close desktop",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Adds the access rights to those the DACL of a window station or desktop grants the SID
+///
+/// Rights the SID is granted already are kept, so granting the same rights again changes nothing.
+pub fn grant_window_object_access(handle: HANDLE, sid: PSID, access: u32) -> Result<(), Error> {
+    let mut dacl: *mut ACL = std::ptr::null_mut();
+    let mut security_descriptor = PSECURITY_DESCRIPTOR::default();
+
+    let status = unsafe {
+        GetSecurityInfo(
+            handle,
+            SE_WINDOW_OBJECT,
+            security_information(DACL_SECURITY_INFORMATION),
+            None,
+            None,
+            Some(&mut dacl),
+            None,
+            Some(&mut security_descriptor),
+        )
+    };
+
+    if let Err(err) = status.into_result() {
+        return Err(Error::windows(
+            Stage::DesktopAccess,
+            "Unable to get the DACL of a window object",
+            err,
+        ));
+    }
+
+    let entry = EXPLICIT_ACCESS_W {
+        grfAccessPermissions: access,
+        grfAccessMode: GRANT_ACCESS,
+        grfInheritance: NO_INHERITANCE,
+        Trustee: TRUSTEE_W {
+            TrusteeForm: TRUSTEE_IS_SID,
+            TrusteeType: TRUSTEE_IS_USER,
+            ptstrName: PWSTR::from_raw(sid.0 as *mut u16),
+            ..Default::default()
+        },
+    };
+    let mut new_dacl: *mut ACL = std::ptr::null_mut();
+
+    let status = unsafe { SetEntriesInAclW(Some(&[entry]), Some(dacl), &mut new_dacl) };
+
+    let set = if let Err(err) = status.into_result() {
+        Err(Error::windows(
+            Stage::DesktopAccess,
+            "Unable to add an entry to the DACL of a window object",
+            err,
+        ))
+    } else {
+        let status = unsafe {
+            SetSecurityInfo(
+                handle,
+                SE_WINDOW_OBJECT,
+                security_information(DACL_SECURITY_INFORMATION),
+                PSID::default(),
+                PSID::default(),
+                Some(new_dacl as *const ACL),
+                None,
+            )
+        };
+        let set = if let Err(err) = status.into_result() {
+            Err(Error::windows(
+                Stage::DesktopAccess,
+                "Unable to set the DACL of a window object",
+                err,
+            ))
+        } else {
+            Ok(())
+        };
+        let freed = unsafe { local_free(new_dacl as *mut c_void) }.map_err(|err| {
+            Error::windows(
+                Stage::Cleanup,
+                "Unable to free the DACL of a window object",
+                err,
+            )
+        });
+        match (set, freed) {
+            (Ok(()), Ok(_)) => Ok(()),
+            (Ok(()), Err(err)) => Err(err),
+            (Err(err), Ok(_)) => Err(err),
+            (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
+        }
+    };
+
+    // The DACL points into the security descriptor, so both are freed along with it
+    let freed = unsafe { local_free(security_descriptor.0) }.map_err(|err| {
+        Error::windows(
+            Stage::Cleanup,
+            "Unable to free the security descriptor of a window object",
+            err,
+        )
+    });
+
+    match (set, freed) {
+        (Ok(()), Ok(_)) => Ok(()),
+        (Ok(()), Err(err)) => Err(err),
+        (Err(err), Ok(_)) => Err(err),
+        (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
+    }
+}
+
+/// Builds the DACL described by the SDDL string and passes it to the function applying it to an object
+fn set_dacl_from_sddl(
+    sddl: PCWSTR,
+    stage: Stage,
+    apply: impl FnOnce(*const ACL) -> windows::core::Result<()>,
+) -> Result<(), Error> {
+    let mut security_descriptor = PSECURITY_DESCRIPTOR::default();
+
+    let success = unsafe {
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            sddl,
+            SDDL_REVISION_1,
+            &mut security_descriptor,
+            None,
+        )
+        .succeeded()
+    };
+
+    if !success {
+        return Err(Error::last_os_error(
+            stage,
+            "Unable to build the security descriptor of an object",
+        ));
+    }
+
+    // Older `windows` versions take the flags as `i32`, newer ones as `BOOL`
+    let mut dacl_present = Default::default();
+    let mut dacl: *mut ACL = std::ptr::null_mut();
+    let mut dacl_defaulted = Default::default();
+    let set = if unsafe {
+        GetSecurityDescriptorDacl(
+            security_descriptor,
+            &mut dacl_present,
+            &mut dacl,
+            &mut dacl_defaulted,
+        )
+        .succeeded()
+    } {
+        let status = apply(dacl as *const ACL);
+        if let Err(err) = status.into_result() {
+            Err(Error::windows(
+                stage,
+                "Unable to set the DACL of an object",
+                err,
+            ))
+        } else {
+            Ok(())
+        }
+    } else {
+        Err(Error::last_os_error(
+            stage,
+            "Unable to get the DACL of a security descriptor",
+        ))
+    };
+
+    let freed = unsafe { local_free(security_descriptor.0) }.map_err(|err| {
+        Error::windows(
+            Stage::Cleanup,
+            "Unable to free the security descriptor of an object",
+            err,
+        )
+    });
+
+    match (set, freed) {
+        (Ok(()), Ok(_)) => Ok(()),
+        (Ok(()), Err(err)) => Err(err),
+        (Err(err), Ok(_)) => Err(err),
+        (Err(err), Err(cleanup)) => Err(Error::combine(vec![err, cleanup])),
+    }
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Makes the calling thread act as the user of the token until [`revert_to_self`] is called
+pub fn impersonate_logged_on_user(token: HANDLE) -> Result<(), Error> {
+    let success = unsafe { ImpersonateLoggedOnUser(token).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::DriveMapping,
+            "Unable to impersonate the user of the token",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Safe binding to a windows api version of the function
+pub fn revert_to_self() -> Result<(), Error> {
+    let success = unsafe { RevertToSelf().succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::DriveMapping,
+            "Unable to stop impersonating",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Identifies the level AppLocker and Software Restriction Policies assign an executable
+/// for the user of the calling thread, which is closed with [`safer_close_level`]
+pub fn safer_identify_level(image_path: PCWSTR) -> Result<SAFER_LEVEL_HANDLE, Error> {
+    let code_properties = SAFER_CODE_PROPERTIES_V2 {
+        cbSize: std::mem::size_of::<SAFER_CODE_PROPERTIES_V2>() as u32,
+        dwCheckFlags: SAFER_CRITERIA_IMAGEPATH | SAFER_CRITERIA_IMAGEHASH,
+        ImagePath: image_path,
+        ..Default::default()
+    };
+    let mut level = SAFER_LEVEL_HANDLE::default();
+
+    let success =
+        unsafe { SaferIdentifyLevel(Some(&[code_properties]), &mut level, None).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::ExecutionPolicy,
+            "Unable to identify the execution policy level of the executable",
+        ));
+    }
+
+    Ok(level)
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Returns the id of the level, e.g. `SAFER_LEVELID_DISALLOWED`
+pub fn safer_get_level_id(level: SAFER_LEVEL_HANDLE) -> Result<u32, Error> {
+    let mut level_id = 0u32;
+    let mut returned = 0u32;
+
+    let success = unsafe {
+        SaferGetLevelInformation(
+            level,
+            SaferObjectLevelId,
+            Some(&mut level_id as *mut u32 as *mut c_void),
+            std::mem::size_of::<u32>() as u32,
+            &mut returned,
+        )
+        .succeeded()
+    };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::ExecutionPolicy,
+            "Unable to get the id of the execution policy level",
+        ));
+    }
+
+    Ok(level_id)
+}
+
+/// Safe binding to a windows api version of the function
+pub fn safer_close_level(level: SAFER_LEVEL_HANDLE) -> Result<(), Error> {
+    let success = unsafe { SaferCloseLevel(level).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::Cleanup,
+            "Unable to close the execution policy level",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Maps a drive letter, e.g. `Z:`, to a share for the logon session of the calling thread.
+/// Returns whether the drive was mapped, `false` if the letter is already in use.
+pub fn wnet_add_connection2_w(local_name: PWSTR, remote_name: PWSTR) -> Result<bool, Error> {
+    let resource = NETRESOURCEW {
+        dwType: RESOURCETYPE_DISK,
+        lpLocalName: local_name,
+        lpRemoteName: remote_name,
+        ..Default::default()
+    };
+
+    let status = unsafe { WNetAddConnection2W(&resource, PCWSTR::null(), PCWSTR::null(), 0) };
+
+    match status.into_result() {
+        Ok(()) => Ok(true),
+        Err(err) if err.code() == ERROR_ALREADY_ASSIGNED.to_hresult() => Ok(false),
+        Err(err) => Err(Error::windows(
+            Stage::DriveMapping,
+            "Unable to map a drive",
+            err,
+        )),
+    }
+}
+
+/// Duplicates a handle of the current process into another process
+///
+/// Returns the value of the handle in the other process
+pub fn duplicate_handle(
+    handle: HANDLE,
+    target_process_handle: HANDLE,
+    inherit: bool,
+) -> Result<HANDLE, Error> {
+    let mut target_handle = HANDLE::default();
+
+    let success = unsafe {
+        DuplicateHandle(
+            GetCurrentProcess(),
+            handle,
+            target_process_handle,
+            &mut target_handle,
+            0,
+            inherit,
+            DUPLICATE_SAME_ACCESS,
+        )
+        .succeeded()
+    };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::Duplication,
+            "Unable to duplicate a handle into another process",
+        ));
+    }
+
+    Ok(target_handle)
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// The returned handle can be inherited by child processes
+pub fn create_file_w(
+    file_name: PCWSTR,
+    desired_access: u32,
+    creation_disposition: FILE_CREATION_DISPOSITION,
+) -> Result<HANDLE, Error> {
+    let security_attributes = SECURITY_ATTRIBUTES {
+        nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+        lpSecurityDescriptor: std::ptr::null_mut(),
+        bInheritHandle: true.into(),
+    };
+
+    unsafe {
+        CreateFileW(
+            file_name,
+            desired_access,
+            FILE_SHARE_READ | FILE_SHARE_WRITE,
+            Some(&security_attributes),
+            creation_disposition,
+            FILE_ATTRIBUTE_NORMAL,
+            HANDLE::default(),
+        )
+    }
+    .map_err(|err| Error::windows(Stage::Redirection, "Unable to open file", err))
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Returns the size in bytes of an attribute list holding the given number of attributes
+pub fn get_proc_thread_attribute_list_size(attribute_count: u32) -> Result<usize, Error> {
+    let mut size = 0usize;
+
+    // Fails by design when asked for the size only
+    unsafe {
+        InitializeProcThreadAttributeList(
+            LPPROC_THREAD_ATTRIBUTE_LIST::default(),
+            attribute_count,
+            0,
+            &mut size,
+        )
+        .succeeded()
+    };
+
+    if size == 0 {
+        return Err(Error::last_os_error(
+            Stage::Redirection,
+            "Unable to get the size of an attribute list",
+        ));
+    }
+
+    Ok(size)
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// The list must point at a buffer of the size returned by [`get_proc_thread_attribute_list_size`]
+pub fn initialize_proc_thread_attribute_list(
+    attribute_list: LPPROC_THREAD_ATTRIBUTE_LIST,
+    attribute_count: u32,
+    mut size: usize,
+) -> Result<(), Error> {
+    let success = unsafe {
+        InitializeProcThreadAttributeList(attribute_list, attribute_count, 0, &mut size).succeeded()
+    };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::Redirection,
+            "Unable to initialize an attribute list",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// The value must stay alive until the attribute list is deleted
+pub fn update_proc_thread_attribute(
+    attribute_list: LPPROC_THREAD_ATTRIBUTE_LIST,
+    attribute: u32,
+    value: *const c_void,
+    size: usize,
+) -> Result<(), Error> {
+    let success = unsafe {
+        UpdateProcThreadAttribute(
+            attribute_list,
+            0,
+            attribute as usize,
+            Some(value),
+            size,
+            None,
+            None,
+        )
+        .succeeded()
+    };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::Redirection,
+            "Unable to update an attribute list",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Safe binding to a windows api version of the function
+pub fn delete_proc_thread_attribute_list(attribute_list: LPPROC_THREAD_ATTRIBUTE_LIST) {
+    unsafe { DeleteProcThreadAttributeList(attribute_list) }
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Returns the reading and the writing end, neither of which can be inherited
+pub fn create_pipe() -> Result<(HANDLE, HANDLE), Error> {
+    let mut read_handle = HANDLE::default();
+    let mut write_handle = HANDLE::default();
+
+    let success = unsafe { CreatePipe(&mut read_handle, &mut write_handle, None, 0).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::Redirection,
+            "Unable to create a pipe",
+        ));
+    }
+
+    Ok((read_handle, write_handle))
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Returns the number of bytes read into the buffer
+pub fn read_file(handle: HANDLE, buffer: &mut [u8]) -> Result<usize, Error> {
+    let mut read = 0u32;
+
+    let success = unsafe { compat::read_file(handle, buffer, &mut read) };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::Redirection,
+            "Unable to read from a file",
+        ));
+    }
+
+    Ok(read as usize)
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Converts text in a code page to UTF-16, replacing invalid characters
+pub fn multi_byte_to_wide_char(code_page: u32, bytes: &[u8]) -> Result<Vec<u16>, Error> {
+    if bytes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Ask for the required buffer size first
+    let required =
+        unsafe { MultiByteToWideChar(code_page, MULTI_BYTE_TO_WIDE_CHAR_FLAGS(0), bytes, None) };
+    if required <= 0 {
+        return Err(Error::last_os_error(
+            Stage::Conversion,
+            "Unable to get the size of converted text",
+        ));
+    }
+
+    // Then convert into a buffer of that size
+    let mut wide = vec![0u16; required as usize];
+    let converted = unsafe {
+        MultiByteToWideChar(
+            code_page,
+            MULTI_BYTE_TO_WIDE_CHAR_FLAGS(0),
+            bytes,
+            Some(&mut wide),
+        )
+    };
+    if converted <= 0 {
+        return Err(Error::last_os_error(
+            Stage::Conversion,
+            "Unable to convert text from its code page",
+        ));
+    }
+    wide.truncate(converted as usize);
+
+    Ok(wide)
+}
+
+/// Safe binding to a windows api version of the function
+pub fn get_oem_cp() -> u32 {
+    unsafe { GetOEMCP() }
+}
+
+/// Safe binding to a windows api version of the function
+pub fn get_acp() -> u32 {
+    unsafe { GetACP() }
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Opens a registry key for setting values, creating it if it does not exist
+pub fn reg_create_key_ex_w(key: HKEY, subkey: PCWSTR) -> Result<HKEY, Error> {
+    let mut created = HKEY::default();
+
+    let status = unsafe {
+        RegCreateKeyExW(
+            key,
+            subkey,
+            0,
+            PCWSTR::null(),
+            REG_OPTION_NON_VOLATILE,
+            KEY_SET_VALUE,
+            None,
+            &mut created,
+            None,
+        )
+    };
+
+    if let Err(err) = status.into_result() {
+        return Err(Error::windows(
+            Stage::ConsoleSetup,
+            "Unable to create registry key",
+            err,
+        ));
+    }
+
+    Ok(created)
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Sets a DWORD value of a registry key
+pub fn reg_set_value_ex_w(key: HKEY, value: PCWSTR, data: u32) -> Result<(), Error> {
+    let status = unsafe { RegSetValueExW(key, value, 0, REG_DWORD, Some(&data.to_le_bytes())) };
+
+    if let Err(err) = status.into_result() {
+        return Err(Error::windows(
+            Stage::ConsoleSetup,
+            "Unable to set registry value",
+            err,
+        ));
+    }
+
+    Ok(())
+}
+
+/// Safe binding to a windows api version of the function
+pub fn reg_close_key(key: HKEY) -> Result<(), Error> {
+    let status = unsafe { RegCloseKey(key) };
+
+    if let Err(err) = status.into_result() {
+        return Err(Error::windows(
+            Stage::Cleanup,
+            "Unable to close registry key",
+            err,
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Creates an instance of a named pipe in message mode that rejects remote clients
+///
+/// The pipe is secured with the security descriptor described by the SDDL string.
+/// The first instance fails if another process already created a pipe by the name.
+pub fn create_named_pipe_w(
+    name: PCWSTR,
+    sddl: PCWSTR,
+    first_instance: bool,
+    buffer_size: u32,
+) -> Result<HANDLE, Error> {
+    let mut security_descriptor = PSECURITY_DESCRIPTOR::default();
+
+    let success = unsafe {
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            sddl,
+            SDDL_REVISION_1,
+            &mut security_descriptor,
+            None,
+        )
+        .succeeded()
+    };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::LaunchServer,
+            "Unable to build the security descriptor of a pipe",
+        ));
+    }
+
+    let security_attributes = SECURITY_ATTRIBUTES {
+        nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+        lpSecurityDescriptor: security_descriptor.0,
+        bInheritHandle: false.into(),
+    };
+
+    let open_mode = if first_instance {
+        PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE
+    } else {
+        PIPE_ACCESS_DUPLEX
+    };
+
+    let pipe = unsafe {
+        CreateNamedPipeW(
+            name,
+            open_mode,
+            PIPE_TYPE_MESSAGE | PIPE_READMODE_MESSAGE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            PIPE_UNLIMITED_INSTANCES,
+            buffer_size,
+            buffer_size,
+            0,
+            Some(&security_attributes),
+        )
+    };
+    // Read right away, before anything else can overwrite the last error
+    let created = if pipe.is_invalid() {
+        Err(Error::last_os_error(
+            Stage::LaunchServer,
+            "Unable to create a named pipe",
+        ))
+    } else {
+        Ok(pipe)
+    };
+    let freed = unsafe { local_free(security_descriptor.0) };
+
+    let pipe = created?;
+    if let Err(err) = freed {
+        let mut errors = vec![Error::windows(
+            Stage::Cleanup,
+            "Unable to free the security descriptor of a pipe",
+            err,
+        )];
+        if let Err(err) = close_token(pipe) {
+            errors.push(err);
+        }
+        return Err(Error::combine(errors));
+    }
+
+    Ok(pipe)
+}
+
+#[cfg(feature = "server")]
+/// Safe binding to a windows api version of the function
+///
+/// Waits for a client to connect, succeeding as well if one connected before the call
+pub fn connect_named_pipe(pipe: HANDLE) -> Result<(), Error> {
+    let connected = unsafe { ConnectNamedPipe(pipe, None).succeeded() };
+
+    if !connected && last_error() != ERROR_PIPE_CONNECTED {
+        return Err(Error::last_os_error(
+            Stage::LaunchServer,
+            "Unable to wait for a pipe client",
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Safe binding to a windows api version of the function
+pub fn disconnect_named_pipe(pipe: HANDLE) -> Result<(), Error> {
+    let disconnected = unsafe { DisconnectNamedPipe(pipe).succeeded() };
+
+    if !disconnected {
+        return Err(Error::last_os_error(
+            Stage::LaunchServer,
+            "Unable to disconnect a pipe client",
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Safe binding to a windows api version of the function
+pub fn flush_file_buffers(handle: HANDLE) -> Result<(), Error> {
+    let flushed = unsafe { FlushFileBuffers(handle).succeeded() };
+
+    if !flushed {
+        return Err(Error::last_os_error(
+            Stage::LaunchServer,
+            "Unable to flush a pipe",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Safe binding to a windows api version of the function
+///
+/// Returns the number of bytes written
+pub fn write_file(handle: HANDLE, data: &[u8]) -> Result<usize, Error> {
+    let mut written = 0u32;
+    let data = &data[..data.len().min(u32::MAX as usize)];
+
+    let success = unsafe { WriteFile(handle, Some(data), Some(&mut written), None).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::Redirection,
+            "Unable to write to a file",
+        ));
+    }
+
+    Ok(written as usize)
+}
+
+#[cfg(feature = "server")]
+/// Safe binding to a windows api version of the function
+///
+/// Connects to a message mode pipe, sends a message and reads the reply into the buffer.
+/// Returns the number of bytes of the reply.
+pub fn call_named_pipe_w(
+    name: PCWSTR,
+    message: &[u8],
+    reply: &mut [u8],
+    timeout_ms: u32,
+) -> Result<usize, Error> {
+    let mut read = 0u32;
+
+    let success = unsafe {
+        CallNamedPipeW(
+            name,
+            Some(message.as_ptr() as *const c_void),
+            message.len() as u32,
+            Some(reply.as_mut_ptr() as *mut c_void),
+            reply.len() as u32,
+            &mut read,
+            timeout_ms,
+        )
+        .succeeded()
+    };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::LaunchServer,
+            "Unable to call the launch server",
+        ));
+    }
+
+    Ok(read as usize)
+}
+
+#[cfg(feature = "server")]
+/// Safe binding to a windows api version of the function
+///
+/// Makes the calling thread act as the client of a pipe instance until [`revert_to_self`] is called
+pub fn impersonate_named_pipe_client(pipe: HANDLE) -> Result<(), Error> {
+    let success = unsafe { ImpersonateNamedPipeClient(pipe).succeeded() };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::LaunchServer,
+            "Unable to impersonate the pipe client",
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+/// Safe binding to a windows api version of the function
+///
+/// Opens the impersonation token of the calling thread, checking access as the process itself
+pub fn open_thread_token(desired_access: TOKEN_ACCESS_MASK) -> Result<HANDLE, Error> {
+    let mut token_handle = HANDLE::default();
+
+    let success = unsafe {
+        OpenThreadToken(GetCurrentThread(), desired_access, true, &mut token_handle).succeeded()
+    };
+
+    if !success {
+        return Err(Error::last_os_error(
+            Stage::TokenOpen,
+            "Unable to open the thread token",
+        ));
+    }
+
+    Ok(token_handle)
+}
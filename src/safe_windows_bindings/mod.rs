@@ -1,2 +1,8 @@
+/// Differences between the supported `windows` versions, of which `windows-0-48` takes precedence
+/// when both are enabled
+mod compat;
 pub mod high_level;
 mod low_level;
+/// The `windows` types and constants used outside of the bindings, so that moving to another
+/// `windows` version only has to touch this module
+pub mod types;
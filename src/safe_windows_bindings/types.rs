@@ -0,0 +1,22 @@
+pub use windows::Win32::Foundation::HANDLE;
+
+pub(crate) use windows::core::{Error as WindowsError, GUID};
+pub(crate) use windows::Win32::Foundation::{
+    ERROR_ACCESS_DENIED, ERROR_INVALID_PARAMETER, ERROR_NOT_LOGGED_ON, ERROR_NO_SUCH_LOGON_SESSION,
+    ERROR_NO_TOKEN, ERROR_PRIVILEGE_NOT_HELD, ERROR_USER_PROFILE_LOAD, WIN32_ERROR,
+};
+pub(crate) use windows::Win32::Security::{
+    LOGON32_LOGON, LOGON32_LOGON_BATCH, LOGON32_LOGON_INTERACTIVE, LOGON32_LOGON_NETWORK,
+    LOGON32_LOGON_NETWORK_CLEARTEXT, LOGON32_LOGON_NEW_CREDENTIALS, LOGON32_LOGON_SERVICE,
+};
+pub(crate) use windows::Win32::System::Threading::{
+    ABOVE_NORMAL_PRIORITY_CLASS, BELOW_NORMAL_PRIORITY_CLASS, CREATE_PROCESS_LOGON_FLAGS,
+    HIGH_PRIORITY_CLASS, IDLE_PRIORITY_CLASS, LOGON_NETCREDENTIALS_ONLY, LOGON_WITH_PROFILE,
+    NORMAL_PRIORITY_CLASS, PROCESS_CREATION_FLAGS, REALTIME_PRIORITY_CLASS,
+};
+pub(crate) use windows::Win32::UI::WindowsAndMessaging::{
+    IDABORT, IDCANCEL, IDIGNORE, IDNO, IDOK, IDRETRY, IDTIMEOUT, IDYES, MB_ABORTRETRYIGNORE, MB_OK,
+    MB_OKCANCEL, MB_RETRYCANCEL, MB_SETFOREGROUND, MB_TOPMOST, MB_YESNO, MB_YESNOCANCEL,
+    MESSAGEBOX_RESULT, SHOW_WINDOW_CMD, SW_HIDE, SW_SHOWMAXIMIZED, SW_SHOWMINIMIZED,
+    SW_SHOWMINNOACTIVE, SW_SHOWNOACTIVATE, SW_SHOWNORMAL,
+};
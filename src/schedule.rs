@@ -0,0 +1,400 @@
+use crate::cancellation::cancelled;
+use crate::child::Child;
+use crate::error::{Error, Stage};
+use crate::events::LaunchEvent;
+use crate::safe_windows_bindings::high_level::{
+    get_local_time_of_day, get_logged_on_session_ids, get_session_idle_time,
+};
+use crate::ProcessBuilder;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{spawn, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often a recurring launch whose conditions are not met checks them again
+const CONDITION_POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// The length of a day, ignoring daylight saving time shifts
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// When a scheduled launch is due
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum When {
+    /// At a time of the wall clock, following changes to it
+    At(SystemTime),
+    /// Once a point in time has passed, regardless of changes to the wall clock
+    After(Instant),
+}
+
+impl When {
+    /// How long until the launch is due, zero once it is
+    fn remaining(&self) -> Duration {
+        match self {
+            When::At(time) => time
+                .duration_since(SystemTime::now())
+                .unwrap_or(Duration::ZERO),
+            When::After(instant) => instant.saturating_duration_since(Instant::now()),
+        }
+    }
+}
+
+/// Where a scheduled launch stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Waiting,
+    Cancelled,
+    Launched,
+}
+
+/// The state a handle shares with the thread of its launch
+struct Shared {
+    state: Mutex<State>,
+    /// Wakes the thread when the launch is cancelled
+    wake: Condvar,
+}
+
+/// A launch waiting for its time, created by [`ProcessBuilder::run_at`] and [`ProcessBuilder::run_after`]
+///
+/// The launch waits on a thread of its own. Dropping the handle leaves the launch scheduled,
+/// [`ScheduledLaunch::cancel`] calls it off, as does cancelling the builder's cancellation token.
+///
+/// ```no_run
+/// # fn main() -> Result<(), win_run::Error> {
+/// use std::time::Duration;
+/// use win_run::win;
+///
+/// let reminder = win("/path/to/reminder").run_after(Duration::from_secs(15 * 60));
+///
+/// // The user dealt with it in the meantime
+/// if !reminder.cancel() {
+///     let child = reminder.wait()?;
+///     println!("Reminded through process {}", child.id());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct ScheduledLaunch {
+    shared: Arc<Shared>,
+    worker: JoinHandle<Result<Child, Error>>,
+}
+
+impl ScheduledLaunch {
+    /// Schedules a launch of the builder
+    pub(crate) fn start(builder: ProcessBuilder, when: When) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::Waiting),
+            wake: Condvar::new(),
+        });
+
+        if let Some(token) = &builder.cancellation {
+            let shared = Arc::downgrade(&shared);
+            token.on_cancel(move || {
+                if let Some(shared) = shared.upgrade() {
+                    shared.cancel();
+                }
+            });
+        }
+        if let Some(events) = &builder.events {
+            events.send(LaunchEvent::LaunchQueued {
+                path: events.path(),
+            });
+        }
+
+        let worker = {
+            let shared = Arc::clone(&shared);
+            spawn(move || {
+                let mut state = lock(&shared.state);
+                loop {
+                    let remaining = when.remaining();
+                    if *state != State::Waiting || remaining.is_zero() {
+                        break;
+                    }
+                    // The wall clock may have been changed in the meantime, so the time is checked again
+                    state = shared
+                        .wake
+                        .wait_timeout_while(state, remaining, |state| *state == State::Waiting)
+                        .unwrap_or_else(|err| err.into_inner())
+                        .0;
+                }
+                if *state == State::Cancelled {
+                    return Err(cancelled());
+                }
+                *state = State::Launched;
+                drop(state);
+                builder.spawn()
+            })
+        };
+
+        Self { shared, worker }
+    }
+
+    /// Calls the launch off, unless it has already started
+    ///
+    /// Returns whether it was called off.
+    pub fn cancel(&self) -> bool {
+        self.shared.cancel()
+    }
+
+    /// Whether the launch is over, successful, failed or called off
+    pub fn is_finished(&self) -> bool {
+        self.worker.is_finished()
+    }
+
+    /// Blocks until the launch is over and returns its outcome
+    ///
+    /// A launch that was called off fails at [`Stage::Cancellation`].
+    pub fn wait(self) -> Result<Child, Error> {
+        self.worker.join().unwrap_or_else(|_| {
+            Err(Error::other(
+                Stage::Scheduling,
+                "The thread of the scheduled launch panicked",
+            ))
+        })
+    }
+}
+
+impl Shared {
+    /// Calls the launch off unless it has already started, returning whether it was called off
+    fn cancel(&self) -> bool {
+        let mut state = lock(&self.state);
+        match *state {
+            State::Waiting => {
+                *state = State::Cancelled;
+                self.wake.notify_one();
+                true
+            }
+            State::Cancelled => true,
+            State::Launched => false,
+        }
+    }
+}
+
+/// How often a recurring launch is due
+#[derive(Debug, Clone, Copy)]
+enum Period {
+    Every(Duration),
+    /// At a time of the local day, as the time passed since midnight
+    DailyAt(Duration),
+}
+
+impl Period {
+    /// How long from now until the launch is due again
+    fn until_next(&self) -> Duration {
+        match self {
+            Period::Every(interval) => *interval,
+            Period::DailyAt(time_of_day) => {
+                let now = get_local_time_of_day();
+                match time_of_day.checked_sub(now) {
+                    Some(remaining) if !remaining.is_zero() => remaining,
+                    _ => DAY.saturating_sub(now) + *time_of_day,
+                }
+            }
+        }
+    }
+}
+
+/// How often a recurring launch runs and what it waits for, see [`ProcessBuilder::run_every`]
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use win_run::{win, Recurrence};
+///
+/// // Clean up at 3 AM, once the user has been away for 10 minutes
+/// let recurrence = Recurrence::daily_at(3, 0)
+///     .only_when_logged_on(true)
+///     .only_when_idle(Duration::from_secs(10 * 60));
+/// let cleanup = win("/path/to/cleanup").run_every(recurrence);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Recurrence {
+    period: Period,
+    /// Whether to wait for a user to be logged on to the target session
+    logged_on: bool,
+    /// How long the target session has to be without input, if it matters
+    idle: Option<Duration>,
+}
+
+impl Recurrence {
+    /// Runs the launch every interval, starting one interval from now
+    pub fn every(interval: Duration) -> Self {
+        Self::new(Period::Every(interval))
+    }
+
+    /// Runs the launch every day at a time of the local day
+    ///
+    /// Hours and minutes past their range wrap around, e.g. 24:00 is midnight.
+    pub fn daily_at(hour: u8, minute: u8) -> Self {
+        let time_of_day = Duration::from_secs(u64::from(hour) * 3600 + u64::from(minute) * 60);
+        Self::new(Period::DailyAt(Duration::from_secs(
+            time_of_day.as_secs() % DAY.as_secs(),
+        )))
+    }
+
+    fn new(period: Period) -> Self {
+        let logged_on = false;
+        let idle = None;
+
+        Self {
+            period,
+            logged_on,
+            idle,
+        }
+    }
+
+    /// Sets whether a due launch waits for a user to be logged on to the target session, default is **false**
+    pub fn only_when_logged_on(mut self, logged_on: bool) -> Self {
+        self.logged_on = logged_on;
+        self
+    }
+
+    /// Sets how long the target session has to be without input before a due launch runs, default is **not at all**
+    ///
+    /// Sessions that do not report their input count as idle.
+    pub fn only_when_idle(mut self, idle: Duration) -> Self {
+        self.idle = Some(idle);
+        self
+    }
+
+    /// Whether the conditions of a due launch are met for the builder's target session
+    fn conditions_met(&self, builder: &ProcessBuilder) -> bool {
+        if !self.logged_on && self.idle.is_none() {
+            return true;
+        }
+        let Ok(session_id) = builder.target_session() else {
+            return false;
+        };
+        if self.logged_on
+            && !get_logged_on_session_ids().is_ok_and(|sessions| sessions.contains(&session_id))
+        {
+            return false;
+        }
+        match self.idle {
+            Some(required) => match get_session_idle_time(session_id) {
+                Ok(Some(idle)) => idle >= required,
+                Ok(None) => true,
+                Err(_) => false,
+            },
+            None => true,
+        }
+    }
+}
+
+/// The state a recurring launch shares with its thread
+struct Recurring {
+    stop: Mutex<bool>,
+    /// Wakes the thread when it is asked to stop
+    wake: Condvar,
+    /// How many times the launch ran, successfully or not
+    runs: Mutex<u64>,
+    /// Why the last run failed, if it did
+    last_error: Mutex<Option<Error>>,
+}
+
+/// A launch running again and again, created by [`ProcessBuilder::run_every`]
+///
+/// Each run is on its own: the launched processes are left running and not waited for,
+/// so use [`ProcessBuilder::unless_running`] to keep runs from overlapping.
+/// A run whose conditions are not met waits for them, checking them every minute,
+/// and the next run is counted from when it finally ran.
+///
+/// Dropping the handle or cancelling the builder's cancellation token stops the runs,
+/// the processes already launched keep running unless the token kills them.
+pub struct RecurringLaunch {
+    shared: Arc<Recurring>,
+    /// The thread running the launch, taken when the handle is dropped
+    worker: Option<JoinHandle<()>>,
+}
+
+impl RecurringLaunch {
+    /// Starts running the builder according to the recurrence
+    pub(crate) fn start(builder: ProcessBuilder, recurrence: Recurrence) -> Self {
+        let shared = Arc::new(Recurring {
+            stop: Mutex::new(false),
+            wake: Condvar::new(),
+            runs: Mutex::new(0),
+            last_error: Mutex::new(None),
+        });
+
+        if let Some(token) = &builder.cancellation {
+            let shared = Arc::downgrade(&shared);
+            token.on_cancel(move || {
+                if let Some(shared) = shared.upgrade() {
+                    shared.stop();
+                }
+            });
+        }
+
+        let worker = {
+            let shared = Arc::clone(&shared);
+            spawn(move || shared.work(&builder, &recurrence))
+        };
+
+        Self {
+            shared,
+            worker: Some(worker),
+        }
+    }
+
+    /// How many times the launch ran, successfully or not
+    pub fn runs(&self) -> u64 {
+        *lock(&self.shared.runs)
+    }
+
+    /// Why the last run failed, `None` if it succeeded or there was none yet
+    pub fn last_error(&self) -> Option<Error> {
+        lock(&self.shared.last_error).clone()
+    }
+}
+
+impl Drop for RecurringLaunch {
+    fn drop(&mut self) {
+        self.shared.stop();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Recurring {
+    /// Asks the thread to stop
+    fn stop(&self) {
+        *lock(&self.stop) = true;
+        self.wake.notify_one();
+    }
+
+    /// Runs the launch whenever it is due and its conditions are met, until asked to stop
+    fn work(&self, builder: &ProcessBuilder, recurrence: &Recurrence) {
+        let mut wait = recurrence.period.until_next();
+        loop {
+            let stop = lock(&self.stop);
+            let (stop, _) = self
+                .wake
+                .wait_timeout_while(stop, wait, |stop| !*stop)
+                .unwrap_or_else(|err| err.into_inner());
+            if *stop {
+                return;
+            }
+            drop(stop);
+
+            if !recurrence.conditions_met(builder) {
+                wait = CONDITION_POLL_INTERVAL;
+                continue;
+            }
+
+            // The process is not managed by the recurrence, dropping the child leaves it running
+            let outcome = builder.spawn();
+            *lock(&self.runs) += 1;
+            *lock(&self.last_error) = outcome.err();
+            wait = recurrence.period.until_next();
+            if let Some(events) = &builder.events {
+                events.send(LaunchEvent::RestartScheduled {
+                    path: events.path(),
+                    delay: wait,
+                });
+            }
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // A thread that panicked while holding the lock leaves the data as usable as before
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
@@ -0,0 +1,92 @@
+use crate::child::Child;
+use crate::error::Error;
+use crate::pool::TokenPool;
+use crate::ProcessBuilder;
+use std::time::Duration;
+
+/// What a [`Sequence`] does after a step fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnFailure {
+    /// Skip the remaining steps
+    #[default]
+    Stop,
+    /// Run the remaining steps anyway
+    Continue,
+}
+
+/// Runs several launches back to back, sharing the acquired tokens between them
+///
+/// Each step starts once the process of the previous one exits. Steps with the same
+/// elevation use copies of one token, so it is acquired only once for the whole sequence,
+/// unless a step brings its own [`TokenPool`].
+///
+/// ```no_run
+/// use win_run::{win, Elevation, OnFailure, Sequence};
+///
+/// let results = Sequence::new()
+///     .step(win("/path/to/setup").arg("--unpack"))
+///     .step(win("/path/to/setup").arg("--register"))
+///     .step(win("/path/to/setup").arg("--shortcuts").elevation(Elevation::User))
+///     .on_failure(OnFailure::Stop)
+///     .run();
+///
+/// for (step, result) in results.iter().enumerate() {
+///     if let Err(err) = result {
+///         eprintln!("Step {step} failed: {err}");
+///     }
+/// }
+/// ```
+#[derive(Default)]
+pub struct Sequence {
+    /// The launches to run, in order
+    pub(crate) steps: Vec<ProcessBuilder>,
+    /// What to do after a step fails
+    pub(crate) on_failure: OnFailure,
+}
+
+impl Sequence {
+    /// Creates an empty sequence
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a launch to the sequence
+    pub fn step(mut self, builder: ProcessBuilder) -> Self {
+        self.steps.push(builder);
+        self
+    }
+
+    /// Sets what to do after a step fails, default is **OnFailure::Stop**
+    pub fn on_failure(mut self, on_failure: OnFailure) -> Self {
+        self.on_failure = on_failure;
+        self
+    }
+
+    /// Runs the steps in order and returns the result of each step that ran
+    ///
+    /// A step fails if its process could not be launched. When stopping on failure,
+    /// the failed step's result is the last one returned.
+    pub fn run(self) -> Vec<Result<Child, Error>> {
+        // The tokens only live as long as the sequence runs
+        let token_pool = TokenPool::new(Duration::MAX);
+        let mut results = Vec::with_capacity(self.steps.len());
+
+        for mut step in self.steps {
+            if step.token_pool.is_none() {
+                step.token_pool = Some(token_pool.clone());
+            }
+
+            let result = step
+                .spawn()
+                .and_then(|child| child.wait_for_exit().map(|()| child));
+            let failed = result.is_err();
+            results.push(result);
+
+            if failed && self.on_failure == OnFailure::Stop {
+                break;
+            }
+        }
+
+        results
+    }
+}
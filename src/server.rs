@@ -0,0 +1,386 @@
+use crate::error::{Error, Stage};
+use crate::policy::{LaunchPolicy, PipeClient};
+use crate::record::{LaunchRecord, RecordKind};
+#[cfg(feature = "json-rpc")]
+use crate::rpc::ServedChild;
+use crate::safe_windows_bindings::high_level::{
+    call_server_pipe, close_handle, create_server_pipe, disconnect_pipe_client, read_pipe_message,
+    wait_for_pipe_client, write_pipe_message,
+};
+use crate::safe_windows_bindings::types::HANDLE;
+use crate::{Elevation, ProcessBuilder};
+use std::sync::Arc;
+#[cfg(feature = "json-rpc")]
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The largest request or reply exchanged over the pipe
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+/// How long a client waits for the server to take its request
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How launch requests are reported in errors
+const REQUEST: RecordKind = RecordKind {
+    stage: Stage::LaunchServer,
+    name: "launch request",
+    no_credentials: "Launching with credentials cannot be requested from a launch server",
+};
+
+/// A launch a client asks the launch server for
+///
+/// Requests travel as `key=value` lines, so no value can contain a line break.
+/// Launching with credentials cannot be requested, as they would cross the pipe in plain text.
+#[derive(Debug, Clone, Default)]
+pub struct LaunchMessage {
+    /// Path to the executable
+    pub path: String,
+    /// Arguments for the executable
+    pub args: String,
+    /// Working directory of the process, the server's if missing
+    pub directory: Option<String>,
+    /// Elevation of the process, one of `User`, `Admin` and `LocalSystem`
+    pub elevation: Elevation,
+    /// Session to launch into, the active console session if missing
+    pub session: Option<u32>,
+}
+
+impl LaunchMessage {
+    /// Creates a request to launch the executable as User in the active console session
+    pub fn new(path: impl AsRef<str>) -> Self {
+        Self {
+            path: path.as_ref().to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// The launch the request asks for
+    fn record(&self) -> LaunchRecord {
+        LaunchRecord {
+            path: self.path.clone(),
+            args: self.args.clone(),
+            directory: self.directory.clone(),
+            elevation: self.elevation.clone(),
+            session: self.session,
+        }
+    }
+
+    /// Serializes the request into its `key=value` lines
+    fn encode(&self) -> Result<String, Error> {
+        self.record().encode(&REQUEST, Vec::new())
+    }
+
+    /// Parses a request from its `key=value` lines
+    fn decode(text: &str) -> Result<Self, Error> {
+        let record = LaunchRecord::decode(text, &REQUEST, |_, _| Ok(false))?;
+        Ok(Self {
+            path: record.path,
+            args: record.args,
+            directory: record.directory,
+            elevation: record.elevation,
+            session: record.session,
+        })
+    }
+
+    /// Creates the builder the server launches the request with
+    fn builder(&self) -> ProcessBuilder {
+        self.record().builder()
+    }
+}
+
+/// Adjusts the builder of every request before it is launched
+type Configure = Arc<dyn Fn(ProcessBuilder) -> ProcessBuilder + Send + Sync>;
+
+/// A named pipe through which processes without the service's rights ask it for launches
+///
+/// The pipe only accepts local clients: the system, administrators and interactive users.
+/// What they can launch is limited by a [`LaunchPolicy`], by default to launching as User
+/// into their own session.
+/// Each request is launched on a thread of its own and answered with the pid of the new
+/// process or why it could not be launched.
+///
+/// ```no_run
+/// # fn main() -> Result<(), win_run::Error> {
+/// use win_run::LaunchServer;
+///
+/// LaunchServer::new("my-service-launcher")
+///     .configure(|builder| builder.verify_identity(true))
+///     .serve()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// A client in a user's session then asks for a launch with [`request_launch`].
+///
+/// # JSON-RPC
+///
+/// With the `json-rpc` feature the server also answers JSON-RPC 2.0 requests, one per
+/// pipe message, so clients in PowerShell or C# can use it through `NamedPipeClientStream`
+/// in message read mode. The parameters of every method may carry `"protocol": 1` to make
+/// sure the server speaks that version of the schema.
+///
+/// | Method | Parameters | Result |
+/// |---|---|---|
+/// | `version` | | `{"protocol"}` |
+/// | `launch` | `{"path", "args"?, "directory"?, "elevation"?, "session"?}` | `{"pid"}` |
+/// | `status` | `{"pid"}` | `{"pid", "path", "running"}` |
+/// | `kill` | `{"pid"}` | `{"pid"}` |
+/// | `list_children` | | `[{"pid", "path", "running"}]` |
+///
+/// Elevations are named `user`, `admin` and `system`. Only processes launched through
+/// the server can be queried and killed, and only by the user who asked for them.
+/// The server forgets a process once it was reported to have exited, or when a later launch
+/// finds that it has.
+///
+/// ```text
+/// {"jsonrpc": "2.0", "id": 1, "method": "launch", "params": {"path": "C:\\Tools\\sync.exe"}}
+/// {"jsonrpc": "2.0", "id": 1, "result": {"pid": 4242}}
+/// ```
+///
+/// Failures are answered with the standard error codes, plus `-32000` for failed launches
+/// and denied requests, `-32001` for unknown pids and `-32002` for unsupported versions.
+#[derive(Clone)]
+pub struct LaunchServer {
+    /// The name of the pipe, without the `\\.\pipe\` prefix
+    pub(crate) pipe_name: String,
+    /// What clients may ask for
+    pub(crate) policy: LaunchPolicy,
+    /// Applied to the builder of every request before it is launched
+    pub(crate) configure: Option<Configure>,
+    /// The processes launched through the server, kept for JSON-RPC clients to manage
+    #[cfg(feature = "json-rpc")]
+    pub(crate) children: Arc<Mutex<Vec<ServedChild>>>,
+}
+
+impl LaunchServer {
+    /// Creates a server for the pipe `\\.\pipe\<pipe_name>`
+    pub fn new(pipe_name: impl AsRef<str>) -> Self {
+        let pipe_name = pipe_name.as_ref().to_string();
+        let policy = LaunchPolicy::new();
+        let configure = None;
+        #[cfg(feature = "json-rpc")]
+        let children = Arc::new(Mutex::new(Vec::new()));
+
+        Self {
+            pipe_name,
+            policy,
+            configure,
+            #[cfg(feature = "json-rpc")]
+            children,
+        }
+    }
+
+    /// Sets what clients may ask for, default is **launching as User into their own session**
+    ///
+    /// Requests the policy denies are answered with why, without launching anything
+    pub fn policy(mut self, policy: LaunchPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sets a function adjusting the builder of every request, default is **none**
+    ///
+    /// Use it to apply what clients cannot ask for, e.g. a token pool, a throttle or identity verification
+    pub fn configure(
+        mut self,
+        configure: impl Fn(ProcessBuilder) -> ProcessBuilder + Send + Sync + 'static,
+    ) -> Self {
+        self.configure = Some(Arc::new(configure));
+        self
+    }
+
+    /// Serves requests until the pipe cannot be created, e.g. because another process owns it
+    ///
+    /// Never returns otherwise, so run it on a thread of its own.
+    pub fn serve(&self) -> Result<(), Error> {
+        std::thread::scope(|scope| {
+            let mut first_instance = true;
+            loop {
+                let pipe = create_server_pipe(&self.pipe_name, first_instance, MAX_MESSAGE_SIZE)?;
+                first_instance = false;
+
+                if wait_for_pipe_client(pipe).is_err() {
+                    // The client went away before it was connected, wait for the next one
+                    let _ = close_handle(pipe);
+                    continue;
+                }
+                scope.spawn(move || self.answer(pipe));
+            }
+        })
+    }
+
+    /// Answers the request of a connected client, then closes the pipe instance
+    fn answer(&self, pipe: HANDLE) {
+        let reply = match read_pipe_message(pipe, MAX_MESSAGE_SIZE) {
+            Ok(request) => self.reply(pipe, &request),
+            Err(err) => error_reply(err),
+        };
+
+        // The client may have gone away, nothing sensible can be done if replying fails
+        let _ = write_pipe_message(pipe, reply.as_bytes());
+        let _ = disconnect_pipe_client(pipe);
+        let _ = close_handle(pipe);
+    }
+
+    /// Answers a request in the protocol it was made in
+    fn reply(&self, pipe: HANDLE, request: &[u8]) -> String {
+        #[cfg(feature = "json-rpc")]
+        if request.starts_with(b"{") {
+            return crate::rpc::reply(self, pipe, request);
+        }
+
+        let launched = String::from_utf8(request.to_vec())
+            .map_err(|_| Error::other(Stage::LaunchServer, "The launch request is not UTF-8"))
+            .and_then(|request| LaunchMessage::decode(&request))
+            .and_then(|request| self.launch(pipe, request));
+        match launched {
+            Ok(pid) => format!("ok {pid}"),
+            Err(err) => error_reply(err),
+        }
+    }
+
+    /// Checks a request against the policy and launches it, returning the pid of the new process
+    pub(crate) fn launch(&self, pipe: HANDLE, mut request: LaunchMessage) -> Result<u32, Error> {
+        let client = PipeClient::identify(pipe)?;
+        self.policy.authorize(&client, &mut request)?;
+        let owner = client.sid;
+
+        let mut builder = request.builder();
+        if let Some(configure) = &self.configure {
+            builder = configure(builder);
+        }
+        let child = builder.spawn()?;
+        let pid = child.id();
+
+        #[cfg(feature = "json-rpc")]
+        crate::rpc::track(self, child, request.path, owner);
+        #[cfg(not(feature = "json-rpc"))]
+        let _ = (child, owner);
+
+        Ok(pid)
+    }
+}
+
+/// The reply to a request that failed, on a single line
+fn error_reply(err: Error) -> String {
+    format!("error {}", err.to_string().replace(['\r', '\n'], " "))
+}
+
+/// Asks the launch server on the pipe `\\.\pipe\<pipe_name>` for a launch
+///
+/// Returns the pid of the new process. Unlike the rest of the crate this works from any
+/// process, as it is the service running the server that launches.
+///
+/// ```no_run
+/// # fn main() -> Result<(), win_run::Error> {
+/// use win_run::{request_launch, Elevation, LaunchMessage};
+///
+/// let mut message = LaunchMessage::new("/path/to/executable");
+/// message.elevation = Elevation::Admin;
+/// let pid = request_launch("my-service-launcher", &message)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn request_launch(pipe_name: impl AsRef<str>, message: &LaunchMessage) -> Result<u32, Error> {
+    let request = message.encode()?;
+    let reply = call_server_pipe(
+        pipe_name.as_ref(),
+        request.as_bytes(),
+        MAX_MESSAGE_SIZE,
+        CLIENT_TIMEOUT,
+    )?;
+    let reply = String::from_utf8_lossy(&reply);
+
+    if let Some(pid) = reply.strip_prefix("ok ") {
+        return pid.trim().parse().map_err(|_| {
+            Error::other(
+                Stage::LaunchServer,
+                format!("Malformed reply from the launch server: {reply}"),
+            )
+        });
+    }
+    let message = reply.strip_prefix("error ").unwrap_or(&reply);
+    Err(Error::other(
+        Stage::LaunchServer,
+        format!("The launch server could not launch: {message}"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_values_with_separators() {
+        let mut message = LaunchMessage::new("C:\\Tools\\sync.exe");
+        message.args = "--filter=a=b \"x = y\"".to_string();
+        message.directory = Some("C:\\Work=Dir".to_string());
+        message.elevation = Elevation::Admin;
+        message.session = Some(3);
+
+        let decoded = LaunchMessage::decode(&message.encode().unwrap()).unwrap();
+        assert_eq!(decoded.path, message.path);
+        assert_eq!(decoded.args, message.args);
+        assert_eq!(decoded.directory, message.directory);
+        assert!(matches!(decoded.elevation, Elevation::Admin));
+        assert_eq!(decoded.session, Some(3));
+    }
+
+    #[test]
+    fn round_trips_missing_optional_values() {
+        let message = LaunchMessage::new("notepad.exe");
+
+        let decoded = LaunchMessage::decode(&message.encode().unwrap()).unwrap();
+        assert_eq!(decoded.path, "notepad.exe");
+        assert_eq!(decoded.args, "");
+        assert_eq!(decoded.directory, None);
+        assert!(matches!(decoded.elevation, Elevation::User));
+        assert_eq!(decoded.session, None);
+    }
+
+    #[test]
+    fn rejects_line_breaks_in_values() {
+        for args in ["a\nb", "a\r\nb", "a\rb"] {
+            let mut message = LaunchMessage::new("notepad.exe");
+            message.args = args.to_string();
+            assert_eq!(message.encode().unwrap_err().stage(), Stage::LaunchServer);
+        }
+
+        let mut message = LaunchMessage::new("notepad.exe");
+        message.directory = Some("C:\\\nWork".to_string());
+        assert!(message.encode().is_err());
+    }
+
+    #[test]
+    fn rejects_elevations_without_a_name() {
+        let mut message = LaunchMessage::new("notepad.exe");
+        message.elevation = Elevation::FromPid(4);
+        assert!(message.encode().is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_and_malformed_fields() {
+        let cases = [
+            "path=notepad.exe\nuser=admin\n",
+            "path=notepad.exe\nargs\n",
+            "path=notepad.exe\nelevation=root\n",
+            "path=notepad.exe\nsession=-1\n",
+            "path=notepad.exe\nsession=one\n",
+        ];
+        for text in cases {
+            let err = LaunchMessage::decode(text).unwrap_err();
+            assert_eq!(err.stage(), Stage::LaunchServer, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_requests_without_a_path() {
+        assert!(LaunchMessage::decode("").is_err());
+        assert!(LaunchMessage::decode("args=/s\nelevation=user\n").is_err());
+    }
+
+    #[test]
+    fn accepts_empty_values_and_blank_lines() {
+        let decoded = LaunchMessage::decode("\npath=notepad.exe\n\nargs=\n").unwrap();
+        assert_eq!(decoded.path, "notepad.exe");
+        assert_eq!(decoded.args, "");
+    }
+}
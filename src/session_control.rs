@@ -0,0 +1,206 @@
+use crate::error::Error;
+use crate::safe_windows_bindings::high_level::{
+    disconnect_session_client, get_session_idle_time, get_session_locked, log_off_session,
+    show_session_message,
+};
+use crate::safe_windows_bindings::types::{
+    IDABORT, IDCANCEL, IDIGNORE, IDNO, IDOK, IDRETRY, IDTIMEOUT, IDYES, MB_ABORTRETRYIGNORE, MB_OK,
+    MB_OKCANCEL, MB_RETRYCANCEL, MB_SETFOREGROUND, MB_TOPMOST, MB_YESNO, MB_YESNOCANCEL,
+    MESSAGEBOX_RESULT,
+};
+use std::time::Duration;
+
+/// The buttons of a message shown in a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MessageButtons {
+    #[default]
+    Ok,
+    OkCancel,
+    YesNo,
+    YesNoCancel,
+    RetryCancel,
+    AbortRetryIgnore,
+}
+
+/// How the user answered a message shown in a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageResponse {
+    Ok,
+    Cancel,
+    Yes,
+    No,
+    Retry,
+    Abort,
+    Ignore,
+    /// The user did not answer before the timeout elapsed
+    TimedOut,
+    /// An answer not known to this crate, by its button id
+    Other(i32),
+}
+
+impl MessageResponse {
+    /// Converts the id of the button pressed
+    fn from_native(response: i32) -> Self {
+        match MESSAGEBOX_RESULT(response) {
+            IDOK => MessageResponse::Ok,
+            IDCANCEL => MessageResponse::Cancel,
+            IDYES => MessageResponse::Yes,
+            IDNO => MessageResponse::No,
+            IDRETRY => MessageResponse::Retry,
+            IDABORT => MessageResponse::Abort,
+            IDIGNORE => MessageResponse::Ignore,
+            IDTIMEOUT => MessageResponse::TimedOut,
+            _ => MessageResponse::Other(response),
+        }
+    }
+}
+
+/// Shows a message box on the desktop of a session and waits for the user to answer
+///
+/// The message box is shown on top of the other windows, even while the service itself has none.
+///
+/// # Arguments
+///
+/// * `session_id` - Id of the session.
+/// * `title` - Title of the message box.
+/// * `text` - Text of the message box.
+/// * `buttons` - Buttons the user can answer with.
+/// * `timeout` - How long to wait for an answer, indefinitely if `None`.
+///   Whole seconds are waited, rounded up.
+///
+/// ```no_run
+/// # fn main() -> Result<(), win_run::Error> {
+/// use std::time::Duration;
+/// use win_run::{send_message, win, MessageButtons, MessageResponse};
+///
+/// let response = send_message(
+///     2,
+///     "Updates",
+///     "Restart the editor to apply the update now?",
+///     MessageButtons::YesNo,
+///     Some(Duration::from_secs(60)),
+/// )?;
+/// if response == MessageResponse::Yes {
+///     win("/path/to/editor").session(2).run()?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn send_message(
+    session_id: u32,
+    title: &str,
+    text: &str,
+    buttons: MessageButtons,
+    timeout: Option<Duration>,
+) -> Result<MessageResponse, Error> {
+    let buttons = match buttons {
+        MessageButtons::Ok => MB_OK,
+        MessageButtons::OkCancel => MB_OKCANCEL,
+        MessageButtons::YesNo => MB_YESNO,
+        MessageButtons::YesNoCancel => MB_YESNOCANCEL,
+        MessageButtons::RetryCancel => MB_RETRYCANCEL,
+        MessageButtons::AbortRetryIgnore => MB_ABORTRETRYIGNORE,
+    };
+    let style = buttons | MB_SETFOREGROUND | MB_TOPMOST;
+
+    // Zero waits indefinitely, so a timeout of less than a second still waits one
+    let timeout_s = match timeout {
+        Some(timeout) => {
+            let seconds = timeout.as_secs() + u64::from(timeout.subsec_nanos() > 0);
+            seconds.clamp(1, u32::MAX as u64) as u32
+        }
+        None => 0,
+    };
+
+    let response = show_session_message(session_id, title, text, style.0, timeout_s)?;
+    Ok(MessageResponse::from_native(response))
+}
+
+/// Logs the user of a session off, e.g. once maintenance launched into the session is done
+///
+/// Programs in the session are closed without being asked to save their work.
+///
+/// # Arguments
+///
+/// * `session_id` - Id of the session.
+/// * `wait` - Whether to return only once the logoff has finished.
+///
+/// ```no_run
+/// # fn main() -> Result<(), win_run::Error> {
+/// use win_run::{logoff_session, win, LaunchQueue};
+///
+/// // The queue delivers the result once the process has exited
+/// let queue = LaunchQueue::new(1);
+/// let _ = queue.push(win("/path/to/installer").session(2)).recv();
+/// logoff_session(2, true)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn logoff_session(session_id: u32, wait: bool) -> Result<(), Error> {
+    log_off_session(session_id, wait)
+}
+
+/// Disconnects the client of a session, leaving the user logged on and their programs running
+///
+/// The session locks, and the user gets it back as they left it when they reconnect.
+///
+/// # Arguments
+///
+/// * `session_id` - Id of the session.
+/// * `wait` - Whether to return only once the session has been disconnected.
+pub fn disconnect_session(session_id: u32, wait: bool) -> Result<(), Error> {
+    disconnect_session_client(session_id, wait)
+}
+
+/// How long no keyboard or mouse input has been given to a session
+///
+/// Use it to hold maintenance launches back until the user has stepped away.
+/// Returns `None` if the system does not track input for the session, which some
+/// versions of Windows do for the physical console.
+///
+/// # Arguments
+///
+/// * `session_id` - Id of the session.
+///
+/// ```no_run
+/// # fn main() -> Result<(), win_run::Error> {
+/// use std::time::Duration;
+/// use win_run::{session_idle_time, win};
+///
+/// let idle = session_idle_time(2)?;
+/// if idle.is_some_and(|idle| idle >= Duration::from_secs(10 * 60)) {
+///     win("/path/to/defrag").session(2).run()?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn session_idle_time(session_id: u32) -> Result<Option<Duration>, Error> {
+    get_session_idle_time(session_id)
+}
+
+/// Whether a session is locked, i.e. the user sees the lock screen on the secure desktop
+///
+/// A window launched onto [`Desktop::Default`](crate::Desktop::Default) of a locked session
+/// stays out of sight until the user unlocks it, so callers can wait, or launch onto
+/// [`Desktop::Secure`](crate::Desktop::Secure) instead.
+/// Returns `None` if the system does not report the state for the session.
+///
+/// # Arguments
+///
+/// * `session_id` - Id of the session.
+///
+/// ```no_run
+/// # fn main() -> Result<(), win_run::Error> {
+/// use win_run::{is_session_locked, win, Desktop};
+///
+/// let desktop = match is_session_locked(2)? {
+///     Some(true) => Desktop::Secure,
+///     _ => Desktop::Default,
+/// };
+/// win("/path/to/notifier").session(2).desktop(desktop).run()?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn is_session_locked(session_id: u32) -> Result<Option<bool>, Error> {
+    get_session_locked(session_id)
+}
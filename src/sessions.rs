@@ -0,0 +1,66 @@
+use crate::error::Error;
+use crate::safe_windows_bindings::high_level::get_all_sessions;
+
+/// The connection state of a terminal services session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SessionState {
+    /// A user is logged on and connected
+    Active,
+    /// Connected to a client, but no user is logged on
+    Connected,
+    /// In the process of connecting to a client
+    ConnectQuery,
+    /// Shadowing another session
+    Shadow,
+    /// A user is logged on, but no client is connected
+    Disconnected,
+    /// Waiting for a client to connect
+    Idle,
+    /// Listening for connections
+    Listen,
+    /// Being reset
+    Reset,
+    /// Down due to an error
+    Down,
+    /// Initializing
+    Init,
+    /// A state not known to this crate
+    Other(i32),
+}
+
+impl SessionState {
+    /// Converts a native connection state
+    pub(crate) fn from_native(state: i32) -> Self {
+        match state {
+            0 => SessionState::Active,
+            1 => SessionState::Connected,
+            2 => SessionState::ConnectQuery,
+            3 => SessionState::Shadow,
+            4 => SessionState::Disconnected,
+            5 => SessionState::Idle,
+            6 => SessionState::Listen,
+            7 => SessionState::Reset,
+            8 => SessionState::Down,
+            9 => SessionState::Init,
+            other => SessionState::Other(other),
+        }
+    }
+}
+
+/// Information about a terminal services session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionInfo {
+    /// The id of the session
+    pub id: u32,
+    /// The connection state of the session
+    pub state: SessionState,
+    /// The name of the logged on user, if any
+    pub user: Option<String>,
+    /// The domain of the logged on user, if any
+    pub domain: Option<String>,
+}
+
+/// Gets all sessions on the local machine
+pub(crate) fn list_sessions() -> Result<Vec<SessionInfo>, Error> {
+    get_all_sessions()
+}
@@ -0,0 +1,382 @@
+use crate::capture::{pump, LineSplitter, PipeReader, PipeWriter};
+use crate::encoding::Encoding;
+use crate::error::{Error, Stage};
+use crate::rotation::{RotatingLog, RotatingWriter};
+use crate::safe_windows_bindings::high_level::{
+    close_handle, copy_handle_inheritable, open_file_for_reading, open_file_for_writing,
+};
+use crate::safe_windows_bindings::types::HANDLE;
+use std::fmt::{Debug, Formatter};
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// The device that discards everything written to it and reads as empty
+const NULL_DEVICE: &str = "NUL";
+
+/// A writer shared by every launch of a builder, receiving the output of the processes
+#[derive(Clone)]
+pub struct SharedWriter {
+    writer: Arc<Mutex<dyn Write + Send>>,
+}
+
+impl SharedWriter {
+    /// Shares the writer
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Arc::new(Mutex::new(writer)),
+        }
+    }
+}
+
+impl Debug for SharedWriter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedWriter").finish_non_exhaustive()
+    }
+}
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // A writer that panicked is still the caller's writer
+        let mut writer = self.writer.lock().unwrap_or_else(|err| err.into_inner());
+        writer.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let mut writer = self.writer.lock().unwrap_or_else(|err| err.into_inner());
+        writer.flush()
+    }
+}
+
+/// A callback shared by every launch of a builder, receiving the output of the processes line by line
+#[derive(Clone)]
+pub struct LineCallback {
+    callback: Arc<dyn Fn(&str) + Send + Sync>,
+}
+
+impl LineCallback {
+    /// Shares the callback
+    pub fn new(callback: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        Self {
+            callback: Arc::new(callback),
+        }
+    }
+
+    /// Passes a line to the callback
+    pub(crate) fn call(&self, line: &str) {
+        (self.callback)(line)
+    }
+}
+
+impl Debug for LineCallback {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LineCallback").finish_non_exhaustive()
+    }
+}
+
+/// What a standard stream of the new process is connected to
+#[derive(Debug, Clone, Default)]
+pub enum Stdio {
+    /// The stream is left to the process, e.g. a console program writes to its own console
+    #[default]
+    Console,
+    /// The stream reads as empty and discards everything written to it
+    Null,
+    /// The file at the path, which an output stream creates or truncates
+    File(String),
+    /// The file at the path, which an output stream creates or writes to the end of
+    Append(String),
+    /// A handle the caller has opened, e.g. through `AsRawHandle` of a `std::fs::File`
+    ///
+    /// The handle is duplicated for the launch and stays owned by the caller
+    Handle(HANDLE),
+    /// A log file with size and age limits, written by a thread of the service
+    ///
+    /// Only output streams can be written to a log
+    Log(RotatingLog),
+    /// A writer of the caller, written to by a thread of the service as output arrives
+    ///
+    /// Only output streams can be written to a writer
+    Writer(SharedWriter),
+    /// A callback of the caller, called by a thread of the service for every line as it arrives
+    ///
+    /// Lines are passed without their line ending, decoded from the builder's output encoding.
+    /// Only output streams can be passed to a callback.
+    Lines(LineCallback),
+    /// Every one of the targets at once, e.g. a `Writer` to monitor the process and a `Log` to keep its output
+    ///
+    /// The targets can be `Null`, `File`, `Append`, `Log`, `Writer`, `Lines` and nested tees.
+    /// Only output streams can be teed.
+    Tee(Vec<Stdio>),
+    /// A pipe the service reads or writes itself, through [`Child::take_stdin`](crate::Child::take_stdin),
+    /// [`Child::take_stdout`](crate::Child::take_stdout) or [`Child::take_stderr`](crate::Child::take_stderr)
+    ///
+    /// The process blocks once an output pipe is full, so read it while the process runs.
+    /// The process reads the end of its input once the pipe is dropped.
+    /// Lines of a piped stream are not reported as [events](crate::ProcessBuilder::events).
+    Piped,
+}
+
+impl Stdio {
+    /// Adds a line callback to the target, keeping the target unless it is the console
+    pub(crate) fn with_lines(self, callback: LineCallback) -> Self {
+        match self {
+            Stdio::Console => Stdio::Lines(callback),
+            // The service reads the pipe itself, it cannot be teed
+            Stdio::Piped => Stdio::Piped,
+            Stdio::Tee(mut targets) => {
+                targets.push(Stdio::Lines(callback));
+                Stdio::Tee(targets)
+            }
+            target => Stdio::Tee(vec![target, Stdio::Lines(callback)]),
+        }
+    }
+
+    /// Whether the stream is connected to anything but the process's own console
+    pub(crate) fn is_redirected(&self) -> bool {
+        !matches!(self, Stdio::Console)
+    }
+
+    /// Opens an inheritable handle for the stream, invalid if it stays unconnected
+    ///
+    /// Targets the service copies output into are opened by [`Stdio::open_output`].
+    fn open(&self, output: bool) -> Result<HANDLE, Error> {
+        match self {
+            Stdio::Console => Ok(HANDLE::default()),
+            Stdio::Null if output => open_file_for_writing(NULL_DEVICE, true),
+            Stdio::Null => open_file_for_reading(NULL_DEVICE),
+            Stdio::File(path) if output => open_file_for_writing(path, false),
+            Stdio::Append(path) if output => open_file_for_writing(path, true),
+            Stdio::File(path) | Stdio::Append(path) => open_file_for_reading(path),
+            Stdio::Handle(handle) => copy_handle_inheritable(*handle),
+            Stdio::Log(_) | Stdio::Writer(_) | Stdio::Lines(_) | Stdio::Tee(_) | Stdio::Piped => {
+                Err(Error::other(
+                    Stage::Redirection,
+                    "The standard input can only read from a file, handle or pipe",
+                ))
+            }
+        }
+    }
+
+    /// Opens an inheritable handle for an output stream, along with the reading end of a pipe
+    ///
+    /// The thread copying the output into targets of the service is added to the pumps.
+    fn open_output(
+        &self,
+        encoding: Encoding,
+        pumps: &mut Vec<JoinHandle<()>>,
+    ) -> Result<(HANDLE, Option<PipeReader>), Error> {
+        match self {
+            Stdio::Piped => {
+                let (reader, write_handle) = PipeReader::create()?;
+                Ok((write_handle, Some(reader)))
+            }
+            Stdio::Log(_) | Stdio::Writer(_) | Stdio::Lines(_) | Stdio::Tee(_) => {
+                let mut sinks: Vec<Box<dyn Write + Send>> = Vec::new();
+                self.add_sinks(&mut sinks, encoding)?;
+                let (reader, write_handle) = PipeReader::create()?;
+                pumps.push(pump(reader, sinks));
+                Ok((write_handle, None))
+            }
+            stdio => Ok((stdio.open(true)?, None)),
+        }
+    }
+
+    /// Opens an inheritable handle for the input stream, along with the writing end of a pipe
+    fn open_input(&self) -> Result<(HANDLE, Option<PipeWriter>), Error> {
+        match self {
+            Stdio::Piped => {
+                let (writer, read_handle) = PipeWriter::create()?;
+                Ok((read_handle, Some(writer)))
+            }
+            stdio => Ok((stdio.open(false)?, None)),
+        }
+    }
+
+    /// Opens the targets a captured output stream is copied into
+    fn add_sinks(
+        &self,
+        sinks: &mut Vec<Box<dyn Write + Send>>,
+        encoding: Encoding,
+    ) -> Result<(), Error> {
+        match self {
+            Stdio::Null => {}
+            Stdio::File(path) | Stdio::Append(path) => {
+                let append = matches!(self, Stdio::Append(_));
+                let file = OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .append(append)
+                    .truncate(!append)
+                    .open(path)
+                    .map_err(|err| {
+                        Error::other(
+                            Stage::Redirection,
+                            format!("Unable to open file {path}: {err}"),
+                        )
+                    })?;
+                sinks.push(Box::new(file));
+            }
+            Stdio::Log(log) => sinks.push(Box::new(RotatingWriter::open(log.clone())?)),
+            Stdio::Writer(writer) => sinks.push(Box::new(writer.clone())),
+            Stdio::Lines(callback) => {
+                sinks.push(Box::new(LineSplitter::new(callback.clone(), encoding)))
+            }
+            Stdio::Tee(targets) => {
+                for target in targets {
+                    target.add_sinks(sinks, encoding)?;
+                }
+            }
+            Stdio::Console | Stdio::Handle(_) | Stdio::Piped => {
+                return Err(Error::other(
+                    Stage::Redirection,
+                    "The console, handles and pipes cannot be teed",
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The inheritable input, output and error handles of a launch, closed on drop
+pub(crate) struct StdHandles {
+    handles: [HANDLE; 3],
+    /// The writing end of a piped input stream, until handed to the child
+    stdin: Option<ChildStdin>,
+    /// The reading end of a piped output stream, until handed to the child
+    stdout: Option<ChildStdout>,
+    /// The reading end of a piped error stream, until handed to the child
+    stderr: Option<ChildStderr>,
+    /// The threads copying output into targets of the service, until handed to the child
+    pumps: Vec<JoinHandle<()>>,
+}
+
+impl StdHandles {
+    /// Opens the handles of the streams, unless none of them is redirected
+    ///
+    /// Output passed to line callbacks is decoded from the encoding
+    pub(crate) fn open(
+        stdin: &Stdio,
+        stdout: &Stdio,
+        stderr: &Stdio,
+        encoding: Encoding,
+    ) -> Result<Option<Self>, Error> {
+        if ![stdin, stdout, stderr]
+            .into_iter()
+            .any(Stdio::is_redirected)
+        {
+            return Ok(None);
+        }
+
+        // Already opened handles are closed on drop if a later one fails
+        let mut std_handles = Self {
+            handles: [HANDLE::default(); 3],
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            pumps: Vec::new(),
+        };
+        let (handle, writer) = stdin.open_input()?;
+        std_handles.handles[0] = handle;
+        std_handles.stdin = writer.map(|writer| ChildStdin { writer });
+        let (handle, reader) = stdout.open_output(encoding, &mut std_handles.pumps)?;
+        std_handles.handles[1] = handle;
+        std_handles.stdout = reader.map(|reader| ChildStdout { reader });
+        let (handle, reader) = stderr.open_output(encoding, &mut std_handles.pumps)?;
+        std_handles.handles[2] = handle;
+        std_handles.stderr = reader.map(|reader| ChildStderr { reader });
+
+        Ok(Some(std_handles))
+    }
+
+    /// The input, output and error handles, invalid for unconnected streams
+    pub(crate) fn handles(&self) -> [HANDLE; 3] {
+        self.handles
+    }
+
+    /// Takes the ends of the piped streams, to hand them to the child
+    pub(crate) fn take_pipes(
+        &mut self,
+    ) -> (Option<ChildStdin>, Option<ChildStdout>, Option<ChildStderr>) {
+        (self.stdin.take(), self.stdout.take(), self.stderr.take())
+    }
+
+    /// Takes the threads copying output into targets of the service, to hand them to the child
+    pub(crate) fn take_pumps(&mut self) -> Vec<JoinHandle<()>> {
+        std::mem::take(&mut self.pumps)
+    }
+}
+
+impl Drop for StdHandles {
+    fn drop(&mut self) {
+        for handle in self.handles {
+            if handle.is_invalid() {
+                continue;
+            }
+            // The process has its own copies, nothing sensible can be done if closing fails here
+            let _ = close_handle(handle);
+        }
+    }
+}
+
+/// The writing end of the standard input of a child launched with [`Stdio::Piped`], closed on drop
+///
+/// The process reads the end of its input once this is dropped.
+pub struct ChildStdin {
+    writer: PipeWriter,
+}
+
+impl Debug for ChildStdin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChildStdin").finish_non_exhaustive()
+    }
+}
+
+impl Write for ChildStdin {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// The reading end of the standard output of a child launched with [`Stdio::Piped`], closed on drop
+///
+/// Reads return zero once the process, and every process that inherited the stream, closed it.
+pub struct ChildStdout {
+    reader: PipeReader,
+}
+
+impl Debug for ChildStdout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChildStdout").finish_non_exhaustive()
+    }
+}
+
+impl Read for ChildStdout {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+/// The reading end of the standard error of a child launched with [`Stdio::Piped`], closed on drop
+///
+/// Reads return zero once the process, and every process that inherited the stream, closed it.
+pub struct ChildStderr {
+    reader: PipeReader,
+}
+
+impl Debug for ChildStderr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChildStderr").finish_non_exhaustive()
+    }
+}
+
+impl Read for ChildStderr {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
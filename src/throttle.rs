@@ -0,0 +1,193 @@
+use crate::error::{Error, Stage};
+use crate::safe_windows_bindings::high_level::{close_handle, has_exited, open_process_for_wait};
+use crate::safe_windows_bindings::types::HANDLE;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// How often a queued launch checks whether it may proceed
+const THROTTLE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The window over which launches per second are counted
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// What to do with a launch that exceeds a throttle's limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Fail the launch right away
+    #[default]
+    Reject,
+    /// Hold the launch back until it fits the limits, failing it if that takes longer than the timeout
+    Queue(Duration),
+}
+
+/// A running child counted against the concurrency limit, closed when it is no longer counted
+struct TrackedChild(HANDLE);
+
+impl Drop for TrackedChild {
+    fn drop(&mut self) {
+        let _ = close_handle(self.0);
+    }
+}
+
+/// The state shared between the clones of a throttle
+#[derive(Default)]
+struct ThrottleState {
+    /// When the launches of the last second started
+    launches: VecDeque<Instant>,
+    /// Launches let through that have not started a process yet
+    pending: usize,
+    /// Children that were running the last time they were checked
+    children: Vec<TrackedChild>,
+}
+
+/// Limits how fast and how many processes are launched, to contain restart loops
+///
+/// Every builder given the same throttle, or one of its clones, is limited together.
+///
+/// ```no_run
+/// # fn main() -> Result<(), win_run::Error> {
+/// use std::time::Duration;
+/// use win_run::{win, Overflow, Throttle};
+///
+/// let throttle = Throttle::new()
+///     .max_per_second(2)
+///     .max_concurrent(4)
+///     .overflow(Overflow::Queue(Duration::from_secs(10)));
+///
+/// win("/path/to/executable").throttle(throttle.clone()).run()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Throttle {
+    /// The most launches started within a second
+    pub(crate) max_per_second: Option<u32>,
+    /// The most children running at once
+    pub(crate) max_concurrent: Option<usize>,
+    /// What to do with launches over the limits
+    pub(crate) overflow: Overflow,
+    state: Arc<Mutex<ThrottleState>>,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Throttle {
+    /// Creates a throttle without limits
+    pub fn new() -> Self {
+        let max_per_second = None;
+        let max_concurrent = None;
+        let overflow = Overflow::default();
+        let state = Arc::new(Mutex::new(ThrottleState::default()));
+
+        Self {
+            max_per_second,
+            max_concurrent,
+            overflow,
+            state,
+        }
+    }
+
+    /// Sets the most launches started within a second, default is **unlimited**
+    pub fn max_per_second(mut self, max_per_second: u32) -> Self {
+        self.max_per_second = Some(max_per_second);
+        self
+    }
+
+    /// Sets the most children running at once, default is **unlimited**
+    ///
+    /// Only children started through this throttle are counted.
+    pub fn max_concurrent(mut self, max_concurrent: usize) -> Self {
+        self.max_concurrent = Some(max_concurrent);
+        self
+    }
+
+    /// Sets what to do with launches over the limits, default is **Overflow::Reject**
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// The number of children started through the throttle that are still running
+    pub fn running(&self) -> usize {
+        let mut state = self.lock();
+        Self::prune(&mut state);
+        state.children.len()
+    }
+
+    /// Lets a launch through, waiting for room or failing according to the overflow policy
+    ///
+    /// Every admitted launch must be followed by [`Throttle::release`].
+    pub(crate) fn admit(&self) -> Result<(), Error> {
+        let started = Instant::now();
+        loop {
+            let mut state = self.lock();
+            Self::prune(&mut state);
+
+            let over_rate = self
+                .max_per_second
+                .is_some_and(|max| state.launches.len() >= max as usize);
+            let over_concurrency = self
+                .max_concurrent
+                .is_some_and(|max| state.children.len() + state.pending >= max);
+
+            if !over_rate && !over_concurrency {
+                state.launches.push_back(Instant::now());
+                state.pending += 1;
+                return Ok(());
+            }
+
+            let reason = if over_rate {
+                "Too many launches within a second"
+            } else {
+                "Too many children are running"
+            };
+            match self.overflow {
+                Overflow::Reject => return Err(Error::other(Stage::Throttling, reason)),
+                Overflow::Queue(timeout) if started.elapsed() >= timeout => {
+                    return Err(Error::other(
+                        Stage::Throttling,
+                        format!("{reason}, gave up waiting after {timeout:?}"),
+                    ));
+                }
+                Overflow::Queue(_) => {}
+            }
+
+            drop(state);
+            sleep(THROTTLE_POLL_INTERVAL);
+        }
+    }
+
+    /// Finishes an admitted launch, counting the started process if there is one
+    pub(crate) fn release(&self, pid: Option<u32>) {
+        let mut state = self.lock();
+        state.pending -= 1;
+        // A process that cannot be opened cannot be waited for, so it is not counted
+        if let Some(Ok(handle)) = pid.map(open_process_for_wait) {
+            state.children.push(TrackedChild(handle));
+        }
+    }
+
+    /// Forgets launches older than a second and children that exited
+    fn prune(state: &mut ThrottleState) {
+        while let Some(launch) = state.launches.front() {
+            if launch.elapsed() < RATE_WINDOW {
+                break;
+            }
+            state.launches.pop_front();
+        }
+        state
+            .children
+            .retain(|child| matches!(has_exited(child.0), Ok(false)));
+    }
+
+    /// Locks the shared state, ignoring poisoning by a panicking launch
+    fn lock(&self) -> MutexGuard<'_, ThrottleState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
@@ -0,0 +1,82 @@
+use crate::error::Error;
+use crate::identity::TokenOrigin;
+use crate::safe_windows_bindings::high_level::{
+    close_handle, get_process_token, get_token_session_id, get_token_user_sid,
+};
+use crate::safe_windows_bindings::types::HANDLE;
+use crate::ProcessBuilder;
+
+/// An access token, closed when dropped
+///
+/// Lets callers compare who launches would run as, e.g. to notice that the user of the
+/// active console session changed between caching something for them and launching.
+///
+/// ```no_run
+/// # fn main() -> Result<(), win_run::Error> {
+/// use win_run::{win, Token};
+///
+/// let builder = win("/path/to/tray");
+/// let cached_for = Token::acquire(&builder)?;
+///
+/// // Later, before reusing what was cached
+/// if !cached_for.same_user(&Token::acquire(&builder)?)? {
+///     // Another user took over the console, start over for them
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Token {
+    handle: HANDLE,
+    /// Where the token came from, if it was acquired for a builder
+    origin: Option<TokenOrigin>,
+}
+
+impl Token {
+    /// Acquires the token the builder would launch with, through its token pool if it has one
+    ///
+    /// Tokens of [`LaunchBackend`](crate::LaunchBackend)s of the caller cannot be acquired,
+    /// the token is acquired according to the builder's elevation in any case.
+    pub fn acquire(builder: &ProcessBuilder) -> Result<Self, Error> {
+        let (handle, origin) = builder.acquire_token()?;
+        Ok(Self {
+            handle,
+            origin: Some(origin),
+        })
+    }
+
+    /// Opens the token of a running process
+    pub fn of_process(pid: u32) -> Result<Self, Error> {
+        Ok(Self {
+            handle: get_process_token(pid)?,
+            origin: None,
+        })
+    }
+
+    /// The SID of the user the token belongs to, in its string form
+    pub fn user_sid(&self) -> Result<String, Error> {
+        get_token_user_sid(self.handle)
+    }
+
+    /// The session the token belongs to
+    pub fn session_id(&self) -> Result<u32, Error> {
+        get_token_session_id(self.handle)
+    }
+
+    /// Whether both tokens belong to the same user, regardless of their session or elevation
+    pub fn same_user(&self, other: &Token) -> Result<bool, Error> {
+        Ok(self.user_sid()? == other.user_sid()?)
+    }
+
+    /// Where the token came from, `None` for tokens of running processes
+    pub fn origin(&self) -> Option<&TokenOrigin> {
+        self.origin.as_ref()
+    }
+}
+
+impl Drop for Token {
+    fn drop(&mut self) {
+        // Nothing sensible can be done if closing fails
+        let _ = close_handle(self.handle);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::error::Error;
+use std::ffi::OsStr;
+use std::fmt::{Display, Formatter};
+use widestring::U16String;
+
+/// The longest command line process creation accepts, in UTF-16 code units without the terminating null
+pub(crate) const MAX_COMMAND_LINE_LENGTH: usize = 32_766;
+
+/// A part of the process builder's configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Path,
+    Args,
+    Directory,
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Field::Path => "path",
+            Field::Args => "arguments",
+            Field::Directory => "directory",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// A configuration problem detected by [`ProcessBuilder::validate`](crate::ProcessBuilder::validate)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// No path to an executable was provided
+    EmptyPath,
+    /// The executable does not exist
+    ExecutableNotFound(String),
+    /// The executable path points to something other than a file
+    ExecutableNotAFile(String),
+    /// The directory does not exist
+    DirectoryNotFound(String),
+    /// The directory path points to something other than a directory
+    NotADirectory(String),
+    /// A value contains a null character and cannot be passed to Windows
+    InteriorNul(Field),
+    /// Environment variables in a value could not be expanded
+    ExpansionFailed(Field, Error),
+    /// The command line, holding its length in UTF-16 code units, is longer than Windows accepts
+    CommandLineTooLong(usize),
+    /// The window station or desktop name of a custom desktop is unusable, with what is wrong
+    InvalidDesktop(String),
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::EmptyPath => write!(f, "No path to an executable was provided"),
+            Problem::ExecutableNotFound(path) => write!(f, "Executable not found: {path}"),
+            Problem::ExecutableNotAFile(path) => write!(f, "Executable is not a file: {path}"),
+            Problem::DirectoryNotFound(path) => write!(f, "Directory not found: {path}"),
+            Problem::NotADirectory(path) => write!(f, "Not a directory: {path}"),
+            Problem::InteriorNul(field) => write!(f, "Found a null character in the {field}"),
+            Problem::ExpansionFailed(field, err) => {
+                write!(
+                    f,
+                    "Could not expand environment variables in the {field}: {err}"
+                )
+            }
+            Problem::CommandLineTooLong(length) => write!(
+                f,
+                "The command line is {length} characters long, Windows accepts at most {MAX_COMMAND_LINE_LENGTH}"
+            ),
+            Problem::InvalidDesktop(problem) => write!(f, "Invalid desktop: {problem}"),
+        }
+    }
+}
+
+/// The length of a part of a command line in the UTF-16 code units Windows counts
+pub(crate) fn command_line_length(command_line: &OsStr) -> usize {
+    U16String::from_os_str(command_line).len()
+}
+
+/// Fails with the length of the command line if Windows would reject it as too long
+pub(crate) fn check_command_line_length(command_line: &OsStr) -> Result<(), Error> {
+    let length = command_line_length(command_line);
+    if length > MAX_COMMAND_LINE_LENGTH {
+        return Err(Error::command_line_too_long(
+            length,
+            MAX_COMMAND_LINE_LENGTH,
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arguments::command_line;
+
+    /// A path with a space, which the command line quotes, and arguments filling the rest of it
+    fn launch_at_limit() -> (String, String) {
+        let path = "C:\\Program Files\\tool.exe".to_string();
+        // The quotes around the path and the space before the arguments
+        let args = "a".repeat(MAX_COMMAND_LINE_LENGTH - path.len() - 3);
+        (path, args)
+    }
+
+    #[test]
+    fn counts_the_quotes_around_the_executable() {
+        let (path, args) = launch_at_limit();
+        let command_line = command_line(OsStr::new(&path), OsStr::new(&args));
+        assert_eq!(command_line_length(&command_line), MAX_COMMAND_LINE_LENGTH);
+        assert!(check_command_line_length(&command_line).is_ok());
+    }
+
+    #[test]
+    fn rejects_a_quoted_command_line_over_the_limit() {
+        let (path, mut args) = launch_at_limit();
+        args.push('a');
+        let command_line = command_line(OsStr::new(&path), OsStr::new(&args));
+        match check_command_line_length(&command_line) {
+            Err(Error::CommandLineTooLong { length, .. }) => {
+                assert_eq!(length, MAX_COMMAND_LINE_LENGTH + 1)
+            }
+            other => panic!("Expected the command line to be too long, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn counts_no_separator_without_arguments() {
+        let command_line = command_line(OsStr::new("C:\\tool.exe"), OsStr::new(""));
+        assert_eq!(command_line_length(&command_line), "C:\\tool.exe".len());
+    }
+
+    #[test]
+    fn counts_utf16_code_units() {
+        // Outside the basic multilingual plane, so two code units but four UTF-8 bytes
+        assert_eq!(command_line_length(OsStr::new("\u{1F600}")), 2);
+        assert_eq!(command_line_length(OsStr::new("é")), 1);
+    }
+}
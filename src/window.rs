@@ -0,0 +1,44 @@
+use crate::error::Error;
+use crate::safe_windows_bindings::high_level::get_all_windows;
+
+/// Information about a top-level window
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowInfo {
+    /// The raw handle of the window
+    pub hwnd: isize,
+    /// The id of the process that created the window
+    pub pid: u32,
+    /// The session the owning process runs in, if it could be queried
+    pub session_id: Option<u32>,
+    /// The title of the window
+    pub title: String,
+    /// The class name of the window
+    pub class: String,
+    /// Whether the window is visible
+    pub visible: bool,
+}
+
+/// Finds a process in a session by one of its top-level windows
+///
+/// A window matches if its title contains `title` and its class name equals `class`, a `None` matching anything.
+///
+/// Only windows on the desktop of the calling thread can be enumerated,
+/// so a service must call this from a thread attached to the target session's desktop.
+///
+/// # Arguments
+///
+/// * `session_id` - Session in which the process must run.
+/// * `title` - Part of the window title to look for.
+/// * `class` - Window class name to look for, e.g. `Shell_TrayWnd` for the shell.
+pub fn find_process_by_window(
+    session_id: u32,
+    title: Option<&str>,
+    class: Option<&str>,
+) -> Result<Option<WindowInfo>, Error> {
+    let window = get_all_windows()?.into_iter().find(|window| {
+        window.session_id == Some(session_id)
+            && title.is_none_or(|title| window.title.contains(title))
+            && class.is_none_or(|class| window.class == class)
+    });
+    Ok(window)
+}